@@ -0,0 +1,194 @@
+//
+//  Sample application.
+//
+//  A filesystem that stores files encrypted in a directory on disk, and
+//  serves them decrypted. The encryption is done by blocking code that
+//  runs on the blocking thread pool via `BlockingFile`, so it does not
+//  hold up other requests.
+//
+//  The "cipher" here is a simple XOR with a key, a stand-in for a real
+//  one. Do not use it to protect anything.
+//
+//  Stores a file with PUT, shows what is on disk, and reads it back with GET.
+//
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+
+use dav_server::asyncfs::{async_trait, AsyncDavFileSystem};
+use dav_server::blockingfile::{BlockingFile, SyncFile};
+use dav_server::davpath::DavPath;
+use dav_server::fs::*;
+use dav_server::{body::Body, localfs::LocalFs, DavHandler};
+
+// Encrypted file on disk.
+struct EncryptedFile {
+    file: File,
+    key: Arc<Vec<u8>>,
+    pos: u64,
+}
+
+impl EncryptedFile {
+    // en- and decryption are the same operation.
+    fn apply(&self, data: &mut [u8]) {
+        for (i, b) in data.iter_mut().enumerate() {
+            let pos = self.pos as usize + i;
+            *b ^= self.key[pos % self.key.len()];
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Meta(std::fs::Metadata);
+
+impl DavMetaData for Meta {
+    fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Ok(self.0.modified()?)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+}
+
+impl SyncFile for EncryptedFile {
+    fn metadata(&mut self) -> FsResult<Box<dyn DavMetaData>> {
+        Ok(Box::new(Meta(self.file.metadata()?)))
+    }
+
+    fn write(&mut self, data: Bytes) -> FsResult<()> {
+        let mut data = data.to_vec();
+        self.apply(&mut data);
+        self.file.write_all(&data)?;
+        self.pos += data.len() as u64;
+        Ok(())
+    }
+
+    fn read(&mut self, count: usize) -> FsResult<Bytes> {
+        let mut data = vec![0; count];
+        let n = self.file.read(&mut data)?;
+        data.truncate(n);
+        self.apply(&mut data);
+        self.pos += n as u64;
+        Ok(Bytes::from(data))
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsResult<u64> {
+        self.pos = self.file.seek(pos)?;
+        Ok(self.pos)
+    }
+
+    fn flush(&mut self) -> FsResult<()> {
+        Ok(self.file.flush()?)
+    }
+}
+
+// Files are opened here, everything else is done by LocalFs.
+#[derive(Clone)]
+struct EncryptedFs {
+    dir: PathBuf,
+    key: Arc<Vec<u8>>,
+    local: Box<LocalFs>,
+}
+
+#[async_trait]
+impl AsyncDavFileSystem for EncryptedFs {
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        let path = self.dir.join(path.as_rel_ospath());
+        let key = self.key.clone();
+        let open = move || {
+            let file = std::fs::OpenOptions::new()
+                .read(options.read)
+                .write(options.write)
+                .append(options.append)
+                .truncate(options.truncate)
+                .create(options.create)
+                .create_new(options.create_new)
+                .open(path)?;
+            let pos = match options.append {
+                true => file.metadata()?.len(),
+                false => 0,
+            };
+            Ok::<_, FsError>(EncryptedFile { file, key, pos })
+        };
+        let file = tokio::task::spawn_blocking(open)
+            .await
+            .map_err(|_| FsError::GeneralFailure)??;
+        Ok(BlockingFile::new(file))
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        self.local.read_dir(path, meta).await
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        self.local.metadata(path).await
+    }
+
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.local.create_dir(path).await
+    }
+
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.local.remove_dir(path).await
+    }
+
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        self.local.remove_file(path).await
+    }
+
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.local.rename(from, to).await
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let dir = std::env::temp_dir().join(format!("dav-encrypted-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let fs = EncryptedFs {
+        dir: dir.clone(),
+        key: Arc::new(b"not a real key".to_vec()),
+        local: LocalFs::new(&dir, false, false, false),
+    };
+    let dav = DavHandler::builder()
+        .filesystem(Box::new(fs))
+        .build_handler();
+
+    let req = http::Request::builder()
+        .method("PUT")
+        .uri("/secret.txt")
+        .body(Body::from("attack at dawn"))
+        .unwrap();
+    println!("PUT: {}", dav.handle(req).await.status());
+
+    let stored = std::fs::read(dir.join("secret.txt")).unwrap();
+    println!("on disk: {:02x?}", stored);
+
+    let req = http::Request::builder()
+        .method("GET")
+        .uri("/secret.txt")
+        .body(Body::empty())
+        .unwrap();
+    let resp = dav.handle(req).await;
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    println!("GET: {}", String::from_utf8_lossy(&body));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
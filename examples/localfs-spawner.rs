@@ -0,0 +1,88 @@
+//
+//  Sample application.
+//
+//  Uses LocalFs without a tokio runtime. The blocking filesystem calls
+//  are run on a plain thread by a custom spawner, and the handler
+//  future is driven by a tiny executor. With async-std or smol you
+//  would use their `spawn_blocking` and `block_on` instead.
+//
+//  Lists the current directory with a PROPFIND request.
+//
+
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+
+use futures_util::future::{BoxFuture, FutureExt};
+use futures_util::pin_mut;
+use http_body_util::BodyExt;
+
+use dav_server::{body::Body, localfs::LocalFs, localfs::SpawnBlocking, DavHandler};
+
+// Runs every blocking task on a new thread.
+#[derive(Debug)]
+struct ThreadSpawner;
+
+impl SpawnBlocking for ThreadSpawner {
+    fn spawn_blocking(&self, func: Box<dyn FnOnce() + Send + 'static>) -> BoxFuture<'static, ()> {
+        let (tx, rx) = futures_channel::oneshot::channel();
+        thread::spawn(move || {
+            func();
+            let _ = tx.send(());
+        });
+        async move {
+            let _ = rx.await;
+        }
+        .boxed()
+    }
+}
+
+// Minimal block_on().
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    pin_mut!(fut);
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(res) => return res,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let dir = std::env::current_dir().unwrap();
+    let dav = DavHandler::builder()
+        .filesystem(LocalFs::new_with_spawner(
+            dir,
+            false,
+            false,
+            false,
+            Arc::new(ThreadSpawner),
+        ))
+        .build_handler();
+
+    let req = http::Request::builder()
+        .method("PROPFIND")
+        .uri("/")
+        .header("Depth", "1")
+        .body(Body::empty())
+        .unwrap();
+
+    let body = block_on(async move {
+        let resp = dav.handle(req).await;
+        println!("status: {}", resp.status());
+        resp.into_body().collect().await.unwrap().to_bytes()
+    });
+    println!("{}", String::from_utf8_lossy(&body));
+}
@@ -0,0 +1,436 @@
+//! Write a filesystem backend with `async fn` methods.
+//!
+//! The methods of [`DavFileSystem`] return boxed futures, so that the
+//! trait can be used as a trait object. Writing those by hand is a bit
+//! verbose. This module contains [`AsyncDavFileSystem`], a version of the
+//! same trait that can be implemented using `async fn` and the
+//! [`async_trait`] attribute macro (re-exported here, so you do not need
+//! to depend on the `async-trait` crate yourself).
+//!
+//! Every type that implements `AsyncDavFileSystem` automatically
+//! implements `DavFileSystem` as well.
+//!
+//! Example:
+//!
+//! ```
+//! use dav_server::asyncfs::{async_trait, AsyncDavFileSystem};
+//! use dav_server::davpath::DavPath;
+//! use dav_server::fs::*;
+//! use dav_server::memfs::MemFs;
+//!
+//! // A filesystem that does not allow writes.
+//! #[derive(Clone)]
+//! struct ReadOnlyFs(Box<MemFs>);
+//!
+//! #[async_trait]
+//! impl AsyncDavFileSystem for ReadOnlyFs {
+//!     async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+//!         if options.write || options.append || options.truncate || options.create {
+//!             return Err(FsError::Forbidden);
+//!         }
+//!         self.0.open(path, options).await
+//!     }
+//!
+//!     async fn read_dir(
+//!         &self,
+//!         path: &DavPath,
+//!         meta: ReadDirMeta,
+//!     ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+//!         self.0.read_dir(path, meta).await
+//!     }
+//!
+//!     async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+//!         self.0.metadata(path).await
+//!     }
+//! }
+//!
+//! let fs: Box<dyn DavFileSystem> = Box::new(ReadOnlyFs(MemFs::new()));
+//! ```
+//!
+use std::time::SystemTime;
+
+use http::StatusCode;
+
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+pub use async_trait::async_trait;
+
+/// Version of [`DavFileSystem`] with `async fn` methods.
+///
+/// See the [module documentation](self) for an example. The
+/// methods and their default implementations are the same as
+/// those of `DavFileSystem`.
+#[async_trait]
+pub trait AsyncDavFileSystem: Clone + Sync + Send + 'static {
+    /// Open a file.
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>>;
+
+    /// Open a file, when the metadata of the file is already known.
+    async fn open_with_meta(
+        &self,
+        path: &DavPath,
+        options: OpenOptions,
+        meta: Box<dyn DavMetaData>,
+    ) -> FsResult<Box<dyn DavFile>> {
+        let _ = meta;
+        AsyncDavFileSystem::open(self, path, options).await
+    }
+
+    /// Perform read_dir. See [`DavFileSystem::read_dir`].
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>>;
+
+    /// Return the metadata of a file or directory.
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>>;
+
+    /// Return the metadata of a file, directory or symbolic link.
+    ///
+    /// The default implementation calls `metadata()`.
+    async fn symlink_metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        self.metadata(path).await
+    }
+
+    /// Create a directory.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Remove a directory.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Remove a directory and everything below it.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn remove_dir_all(&self, path: &DavPath) -> FsResult<()> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Remove a file.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Rename a file or directory.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Rename a file or directory, and report how it was done.
+    ///
+    /// See [`DavFileSystem::rename_ext`].
+    async fn rename_ext(&self, from: &DavPath, to: &DavPath) -> FsResult<RenameKind> {
+        match self.rename(from, to).await {
+            Ok(()) => Ok(RenameKind::Native),
+            Err(FsError::IsRemote) => Ok(RenameKind::Unsupported),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Copy a file.
+    ///
+    /// See [`DavFileSystem::copy`].
+    ///
+    /// The default implementation returns FsError::NotImplemented,
+    /// and COPY then reads the file and writes it to the destination.
+    #[allow(unused_variables)]
+    async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Set the access time of a file / directory.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn set_accessed(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Set the modified time of a file / directory.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn set_modified(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Indicator that tells if this filesystem driver supports DAV properties.
+    ///
+    /// The default implementation returns `false`.
+    #[allow(unused_variables)]
+    async fn have_props(&self, path: &DavPath) -> bool {
+        false
+    }
+
+    /// Patch the DAV properties of a node (add/remove props).
+    ///
+    /// All or nothing, see [`DavFileSystem::patch_props`].
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn patch_props(
+        &self,
+        path: &DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(StatusCode, DavProp)>> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// List/get the DAV properties of a node.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn get_props(&self, path: &DavPath, do_content: bool) -> FsResult<Vec<DavProp>> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Get one specific named property of a node.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    async fn get_prop(&self, path: &DavPath, prop: DavProp) -> FsResult<Vec<u8>> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// Return a filesystem for a request with this context.
+    ///
+    /// The default implementation ignores the context and returns a clone of `self`.
+    #[allow(unused_variables)]
+    fn with_context(&self, context: &DavContext) -> Box<dyn DavFileSystem> {
+        Box::new(self.clone())
+    }
+
+    /// Get quota of this filesystem (used/total space).
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    async fn get_quota(&self) -> FsResult<(u64, Option<u64>)> {
+        Err(FsError::NotImplemented)
+    }
+
+    /// The number of bytes that GET asks for in one `DavFile::read_bytes` call.
+    ///
+    /// The default implementation returns 16 KiB.
+    fn preferred_read_chunk_size(&self) -> usize {
+        16384
+    }
+
+    /// Get a URL where the content of a file can be retrieved directly.
+    ///
+    /// The default implementation returns `None`.
+    async fn get_redirect_url(&self, path: &DavPath) -> FsResult<Option<String>> {
+        let _ = path;
+        Ok(None)
+    }
+
+    /// Return the ETag of a collection.
+    ///
+    /// The default implementation returns `None`.
+    async fn collection_etag(&self, path: &DavPath) -> FsResult<Option<String>> {
+        let _ = path;
+        Ok(None)
+    }
+
+    /// Is this path a special node, like a mount point?
+    ///
+    /// The default implementation returns `false`.
+    async fn is_special(&self, path: &DavPath) -> FsResult<bool> {
+        let _ = path;
+        Ok(false)
+    }
+}
+
+impl<FS: AsyncDavFileSystem> DavFileSystem for FS {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        AsyncDavFileSystem::open(self, path, options)
+    }
+
+    fn open_with_meta<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+        meta: Box<dyn DavMetaData>,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        AsyncDavFileSystem::open_with_meta(self, path, options, meta)
+    }
+
+    fn read_dir<'a>(
+        &'a self,
+        path: &'a DavPath,
+        meta: ReadDirMeta,
+    ) -> FsFuture<'a, FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        AsyncDavFileSystem::read_dir(self, path, meta)
+    }
+
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        AsyncDavFileSystem::metadata(self, path)
+    }
+
+    fn symlink_metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
+        AsyncDavFileSystem::symlink_metadata(self, path)
+    }
+
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        AsyncDavFileSystem::create_dir(self, path)
+    }
+
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        AsyncDavFileSystem::remove_dir(self, path)
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        AsyncDavFileSystem::remove_dir_all(self, path)
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        AsyncDavFileSystem::remove_file(self, path)
+    }
+
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        AsyncDavFileSystem::rename(self, from, to)
+    }
+
+    fn rename_ext<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, RenameKind> {
+        AsyncDavFileSystem::rename_ext(self, from, to)
+    }
+
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        AsyncDavFileSystem::copy(self, from, to)
+    }
+
+    fn set_accessed<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        AsyncDavFileSystem::set_accessed(self, path, tm)
+    }
+
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        AsyncDavFileSystem::set_modified(self, path, tm)
+    }
+
+    fn have_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = bool> + Send + 'a>> {
+        AsyncDavFileSystem::have_props(self, path)
+    }
+
+    fn patch_props<'a>(
+        &'a self,
+        path: &'a DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
+        AsyncDavFileSystem::patch_props(self, path, patch)
+    }
+
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
+        AsyncDavFileSystem::get_props(self, path, do_content)
+    }
+
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
+        AsyncDavFileSystem::get_prop(self, path, prop)
+    }
+
+    fn with_context(&self, context: &DavContext) -> Box<dyn DavFileSystem> {
+        AsyncDavFileSystem::with_context(self, context)
+    }
+
+    fn get_quota(&self) -> FsFuture<'_, (u64, Option<u64>)> {
+        AsyncDavFileSystem::get_quota(self)
+    }
+
+    fn preferred_read_chunk_size(&self) -> usize {
+        AsyncDavFileSystem::preferred_read_chunk_size(self)
+    }
+
+    fn get_redirect_url<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        AsyncDavFileSystem::get_redirect_url(self, path)
+    }
+
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        AsyncDavFileSystem::collection_etag(self, path)
+    }
+
+    fn is_special<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        AsyncDavFileSystem::is_special(self, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{put, req, run};
+    use crate::DavHandler;
+
+    // Backend that delegates everything except writes to a MemFs.
+    #[derive(Clone)]
+    struct ReadOnlyFs(Box<MemFs>);
+
+    #[async_trait]
+    impl AsyncDavFileSystem for ReadOnlyFs {
+        async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+            if options.write || options.create {
+                return Err(FsError::Forbidden);
+            }
+            self.0.open(path, options).await
+        }
+
+        async fn read_dir(
+            &self,
+            path: &DavPath,
+            meta: ReadDirMeta,
+        ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+            self.0.read_dir(path, meta).await
+        }
+
+        async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+            self.0.metadata(path).await
+        }
+    }
+
+    fn is_davfs<T: DavFileSystem>(_: &T) {}
+
+    #[tokio::test]
+    async fn async_backend() {
+        let memfs = MemFs::new();
+        let rw = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        put(&rw, "/file.txt", "hello").await;
+
+        let fs = ReadOnlyFs(memfs);
+        is_davfs(&fs);
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(fs))
+            .build_handler();
+
+        let (resp, body) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(body, "hello");
+
+        let r = req("PUT", "/file.txt").body(Body::from("bye")).unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert_eq!(resp.status(), 403);
+    }
+}
@@ -0,0 +1,210 @@
+//! Run a synchronous file on the blocking thread pool.
+//!
+//! A filesystem that does CPU heavy work on every read or write, like
+//! compression or encryption, should not do that work on the async
+//! executor: while it runs, no other request on that thread makes
+//! progress. Implement [`SyncFile`] with plain blocking code, and return
+//! it from `DavFileSystem::open` wrapped in a [`BlockingFile`]. Every
+//! call then runs via `tokio::task::spawn_blocking`.
+//!
+//! PUT awaits every write before it reads the next part of the body,
+//! so a slow `write` slows down that upload, but nothing else.
+//!
+//! ```
+//! use std::io::SeekFrom;
+//! use bytes::Bytes;
+//! use dav_server::blockingfile::{BlockingFile, SyncFile};
+//! use dav_server::fs::{DavFile, DavMetaData, FsError, FsResult};
+//!
+//! // Stores the data compressed, or encrypted, or ...
+//! struct MyFile;
+//!
+//! impl SyncFile for MyFile {
+//!     fn metadata(&mut self) -> FsResult<Box<dyn DavMetaData>> {
+//!         Err(FsError::NotImplemented)
+//!     }
+//!     fn write(&mut self, data: Bytes) -> FsResult<()> {
+//!         // expensive work here.
+//!         Ok(())
+//!     }
+//!     fn read(&mut self, count: usize) -> FsResult<Bytes> {
+//!         Ok(Bytes::new())
+//!     }
+//!     fn seek(&mut self, pos: SeekFrom) -> FsResult<u64> {
+//!         Ok(0)
+//!     }
+//!     fn flush(&mut self) -> FsResult<()> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let file: Box<dyn DavFile> = BlockingFile::new(MyFile);
+//! ```
+//!
+//! See `examples/encrypting-fs.rs` for a complete filesystem.
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+
+use bytes::{Buf, Bytes};
+use futures_util::FutureExt;
+
+use crate::fs::*;
+
+/// A file with blocking methods, see [`BlockingFile`].
+///
+/// The methods are the same as those of [`DavFile`], except that
+/// they return their result directly.
+pub trait SyncFile: Send + 'static {
+    fn metadata(&mut self) -> FsResult<Box<dyn DavMetaData>>;
+    fn write(&mut self, data: Bytes) -> FsResult<()>;
+    /// Read at most `count` bytes. Empty `Bytes` means end of file.
+    fn read(&mut self, count: usize) -> FsResult<Bytes>;
+    fn seek(&mut self, pos: SeekFrom) -> FsResult<u64>;
+    fn flush(&mut self) -> FsResult<()>;
+}
+
+/// A [`DavFile`] that runs the methods of a [`SyncFile`] on the
+/// blocking thread pool of the tokio runtime.
+pub struct BlockingFile<F> {
+    file: Arc<Mutex<F>>,
+}
+
+impl<F: SyncFile> BlockingFile<F> {
+    /// Wrap `file`.
+    pub fn new(file: F) -> Box<BlockingFile<F>> {
+        Box::new(BlockingFile {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    // Run `func` on the file, on a blocking thread.
+    async fn run<T, C>(&self, func: C) -> FsResult<T>
+    where
+        T: Send + 'static,
+        C: FnOnce(&mut F) -> FsResult<T> + Send + 'static,
+    {
+        let file = self.file.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            let mut file = file.lock().map_err(|_| FsError::GeneralFailure)?;
+            func(&mut file)
+        });
+        res.await.unwrap_or(Err(FsError::GeneralFailure))
+    }
+}
+
+impl<F> std::fmt::Debug for BlockingFile<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockingFile").finish_non_exhaustive()
+    }
+}
+
+impl<F: SyncFile> DavFile for BlockingFile<F> {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.run(|file| file.metadata()).boxed()
+    }
+
+    fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        let data = buf.copy_to_bytes(buf.remaining());
+        self.write_bytes(data)
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        self.run(move |file| file.write(buf)).boxed()
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        self.run(move |file| file.read(count)).boxed()
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        self.run(move |file| file.seek(pos)).boxed()
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        self.run(|file| file.flush()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asyncfs::async_trait;
+    use crate::body::Body;
+    use crate::davpath::DavPath;
+    use crate::memfs::MemFs;
+    use crate::tests::ForwardFs;
+    use crate::tests::{put, req, run};
+    use crate::DavHandler;
+    use std::time::Duration;
+
+    // Takes a long time for every write.
+    struct SlowFile(Arc<Mutex<Vec<u8>>>);
+
+    impl SyncFile for SlowFile {
+        fn metadata(&mut self) -> FsResult<Box<dyn DavMetaData>> {
+            Err(FsError::NotImplemented)
+        }
+        fn write(&mut self, data: Bytes) -> FsResult<()> {
+            std::thread::sleep(Duration::from_millis(100));
+            self.0.lock().unwrap().extend_from_slice(&data);
+            Ok(())
+        }
+        fn read(&mut self, _count: usize) -> FsResult<Bytes> {
+            Ok(Bytes::new())
+        }
+        fn seek(&mut self, _pos: SeekFrom) -> FsResult<u64> {
+            Ok(0)
+        }
+        fn flush(&mut self) -> FsResult<()> {
+            Ok(())
+        }
+    }
+
+    // Writes go to a SlowFile, everything else to a MemFs.
+    #[derive(Clone)]
+    struct SlowFs(Box<MemFs>, Arc<Mutex<Vec<u8>>>);
+
+    #[async_trait]
+    impl ForwardFs for SlowFs {
+        fn inner(&self) -> &dyn DavFileSystem {
+            &*self.0
+        }
+
+        async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+            if options.write {
+                return Ok(BlockingFile::new(SlowFile(self.1.clone())));
+            }
+            self.0.open(path, options).await
+        }
+    }
+
+    // the default test runtime has one thread, a blocked executor would
+    // not get to the GET until the PUT is done.
+    #[tokio::test]
+    async fn does_not_block() {
+        let memfs = MemFs::new();
+        let plain = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        put(&plain, "/other.txt", "other").await;
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(SlowFs(memfs, written.clone())))
+            .build_handler();
+        let chunks = (0..5).map(|_| Ok::<_, std::io::Error>(Bytes::from("slow!")));
+        let body = Body::from_stream(futures_util::stream::iter(chunks));
+        let r = req("PUT", "/slow.txt").body(body).unwrap();
+        let dav2 = dav.clone();
+        let upload = tokio::spawn(async move { run(&dav2, r).await.0.status() });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let r = req("GET", "/other.txt").body(Body::empty()).unwrap();
+        let get = tokio::time::timeout(Duration::from_millis(200), run(&dav, r));
+        assert_eq!(get.await.expect("GET was blocked").1, "other");
+        assert!(!upload.is_finished());
+
+        assert_eq!(upload.await.unwrap(), 201);
+        assert_eq!(&written.lock().unwrap()[..], "slow!".repeat(5).as_bytes());
+    }
+}
@@ -0,0 +1,267 @@
+//! Locksystem that combines several locksystems.
+//!
+//! `ChainedLs` consults a list of locksystems in order. The first one is
+//! the primary, the others are fallbacks. This can be used to keep
+//! serving LOCK requests from a `MemLs` while a persistent locksystem is
+//! temporarily unavailable.
+//!
+//! Semantics:
+//!
+//! - `check` and `discover` consult all locksystems, so a lock held in any
+//!   of them is honored.
+//! - `lock` first checks all locksystems for conflicts. If there are none,
+//!   the lock is created in the first locksystem that accepts it. A
+//!   locksystem that refuses a lock its own `check` allowed is considered
+//!   to be unavailable.
+//! - `unlock` and `refresh` succeed if any locksystem holds the lock.
+//! - `delete` is applied to all locksystems, and fails if any of them fails.
+//! - `list` returns the locks of all locksystems.
+//!
+//! ```
+//! use dav_server::{chainedls::ChainedLs, memls::MemLs, DavHandler};
+//!
+//! let ls = ChainedLs::new(vec![MemLs::new(), MemLs::new()]);
+//! let dav = DavHandler::builder().locksystem(ls).build_handler();
+//! ```
+use std::time::Duration;
+
+use xmltree::Element;
+
+use crate::davpath::DavPath;
+use crate::ls::*;
+
+/// Chain of locksystems.
+#[derive(Debug, Clone)]
+pub struct ChainedLs {
+    chain: Vec<Box<dyn DavLockSystem>>,
+}
+
+impl ChainedLs {
+    /// Create a new chain. The first locksystem is the primary.
+    ///
+    /// Panics if `chain` is empty.
+    pub fn new(chain: Vec<Box<dyn DavLockSystem>>) -> Box<ChainedLs> {
+        assert!(!chain.is_empty(), "ChainedLs: empty chain");
+        Box::new(ChainedLs { chain })
+    }
+}
+
+impl DavLockSystem for ChainedLs {
+    fn lock(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        owner: Option<&Element>,
+        timeout: Option<Duration>,
+        shared: bool,
+        deep: bool,
+    ) -> Result<DavLock, DavLock> {
+        // a conflicting lock anywhere in the chain wins.
+        for ls in &self.chain {
+            if let Some(l) = ls
+                .discover(path)
+                .into_iter()
+                .chain(deep_locks(&**ls, path, deep))
+                .find(|l| !shared || !l.shared)
+            {
+                return Err(l);
+            }
+        }
+        let mut result = None;
+        for (idx, ls) in self.chain.iter().enumerate() {
+            match ls.lock(path, principal, owner, timeout, shared, deep) {
+                Ok(lock) => return Ok(lock),
+                Err(l) => {
+                    debug!("ChainedLs: locksystem #{} refused lock on {}", idx, path);
+                    result.get_or_insert(l);
+                }
+            }
+        }
+        Err(result.unwrap())
+    }
+
+    fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
+        let mut result = Err(());
+        for ls in &self.chain {
+            if ls.unlock(path, token).is_ok() {
+                result = Ok(());
+            }
+        }
+        result
+    }
+
+    fn refresh(
+        &self,
+        path: &DavPath,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> Result<DavLock, ()> {
+        self.chain
+            .iter()
+            .find_map(|ls| ls.refresh(path, token, timeout).ok())
+            .ok_or(())
+    }
+
+    fn check(
+        &self,
+        path: &DavPath,
+        principal: Option<&str>,
+        ignore_principal: bool,
+        deep: bool,
+        submitted_tokens: Vec<&str>,
+    ) -> Result<(), DavLock> {
+        for ls in &self.chain {
+            ls.check(
+                path,
+                principal,
+                ignore_principal,
+                deep,
+                submitted_tokens.clone(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn discover(&self, path: &DavPath) -> Vec<DavLock> {
+        self.chain.iter().flat_map(|ls| ls.discover(path)).collect()
+    }
+
+    fn delete(&self, path: &DavPath) -> Result<(), ()> {
+        let mut result = Ok(());
+        for ls in &self.chain {
+            if ls.delete(path).is_err() {
+                result = Err(());
+            }
+        }
+        result
+    }
+
+    fn list(&self) -> Vec<DavLock> {
+        self.chain.iter().flat_map(|ls| ls.list()).collect()
+    }
+}
+
+// A deep lock conflicts with locks below the path as well. Use check()
+// to find them. It does not tell us about shared locks, so this is
+// conservative: any lock below the path counts as a conflict.
+fn deep_locks(ls: &dyn DavLockSystem, path: &DavPath, deep: bool) -> Option<DavLock> {
+    if !deep {
+        return None;
+    }
+    ls.check(path, None, true, true, Vec::new()).err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::tests::{put, req, run};
+    use crate::DavHandler;
+
+    // A locksystem that is down: it has no locks, and refuses new ones.
+    #[derive(Debug, Clone)]
+    struct DownLs;
+
+    impl DavLockSystem for DownLs {
+        fn lock(
+            &self,
+            path: &DavPath,
+            principal: Option<&str>,
+            owner: Option<&Element>,
+            timeout: Option<Duration>,
+            shared: bool,
+            deep: bool,
+        ) -> Result<DavLock, DavLock> {
+            Err(DavLock {
+                token: "down".to_string(),
+                path: path.clone(),
+                principal: principal.map(|s| s.to_string()),
+                owner: owner.cloned(),
+                timeout_at: None,
+                timeout,
+                shared,
+                deep,
+            })
+        }
+
+        fn unlock(&self, _path: &DavPath, _token: &str) -> Result<(), ()> {
+            Err(())
+        }
+
+        fn refresh(&self, _: &DavPath, _: &str, _: Option<Duration>) -> Result<DavLock, ()> {
+            Err(())
+        }
+
+        fn check(
+            &self,
+            _path: &DavPath,
+            _principal: Option<&str>,
+            _ignore_principal: bool,
+            _deep: bool,
+            _submitted_tokens: Vec<&str>,
+        ) -> Result<(), DavLock> {
+            Ok(())
+        }
+
+        fn discover(&self, _path: &DavPath) -> Vec<DavLock> {
+            Vec::new()
+        }
+
+        fn delete(&self, _path: &DavPath) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    const LOCKINFO: &str = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+
+    #[tokio::test]
+    async fn fallback() {
+        let fallback = MemLs::new();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(ChainedLs::new(vec![Box::new(DownLs), fallback.clone()]))
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        let r = req("LOCK", "/file.txt").body(Body::from(LOCKINFO)).unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert_eq!(resp.status(), 200);
+        let token = resp.headers()["lock-token"].to_str().unwrap().to_string();
+        let path = DavPath::new("/file.txt").unwrap();
+        assert_eq!(fallback.discover(&path).len(), 1);
+
+        // the lock in the fallback is enforced.
+        let r = req("PUT", "/file.txt").body(Body::from("x")).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 423);
+        let r = req("LOCK", "/file.txt").body(Body::from(LOCKINFO)).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 423);
+
+        let r = req("UNLOCK", "/file.txt")
+            .header("Lock-Token", token)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 204);
+        assert!(fallback.discover(&path).is_empty());
+    }
+
+    #[test]
+    fn primary_first() {
+        let primary = MemLs::new();
+        let fallback = MemLs::new();
+        let ls = ChainedLs::new(vec![primary.clone(), fallback.clone()]);
+        let path = DavPath::new("/dir/").unwrap();
+
+        let lock = ls.lock(&path, None, None, None, true, true).unwrap();
+        assert_eq!(primary.discover(&path).len(), 1);
+        assert!(fallback.discover(&path).is_empty());
+
+        // shared locks do not conflict, exclusive ones do.
+        assert!(ls.lock(&path, None, None, None, true, false).is_ok());
+        let conflict = ls.lock(&path, None, None, None, false, false).unwrap_err();
+        assert_eq!(conflict.token, lock.token);
+        let below = DavPath::new("/dir/file").unwrap();
+        assert!(ls.lock(&below, None, None, None, false, false).is_err());
+    }
+}
@@ -99,6 +99,12 @@ pub(crate) fn http_if_match(
     None
 }
 
+// If-Match on a resource that does not exist always fails (RFC 7232, 3.1).
+// Methods that would return 404 on a missing resource check this first.
+pub(crate) fn if_match_missing(req: &Request) -> bool {
+    req.headers().typed_get::<davheaders::IfMatch>().is_some()
+}
+
 // handle the If header: RFC4918, 10.4.  If Header
 //
 // returns true if the header was not present, or if any of the iflists
@@ -234,3 +240,117 @@ pub(crate) async fn if_match_get_tokens<'a>(
         (false, _) => Err(StatusCode::PRECONDITION_FAILED),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{mkcol, put, req, run};
+    use crate::DavHandler;
+
+    async fn etag(dav: &DavHandler, path: &str) -> String {
+        let (resp, _) = run(dav, req("HEAD", path).body(Body::empty()).unwrap()).await;
+        resp.headers()["etag"].to_str().unwrap().to_string()
+    }
+
+    async fn status(dav: &DavHandler, method: &str, path: &str, headers: &[(&str, &str)]) -> u16 {
+        let mut r = req(method, path);
+        for (name, value) in headers {
+            r = r.header(*name, *value);
+        }
+        let (resp, _) = run(dav, r.body(Body::empty()).unwrap()).await;
+        resp.status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn conditional_methods() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/a.txt", "a").await;
+        put(&dav, "/b.txt", "b").await;
+        mkcol(&dav, "/dir/").await;
+        let a = etag(&dav, "/a.txt").await;
+        let b = etag(&dav, "/b.txt").await;
+        let stale = "\"stale\"";
+        let old = "Thu, 01 Jan 1970 00:00:00 GMT";
+
+        // MKCOL.
+        assert_eq!(
+            status(&dav, "MKCOL", "/new/", &[("If-Match", "*")]).await,
+            412
+        );
+        assert_eq!(
+            status(&dav, "MKCOL", "/dir/", &[("If-None-Match", "*")]).await,
+            412
+        );
+        assert_eq!(
+            status(&dav, "MKCOL", "/new/", &[("If-None-Match", "*")]).await,
+            201
+        );
+
+        // DELETE.
+        assert_eq!(
+            status(&dav, "DELETE", "/a.txt", &[("If-Match", stale)]).await,
+            412
+        );
+        assert_eq!(
+            status(&dav, "DELETE", "/a.txt", &[("If-None-Match", &a)]).await,
+            412
+        );
+        assert_eq!(
+            status(&dav, "DELETE", "/a.txt", &[("If-Unmodified-Since", old)]).await,
+            412
+        );
+
+        // COPY and MOVE: the conditional headers apply to the source. The
+        // destination can be checked with a tagged If header.
+        for method in ["COPY", "MOVE"] {
+            let dest = ("Destination", "/b.txt");
+            let h = [dest, ("If-Match", stale)];
+            assert_eq!(status(&dav, method, "/a.txt", &h).await, 412, "{}", method);
+            let h = [dest, ("If-None-Match", &a)];
+            assert_eq!(status(&dav, method, "/a.txt", &h).await, 412, "{}", method);
+            let h = [dest, ("If-Unmodified-Since", old)];
+            assert_eq!(status(&dav, method, "/a.txt", &h).await, 412, "{}", method);
+            let tagged = format!("</b.txt> ([{}])", stale);
+            let h = [dest, ("If", &tagged)];
+            assert_eq!(status(&dav, method, "/a.txt", &h).await, 412, "{}", method);
+        }
+        let tagged = format!("</b.txt> ([{}])", b);
+        let h = [("Destination", "/b.txt"), ("If", &tagged), ("If-Match", &a)];
+        assert_eq!(status(&dav, "MOVE", "/a.txt", &h).await, 204);
+
+        assert_eq!(
+            status(&dav, "DELETE", "/b.txt", &[("If-Match", &a)]).await,
+            204
+        );
+    }
+
+    #[tokio::test]
+    async fn if_match_star() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let star = [("If-Match", "*")];
+        let proppatch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:x">
+            <D:set><D:prop><Z:color>red</Z:color></D:prop></D:set></D:propertyupdate>"#;
+        async fn send(dav: &DavHandler, method: &str, path: &str, body: &'static str) -> u16 {
+            let r = req(method, path).header("If-Match", "*");
+            let (resp, _) = run(dav, r.body(Body::from(body)).unwrap()).await;
+            resp.status().as_u16()
+        }
+
+        // missing resource: always 412.
+        assert_eq!(send(&dav, "PUT", "/a.txt", "a").await, 412);
+        assert_eq!(send(&dav, "PROPPATCH", "/a.txt", proppatch).await, 412);
+        assert_eq!(status(&dav, "DELETE", "/a.txt", &star).await, 412);
+        assert_eq!(status(&dav, "DELETE", "/a.txt", &[]).await, 404);
+
+        // existing resource: passes.
+        put(&dav, "/a.txt", "a").await;
+        assert_eq!(send(&dav, "PUT", "/a.txt", "b").await, 204);
+        assert_eq!(send(&dav, "PROPPATCH", "/a.txt", proppatch).await, 207);
+        assert_eq!(status(&dav, "DELETE", "/a.txt", &star).await, 204);
+    }
+}
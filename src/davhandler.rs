@@ -2,27 +2,39 @@
 // This module contains the main entry point of the library,
 // DavHandler.
 //
+use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::io;
+use std::pin::Pin;
 use std::sync::Arc;
 
 use axum::body::Body;
 use bytes::{self, buf::Buf};
+use futures_util::StreamExt;
 use headers::HeaderMapExt;
 use http::{Request, Response, StatusCode};
 use http_body::Body as HttpBody;
 use http_body_util::BodyExt;
 
 use crate::davheaders;
-use crate::davpath::DavPath;
-use crate::util::{dav_method, DavMethod, DavMethodSet};
+use crate::davpath::{DavPath, PathPredicate};
+use crate::util::{dav_method, dav_xml_error, glob_match_any, DavMethod, DavMethodSet};
 
 use crate::errors::DavError;
 use crate::fs::*;
 use crate::ls::*;
+use crate::report::ReportHandler;
+use crate::transform::TransformFn;
 use crate::voidfs::{is_voidfs, VoidFs};
 use crate::DavResult;
 
+// Default limits on request bodies, see DavConfig.
+pub(crate) const MAX_REQUEST_XML_SIZE: usize = 4 * 1024 * 1024;
+pub(crate) const MAX_PROPPATCH_PROPS: usize = 4096;
+pub(crate) const MAX_DRAIN_SIZE: usize = 64 * 1024;
+// collections with more entries are not scanned by `case_insensitive`.
+const CASE_SCAN_LIMIT: usize = 1024;
+
 /// The webdav handler struct.
 ///
 /// The `new` and `build` etc methods are used to instantiate a handler.
@@ -33,6 +45,85 @@ pub struct DavHandler {
     pub(crate) config: Arc<DavConfig>,
 }
 
+/// Error returned by [`DavConfig::try_build`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// The prefix of `strip_prefix` does not start with a `/`.
+    InvalidPrefix(String),
+    /// An index file name is empty or contains a `/`. It has to be
+    /// the name of a file in the collection.
+    InvalidIndexFile(String),
+    /// The option needs a locksystem, and none was configured.
+    NoLockSystem(&'static str),
+    /// The option is set to zero, which does not work.
+    ZeroSize(&'static str),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            BuildError::InvalidPrefix(ref p) => write!(f, "prefix {:?} does not start with /", p),
+            BuildError::InvalidIndexFile(ref n) => write!(f, "invalid index file name {:?}", n),
+            BuildError::NoLockSystem(o) => write!(f, "{} is set but there is no locksystem", o),
+            BuildError::ZeroSize(o) => write!(f, "{} cannot be zero", o),
+        }
+    }
+}
+
+impl StdError for BuildError {}
+
+/// How to sort the entries on autoindex pages, see [`DavConfig::autoindex_sort`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// By name.
+    Name,
+    /// By size.
+    Size,
+    /// By last modification time.
+    Modified,
+}
+
+/// Bytes transferred by one request, see [`DavConfig::on_transfer`].
+#[derive(Debug, Clone)]
+pub struct TransferStats {
+    /// The method of the request.
+    pub method: DavMethod,
+    /// The path of the file, without the prefix.
+    pub path: DavPath,
+    /// The principal of the request, if set.
+    pub principal: Option<String>,
+    /// Bytes of the request body that were read.
+    pub bytes_received: u64,
+    /// Bytes of the response body that were sent.
+    pub bytes_sent: u64,
+}
+
+/// Function called by [`DavConfig::on_transfer`].
+pub type TransferFn = Arc<dyn Fn(TransferStats) + Send + Sync>;
+
+// Reports a transfer to `on_transfer` when dropped, which is when the
+// request is done, or was cancelled.
+pub(crate) struct TransferMeter {
+    stats: TransferStats,
+    report: TransferFn,
+}
+
+impl TransferMeter {
+    pub(crate) fn received(&mut self, bytes: u64) {
+        self.stats.bytes_received += bytes;
+    }
+
+    pub(crate) fn sent(&mut self, bytes: u64) {
+        self.stats.bytes_sent += bytes;
+    }
+}
+
+impl Drop for TransferMeter {
+    fn drop(&mut self) {
+        (self.report)(self.stats.clone());
+    }
+}
+
 /// Configuration of the handler.
 #[derive(Default)]
 pub struct DavConfig {
@@ -57,6 +148,70 @@ pub struct DavConfig {
     pub(crate) read_buf_size: Option<usize>,
     // Does GET on a file return 302 redirect.
     pub(crate) redirect: Option<bool>,
+    // Allow DELETE or MOVE of the root collection. `None` maps to `false`.
+    pub(crate) allow_root_delete: Option<bool>,
+    // Transform file content on GET.
+    pub(crate) get_transform: Option<TransformFn>,
+    // Maximum depth of locks on collections. `None` maps to `Infinity`.
+    pub(crate) max_lock_depth: Option<LockDepth>,
+    // Request-scoped context, passed to the filesystem.
+    pub(crate) context: Option<DavContext>,
+    // Store the Content-Type of a PUT and return it on GET.
+    pub(crate) preserve_content_type: Option<bool>,
+    // Maximum number of properties in one PROPPATCH.
+    pub(crate) max_proppatch_props: Option<usize>,
+    // Maximum size of a request body with XML.
+    pub(crate) max_request_xml_size: Option<usize>,
+    // REPORT handlers, by report name.
+    pub(crate) report_handlers: Option<HashMap<String, Arc<dyn ReportHandler>>>,
+    // Hide paths with a segment that matches one of these globs.
+    pub(crate) hide_patterns: Option<Vec<String>>,
+    // Allow PROPFIND with Depth: infinity. `None` maps to `false`.
+    pub(crate) propfind_depth_infinity: Option<bool>,
+    // Paths of files that never change, for Cache-Control.
+    pub(crate) immutable_if: Option<PathPredicate>,
+    // Accept multipart/form-data bodies on PUT.
+    pub(crate) accept_multipart: Option<bool>,
+    // sniff the content type of files with an unknown extension.
+    pub(crate) sniff_content_type: Option<bool>,
+    // how many bytes to read for sniff_content_type.
+    pub(crate) sniff_size: Option<usize>,
+    // check the Content-MD5 header of PUT requests.
+    pub(crate) verify_content_md5: Option<bool>,
+    // index files to look for on GET of a collection.
+    pub(crate) index_files: Option<Vec<String>>,
+    // create missing parent collections on PUT.
+    pub(crate) auto_mkcol_on_put: Option<bool>,
+    // sort order of autoindex pages.
+    pub(crate) autoindex_sort: Option<(SortBy, bool)>,
+    // use the X-Forwarded-* and Forwarded headers for self-referencing URLs.
+    pub(crate) trust_forwarded: Option<bool>,
+    // HTML bodies of error responses, for browsers.
+    pub(crate) error_bodies: Option<HashMap<StatusCode, String>>,
+    // number of chunks that GET reads ahead.
+    pub(crate) readahead: Option<usize>,
+    // serve a tar of a collection on GET with ?archive=tar.
+    pub(crate) allow_archive_download: Option<bool>,
+    // Copy and delete when a MOVE or COPY crosses filesystems.
+    pub(crate) cross_fs_fallback: Option<bool>,
+    // Resolve request paths case-insensitively.
+    pub(crate) case_insensitive: Option<bool>,
+    // Called with the bytes transferred by a GET or PUT.
+    pub(crate) on_transfer: Option<TransferFn>,
+    // read files until EOF instead of trusting their size
+    pub(crate) read_until_eof: Option<bool>,
+    // send headers that Microsoft clients look for in OPTIONS
+    pub(crate) microsoft_compat: Option<bool>,
+    // create files with POST to a collection
+    pub(crate) allow_post_create: Option<bool>,
+    // refuse to create names that do not work on all platforms
+    pub(crate) reject_nonportable_names: Option<bool>,
+    // How much of an unread request body is drained after an error.
+    pub(crate) max_drain_size: Option<usize>,
+    // give the destination of a COPY the modification time of the source.
+    pub(crate) preserve_mtime_on_copy: Option<bool>,
+    // namespace prefixes to use in Multi-Status responses.
+    pub(crate) xml_prefixes: Option<Vec<(String, String)>>,
 }
 
 impl DavConfig {
@@ -66,10 +221,43 @@ impl DavConfig {
     }
 
     /// Use the configuration that was built to generate a DavConfig.
+    ///
+    /// Panics if the configuration is invalid, see [`try_build`](Self::try_build).
     pub fn build_handler(self) -> DavHandler {
-        DavHandler {
+        match self.try_build() {
+            Ok(handler) => handler,
+            Err(e) => panic!("invalid DavConfig: {}", e),
+        }
+    }
+
+    /// Like `build_handler`, but return an error if options are
+    /// set to values that cannot work, or that do not make sense together.
+    pub fn try_build(self) -> Result<DavHandler, BuildError> {
+        self.validate()?;
+        Ok(DavHandler {
             config: Arc::new(self),
+        })
+    }
+
+    fn validate(&self) -> Result<(), BuildError> {
+        if let Some(ref prefix) = self.prefix {
+            if !prefix.is_empty() && !prefix.starts_with('/') {
+                return Err(BuildError::InvalidPrefix(prefix.clone()));
+            }
+        }
+        let index_files = self.index_files.iter().flatten();
+        for name in index_files.chain(self.indexfile.iter()) {
+            if name.is_empty() || name.contains('/') {
+                return Err(BuildError::InvalidIndexFile(name.clone()));
+            }
         }
+        if self.ls.is_none() && self.max_lock_depth.is_some() {
+            return Err(BuildError::NoLockSystem("max_lock_depth"));
+        }
+        if self.sniff_content_type == Some(true) && self.sniff_size == Some(0) {
+            return Err(BuildError::ZeroSize("sniff_size"));
+        }
+        Ok(())
     }
 
     /// Prefix to be stripped off before translating the rest of
@@ -94,6 +282,16 @@ impl DavConfig {
         this
     }
 
+    /// Advertise locking, but do not enforce it.
+    ///
+    /// This sets the locksystem to [`FakeLs`](crate::fakels::FakeLs).
+    /// Clients that need DAV class 2 get lock tokens from LOCK, but no
+    /// request is ever refused because of a lock. Useful for a single
+    /// user that mounts the server from Windows or macOS.
+    pub fn fake_locking(self) -> Self {
+        self.locksystem(crate::fakels::FakeLs::new())
+    }
+
     /// Which methods to allow (default is all methods).
     pub fn methods(self, allow: DavMethodSet) -> Self {
         let mut this = self;
@@ -142,6 +340,401 @@ impl DavConfig {
         this
     }
 
+    /// Allow a DELETE or MOVE of the root collection, and a COPY or MOVE
+    /// onto it (default is false). Without this, such a request is
+    /// refused with 403 Forbidden.
+    pub fn allow_root_delete(self, allow: bool) -> Self {
+        let mut this = self;
+        this.allow_root_delete = Some(allow);
+        this
+    }
+
+    /// Transform the content of files on GET.
+    ///
+    /// The function is called for every GET of a file. If it returns
+    /// a [`Transform`](crate::transform::Transform), the content is
+    /// sent through it. See the [`transform`](crate::transform) module.
+    pub fn get_transform(self, transform: TransformFn) -> Self {
+        let mut this = self;
+        this.get_transform = Some(transform);
+        this
+    }
+
+    /// Maximum depth of a LOCK on a collection (default is `LockDepth::Infinity`).
+    ///
+    /// With `LockDepth::Zero`, a `Depth: infinity` LOCK on a collection is
+    /// refused with 403 Forbidden.
+    pub fn max_lock_depth(self, depth: LockDepth) -> Self {
+        let mut this = self;
+        this.max_lock_depth = Some(depth);
+        this
+    }
+
+    /// Set a request-scoped context.
+    ///
+    /// This is an opaque value that is passed to `DavFileSystem::with_context`
+    /// at the start of the request. The recommended pattern is to
+    /// set it per request via `DavHandler::handle_with`, for example
+    /// with the authenticated user and tenant, and have the filesystem
+    /// backend downcast it to scope its paths.
+    pub fn context(self, context: DavContext) -> Self {
+        let mut this = self;
+        this.context = Some(context);
+        this
+    }
+
+    /// Preserve the `Content-Type` of a PUT request (default is false).
+    ///
+    /// If the filesystem supports dead properties, the `Content-Type` sent
+    /// with a PUT is stored, and returned on GET and PROPFIND instead of
+    /// the type guessed from the file extension.
+    pub fn preserve_content_type(self, preserve: bool) -> Self {
+        let mut this = self;
+        this.preserve_content_type = Some(preserve);
+        this
+    }
+
+    /// Maximum number of properties that a single PROPPATCH may set or remove.
+    ///
+    /// Requests with more properties are rejected with `400 Bad Request`
+    /// before any property is changed. Defaults to 4096.
+    pub fn max_proppatch_props(self, max: usize) -> Self {
+        let mut this = self;
+        this.max_proppatch_props = Some(max);
+        this
+    }
+
+    /// Maximum size of the XML request body of PROPFIND, PROPPATCH, LOCK etc.
+    ///
+    /// Larger bodies are rejected with `413 Payload Too Large`. Defaults
+    /// to 4 MiB. Does not apply to PUT and PATCH.
+    pub fn max_request_xml_size(self, size: usize) -> Self {
+        let mut this = self;
+        this.max_request_xml_size = Some(size);
+        this
+    }
+
+    /// Add a handler for a REPORT.
+    ///
+    /// `name` is the name of the report in Clark notation, for example
+    /// `{DAV:}sync-collection`. See the [`report`](crate::report) module.
+    pub fn report_handler(self, name: &str, handler: Arc<dyn ReportHandler>) -> Self {
+        let mut this = self;
+        this.report_handlers
+            .get_or_insert_with(HashMap::new)
+            .insert(name.to_string(), handler);
+        this
+    }
+
+    /// Hide files and directories whose name matches one of these glob patterns.
+    ///
+    /// Hidden entries are left out of PROPFIND results and directory indexes,
+    /// and any request for them, or for something below them, returns
+    /// `404 Not Found`. Patterns are matched against a single path segment;
+    /// `*` matches any number of characters, `?` exactly one. For
+    /// example `.*` hides all dotfiles.
+    pub fn hide_patterns(self, patterns: Vec<String>) -> Self {
+        let mut this = self;
+        this.hide_patterns = Some(patterns);
+        this
+    }
+
+    /// Allow PROPFIND with `Depth: infinity` (or without a Depth header).
+    ///
+    /// By default such requests are refused with `403 Forbidden` and a
+    /// `DAV:propfind-finite-depth` precondition, since they can walk the
+    /// entire filesystem.
+    pub fn propfind_depth_infinity(self, allow: bool) -> Self {
+        let mut this = self;
+        this.propfind_depth_infinity = Some(allow);
+        this
+    }
+
+    /// Mark files as immutable.
+    ///
+    /// If the function returns `true` for the path of a file, GET and HEAD
+    /// responses include `Cache-Control: public, max-age=31536000, immutable`.
+    /// Useful for content-addressed files, where the name is a hash of the
+    /// content.
+    pub fn immutable_if(self, immutable: PathPredicate) -> Self {
+        let mut this = self;
+        this.immutable_if = Some(immutable);
+        this
+    }
+
+    /// Accept `multipart/form-data` bodies on PUT.
+    ///
+    /// If enabled, and the Content-Type of a PUT is `multipart/form-data`,
+    /// the content of the first part that has a filename is stored, as
+    /// is done by HTML forms that upload a file. The body is parsed
+    /// while it is being received, it is not buffered.
+    pub fn accept_multipart(self, accept: bool) -> Self {
+        let mut this = self;
+        this.accept_multipart = Some(accept);
+        this
+    }
+
+    /// Detect the type of files with an unknown extension (default is false).
+    ///
+    /// If the extension of a file does not say what type it is, the first
+    /// bytes of the file are read on GET and HEAD and checked for a known
+    /// signature, such as the one of a PNG image. How many bytes are read
+    /// is set with [`sniff_size`](Self::sniff_size).
+    pub fn sniff_content_type(self, sniff: bool) -> Self {
+        let mut this = self;
+        this.sniff_content_type = Some(sniff);
+        this
+    }
+
+    /// Number of bytes that are read to detect the type of a file (default is 64).
+    ///
+    /// See [`sniff_content_type`](Self::sniff_content_type).
+    pub fn sniff_size(self, size: usize) -> Self {
+        let mut this = self;
+        this.sniff_size = Some(size);
+        this
+    }
+
+    /// Check the `Content-MD5` header of a PUT (default is false).
+    ///
+    /// The MD5 digest of the body is computed while it is written. If it
+    /// does not match the header, the PUT fails with `400 Bad Request`, and
+    /// a file that was created by the PUT is removed again. An existing file
+    /// is not touched: the body is written to a temporary file next to it,
+    /// which is renamed over it when the digest matches. The dead properties
+    /// of the existing file are copied to the new one first; other state
+    /// that the filesystem keeps, like file permissions, is not. A partial
+    /// update of an existing file with `Content-MD5` fails with `501 Not
+    /// Implemented`, there is no way to check it before it is written.
+    pub fn verify_content_md5(self, verify: bool) -> Self {
+        let mut this = self;
+        this.verify_content_md5 = Some(verify);
+        this
+    }
+
+    /// Index files to serve on GET of a collection, like a web server does.
+    ///
+    /// The names are tried in order, and the first one that exists in the
+    /// collection is served. If there is none, a directory index is
+    /// generated if [`autoindex`](Self::autoindex) is enabled, or else the
+    /// response is `404 Not Found`. These are tried before
+    /// [`indexfile`](Self::indexfile).
+    pub fn index_files(self, files: Vec<String>) -> Self {
+        let mut this = self;
+        this.index_files = Some(files);
+        this
+    }
+
+    /// Create missing parent collections on PUT (default is false).
+    ///
+    /// RFC4918 says that a PUT to a resource whose parent collection does
+    /// not exist fails with `409 Conflict`, and that is what happens by
+    /// default. Some clients expect that the parent collections are created,
+    /// like `mkdir -p` does. Enable this for them.
+    pub fn auto_mkcol_on_put(self, create: bool) -> Self {
+        let mut this = self;
+        this.auto_mkcol_on_put = Some(create);
+        this
+    }
+
+    /// Default sort order of the entries on autoindex pages (default: by name,
+    /// ascending). Collections are always listed first. With `descending` set,
+    /// the order is reversed.
+    ///
+    /// The order can also be picked with Apache-style query parameters, like
+    /// `?C=S&O=D` for the largest files first. `C` is `N` (name), `S` (size)
+    /// or `M` (last modified), and `O` is `A` (ascending) or `D` (descending).
+    /// The column headers on the page link to these.
+    pub fn autoindex_sort(self, sort_by: SortBy, descending: bool) -> Self {
+        let mut this = self;
+        this.autoindex_sort = Some((sort_by, descending));
+        this
+    }
+
+    /// Trust the headers of a reverse proxy (default is false).
+    ///
+    /// When enabled, self-referencing URLs like `Content-Location` and the
+    /// redirect to a collection are absolute URLs, built from the
+    /// `Forwarded` header (RFC7239), or from `X-Forwarded-Host` and
+    /// `X-Forwarded-Proto`. The last value of a header is used, which is
+    /// the one added by the proxy closest to the handler. Only enable this
+    /// if the handler can only be reached through a proxy that sets these
+    /// headers, otherwise clients can make it generate links to any host.
+    pub fn trust_forwarded(self, trust: bool) -> Self {
+        let mut this = self;
+        this.trust_forwarded = Some(trust);
+        this
+    }
+
+    /// Set the body of an error response for browsers.
+    ///
+    /// When a request that accepts `text/html` fails with `status`, the
+    /// response has this HTML body instead of the default, usually empty,
+    /// body. Other clients still get the default. Call this once for every
+    /// status that needs a custom page.
+    pub fn error_body(self, status: StatusCode, html: String) -> Self {
+        let mut this = self;
+        this.error_bodies
+            .get_or_insert_with(HashMap::new)
+            .insert(status, html);
+        this
+    }
+
+    /// Read ahead while sending the body of a GET (default is 0, off).
+    ///
+    /// With `buffers` > 0, the file is read by a separate task that stays
+    /// up to `buffers` chunks ahead of the client, so that the next
+    /// `read_bytes` runs while the current chunk is being sent. That
+    /// helps for filesystems with a high latency per read. The task is
+    /// spawned on the tokio runtime, and it is stopped when the response
+    /// body is dropped, for example because the client went away.
+    pub fn readahead(self, buffers: usize) -> Self {
+        let mut this = self;
+        this.readahead = Some(buffers);
+        this
+    }
+
+    /// Allow downloading a collection as a tar archive (default is false).
+    ///
+    /// When enabled, `GET /dir/?archive=tar` returns a tar archive with
+    /// everything below the collection, generated while it is sent. Entries
+    /// hidden by `hide_patterns` and symbolic links are left out.
+    pub fn allow_archive_download(self, allow: bool) -> Self {
+        let mut this = self;
+        this.allow_archive_download = Some(allow);
+        this
+    }
+
+    /// Fall back to copying when a MOVE or COPY crosses filesystems (default is false).
+    ///
+    /// When the filesystem cannot rename or copy between the source and the
+    /// destination itself (`FsError::IsRemote`), the data is copied instead,
+    /// and for MOVE the source is deleted afterwards. When disabled, such a
+    /// request fails with `502 Bad Gateway`.
+    pub fn cross_fs_fallback(self, fallback: bool) -> Self {
+        let mut this = self;
+        this.cross_fs_fallback = Some(fallback);
+        this
+    }
+
+    /// Match request paths case-insensitively (default is false).
+    ///
+    /// For clients that expect Windows or macOS semantics on a case-sensitive
+    /// backend. When a path does not exist, each segment that is missing is
+    /// looked up in its parent collection ignoring case, so that `/File.TXT`
+    /// finds `/file.txt`. A segment that matches more than one entry is not
+    /// resolved, and collections with more than 1024 entries are not scanned.
+    /// Only the request path is resolved, not the `Destination` of a COPY
+    /// or MOVE.
+    pub fn case_insensitive(self, enable: bool) -> Self {
+        let mut this = self;
+        this.case_insensitive = Some(enable);
+        this
+    }
+
+    /// Call a function with the number of bytes transferred by every GET and PUT.
+    ///
+    /// For accounting, like metering bandwidth per principal. The function gets
+    /// a [`TransferStats`] when the request is done, which for a GET is when
+    /// the response body has been sent, or the client went away. It is called
+    /// for GET of files and of archives, and for PUT and PATCH, also when the
+    /// request failed halfway.
+    pub fn on_transfer(self, report: TransferFn) -> Self {
+        let mut this = self;
+        this.on_transfer = Some(report);
+        this
+    }
+
+    /// Serve files of which the size may change while they are read,
+    /// like a growing log file (default is false).
+    ///
+    /// GET does not send a `Content-Length` or serve byte ranges, the body
+    /// is sent chunked and the file is read until `read_bytes` returns
+    /// no data.
+    pub fn read_until_eof(self, enable: bool) -> Self {
+        let mut this = self;
+        this.read_until_eof = Some(enable);
+        this
+    }
+
+    /// Send the `Public` header on OPTIONS, for Microsoft Office and the
+    /// Windows WebClient (default is false).
+    ///
+    /// `Public` lists all methods the server supports, not just the ones
+    /// allowed on the resource. `MS-Author-Via: DAV` is always sent.
+    pub fn microsoft_compat(self, enable: bool) -> Self {
+        let mut this = self;
+        this.microsoft_compat = Some(enable);
+        this
+    }
+
+    /// Allow POST to a collection to create a new file in it (default
+    /// is false).
+    ///
+    /// The name of the file is taken from the `Slug` header (RFC5023),
+    /// made safe, and if it exists a counter is added, like `name-2.txt`.
+    /// The body is stored like with PUT, and the response is 201 Created
+    /// with the URL of the new file in `Location`.
+    pub fn allow_post_create(self, allow: bool) -> Self {
+        let mut this = self;
+        this.allow_post_create = Some(allow);
+        this
+    }
+
+    /// Refuse to create files and collections with names that do not
+    /// work on all platforms (default is false).
+    ///
+    /// PUT of a new file, MKCOL, and the destination of COPY and MOVE get
+    /// 403 Forbidden if the name is not
+    /// [`portable`](crate::davpath::DavPathRef::is_portable_name), like
+    /// `aux`, `con.txt`, `a:b` or `name.`, so that a share can also be
+    /// used from Windows.
+    pub fn reject_nonportable_names(self, reject: bool) -> Self {
+        let mut this = self;
+        this.reject_nonportable_names = Some(reject);
+        this
+    }
+
+    /// Maximum number of bytes of a PUT, PATCH or POST body that is read
+    /// and thrown away when the request fails before the body was read.
+    /// The body of DELETE, COPY and MOVE, which has no meaning, is thrown
+    /// away the same way. This lets the client send its next request on
+    /// the same connection.
+    /// If the rest of the body is larger, the connection is closed instead.
+    /// Defaults to 64 KiB, 0 always closes the connection.
+    pub fn max_drain_size(self, size: usize) -> Self {
+        let mut this = self;
+        this.max_drain_size = Some(size);
+        this
+    }
+
+    /// Give the destination of a COPY the modification time of the source.
+    ///
+    /// By default the copy gets whatever modification time the filesystem
+    /// gives it, usually the current time. With this set, the modification
+    /// time of every copied file and collection is set to that of its
+    /// source, if the filesystem implements `DavFileSystem::set_modified`. Backup tools that compare
+    /// timestamps need this. Defaults to `false`.
+    pub fn preserve_mtime_on_copy(self, preserve: bool) -> Self {
+        let mut this = self;
+        this.preserve_mtime_on_copy = Some(preserve);
+        this
+    }
+
+    /// Namespace prefixes to use in PROPFIND and PROPPATCH responses.
+    ///
+    /// Pairs of a namespace URI and the prefix to use for it. An empty
+    /// prefix makes it the default namespace, so `("DAV:", "")` gives
+    /// `<multistatus xmlns="DAV:">` instead of `<D:multistatus xmlns:D="DAV:">`,
+    /// for clients that cannot handle one or the other. Namespaces that
+    /// are not listed keep the prefix they have now: `D` for `DAV:`, and the
+    /// prefix used in the request, or in the stored property, for others.
+    pub fn xml_prefixes(self, prefixes: Vec<(String, String)>) -> Self {
+        let mut this = self;
+        this.xml_prefixes = Some(prefixes);
+        this
+    }
+
     fn merge(&self, new: DavConfig) -> DavConfig {
         DavConfig {
             prefix: new.prefix.or_else(|| self.prefix.clone()),
@@ -154,6 +747,43 @@ impl DavConfig {
             indexfile: new.indexfile.or_else(|| self.indexfile.clone()),
             read_buf_size: new.read_buf_size.or(self.read_buf_size),
             redirect: new.redirect.or(self.redirect),
+            allow_root_delete: new.allow_root_delete.or(self.allow_root_delete),
+            get_transform: new.get_transform.or_else(|| self.get_transform.clone()),
+            max_lock_depth: new.max_lock_depth.or(self.max_lock_depth),
+            context: new.context.or_else(|| self.context.clone()),
+            preserve_content_type: new.preserve_content_type.or(self.preserve_content_type),
+            max_proppatch_props: new.max_proppatch_props.or(self.max_proppatch_props),
+            max_request_xml_size: new.max_request_xml_size.or(self.max_request_xml_size),
+            report_handlers: match (&self.report_handlers, new.report_handlers) {
+                (Some(old), Some(new)) => Some(old.clone().into_iter().chain(new).collect()),
+                (old, new) => new.or_else(|| old.clone()),
+            },
+            hide_patterns: new.hide_patterns.or_else(|| self.hide_patterns.clone()),
+            propfind_depth_infinity: new.propfind_depth_infinity.or(self.propfind_depth_infinity),
+            immutable_if: new.immutable_if.or_else(|| self.immutable_if.clone()),
+            accept_multipart: new.accept_multipart.or(self.accept_multipart),
+            sniff_content_type: new.sniff_content_type.or(self.sniff_content_type),
+            sniff_size: new.sniff_size.or(self.sniff_size),
+            verify_content_md5: new.verify_content_md5.or(self.verify_content_md5),
+            index_files: new.index_files.or_else(|| self.index_files.clone()),
+            auto_mkcol_on_put: new.auto_mkcol_on_put.or(self.auto_mkcol_on_put),
+            autoindex_sort: new.autoindex_sort.or(self.autoindex_sort),
+            trust_forwarded: new.trust_forwarded.or(self.trust_forwarded),
+            error_bodies: new.error_bodies.or_else(|| self.error_bodies.clone()),
+            readahead: new.readahead.or(self.readahead),
+            allow_archive_download: new.allow_archive_download.or(self.allow_archive_download),
+            cross_fs_fallback: new.cross_fs_fallback.or(self.cross_fs_fallback),
+            case_insensitive: new.case_insensitive.or(self.case_insensitive),
+            on_transfer: new.on_transfer.or_else(|| self.on_transfer.clone()),
+            read_until_eof: new.read_until_eof.or(self.read_until_eof),
+            microsoft_compat: new.microsoft_compat.or(self.microsoft_compat),
+            allow_post_create: new.allow_post_create.or(self.allow_post_create),
+            reject_nonportable_names: new
+                .reject_nonportable_names
+                .or(self.reject_nonportable_names),
+            max_drain_size: new.max_drain_size.or(self.max_drain_size),
+            preserve_mtime_on_copy: new.preserve_mtime_on_copy.or(self.preserve_mtime_on_copy),
+            xml_prefixes: new.xml_prefixes.or_else(|| self.xml_prefixes.clone()),
         }
     }
 }
@@ -173,6 +803,40 @@ pub(crate) struct DavInner {
     pub indexfile: Option<String>,
     pub read_buf_size: Option<usize>,
     pub redirect: Option<bool>,
+    pub allow_root_delete: Option<bool>,
+    pub get_transform: Option<TransformFn>,
+    pub max_lock_depth: Option<LockDepth>,
+    pub context: Option<DavContext>,
+    pub preserve_content_type: Option<bool>,
+    pub max_proppatch_props: Option<usize>,
+    pub max_request_xml_size: Option<usize>,
+    pub report_handlers: Option<HashMap<String, Arc<dyn ReportHandler>>>,
+    pub hide_patterns: Option<Vec<String>>,
+    pub propfind_depth_infinity: Option<bool>,
+    pub immutable_if: Option<PathPredicate>,
+    pub accept_multipart: Option<bool>,
+    pub sniff_content_type: Option<bool>,
+    pub sniff_size: Option<usize>,
+    pub verify_content_md5: Option<bool>,
+    pub index_files: Option<Vec<String>>,
+    pub auto_mkcol_on_put: Option<bool>,
+    pub autoindex_sort: Option<(SortBy, bool)>,
+    pub trust_forwarded: Option<bool>,
+    // per request: scheme and host from the proxy headers.
+    pub base_url: Option<String>,
+    pub error_bodies: Option<HashMap<StatusCode, String>>,
+    pub readahead: Option<usize>,
+    pub allow_archive_download: Option<bool>,
+    pub cross_fs_fallback: Option<bool>,
+    pub case_insensitive: Option<bool>,
+    pub on_transfer: Option<TransferFn>,
+    pub read_until_eof: Option<bool>,
+    pub microsoft_compat: Option<bool>,
+    pub allow_post_create: Option<bool>,
+    pub reject_nonportable_names: Option<bool>,
+    pub max_drain_size: Option<usize>,
+    pub preserve_mtime_on_copy: Option<bool>,
+    pub xml_prefixes: Option<Vec<(String, String)>>,
 }
 
 impl From<DavConfig> for DavInner {
@@ -188,6 +852,39 @@ impl From<DavConfig> for DavInner {
             indexfile: cfg.indexfile,
             read_buf_size: cfg.read_buf_size,
             redirect: cfg.redirect,
+            allow_root_delete: cfg.allow_root_delete,
+            get_transform: cfg.get_transform,
+            max_lock_depth: cfg.max_lock_depth,
+            context: cfg.context,
+            preserve_content_type: cfg.preserve_content_type,
+            max_proppatch_props: cfg.max_proppatch_props,
+            max_request_xml_size: cfg.max_request_xml_size,
+            report_handlers: cfg.report_handlers,
+            hide_patterns: cfg.hide_patterns,
+            propfind_depth_infinity: cfg.propfind_depth_infinity,
+            immutable_if: cfg.immutable_if,
+            accept_multipart: cfg.accept_multipart,
+            sniff_content_type: cfg.sniff_content_type,
+            sniff_size: cfg.sniff_size,
+            verify_content_md5: cfg.verify_content_md5,
+            index_files: cfg.index_files,
+            auto_mkcol_on_put: cfg.auto_mkcol_on_put,
+            autoindex_sort: cfg.autoindex_sort,
+            trust_forwarded: cfg.trust_forwarded,
+            base_url: None,
+            error_bodies: cfg.error_bodies,
+            readahead: cfg.readahead,
+            allow_archive_download: cfg.allow_archive_download,
+            cross_fs_fallback: cfg.cross_fs_fallback,
+            case_insensitive: cfg.case_insensitive,
+            on_transfer: cfg.on_transfer,
+            read_until_eof: cfg.read_until_eof,
+            microsoft_compat: cfg.microsoft_compat,
+            allow_post_create: cfg.allow_post_create,
+            reject_nonportable_names: cfg.reject_nonportable_names,
+            max_drain_size: cfg.max_drain_size,
+            preserve_mtime_on_copy: cfg.preserve_mtime_on_copy,
+            xml_prefixes: cfg.xml_prefixes,
         }
     }
 }
@@ -200,7 +897,7 @@ impl From<&DavConfig> for DavInner {
                 .as_ref()
                 .map(|p| p.to_owned())
                 .unwrap_or_default(),
-            fs: cfg.fs.clone().unwrap(),
+            fs: cfg.fs.clone().unwrap_or_else(|| VoidFs::new()),
             ls: cfg.ls.clone(),
             allow: cfg.allow,
             principal: cfg.principal.clone(),
@@ -209,6 +906,39 @@ impl From<&DavConfig> for DavInner {
             indexfile: cfg.indexfile.clone(),
             read_buf_size: cfg.read_buf_size,
             redirect: cfg.redirect,
+            allow_root_delete: cfg.allow_root_delete,
+            get_transform: cfg.get_transform.clone(),
+            max_lock_depth: cfg.max_lock_depth,
+            context: cfg.context.clone(),
+            preserve_content_type: cfg.preserve_content_type,
+            max_proppatch_props: cfg.max_proppatch_props,
+            max_request_xml_size: cfg.max_request_xml_size,
+            report_handlers: cfg.report_handlers.clone(),
+            hide_patterns: cfg.hide_patterns.clone(),
+            propfind_depth_infinity: cfg.propfind_depth_infinity,
+            immutable_if: cfg.immutable_if.clone(),
+            accept_multipart: cfg.accept_multipart,
+            sniff_content_type: cfg.sniff_content_type,
+            sniff_size: cfg.sniff_size,
+            verify_content_md5: cfg.verify_content_md5,
+            index_files: cfg.index_files.clone(),
+            auto_mkcol_on_put: cfg.auto_mkcol_on_put,
+            autoindex_sort: cfg.autoindex_sort,
+            trust_forwarded: cfg.trust_forwarded,
+            base_url: None,
+            error_bodies: cfg.error_bodies.clone(),
+            readahead: cfg.readahead,
+            allow_archive_download: cfg.allow_archive_download,
+            cross_fs_fallback: cfg.cross_fs_fallback,
+            case_insensitive: cfg.case_insensitive,
+            on_transfer: cfg.on_transfer.clone(),
+            read_until_eof: cfg.read_until_eof,
+            microsoft_compat: cfg.microsoft_compat,
+            allow_post_create: cfg.allow_post_create,
+            reject_nonportable_names: cfg.reject_nonportable_names,
+            max_drain_size: cfg.max_drain_size,
+            preserve_mtime_on_copy: cfg.preserve_mtime_on_copy,
+            xml_prefixes: cfg.xml_prefixes.clone(),
         }
     }
 }
@@ -226,6 +956,39 @@ impl Clone for DavInner {
             indexfile: self.indexfile.clone(),
             read_buf_size: self.read_buf_size,
             redirect: self.redirect,
+            allow_root_delete: self.allow_root_delete,
+            get_transform: self.get_transform.clone(),
+            max_lock_depth: self.max_lock_depth,
+            context: self.context.clone(),
+            preserve_content_type: self.preserve_content_type,
+            max_proppatch_props: self.max_proppatch_props,
+            max_request_xml_size: self.max_request_xml_size,
+            report_handlers: self.report_handlers.clone(),
+            hide_patterns: self.hide_patterns.clone(),
+            propfind_depth_infinity: self.propfind_depth_infinity,
+            immutable_if: self.immutable_if.clone(),
+            accept_multipart: self.accept_multipart,
+            sniff_content_type: self.sniff_content_type,
+            sniff_size: self.sniff_size,
+            verify_content_md5: self.verify_content_md5,
+            index_files: self.index_files.clone(),
+            auto_mkcol_on_put: self.auto_mkcol_on_put,
+            autoindex_sort: self.autoindex_sort,
+            trust_forwarded: self.trust_forwarded,
+            base_url: self.base_url.clone(),
+            error_bodies: self.error_bodies.clone(),
+            readahead: self.readahead,
+            allow_archive_download: self.allow_archive_download,
+            cross_fs_fallback: self.cross_fs_fallback,
+            case_insensitive: self.case_insensitive,
+            on_transfer: self.on_transfer.clone(),
+            read_until_eof: self.read_until_eof,
+            microsoft_compat: self.microsoft_compat,
+            allow_post_create: self.allow_post_create,
+            reject_nonportable_names: self.reject_nonportable_names,
+            max_drain_size: self.max_drain_size,
+            preserve_mtime_on_copy: self.preserve_mtime_on_copy,
+            xml_prefixes: self.xml_prefixes.clone(),
         }
     }
 }
@@ -248,6 +1011,16 @@ impl DavHandler {
         DavConfig::new()
     }
 
+    /// List the active locks of the locksystem.
+    ///
+    /// Returns an empty list if no locksystem is configured.
+    pub fn list_locks(&self) -> Vec<DavLock> {
+        match self.config.ls {
+            Some(ref ls) => ls.list(),
+            None => Vec::new(),
+        }
+    }
+
     /// Handle a webdav request.
     pub async fn handle<ReqBody, ReqData, ReqError>(&self, req: Request<ReqBody>) -> Response<Body>
     where
@@ -266,6 +1039,19 @@ impl DavHandler {
     /// Or, the default config has no locksystem, and you pass in
     /// a fake locksystem (`FakeLs`) because this is a request from a
     /// windows or macos client that needs to see locking support.
+    ///
+    /// Or, every user or tenant gets their own filesystem and
+    /// locksystem, so that one handler can serve all of them:
+    ///
+    /// ```no_run
+    /// # use dav_server::{body::Body, localfs::LocalFs, memls::MemLs, DavConfig, DavHandler};
+    /// # async fn f(dav: DavHandler, req: http::Request<Body>, user: &str) {
+    /// let config = DavConfig::new()
+    ///     .filesystem(LocalFs::new(format!("/srv/dav/{}", user), false, false, false))
+    ///     .principal(user);
+    /// let resp = dav.handle_with(config, req).await;
+    /// # }
+    /// ```
     pub async fn handle_with<ReqBody, ReqData, ReqError>(
         &self,
         config: DavConfig,
@@ -281,12 +1067,138 @@ impl DavHandler {
     }
 }
 
+/// `tower::Service` implementation.
+///
+/// This makes it possible to compose the handler with other
+/// tower middleware, like timeouts or authentication.
+///
+/// ```
+/// use dav_server::{memfs::MemFs, DavHandler};
+/// use tower::{ServiceBuilder, ServiceExt};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let dav = DavHandler::builder()
+///     .filesystem(MemFs::new())
+///     .build_handler();
+/// let svc = ServiceBuilder::new()
+///     .map_request(|req: http::Request<String>| {
+///         println!("{} {}", req.method(), req.uri());
+///         req
+///     })
+///     .service(dav);
+///
+/// let req = http::Request::options("/").body(String::new()).unwrap();
+/// let resp = svc.oneshot(req).await.unwrap();
+/// assert_eq!(resp.status(), 200);
+/// # }
+/// ```
+#[cfg(any(docsrs, feature = "tower"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "tower")))]
+impl<ReqBody, ReqData, ReqError> tower_service::Service<Request<ReqBody>> for DavHandler
+where
+    ReqData: Buf + Send + 'static,
+    ReqError: StdError + Send + Sync + 'static,
+    ReqBody: HttpBody<Data = ReqData, Error = ReqError> + Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = std::convert::Infallible;
+    type Future = futures_util::future::BoxFuture<'static, Result<Response<Body>, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let this = self.clone();
+        Box::pin(async move { Ok(this.handle(req).await) })
+    }
+}
+
 impl Default for DavHandler {
     fn default() -> Self {
         Self::new()
     }
 }
 
+// Does the client ask for HTML, like a browser does.
+fn accepts_html<B>(req: &Request<B>) -> bool {
+    let accept = match req.headers().get("accept").and_then(|a| a.to_str().ok()) {
+        Some(a) => a,
+        None => return false,
+    };
+    accept.split(',').any(|t| {
+        let mut parts = t.split(';').map(str::trim);
+        let html = parts.next().map(|m| m.eq_ignore_ascii_case("text/html"));
+        html == Some(true) && !parts.any(|p| p.replace(' ', "") == "q=0")
+    })
+}
+
+// Add header names to the Vary header, unless they are already in it.
+pub(crate) fn add_vary(headers: &mut http::HeaderMap, names: &[&str]) {
+    let mut list: Vec<String> = headers
+        .get_all(http::header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect();
+    if list.iter().any(|v| v == "*") {
+        return;
+    }
+    for name in names {
+        if !list.iter().any(|v| v.eq_ignore_ascii_case(name)) {
+            list.push(name.to_string());
+        }
+    }
+    if let Ok(value) = list.join(", ").parse() {
+        headers.insert(http::header::VARY, value);
+    }
+}
+
+// Get "scheme://host" from the Forwarded (RFC7239) header, or from
+// X-Forwarded-Host and X-Forwarded-Proto. Proxies append to these, so
+// only the last value, the one added by the proxy in front of us, is
+// used. Earlier values are whatever the client sent.
+fn forwarded_base(headers: &http::HeaderMap) -> Option<String> {
+    let last = |name: &str| {
+        let v = headers.get_all(name).iter().next_back()?.to_str().ok()?;
+        let v = v.rsplit(',').next()?.trim();
+        Some(v.to_string())
+    };
+    let (mut proto, mut host) = (None, None);
+    if let Some(fwd) = last("forwarded") {
+        for pair in fwd.split(';') {
+            let mut kv = pair.trim().splitn(2, '=');
+            let key = kv.next().unwrap_or("").to_ascii_lowercase();
+            let value = kv.next().unwrap_or("").trim_matches('"').to_string();
+            match key.as_str() {
+                "proto" => proto = Some(value),
+                "host" => host = Some(value),
+                _ => {}
+            }
+        }
+    } else {
+        host = last("x-forwarded-host");
+        proto = last("x-forwarded-proto");
+    }
+
+    let host = host.filter(|h| {
+        !h.is_empty()
+            && h.bytes()
+                .all(|c| c.is_ascii_alphanumeric() || b".-:[]_".contains(&c))
+    })?;
+    let proto = match proto.map(|p| p.to_ascii_lowercase()) {
+        Some(p) if p == "https" => "https",
+        _ => "http",
+    };
+    Some(format!("{}://{}", proto, host))
+}
+
 impl DavInner {
     // helper.
     pub(crate) async fn has_parent<'a>(&'a self, path: &'a DavPath) -> bool {
@@ -304,6 +1216,177 @@ impl DavInner {
         DavPath::from_uri_and_prefix(req.uri(), &self.prefix).unwrap()
     }
 
+    // helper. Is this path, or one of its parents, hidden by `hide_patterns`.
+    pub(crate) fn is_hidden(&self, path: &DavPath) -> bool {
+        let patterns = match self.hide_patterns {
+            Some(ref p) if !p.is_empty() => p,
+            _ => return false,
+        };
+        path.as_bytes()
+            .split(|&c| c == b'/')
+            .filter(|seg| !seg.is_empty())
+            .any(|seg| glob_match_any(patterns, seg))
+    }
+
+    // helper. Is this a name that we do not want to create.
+    pub(crate) fn is_nonportable(&self, path: &DavPath) -> bool {
+        self.reject_nonportable_names.unwrap_or(false) && !path.is_portable_name()
+    }
+
+    // helper. Start counting the bytes of a transfer, if `on_transfer` is set.
+    pub(crate) fn transfer_meter(
+        &self,
+        method: DavMethod,
+        path: &DavPath,
+    ) -> Option<TransferMeter> {
+        let report = self.on_transfer.clone()?;
+        let stats = TransferStats {
+            method,
+            path: path.clone(),
+            principal: self.principal.clone(),
+            bytes_received: 0,
+            bytes_sent: 0,
+        };
+        Some(TransferMeter { stats, report })
+    }
+
+    // With `case_insensitive`, find what a path that does not exist refers
+    // to, one segment at a time. Returns `None` if nothing had to be changed.
+    // The last segment may not exist at all, like for a PUT.
+    async fn resolve_case(&self, path: &DavPath) -> Option<DavPath> {
+        if self.fs.metadata(path).await.is_ok() {
+            return None;
+        }
+        let mut cur = path.root();
+        let segs = path
+            .as_bytes()
+            .split(|&c| c == b'/')
+            .filter(|seg| !seg.is_empty())
+            .collect::<Vec<_>>();
+        let mut changed = false;
+        for (i, seg) in segs.iter().enumerate() {
+            let mut next = cur.clone();
+            next.push_segment(seg);
+            if self.fs.metadata(&next).await.is_err() {
+                match self.find_case(&cur, seg).await {
+                    Some(name) => {
+                        next = cur.clone();
+                        next.push_segment(&name);
+                        changed = true;
+                    }
+                    None if i + 1 < segs.len() => return None,
+                    None => {}
+                }
+            }
+            if i + 1 < segs.len() {
+                next.add_slash();
+            }
+            cur = next;
+        }
+        cur.add_slash_if(path.is_collection());
+        changed.then_some(cur)
+    }
+
+    // The one entry of `dir` that is `name` ignoring case.
+    async fn find_case(&self, dir: &DavPath, name: &[u8]) -> Option<Vec<u8>> {
+        let name = String::from_utf8_lossy(name).to_lowercase();
+        let mut entries = self.fs.read_dir(dir, ReadDirMeta::None).await.ok()?;
+        let mut found = None;
+        let mut count = 0;
+        while let Some(dirent) = entries.next().await {
+            // a match could be in what cannot be read.
+            let dirent = dirent.ok()?;
+            count += 1;
+            if count > CASE_SCAN_LIMIT {
+                debug!("find_case: {}: too many entries", dir);
+                return None;
+            }
+            let n = dirent.name();
+            if String::from_utf8_lossy(&n).to_lowercase() == name {
+                if found.is_some() {
+                    debug!("find_case: {}: more than one match", dir);
+                    return None;
+                }
+                found = Some(n);
+            }
+        }
+        found
+    }
+
+    // helper. Is a directory entry hidden by `hide_patterns`.
+    pub(crate) fn is_hidden_entry(&self, name: &[u8]) -> bool {
+        match self.hide_patterns {
+            Some(ref p) => glob_match_any(p, name),
+            None => false,
+        }
+    }
+
+    // helper. 423 Locked, with the root of the lock that was in the way
+    // (RFC4918 16, DAV:lock-token-submitted).
+    pub(crate) fn locked(&self, lock: &DavLock) -> Response<Body> {
+        let href = lock.path.with_prefix().as_url_string();
+        let body = format!(
+            "<D:lock-token-submitted><D:href>{}</D:href></D:lock-token-submitted>",
+            href
+        );
+        let mut res = Response::new(dav_xml_error(&body));
+        *res.status_mut() = StatusCode::LOCKED;
+        res.headers_mut().typed_insert(davheaders::ContentType(
+            "application/xml; charset=utf-8".to_owned(),
+        ));
+        res
+    }
+
+    // helper. Refuse DELETE / MOVE of the root collection unless allowed.
+    pub(crate) fn protect_root(&self, path: &DavPath) -> Option<Response<Body>> {
+        if path.as_bytes() != b"/" || self.allow_root_delete.unwrap_or(false) {
+            return None;
+        }
+        debug!("refusing to delete or move the root collection");
+        let mut res = Response::new(dav_xml_error("<D:cannot-modify-protected-property/>"));
+        *res.status_mut() = StatusCode::FORBIDDEN;
+        res.headers_mut().typed_insert(davheaders::ContentType(
+            "application/xml; charset=utf-8".to_owned(),
+        ));
+        Some(res)
+    }
+
+    // Redirect requests for a redirect reference to its target (RFC4437),
+    // unless the request is for the reference itself.
+    pub(crate) fn follow_redirect_ref(
+        &self,
+        req: &Request<()>,
+        meta: &dyn DavMetaData,
+    ) -> DavResult<Option<Response<Body>>> {
+        let target = match meta.redirect_ref() {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        let apply = req.headers().get("apply-to-redirect-ref");
+        if apply.map(|v| v.as_bytes().eq_ignore_ascii_case(b"T")) == Some(true) {
+            return Ok(None);
+        }
+        let location: http::HeaderValue = target
+            .parse()
+            .map_err(|_| DavError::Status(StatusCode::INTERNAL_SERVER_ERROR))?;
+        let mut res = Response::new(Body::empty());
+        res.headers_mut().insert("Location", location.clone());
+        res.headers_mut().insert("Redirect-Ref", location);
+        res.headers_mut().typed_insert(headers::ContentLength(0));
+        *res.status_mut() = StatusCode::FOUND;
+        Ok(Some(res))
+    }
+
+    // URL of a path on this server, for headers like Content-Location.
+    // absolute if it is known how the proxy in front of us is reached.
+    pub(crate) fn self_url(&self, path: &DavPath) -> String {
+        let url = path.with_prefix().as_url_string();
+        match self.base_url {
+            Some(ref base) => format!("{}{}", base, url),
+            None => url,
+        }
+    }
+
     // See if this is a directory and if so, if we have
     // to fixup the path by adding a slash at the end.
     pub(crate) fn fixpath(
@@ -312,16 +1395,21 @@ impl DavInner {
         path: &mut DavPath,
         meta: Box<dyn DavMetaData>,
     ) -> Box<dyn DavMetaData> {
-        if meta.is_dir() && !path.is_collection() {
-            path.add_slash();
-            let newloc = path.with_prefix().as_url_string();
+        // collections end in a slash, other resources do not.
+        if meta.is_dir() != path.is_collection() && path.as_bytes() != b"/" {
+            if meta.is_dir() {
+                path.add_slash();
+            } else {
+                path.remove_slash();
+            }
+            let newloc = self.self_url(path);
             res.headers_mut()
                 .typed_insert(davheaders::ContentLocation(newloc));
         }
         meta
     }
 
-    // drain request body and return length.
+    // drain request body and return its contents, up to max_request_xml_size.
     pub(crate) async fn read_request<ReqBody, ReqData, ReqError>(
         &self,
         body: ReqBody,
@@ -331,18 +1419,33 @@ impl DavInner {
         ReqData: Buf + Send + 'static,
         ReqError: StdError + Send + Sync + 'static,
     {
-        let Ok(collected) = body.collect().await else {
-            return Err(DavError::IoError(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "UnexpectedEof",
-            )));
-        };
-        let data = collected.to_bytes().to_vec();
-        Ok(data.to_vec())
+        let max_size = self.max_request_xml_size.unwrap_or(MAX_REQUEST_XML_SIZE);
+        let mut data = Vec::new();
+        pin_utils::pin_mut!(body);
+        while let Some(frame) = body.frame().await {
+            let Ok(frame) = frame else {
+                return Err(DavError::IoError(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "UnexpectedEof",
+                )));
+            };
+            if let Ok(mut buf) = frame.into_data() {
+                if data.len() + buf.remaining() > max_size {
+                    return Err(DavError::StatusClose(StatusCode::PAYLOAD_TOO_LARGE));
+                }
+                while buf.has_remaining() {
+                    let chunk = buf.chunk();
+                    let len = chunk.len();
+                    data.extend_from_slice(chunk);
+                    buf.advance(len);
+                }
+            }
+        }
+        Ok(data)
     }
 
     // internal dispatcher.
-    async fn handle<ReqBody, ReqData, ReqError>(self, req: Request<ReqBody>) -> Response<Body>
+    async fn handle<ReqBody, ReqData, ReqError>(mut self, req: Request<ReqBody>) -> Response<Body>
     where
         ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
         ReqData: Buf + Send + 'static,
@@ -354,9 +1457,22 @@ impl DavInner {
             .and_then(|s| s.to_str().ok())
             .map(|s| s.contains("Microsoft"))
             .unwrap_or(false);
+        let error_pages = self.error_bodies.is_some();
+        let error_bodies = match accepts_html(&req) {
+            true => self.error_bodies.take(),
+            false => None,
+        };
+
+        // the request headers that the response depends on, so caches
+        // do not serve the wrong variant.
+        let mut vary = Vec::new();
+        let get = matches!(*req.method(), http::Method::GET | http::Method::HEAD);
+        if get && self.trust_forwarded.unwrap_or(false) {
+            vary.extend(["Forwarded", "X-Forwarded-Host", "X-Forwarded-Proto"]);
+        }
 
         // Turn any DavError results into a HTTP error response.
-        match self.handle2(req).await {
+        let mut resp = match self.handle2(req).await {
             Ok(resp) => {
                 debug!("== END REQUEST result OK");
                 resp
@@ -380,13 +1496,61 @@ impl DavInner {
                         .header("Expires", "0")
                         .header("Vary", "*");
                 }
-                resp = resp.header("Content-Length", "0").status(err.statuscode());
+                resp = resp.status(err.statuscode());
+                if let Some(secs) = err.retry_after() {
+                    resp = resp.header("Retry-After", secs);
+                }
                 if err.must_close() {
                     resp = resp.header("connection", "close");
                 }
-                resp.body(Body::empty()).unwrap()
+                if error_pages {
+                    vary.push("Accept");
+                }
+                let html = error_bodies.and_then(|mut b| b.remove(&err.statuscode()));
+                if let Some(html) = html {
+                    resp.header("Content-Type", "text/html; charset=utf-8")
+                        .header("Content-Length", html.len())
+                        .body(Body::from(html))
+                        .unwrap()
+                } else {
+                    match err.explanation() {
+                        Some(text) => {
+                            let text = text + "\n";
+                            resp.header("Content-Type", "text/plain; charset=utf-8")
+                                .header("Content-Length", text.len())
+                                .body(Body::from(text))
+                                .unwrap()
+                        }
+                        None => resp
+                            .header("Content-Length", "0")
+                            .body(Body::empty())
+                            .unwrap(),
+                    }
+                }
             }
+        };
+        if !vary.is_empty() {
+            add_vary(resp.headers_mut(), &vary);
         }
+        resp
+    }
+
+    // 405 Method Not Allowed, with an Allow header that lists the
+    // methods that are (RFC9110 15.5.6). Same list as for OPTIONS.
+    async fn method_not_allowed(&self, req: &Request<()>) -> DavResult<Response<Body>> {
+        DavPath::from_uri_and_prefix(req.uri(), &self.prefix)?;
+        let allow = self
+            .handle_options(req)
+            .await?
+            .headers_mut()
+            .remove("allow");
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        if let Some(allow) = allow {
+            res.headers_mut().insert("allow", allow);
+        }
+        res.headers_mut().typed_insert(headers::ContentLength(0));
+        Ok(res)
     }
 
     // internal dispatcher part 2.
@@ -399,11 +1563,20 @@ impl DavInner {
         ReqData: Buf + Send + 'static,
         ReqError: StdError + Send + Sync + 'static,
     {
-        let (req, body) = {
+        let (mut req, body) = {
             let (parts, body) = req.into_parts();
             (Request::from_parts(parts, ()), body)
         };
 
+        // let the filesystem know about the request context.
+        if let Some(ref context) = self.context {
+            self.fs = self.fs.with_context(context);
+        }
+
+        if self.trust_forwarded.unwrap_or(false) {
+            self.base_url = forwarded_base(req.headers());
+        }
+
         // debug when running the webdav litmus tests.
         if log_enabled!(log::Level::Debug) {
             if let Some(t) = req.headers().typed_get::<davheaders::XLitmus>() {
@@ -411,12 +1584,13 @@ impl DavInner {
             }
         }
 
-        // translate HTTP method to Webdav method.
+        // translate HTTP method to Webdav method. Anything else, like
+        // TRACE or CONNECT, is not allowed.
         let method = match dav_method(req.method()) {
             Ok(m) => m,
-            Err(e) => {
+            Err(_) => {
                 debug!("refusing method {} request {}", req.method(), req.uri());
-                return Err(e);
+                return self.method_not_allowed(&req).await;
             }
         };
 
@@ -450,17 +1624,56 @@ impl DavInner {
                     req.method(),
                     req.uri()
                 );
-                return Err(DavError::StatusClose(StatusCode::METHOD_NOT_ALLOWED));
+                return self.method_not_allowed(&req).await;
             }
         }
 
         // make sure the request path is valid.
-        let path = DavPath::from_uri_and_prefix(req.uri(), &self.prefix)?;
+        let mut path = DavPath::from_uri_and_prefix(req.uri(), &self.prefix)?;
+        if self.is_hidden(&path) {
+            debug!("hidden path {}", path);
+            return Err(StatusCode::NOT_FOUND.into());
+        }
 
-        // PUT is the only handler that reads the body itself. All the
-        // other handlers either expected no body, or a pre-read Vec<u8>.
+        // the handlers get the path from the request, so rewrite its URI.
+        if self.case_insensitive.unwrap_or(false) && !path.is_star() {
+            if let Some(real) = self.resolve_case(&path).await {
+                debug!("case_insensitive: {} -> {}", path, real);
+                if self.is_hidden(&real) {
+                    return Err(StatusCode::NOT_FOUND.into());
+                }
+                let mut pq = real.with_prefix().as_url_string();
+                if let Some(query) = req.uri().query() {
+                    pq = format!("{}?{}", pq, query);
+                }
+                let mut parts = req.uri().clone().into_parts();
+                parts.path_and_query = Some(pq.parse().map_err(|_| StatusCode::BAD_REQUEST)?);
+                *req.uri_mut() =
+                    http::Uri::from_parts(parts).map_err(|_| StatusCode::BAD_REQUEST)?;
+                path = real;
+            }
+        }
+
+        // POST only creates files, when that is enabled.
+        if method == DavMethod::Post && !self.allow_post_create.unwrap_or(false) {
+            return self.method_not_allowed(&req).await;
+        }
+
+        // PUT and POST are the only handlers that read the body
+        // themselves. All the other handlers either expected no
+        // body, or a pre-read Vec<u8>. DELETE, COPY and MOVE do not
+        // define one, but it is harmless, so it is drained and ignored.
+        let max_drain = self.max_drain_size.unwrap_or(MAX_DRAIN_SIZE);
+        let mut drained = true;
         let (body_strm, body_data) = match method {
-            DavMethod::Put | DavMethod::Patch => (Some(body), Vec::new()),
+            DavMethod::Put | DavMethod::Patch | DavMethod::Post => (Some(body), Vec::new()),
+            DavMethod::Delete | DavMethod::Copy | DavMethod::Move => {
+                pin_utils::pin_mut!(body);
+                if !body.is_end_stream() {
+                    drained = drain_body(body, max_drain).await;
+                }
+                (None, Vec::new())
+            }
             _ => (None, self.read_request(body).await?),
         };
 
@@ -468,9 +1681,14 @@ impl DavInner {
         match method {
             DavMethod::Put
             | DavMethod::Patch
+            | DavMethod::Post
             | DavMethod::PropFind
             | DavMethod::PropPatch
-            | DavMethod::Lock => {}
+            | DavMethod::Lock
+            | DavMethod::Report
+            | DavMethod::Delete
+            | DavMethod::Copy
+            | DavMethod::Move => {}
             _ => {
                 if !body_data.is_empty() {
                     return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE.into());
@@ -485,12 +1703,675 @@ impl DavInner {
             DavMethod::PropFind => self.handle_propfind(&req, &body_data).await,
             DavMethod::PropPatch => self.handle_proppatch(&req, &body_data).await,
             DavMethod::MkCol => self.handle_mkcol(&req).await,
-            DavMethod::Delete => self.handle_delete(&req).await,
+            DavMethod::Delete => close_unless(drained, self.handle_delete(&req).await),
             DavMethod::Lock => self.handle_lock(&req, &body_data).await,
+            DavMethod::Report => self.handle_report(&req, &body_data).await,
             DavMethod::Unlock => self.handle_unlock(&req).await,
             DavMethod::Head | DavMethod::Get => self.handle_get(&req).await,
-            DavMethod::Copy | DavMethod::Move => self.handle_copymove(&req, method).await,
-            DavMethod::Put | DavMethod::Patch => self.handle_put(&req, body_strm.unwrap()).await,
+            DavMethod::Copy | DavMethod::Move => {
+                close_unless(drained, self.handle_copymove(&req, method).await)
+            }
+            DavMethod::Put | DavMethod::Patch | DavMethod::Post => {
+                let body = body_strm.unwrap();
+                pin_utils::pin_mut!(body);
+                let res = match method {
+                    DavMethod::Post => self.handle_post(&req, body.as_mut()).await,
+                    _ => self.handle_put(&req, body.as_mut()).await,
+                };
+
+                // if the body was not read, read the rest of it, so that
+                // the connection can be used for the next request.
+                if body.is_end_stream() {
+                    return res;
+                }
+                let drained = drain_body(body, max_drain).await;
+                match res {
+                    Err(DavError::StatusClose(s)) if drained => Err(DavError::Status(s)),
+                    res => close_unless(drained, res),
+                }
+            }
         }
     }
 }
+
+// If the request body could not be read to the end, the connection
+// cannot be used for another request.
+fn close_unless(drained: bool, res: DavResult<Response<Body>>) -> DavResult<Response<Body>> {
+    match res {
+        Err(e) if !drained && !e.must_close() => Err(DavError::StatusClose(e.statuscode())),
+        Ok(mut resp) if !drained => {
+            resp.headers_mut()
+                .typed_insert(headers::Connection::close());
+            Ok(resp)
+        }
+        res => res,
+    }
+}
+
+// Read and throw away the rest of a request body. Returns false if
+// there was more than `max` bytes left, or if reading it failed.
+async fn drain_body<B: HttpBody>(mut body: Pin<&mut B>, max: usize) -> bool {
+    if max == 0 {
+        return false;
+    }
+    let mut drained = 0;
+    while let Some(frame) = body.frame().await {
+        let Ok(frame) = frame else {
+            return false;
+        };
+        if let Ok(buf) = frame.into_data() {
+            drained += buf.remaining();
+            if drained > max {
+                debug!("drain_body: more than {} bytes left, closing", max);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+    use crate::memfs::MemFs;
+    use crate::tests::{mkcol, put, req, run};
+
+    // Serves "/<tenant>/" of a MemFs as the root, the tenant
+    // comes from the request context.
+    #[derive(Clone)]
+    struct TenantFs {
+        fs: Box<MemFs>,
+        tenant: String,
+    }
+
+    impl TenantFs {
+        fn scoped(&self, path: &DavPath) -> FsResult<DavPath> {
+            let p = format!("/{}{}", self.tenant, path.as_url_string());
+            DavPath::new(&p).map_err(|_| FsError::GeneralFailure)
+        }
+    }
+
+    #[async_trait]
+    impl AsyncDavFileSystem for TenantFs {
+        async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+            self.fs.open(&self.scoped(path)?, options).await
+        }
+
+        async fn read_dir(
+            &self,
+            path: &DavPath,
+            meta: ReadDirMeta,
+        ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+            self.fs.read_dir(&self.scoped(path)?, meta).await
+        }
+
+        async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+            self.fs.metadata(&self.scoped(path)?).await
+        }
+
+        fn with_context(&self, context: &DavContext) -> Box<dyn DavFileSystem> {
+            let tenant = context.downcast_ref::<String>().unwrap();
+            Box::new(TenantFs {
+                fs: self.fs.clone(),
+                tenant: tenant.clone(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn request_context() {
+        let memfs = MemFs::new();
+        let rw = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        mkcol(&rw, "/alice/").await;
+        mkcol(&rw, "/bob/").await;
+        mkcol(&rw, "/nobody/").await;
+        put(&rw, "/alice/file.txt", "alice").await;
+
+        let fs = TenantFs {
+            fs: memfs,
+            tenant: "nobody".to_string(),
+        };
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(fs))
+            .build_handler();
+
+        for (tenant, status) in [("alice", 200), ("bob", 404)] {
+            let ctx: DavContext = Arc::new(tenant.to_string());
+            let config = DavConfig::new().context(ctx);
+            let r = req("GET", "/file.txt").body(Body::empty()).unwrap();
+            let resp = dav.handle_with(config, r).await;
+            assert_eq!(resp.status(), status, "{}", tenant);
+        }
+        let (resp, _) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn list_locks() {
+        use crate::memls::MemLs;
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        put(&dav, "/a.txt", "a").await;
+        mkcol(&dav, "/dir/").await;
+        assert!(dav.list_locks().is_empty());
+
+        let lockinfo = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype><D:owner>me</D:owner></D:lockinfo>"#;
+        for (path, depth) in [("/a.txt", "0"), ("/dir/", "infinity")] {
+            let r = req("LOCK", path)
+                .header("Depth", depth)
+                .header("Timeout", "Second-600")
+                .body(Body::from(lockinfo))
+                .unwrap();
+            assert_eq!(run(&dav, r).await.0.status(), 200);
+        }
+
+        let locks = dav.list_locks();
+        assert_eq!(locks.len(), 2);
+        assert_eq!(locks[0].path.as_url_string(), "/a.txt");
+        assert!(!locks[0].deep);
+        assert_eq!(locks[1].path.as_url_string(), "/dir/");
+        assert!(locks[1].deep);
+        for lock in &locks {
+            assert!(!lock.shared);
+            assert!(lock.owner.is_some());
+            assert_eq!(lock.timeout, Some(std::time::Duration::from_secs(600)));
+            assert!(lock.timeout_at.is_some());
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        assert!(dav.list_locks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_depth() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(crate::memls::MemLs::new())
+            .build_handler();
+        mkcol(&dav, "/dir/").await;
+
+        for method in ["PROPFIND", "COPY", "MOVE", "LOCK", "DELETE"] {
+            for depth in ["2", "garbage", "-1", ""] {
+                let r = req(method, "/dir/")
+                    .header("Depth", depth)
+                    .header("Destination", "/other/")
+                    .body(Body::empty())
+                    .unwrap();
+                let (resp, _) = run(&dav, r).await;
+                assert_eq!(resp.status(), 400, "{} Depth: {:?}", method, depth);
+            }
+        }
+
+        // the value is case-insensitive.
+        let r = req("PROPFIND", "/dir/")
+            .header("Depth", "INFINITY")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert_eq!(resp.status(), 403);
+        let r = req("COPY", "/dir/")
+            .header("Depth", "InFiNiTy")
+            .header("Destination", "/other/")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+    }
+
+    #[test]
+    fn try_build() {
+        assert!(DavHandler::builder().try_build().is_ok());
+        assert!(DavHandler::builder()
+            .strip_prefix("/dav")
+            .indexfile("index.html")
+            .try_build()
+            .is_ok());
+
+        let err = DavHandler::builder().strip_prefix("dav").try_build().err();
+        assert_eq!(err, Some(BuildError::InvalidPrefix("dav".to_string())));
+
+        let err = DavHandler::builder()
+            .index_files(vec!["index.html".to_string(), "www/index.html".to_string()])
+            .try_build()
+            .err();
+        assert_eq!(
+            err,
+            Some(BuildError::InvalidIndexFile("www/index.html".to_string()))
+        );
+
+        let err = DavHandler::builder()
+            .max_lock_depth(LockDepth::Zero)
+            .try_build()
+            .err();
+        assert_eq!(err, Some(BuildError::NoLockSystem("max_lock_depth")));
+        assert!(DavHandler::builder()
+            .locksystem(crate::memls::MemLs::new())
+            .max_lock_depth(LockDepth::Zero)
+            .try_build()
+            .is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid DavConfig")]
+    fn build_handler_panics() {
+        DavHandler::builder()
+            .sniff_content_type(true)
+            .sniff_size(0)
+            .build_handler();
+    }
+
+    #[tokio::test]
+    async fn trust_forwarded() {
+        let propfind = |dav: DavHandler, headers: Vec<(&'static str, &'static str)>| async move {
+            let mut r = req("PROPFIND", "/dir").header("Depth", "0");
+            for (name, value) in headers {
+                r = r.header(name, value);
+            }
+            let (resp, _) = run(&dav, r.body(Body::empty()).unwrap()).await;
+            resp.headers()
+                .get("content-location")
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+        let proxied = vec![
+            ("X-Forwarded-Host", "dav.example.com"),
+            ("X-Forwarded-Proto", "https"),
+        ];
+
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        mkcol(&dav, "/dir/").await;
+        assert_eq!(
+            propfind(dav, proxied.clone()).await.as_deref(),
+            Some("/dir/")
+        );
+
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .trust_forwarded(true)
+            .build_handler();
+        assert_eq!(
+            propfind(dav.clone(), vec![]).await.as_deref(),
+            Some("/dir/")
+        );
+        assert_eq!(
+            propfind(dav.clone(), proxied).await.as_deref(),
+            Some("https://dav.example.com/dir/")
+        );
+        let fwd = vec![(
+            "Forwarded",
+            r#"for=192.0.2.60;proto=http;host="dav.example.com:8080""#,
+        )];
+        assert_eq!(
+            propfind(dav.clone(), fwd).await.as_deref(),
+            Some("http://dav.example.com:8080/dir/")
+        );
+
+        // what the client sent itself comes before what the proxy appended.
+        let spoofed = vec![
+            ("X-Forwarded-Host", "evil.com, dav.example.com"),
+            ("X-Forwarded-Proto", "http, https"),
+        ];
+        assert_eq!(
+            propfind(dav.clone(), spoofed).await.as_deref(),
+            Some("https://dav.example.com/dir/")
+        );
+        let spoofed = vec![
+            ("X-Forwarded-Host", "evil.com"),
+            ("X-Forwarded-Host", "dav.example.com"),
+        ];
+        assert_eq!(
+            propfind(dav.clone(), spoofed).await.as_deref(),
+            Some("http://dav.example.com/dir/")
+        );
+        let spoofed = vec![(
+            "Forwarded",
+            r#"host=evil.com;proto=https, for=192.0.2.60;host=dav.example.com"#,
+        )];
+        assert_eq!(
+            propfind(dav.clone(), spoofed).await.as_deref(),
+            Some("http://dav.example.com/dir/")
+        );
+
+        // a host that is not a plain hostname is ignored.
+        let bad = vec![("X-Forwarded-Host", "evil.com/x?")];
+        assert_eq!(propfind(dav, bad).await.as_deref(), Some("/dir/"));
+    }
+
+    #[tokio::test]
+    async fn error_body() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .error_body(StatusCode::NOT_FOUND, "<h1>Not here</h1>".to_string())
+            .build_handler();
+
+        let browser = "text/html,application/xhtml+xml,*/*;q=0.8";
+        let r = req("GET", "/missing").header("Accept", browser);
+        let (resp, body) = run(&dav, r.body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 404);
+        assert_eq!(resp.headers()["content-type"], "text/html; charset=utf-8");
+        assert_eq!(body, "<h1>Not here</h1>");
+
+        // DAV clients get the usual empty body.
+        for accept in [None, Some("*/*"), Some("text/html;q=0, */*")] {
+            let mut r = req("PROPFIND", "/missing").header("Depth", "0");
+            if let Some(accept) = accept {
+                r = r.header("Accept", accept);
+            }
+            let (resp, body) = run(&dav, r.body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 404);
+            assert_eq!(body, "", "{:?}", accept);
+        }
+
+        // other statuses keep their default body.
+        let r = req("DELETE", "/").header("Accept", browser);
+        let (resp, body) = run(&dav, r.body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 403);
+        assert!(body.contains("cannot-modify-protected-property"));
+    }
+
+    #[tokio::test]
+    async fn case_insensitive() {
+        let fs = MemFs::new();
+        let plain = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        mkcol(&plain, "/Docs/").await;
+        put(&plain, "/Docs/file.txt", "hello").await;
+        put(&plain, "/Docs/twice.txt", "one").await;
+        put(&plain, "/Docs/TWICE.txt", "two").await;
+        let r = req("GET", "/docs/file.txt").body(Body::empty()).unwrap();
+        assert_eq!(run(&plain, r).await.0.status(), 404);
+
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .case_insensitive(true)
+            .build_handler();
+
+        let get = |path: &str| run(&dav, req("GET", path).body(Body::empty()).unwrap());
+        let (resp, body) = get("/docs/File.TXT").await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(body, "hello");
+        assert_eq!(get("/docs/Twice.txt").await.0.status(), 404);
+        assert_eq!(get("/docs/twice.txt").await.1, "one");
+
+        // writes go to the existing entry.
+        put(&dav, "/DOCS/FILE.txt", "changed").await;
+        put(&dav, "/docs/new.txt", "new").await;
+        assert_eq!(get("/Docs/file.txt").await.1, "changed");
+        assert_eq!(get("/Docs/new.txt").await.1, "new");
+
+        // no duplicates that only differ in case.
+        put(&dav, "/docs/NEW.TXT", "newer").await;
+        assert_eq!(get("/Docs/new.txt").await.1, "newer");
+    }
+
+    #[tokio::test]
+    async fn on_transfer() {
+        use std::sync::Mutex;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = seen.clone();
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .principal("alice")
+            .on_transfer(Arc::new(move |stats| s.lock().unwrap().push(stats)))
+            .build_handler();
+
+        let content = "x".repeat(100_000);
+        put(&dav, "/file.txt", &content).await;
+        let (resp, body) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(body.len(), content.len());
+        let r = req("GET", "/file.txt").header("Range", "bytes=0-9");
+        assert_eq!(run(&dav, r.body(Body::empty()).unwrap()).await.1.len(), 10);
+        mkcol(&dav, "/dir/").await;
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[0].method, DavMethod::Put);
+        assert_eq!(seen[0].path.as_bytes(), b"/file.txt");
+        assert_eq!(seen[0].principal.as_deref(), Some("alice"));
+        assert_eq!(seen[0].bytes_received, 100_000);
+        assert_eq!(seen[0].bytes_sent, 0);
+        assert_eq!(seen[1].method, DavMethod::Get);
+        assert_eq!(seen[1].bytes_received, 0);
+        assert_eq!(seen[1].bytes_sent, 100_000);
+        assert_eq!(seen[2].bytes_sent, 10);
+    }
+
+    #[tokio::test]
+    async fn vary() {
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        mkcol(&dav, "/dir/").await;
+        put(&dav, "/file.txt", "data").await;
+        async fn get(dav: &DavHandler, path: &str) -> Response<()> {
+            run(dav, req("GET", path).body(Body::empty()).unwrap())
+                .await
+                .0
+        }
+        assert!(get(&dav, "/dir").await.headers().get("vary").is_none());
+
+        // the Location of the redirect depends on the forwarded headers.
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .trust_forwarded(true)
+            .build_handler();
+        let resp = get(&dav, "/dir").await;
+        assert_eq!(resp.status(), 302);
+        assert_eq!(
+            resp.headers()["vary"],
+            "Forwarded, X-Forwarded-Host, X-Forwarded-Proto"
+        );
+
+        // so does the body of an error, when there are error pages.
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .error_body(StatusCode::NOT_FOUND, "<h1>Not here</h1>".to_string())
+            .build_handler();
+        let resp = get(&dav, "/missing").await;
+        assert_eq!(resp.status(), 404);
+        assert_eq!(resp.headers()["vary"], "Accept");
+        assert!(get(&dav, "/file.txt").await.headers().get("vary").is_none());
+
+        let mut headers = http::HeaderMap::new();
+        headers.insert("vary", "accept-encoding".parse().unwrap());
+        add_vary(&mut headers, &["Accept-Encoding", "Accept"]);
+        assert_eq!(headers["vary"], "accept-encoding, Accept");
+        headers.insert("vary", "*".parse().unwrap());
+        add_vary(&mut headers, &["Accept"]);
+        assert_eq!(headers["vary"], "*");
+    }
+
+    #[tokio::test]
+    async fn reject_nonportable_names() {
+        let fs = MemFs::new();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .reject_nonportable_names(true)
+            .build_handler();
+        async fn status(dav: &DavHandler, method: &str, path: &str, dest: Option<&str>) -> u16 {
+            let mut r = req(method, path);
+            if let Some(dest) = dest {
+                r = r.header("Destination", dest);
+            }
+            let body = if method == "PUT" { "data" } else { "" };
+            run(dav, r.body(Body::from(body)).unwrap())
+                .await
+                .0
+                .status()
+                .as_u16()
+        }
+
+        for name in [
+            "/aux",
+            "/CON.txt",
+            "/lpt1",
+            "/a%3Ab.txt",
+            "/dot.",
+            "/space%20",
+        ] {
+            assert_eq!(status(&dav, "PUT", name, None).await, 403, "{}", name);
+            let dir = format!("{}/", name);
+            assert_eq!(status(&dav, "MKCOL", &dir, None).await, 403, "{}", name);
+        }
+        assert_eq!(status(&dav, "PUT", "/auxiliary.txt", None).await, 201);
+        assert_eq!(status(&dav, "MKCOL", "/dir/", None).await, 201);
+        for method in ["COPY", "MOVE"] {
+            let s = status(&dav, method, "/auxiliary.txt", Some("/dir/nul")).await;
+            assert_eq!(s, 403, "{}", method);
+        }
+        let s = status(&dav, "MOVE", "/auxiliary.txt", Some("/dir/ok.txt")).await;
+        assert_eq!(s, 201);
+
+        // off by default, and files that exist can still be written.
+        let plain = DavHandler::builder().filesystem(fs).build_handler();
+        assert_eq!(status(&plain, "PUT", "/aux", None).await, 201);
+        assert_eq!(status(&dav, "PUT", "/aux", None).await, 204);
+    }
+
+    #[tokio::test]
+    async fn per_request_filesystem() {
+        use crate::memls::MemLs;
+
+        // no filesystem by default, one per principal.
+        let dav = DavHandler::new();
+        let tenants = [
+            ("alice", MemFs::new(), MemLs::new()),
+            ("bob", MemFs::new(), MemLs::new()),
+        ];
+        let config = |user: &str| {
+            let (_, fs, ls) = tenants.iter().find(|(u, _, _)| *u == user).unwrap();
+            DavConfig::new()
+                .filesystem(fs.clone())
+                .locksystem(ls.clone())
+                .principal(user)
+        };
+        let handle = |user: &'static str, r: http::request::Builder, body: &'static str| {
+            let resp = dav.handle_with(config(user), r.body(Body::from(body)).unwrap());
+            async move {
+                let (parts, body) = resp.await.into_parts();
+                let body = body.collect().await.unwrap().to_bytes();
+                (parts.status, String::from_utf8_lossy(&body).to_string())
+            }
+        };
+
+        let (status, _) = handle("alice", req("PUT", "/notes.txt"), "alice's notes").await;
+        assert_eq!(status, 201);
+        let (status, _) = handle("bob", req("PUT", "/notes.txt"), "bob's notes").await;
+        assert_eq!(status, 201);
+        for user in ["alice", "bob"] {
+            let (status, body) = handle(user, req("GET", "/notes.txt"), "").await;
+            assert_eq!(status, 200);
+            assert_eq!(body, format!("{}'s notes", user));
+        }
+        let (_, body) = handle("bob", req("PROPFIND", "/").header("Depth", "1"), "").await;
+        assert!(body.contains("/notes.txt"), "{}", body);
+
+        // and so are the locks.
+        let lockinfo = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+        let (status, _) = handle("alice", req("LOCK", "/notes.txt"), lockinfo).await;
+        assert_eq!(status, 200);
+        let (status, _) = handle("bob", req("PUT", "/notes.txt"), "more").await;
+        assert_eq!(status, 204);
+        let (status, _) = handle("alice", req("PUT", "/notes.txt"), "more").await;
+        assert_eq!(status, 423);
+
+        // the handler itself still has no filesystem.
+        let r = req("GET", "/notes.txt").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 501);
+    }
+
+    #[tokio::test]
+    async fn drain_rejected_put() {
+        use hyper_util::rt::TokioIo;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // two requests on one connection: a PUT that fails before its
+        // body is read, and an OPTIONS.
+        async fn two_requests(dav: DavHandler) -> String {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = hyper::service::service_fn(move |req| {
+                    let dav = dav.clone();
+                    async move { Ok::<_, std::convert::Infallible>(dav.handle(req).await) }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let body = "x".repeat(1000);
+            let put = format!(
+                "PUT /a.txt HTTP/1.1\r\nHost: x\r\nIf-Match: *\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let options = "OPTIONS / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n";
+            stream.write_all(put.as_bytes()).await.unwrap();
+            stream.write_all(options.as_bytes()).await.unwrap();
+            let mut text = String::new();
+            stream.read_to_string(&mut text).await.unwrap();
+            text
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let text = two_requests(dav).await;
+        assert!(text.starts_with("HTTP/1.1 412"), "{}", text);
+        let (first, _) = text.split_once("HTTP/1.1 200").expect(&text);
+        assert!(!first.contains("connection: close"), "{}", text);
+
+        // over the limit, the connection is closed after the PUT.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .max_drain_size(100)
+            .build_handler();
+        let text = two_requests(dav).await;
+        assert!(text.starts_with("HTTP/1.1 412"), "{}", text);
+        assert!(text.contains("connection: close"), "{}", text);
+        assert!(!text.contains("HTTP/1.1 200"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn traversal() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        mkcol(&dav, "/dir/").await;
+        put(&dav, "/dir/file.txt", "data").await;
+
+        let attempts = [
+            "/../etc/passwd",
+            "/%2e%2e/etc/passwd",
+            "/dir/..%2f..%2fetc/passwd",
+            "/dir/..%2F..%2Fetc/passwd",
+            "/dir/%2e%2e%2f%2e%2e%2fetc/passwd",
+            "/dir/..%5c..%5cetc/passwd",
+        ];
+        for path in attempts {
+            for method in ["GET", "PUT", "DELETE", "PROPFIND", "MKCOL"] {
+                let r = req(method, path).header("Depth", "0");
+                let (resp, _) = run(&dav, r.body(Body::empty()).unwrap()).await;
+                assert_eq!(resp.status(), 403, "{} {}", method, path);
+            }
+            let r = req("COPY", "/dir/file.txt").header("Destination", path);
+            let (resp, _) = run(&dav, r.body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 403, "COPY to {}", path);
+        }
+
+        // dotdots that stay inside the root are fine.
+        let r = req("GET", "/dir/../dir/file.txt")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.1, "data");
+    }
+}
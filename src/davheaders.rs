@@ -104,11 +104,11 @@ impl Header for Depth {
     where
         I: Iterator<Item = &'i HeaderValue>,
     {
-        let value = one(values)?;
-        match value.as_bytes() {
+        let value = one(values)?.as_bytes();
+        match value {
             b"0" => Ok(Depth::Zero),
             b"1" => Ok(Depth::One),
-            b"infinity" | b"Infinity" => Ok(Depth::Infinity),
+            _ if value.eq_ignore_ascii_case(b"infinity") => Ok(Depth::Infinity),
             _ => Err(invalid()),
         }
     }
@@ -754,8 +754,14 @@ impl Header for If {
                 IfState::Start => match tok {
                     IfToken::ListOpen => IfState::List,
                     IfToken::Pointy(url) => {
-                        let u = url::Url::parse(&url).map_err(map_invalid)?;
-                        cur_list.resource_tag = Some(u);
+                        // a resource tag can also be an absolute path
+                        // (RFC4918 10.4.2). only the path is used later.
+                        let u = if url.starts_with('/') {
+                            url::Url::parse("http://localhost/").and_then(|base| base.join(&url))
+                        } else {
+                            url::Url::parse(&url)
+                        };
+                        cur_list.resource_tag = Some(u.map_err(map_invalid)?);
                         IfState::RTag
                     }
                     IfToken::End => {
@@ -22,6 +22,9 @@ const PATH_ENCODE_SET: &pct::AsciiSet = &pct::NON_ALPHANUMERIC
     .remove(b'~')
     .remove(b'/');
 
+/// A predicate on paths, see for example `DavConfig::immutable_if`.
+pub type PathPredicate = std::sync::Arc<dyn Fn(&DavPath) -> bool + Send + Sync>;
+
 /// URL path, with hidden prefix.
 #[derive(Clone)]
 pub struct DavPath {
@@ -95,9 +98,11 @@ fn encode_path(src: &[u8]) -> Vec<u8> {
 // - make sure path is absolute
 // - remove query part (everything after ?)
 // - merge consecutive slashes
-// - decode percent encoded bytes, fail on invalid encodings.
+// - decode percent encoded bytes, fail on invalid encodings. this is
+//   RFC3986 decoding, not form decoding: a '+' is a literal plus.
 // - process . and ..
-// - do not allow NUL or '/' in segments.
+// - do not allow NUL or '/' in segments, or a '..' between encoded
+//   slashes or backslashes, which a backend might see as separators.
 fn normalize_path(rp: &[u8]) -> Result<Vec<u8>, ParseError> {
     // must consist of printable ASCII
     if rp.iter().any(|&x| !(32..=126).contains(&x)) {
@@ -136,6 +141,9 @@ fn normalize_path(rp: &[u8]) -> Result<Vec<u8>, ParseError> {
                 v.pop();
             }
             s => {
+                if s.split(|c| *c == b'/' || *c == b'\\').any(|p| p == b"..") {
+                    return Err(ParseError::ForbiddenPath);
+                }
                 // a decoded segment can contain any value except '/' or '\0'
                 if s.iter().any(|x| *x == 0 || *x == b'/') {
                     return Err(ParseError::InvalidPath);
@@ -242,6 +250,13 @@ impl DavPath {
         }
     }
 
+    // remove the slash at the end of the path, unless it is the root.
+    pub(crate) fn remove_slash(&mut self) {
+        if self.is_collection() && self.as_bytes() != b"/" {
+            self.fullpath.pop();
+        }
+    }
+
     // add a slash
     pub(crate) fn add_slash_if(&mut self, b: bool) {
         if b && !self.is_collection() {
@@ -257,6 +272,17 @@ impl DavPath {
         self.fullpath.extend_from_slice(b);
     }
 
+    // The root collection, with the same prefix.
+    pub(crate) fn root(&self) -> DavPath {
+        let pfxlen = self.pfxlen.unwrap_or(0);
+        let mut fullpath = self.fullpath[..pfxlen].to_vec();
+        fullpath.push(b'/');
+        DavPath {
+            fullpath,
+            pfxlen: self.pfxlen,
+        }
+    }
+
     // as URL encoded string, with prefix.
     pub(crate) fn as_url_string_with_prefix_debug(&self) -> String {
         let mut p = encode_path(self.get_path());
@@ -419,6 +445,44 @@ impl DavPathRef {
         }
     }
 
+    /// Can the filename be used on all common platforms.
+    ///
+    /// Not portable are names with characters that Windows does not
+    /// allow, such as `:`, `\`, `?` or `*`, names that end in a dot or
+    /// a space, and reserved device names such as `aux`, `con.txt` or
+    /// `LPT1`. A path without a filename, like `/`, is portable.
+    pub fn is_portable_name(&self) -> bool {
+        let name = self.file_name_bytes();
+        name.is_empty() || native_segment_ok(name, true)
+    }
+
+    /// Map the path to a path on the local filesystem, below `base`.
+    ///
+    /// Returns `None` if a segment of the path cannot be used safely as a
+    /// file name on this platform: `.` and `..`, names that contain a NUL
+    /// byte, and on Windows also names with characters such as `:` or `\`,
+    /// names that end in a dot or a space, and reserved device names such
+    /// as `CON` or `LPT1`.
+    ///
+    /// This is the mapping that `LocalFs` uses.
+    pub fn to_native(&self, base: &Path) -> Option<PathBuf> {
+        let mut pathbuf = base.to_path_buf();
+        for seg in self
+            .get_path()
+            .split(|&c| c == b'/')
+            .filter(|s| !s.is_empty())
+        {
+            if !native_segment_ok(seg, cfg!(target_os = "windows")) {
+                return None;
+            }
+            #[cfg(not(target_os = "windows"))]
+            pathbuf.push(OsStr::from_bytes(seg));
+            #[cfg(target_os = "windows")]
+            pathbuf.push(std::str::from_utf8(seg).ok()?);
+        }
+        Some(pathbuf)
+    }
+
     pub(crate) fn get_mime_type_str(&self) -> &'static str {
         let name = self.file_name_bytes();
         let d = name.rsplitn(2, |&c| c == b'.').collect::<Vec<&[u8]>>();
@@ -432,3 +496,124 @@ impl DavPathRef {
         "application/octet-stream"
     }
 }
+
+// Can this path segment be used as a file name.
+fn native_segment_ok(seg: &[u8], windows: bool) -> bool {
+    if seg == b"." || seg == b".." || seg.contains(&0) {
+        return false;
+    }
+    if !windows {
+        return true;
+    }
+    let name = match std::str::from_utf8(seg) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    if name.ends_with('.') || name.ends_with(' ') {
+        return false;
+    }
+    if name
+        .chars()
+        .any(|c| c < ' ' || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*'))
+    {
+        return false;
+    }
+    // "CON", but also "con.txt", is the console device.
+    let stem = name.split('.').next().unwrap_or("").trim_end();
+    let reserved = ["CON", "PRN", "AUX", "NUL"];
+    if reserved.iter().any(|r| stem.eq_ignore_ascii_case(r)) {
+        return false;
+    }
+    let upper = stem.to_ascii_uppercase();
+    if (upper.starts_with("COM") || upper.starts_with("LPT"))
+        && upper.len() == 4
+        && matches!(upper.as_bytes()[3], b'1'..=b'9')
+    {
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_native() {
+        let base = Path::new("/srv/dav");
+        let native = |p: &str| DavPath::new(p).ok().and_then(|p| p.to_native(base));
+
+        assert_eq!(native("/"), Some(PathBuf::from("/srv/dav")));
+        assert_eq!(
+            native("/dir/file%20name.txt"),
+            Some(PathBuf::from("/srv/dav/dir/file name.txt"))
+        );
+        // dotdots are resolved, or refused, when parsing.
+        assert_eq!(native("/a/../b"), Some(PathBuf::from("/srv/dav/b")));
+        assert_eq!(native("/../../etc/passwd"), None);
+        assert_eq!(native("/a/%2e%2e/%2e%2e/etc"), None);
+        assert_eq!(native("/a%00b"), None);
+        assert!(matches!(
+            DavPath::new("/..%2f..%2fetc/passwd"),
+            Err(ParseError::ForbiddenPath)
+        ));
+        assert!(matches!(
+            DavPath::new("/a/..%5C..%5Cetc"),
+            Err(ParseError::ForbiddenPath)
+        ));
+        assert_eq!(native("/a%5Cb"), Some(PathBuf::from("/srv/dav/a\\b")));
+    }
+
+    #[test]
+    fn plus_sign() {
+        let p = DavPath::new("/a+b/c%2Bd%20e").unwrap();
+        assert_eq!(p.as_bytes(), b"/a+b/c+d e");
+        assert_eq!(p.as_url_string(), "/a%2Bb/c%2Bd%20e");
+        let mut q = DavPath::new("/").unwrap();
+        q.push_segment(b"a+b");
+        assert_eq!(DavPath::new("/a%2Bb").unwrap(), q);
+    }
+
+    #[test]
+    fn windows_names() {
+        for name in ["file.txt", "CONSOLE", "com0", "COM10", "a b", ".hidden"] {
+            assert!(native_segment_ok(name.as_bytes(), true), "{}", name);
+        }
+        let bad = [
+            "CON",
+            "con.txt",
+            "Nul",
+            "aux .tar.gz",
+            "COM1",
+            "lpt9.log",
+            "a:b",
+            "a\\b",
+            "a|b",
+            "a?",
+            "trailing.",
+            "trailing ",
+            "..",
+            "a\tb",
+        ];
+        for name in bad {
+            assert!(!native_segment_ok(name.as_bytes(), true), "{}", name);
+        }
+        // on other platforms these are just file names.
+        assert!(native_segment_ok(b"CON", false));
+        assert!(native_segment_ok(b"a:b", false));
+        assert!(!native_segment_ok(b"..", false));
+    }
+
+    #[test]
+    fn portable_name() {
+        let portable = |p: &str| DavPath::new(p).unwrap().is_portable_name();
+        assert!(portable("/"));
+        assert!(portable("/dir/report.txt"));
+        assert!(portable("/aux/file.txt"));
+        assert!(!portable("/dir/aux"));
+        assert!(!portable("/dir/Con.txt/"));
+        assert!(!portable("/a%3Ab"));
+        assert!(!portable("/name."));
+        assert!(!portable("/name%20"));
+    }
+}
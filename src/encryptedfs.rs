@@ -0,0 +1,727 @@
+//! Filesystem wrapper that stores files encrypted.
+//!
+//! `EncryptedFs` encrypts file contents before they are written to the
+//! filesystem it wraps, and decrypts them when they are read. Clients
+//! see the plaintext, the storage below only ever sees ciphertext.
+//!
+//! A file is stored as a series of chunks of 16 KiB of plaintext. Every
+//! chunk is encrypted with ChaCha20-Poly1305 (RFC8439), from the
+//! `chacha20poly1305` crate, under a random nonce that is stored in front
+//! of it, and followed by its tag. The index of the chunk, and whether it
+//! is the last one, are part of the authenticated data, so chunks cannot
+//! be reordered, and a file cannot be truncated at a chunk boundary
+//! without that being noticed. The exception is a file that is cut down
+//! to 0 bytes: that looks like an empty file, and is not detected. A chunk
+//! that does not decrypt makes the read fail. Because every chunk can be
+//! decrypted on its own, seeking and byte ranges work as usual.
+//!
+//! Names of files and directories, the directory structure, the size of
+//! the files (rounded to nothing) and the timestamps are not encrypted.
+//! Properties are passed through as they are. Chunks of one file could
+//! be swapped with the same chunks of another file stored under the same
+//! key.
+//!
+//! This module is only available with the `encryptedfs` feature.
+//!
+//! ```
+//! use dav_server::{encryptedfs::EncryptedFs, localfs::LocalFs, DavHandler};
+//!
+//! let key = [0x42; 32]; // use a real, secret key.
+//! let local = LocalFs::new("/tmp", false, false, false);
+//! let dav = DavHandler::builder()
+//!     .filesystem(EncryptedFs::new(local, key))
+//!     .build_handler();
+//! ```
+use std::convert::TryFrom;
+use std::io::SeekFrom;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytes::{Buf, Bytes};
+use chacha20poly1305::{AeadInOut, ChaCha20Poly1305, Key, KeyInit, Nonce, Tag};
+use futures_util::{FutureExt, StreamExt};
+
+use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+// Plaintext bytes per chunk.
+const CHUNK: usize = 16 * 1024;
+// Bytes added to every chunk: nonce in front, tag at the end.
+const OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+// Size of a full chunk on disk.
+const STORED_CHUNK: u64 = (CHUNK + OVERHEAD) as u64;
+
+// Plaintext size of a file of `len` bytes on disk.
+fn plain_len(len: u64) -> u64 {
+    let rem = len % STORED_CHUNK;
+    (len / STORED_CHUNK) * CHUNK as u64 + rem.saturating_sub(OVERHEAD as u64)
+}
+
+// Size on disk of a file with `len` bytes of plaintext.
+fn stored_len(len: u64) -> u64 {
+    let rem = len % CHUNK as u64;
+    let last = if rem > 0 { rem + OVERHEAD as u64 } else { 0 };
+    (len / CHUNK as u64) * STORED_CHUNK + last
+}
+
+// Authenticated data of a chunk.
+fn chunk_aad(index: u64, last: bool) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&index.to_be_bytes());
+    aad[8] = last as u8;
+    aad
+}
+
+/// Filesystem wrapper that encrypts file contents.
+#[derive(Clone)]
+pub struct EncryptedFs {
+    inner: Box<dyn DavFileSystem>,
+    cipher: Arc<ChaCha20Poly1305>,
+}
+
+impl EncryptedFs {
+    /// Wrap a filesystem, and encrypt all files stored in it with `key`.
+    ///
+    /// Files that are already in the filesystem but were not stored
+    /// through an `EncryptedFs` with the same key cannot be read.
+    pub fn new(inner: Box<dyn DavFileSystem>, key: [u8; KEY_LEN]) -> Box<EncryptedFs> {
+        Box::new(EncryptedFs {
+            inner,
+            cipher: Arc::new(ChaCha20Poly1305::new(&Key::from(key))),
+        })
+    }
+}
+
+#[async_trait]
+impl AsyncDavFileSystem for EncryptedFs {
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        // a write can change part of a chunk, which means reading it first.
+        // appending is done by seeking to the end ourselves.
+        let mut oo = options.clone();
+        oo.read = true;
+        oo.append = false;
+        oo.size = options.size.map(stored_len);
+        // a checksum of the plaintext would not match what is stored.
+        oo.checksum = None;
+        let mut file = self.inner.open(path, oo).await?;
+        let len = match options.truncate {
+            true => 0,
+            false => plain_len(file.metadata().await?.len()),
+        };
+        Ok(Box::new(EncryptedFile {
+            file,
+            cipher: self.cipher.clone(),
+            pos: if options.append { len } else { 0 },
+            len,
+            stored: len,
+            sealed_last: len.checked_sub(1).map(|n| n / CHUNK as u64),
+            append: options.append,
+            chunk: None,
+        }))
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        let entries = self.inner.read_dir(path, meta).await?;
+        let entries = entries
+            .map(|entry| entry.map(|e| Box::new(EncryptedDirEntry(e)) as Box<dyn DavDirEntry>));
+        Ok(Box::pin(entries))
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        Ok(EncryptedMeta::boxed(self.inner.metadata(path).await?))
+    }
+
+    async fn symlink_metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        Ok(EncryptedMeta::boxed(
+            self.inner.symlink_metadata(path).await?,
+        ))
+    }
+
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.inner.create_dir(path).await
+    }
+
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.inner.remove_dir(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &DavPath) -> FsResult<()> {
+        self.inner.remove_dir_all(path).await
+    }
+
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        self.inner.remove_file(path).await
+    }
+
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.inner.rename(from, to).await
+    }
+
+    // the chunks do not depend on the path, so copying the ciphertext
+    // gives a file that decrypts fine.
+    async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn set_accessed(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner.set_accessed(path, tm).await
+    }
+
+    async fn set_modified(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner.set_modified(path, tm).await
+    }
+
+    async fn have_props(&self, path: &DavPath) -> bool {
+        self.inner.have_props(path).await
+    }
+
+    async fn patch_props(
+        &self,
+        path: &DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(http::StatusCode, DavProp)>> {
+        self.inner.patch_props(path, patch).await
+    }
+
+    async fn get_props(&self, path: &DavPath, do_content: bool) -> FsResult<Vec<DavProp>> {
+        self.inner.get_props(path, do_content).await
+    }
+
+    async fn get_prop(&self, path: &DavPath, prop: DavProp) -> FsResult<Vec<u8>> {
+        self.inner.get_prop(path, prop).await
+    }
+
+    fn with_context(&self, context: &DavContext) -> Box<dyn DavFileSystem> {
+        Box::new(EncryptedFs {
+            inner: self.inner.with_context(context),
+            cipher: self.cipher.clone(),
+        })
+    }
+
+    async fn get_quota(&self) -> FsResult<(u64, Option<u64>)> {
+        self.inner.get_quota().await
+    }
+
+    fn preferred_read_chunk_size(&self) -> usize {
+        CHUNK
+    }
+
+    // a redirect would send the client to the ciphertext.
+    async fn get_redirect_url(&self, _path: &DavPath) -> FsResult<Option<String>> {
+        Ok(None)
+    }
+
+    async fn collection_etag(&self, path: &DavPath) -> FsResult<Option<String>> {
+        self.inner.collection_etag(path).await
+    }
+
+    async fn is_special(&self, path: &DavPath) -> FsResult<bool> {
+        self.inner.is_special(path).await
+    }
+}
+
+// Metadata, with the plaintext size.
+#[derive(Debug, Clone)]
+struct EncryptedMeta(Box<dyn DavMetaData>);
+
+impl EncryptedMeta {
+    fn boxed(meta: Box<dyn DavMetaData>) -> Box<dyn DavMetaData> {
+        Box::new(EncryptedMeta(meta))
+    }
+}
+
+impl DavMetaData for EncryptedMeta {
+    fn len(&self) -> u64 {
+        match self.0.is_file() {
+            true => plain_len(self.0.len()),
+            false => self.0.len(),
+        }
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        self.0.modified()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.0.is_dir()
+    }
+
+    fn etag(&self) -> Option<String> {
+        self.0.etag()
+    }
+
+    fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+
+    fn accessed(&self) -> FsResult<SystemTime> {
+        self.0.accessed()
+    }
+
+    fn created(&self) -> FsResult<SystemTime> {
+        self.0.created()
+    }
+
+    fn status_changed(&self) -> FsResult<SystemTime> {
+        self.0.status_changed()
+    }
+
+    fn executable(&self) -> FsResult<bool> {
+        self.0.executable()
+    }
+
+    fn extra_props(&self) -> Vec<DavProp> {
+        self.0.extra_props()
+    }
+
+    fn redirect_ref(&self) -> Option<String> {
+        self.0.redirect_ref()
+    }
+}
+
+struct EncryptedDirEntry(Box<dyn DavDirEntry>);
+
+impl DavDirEntry for EncryptedDirEntry {
+    fn name(&self) -> Vec<u8> {
+        self.0.name()
+    }
+
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.0
+            .metadata()
+            .map(|r| r.map(EncryptedMeta::boxed))
+            .boxed()
+    }
+
+    fn is_dir(&self) -> FsFuture<'_, bool> {
+        self.0.is_dir()
+    }
+
+    fn is_file(&self) -> FsFuture<'_, bool> {
+        self.0.is_file()
+    }
+
+    fn is_symlink(&self) -> FsFuture<'_, bool> {
+        self.0.is_symlink()
+    }
+}
+
+// The chunk that is being read or written.
+struct Chunk {
+    index: u64,
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+struct EncryptedFile {
+    file: Box<dyn DavFile>,
+    cipher: Arc<ChaCha20Poly1305>,
+    // position and size of the plaintext.
+    pos: u64,
+    len: u64,
+    // plaintext bytes that are on disk.
+    stored: u64,
+    // the chunk that is on disk with the "last" flag.
+    sealed_last: Option<u64>,
+    append: bool,
+    chunk: Option<Chunk>,
+}
+
+impl std::fmt::Debug for EncryptedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedFile")
+            .field("file", &self.file)
+            .field("pos", &self.pos)
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptedFile {
+    fn is_last(&self, index: u64) -> bool {
+        self.len.checked_sub(1).map(|n| n / CHUNK as u64) == Some(index)
+    }
+
+    // Read chunk `index` from disk, and decrypt it.
+    async fn read_chunk(&mut self, index: u64) -> FsResult<Vec<u8>> {
+        let start = index * CHUNK as u64;
+        if start >= self.stored {
+            return Ok(Vec::new());
+        }
+        let size = (self.stored - start).min(CHUNK as u64) as usize + OVERHEAD;
+        self.file
+            .seek(SeekFrom::Start(index * STORED_CHUNK))
+            .await?;
+        let mut buf = Vec::with_capacity(size);
+        while buf.len() < size {
+            let bytes = self.file.read_bytes(size - buf.len()).await?;
+            if bytes.is_empty() {
+                error!("EncryptedFs: chunk {} is short", index);
+                return Err(FsError::GeneralFailure);
+            }
+            buf.extend_from_slice(&bytes);
+        }
+
+        let (nonce, rest) = buf.split_at_mut(NONCE_LEN);
+        let (data, tag) = rest.split_at_mut(size - OVERHEAD);
+        let nonce = Nonce::try_from(&nonce[..]).unwrap();
+        let tag = Tag::try_from(&tag[..]).unwrap();
+        let aad = chunk_aad(index, self.sealed_last == Some(index));
+        let res = self
+            .cipher
+            .decrypt_inout_detached(&nonce, &aad, data.into(), &tag);
+        if res.is_err() {
+            error!("EncryptedFs: chunk {} does not decrypt", index);
+            return Err(FsError::GeneralFailure);
+        }
+        Ok(data.to_vec())
+    }
+
+    // Encrypt chunk `index`, and write it to disk.
+    async fn write_chunk(&mut self, index: u64, mut data: Vec<u8>, last: bool) -> FsResult<()> {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::fill(&mut nonce).map_err(|_| FsError::GeneralFailure)?;
+        let aad = chunk_aad(index, last);
+        let tag = self
+            .cipher
+            .encrypt_inout_detached(&Nonce::from(nonce), &aad, data.as_mut_slice().into())
+            .map_err(|_| FsError::GeneralFailure)?;
+        let end = index * CHUNK as u64 + data.len() as u64;
+
+        let mut buf = Vec::with_capacity(data.len() + OVERHEAD);
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&data);
+        buf.extend_from_slice(&tag);
+        self.file
+            .seek(SeekFrom::Start(index * STORED_CHUNK))
+            .await?;
+        self.file.write_bytes(Bytes::from(buf)).await?;
+        self.stored = self.stored.max(end);
+        Ok(())
+    }
+
+    // Write the current chunk to disk, if it was changed.
+    async fn store(&mut self) -> FsResult<()> {
+        let (index, data) = match self.chunk.as_mut() {
+            Some(chunk) if chunk.dirty => {
+                chunk.dirty = false;
+                (chunk.index, chunk.data.clone())
+            }
+            _ => return Ok(()),
+        };
+        let last = self.is_last(index);
+        self.write_chunk(index, data, last).await?;
+
+        if !last {
+            if self.sealed_last == Some(index) {
+                self.sealed_last = None;
+            }
+            return Ok(());
+        }
+        // the chunk that used to be the last one is not anymore.
+        if let Some(old) = self.sealed_last.filter(|&old| old != index) {
+            let data = self.read_chunk(old).await?;
+            self.sealed_last = None;
+            self.write_chunk(old, data, false).await?;
+        }
+        self.sealed_last = Some(index);
+        Ok(())
+    }
+
+    // Make chunk `index` the current chunk.
+    async fn load(&mut self, index: u64) -> FsResult<()> {
+        if matches!(&self.chunk, Some(chunk) if chunk.index == index) {
+            return Ok(());
+        }
+        self.store().await?;
+        let data = self.read_chunk(index).await?;
+        self.chunk = Some(Chunk {
+            index,
+            data,
+            dirty: false,
+        });
+        Ok(())
+    }
+
+    // Write `data` at the current position.
+    async fn write_at_pos(&mut self, mut data: &[u8]) -> FsResult<()> {
+        // a chunk written on the way must not be marked as the last one.
+        self.len = self.len.max(self.pos + data.len() as u64);
+        while !data.is_empty() {
+            self.load(self.pos / CHUNK as u64).await?;
+            let offset = (self.pos % CHUNK as u64) as usize;
+            let chunk = self.chunk.as_mut().unwrap();
+            let n = data.len().min(CHUNK - offset);
+            if chunk.data.len() < offset + n {
+                chunk.data.resize(offset + n, 0);
+            }
+            chunk.data[offset..offset + n].copy_from_slice(&data[..n]);
+            chunk.dirty = true;
+            self.pos += n as u64;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, data: Bytes) -> FsResult<()> {
+        if self.append {
+            self.pos = self.len;
+        }
+        // fill a hole before the data with zeroes.
+        if self.pos > self.len {
+            let pos = self.pos;
+            self.pos = self.len;
+            while self.pos < pos {
+                let n = (pos - self.pos).min(CHUNK as u64) as usize;
+                self.write_at_pos(&vec![0; n]).await?;
+            }
+        }
+        self.write_at_pos(&data).await
+    }
+
+    async fn read(&mut self, count: usize) -> FsResult<Bytes> {
+        if self.pos >= self.len || count == 0 {
+            return Ok(Bytes::new());
+        }
+        self.load(self.pos / CHUNK as u64).await?;
+        let offset = (self.pos % CHUNK as u64) as usize;
+        let data = &self.chunk.as_ref().unwrap().data;
+        let n = count.min(data.len().saturating_sub(offset));
+        let bytes = Bytes::copy_from_slice(&data[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(bytes)
+    }
+}
+
+impl DavFile for EncryptedFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.file
+            .metadata()
+            .map(|r| r.map(EncryptedMeta::boxed))
+            .boxed()
+    }
+
+    fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        let data = buf.copy_to_bytes(buf.remaining());
+        self.write(data).boxed()
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        self.write(buf).boxed()
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        self.read(count).boxed()
+    }
+
+    // only the plaintext position changes, the chunk it is in is read
+    // when it is needed.
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        let res = match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(FsError::GeneralFailure),
+        };
+        futures_util::future::ready(res).boxed()
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        async move {
+            self.store().await?;
+            self.file.flush().await
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{put, req, run};
+    use crate::DavHandler;
+
+    const KEY: [u8; KEY_LEN] = [7; KEY_LEN];
+
+    fn content(len: usize) -> String {
+        (0..len).map(|i| (b'a' + (i % 26) as u8) as char).collect()
+    }
+
+    async fn stored(fs: &MemFs, path: &str) -> Vec<u8> {
+        let path = DavPath::new(path).unwrap();
+        let mut file = fs.open(&path, OpenOptions::read()).await.unwrap();
+        let mut data = Vec::new();
+        loop {
+            let bytes = file.read_bytes(65536).await.unwrap();
+            if bytes.is_empty() {
+                return data;
+            }
+            data.extend_from_slice(&bytes);
+        }
+    }
+
+    #[test]
+    fn lengths() {
+        for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 3 * CHUNK + 5] {
+            let len = len as u64;
+            assert_eq!(plain_len(stored_len(len)), len);
+        }
+        assert_eq!(
+            stored_len(CHUNK as u64 + 1),
+            STORED_CHUNK + 1 + OVERHEAD as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let memfs = MemFs::new();
+        let dav = DavHandler::builder()
+            .filesystem(EncryptedFs::new(memfs.clone(), KEY))
+            .build_handler();
+
+        let data = content(2 * CHUNK + 1000);
+        put(&dav, "/file.txt", &data).await;
+        let (resp, body) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-length"], data.len().to_string());
+        assert!(body == data);
+
+        // what is stored is not the plaintext, and it is longer.
+        let disk = stored(&memfs, "/file.txt").await;
+        assert_eq!(disk.len() as u64, stored_len(data.len() as u64));
+        assert!(!disk.windows(16).any(|w| w == &data.as_bytes()[..16]));
+
+        // the same data again is stored under new nonces.
+        put(&dav, "/copy.txt", &data).await;
+        let copy = stored(&memfs, "/copy.txt").await;
+        assert_ne!(copy[..NONCE_LEN], disk[..NONCE_LEN]);
+        assert_ne!(copy, disk);
+
+        let r = req("PROPFIND", "/file.txt")
+            .header("Depth", "0")
+            .body(Body::empty())
+            .unwrap();
+        let (_, body) = run(&dav, r).await;
+        let len = format!("<D:getcontentlength>{}</D:getcontentlength>", data.len());
+        assert!(body.contains(&len), "{}", body);
+
+        // a shorter file replaces it.
+        put(&dav, "/file.txt", "short").await;
+        let (_, body) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "short");
+
+        // a different key cannot read it.
+        let other: Box<dyn DavFileSystem> = EncryptedFs::new(memfs, [8; KEY_LEN]);
+        let path = DavPath::new("/file.txt").unwrap();
+        let mut file = other.open(&path, OpenOptions::read()).await.unwrap();
+        assert!(file.read_bytes(5).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn seek() {
+        let memfs = MemFs::new();
+        let dav = DavHandler::builder()
+            .filesystem(EncryptedFs::new(memfs, KEY))
+            .build_handler();
+        let data = content(3 * CHUNK);
+        put(&dav, "/file.txt", &data).await;
+
+        // a range that starts in one chunk and ends in the next.
+        let (start, end) = (CHUNK + 100, 2 * CHUNK + 99);
+        let r = req("GET", "/file.txt").header("Range", format!("bytes={}-{}", start, end));
+        let (resp, body) = run(&dav, r.body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 206);
+        assert!(body == data[start..=end]);
+
+        // writes in the middle, and past the end.
+        let fs: Box<dyn DavFileSystem> = EncryptedFs::new(MemFs::new(), KEY);
+        let path = DavPath::new("/file").unwrap();
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        let mut file = fs.open(&path, oo).await.unwrap();
+        file.write_bytes(Bytes::from(data.clone())).await.unwrap();
+        file.seek(SeekFrom::Start(CHUNK as u64 - 2)).await.unwrap();
+        file.write_bytes(Bytes::from("XXXX")).await.unwrap();
+        file.seek(SeekFrom::Start(4 * CHUNK as u64)).await.unwrap();
+        file.write_bytes(Bytes::from("end")).await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+
+        let mut expected = data.into_bytes();
+        expected[CHUNK - 2..CHUNK + 2].copy_from_slice(b"XXXX");
+        expected.resize(4 * CHUNK, 0);
+        expected.extend_from_slice(b"end");
+        let meta = fs.metadata(&path).await.unwrap();
+        assert_eq!(meta.len(), expected.len() as u64);
+
+        let mut file = fs.open(&path, OpenOptions::read()).await.unwrap();
+        assert_eq!(
+            file.seek(SeekFrom::End(-3)).await.unwrap(),
+            4 * CHUNK as u64
+        );
+        assert_eq!(file.read_bytes(100).await.unwrap(), "end");
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut all = Vec::new();
+        loop {
+            let bytes = file.read_bytes(10000).await.unwrap();
+            if bytes.is_empty() {
+                break;
+            }
+            all.extend_from_slice(&bytes);
+        }
+        assert!(all == expected);
+    }
+
+    #[tokio::test]
+    async fn tampering() {
+        let memfs = MemFs::new();
+        let fs: Box<dyn DavFileSystem> = EncryptedFs::new(memfs.clone(), KEY);
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        put(&dav, "/file.txt", &content(2 * CHUNK)).await;
+
+        // flip one bit of the second chunk.
+        let path = DavPath::new("/file.txt").unwrap();
+        let mut disk = stored(&memfs, "/file.txt").await;
+        disk[STORED_CHUNK as usize + 100] ^= 1;
+        let mut oo = OpenOptions::write();
+        oo.truncate = true;
+        let mut file = memfs.open(&path, oo).await.unwrap();
+        file.write_bytes(Bytes::from(disk.clone())).await.unwrap();
+        file.flush().await.unwrap();
+
+        let mut file = fs.open(&path, OpenOptions::read()).await.unwrap();
+        assert_eq!(file.read_bytes(CHUNK).await.unwrap().len(), CHUNK);
+        assert!(file.read_bytes(CHUNK).await.is_err());
+
+        // cutting off the last chunk is noticed, too.
+        disk.truncate(STORED_CHUNK as usize);
+        let mut oo = OpenOptions::write();
+        oo.truncate = true;
+        let mut file = memfs.open(&path, oo).await.unwrap();
+        file.write_bytes(Bytes::from(disk)).await.unwrap();
+        file.flush().await.unwrap();
+        let mut file = fs.open(&path, OpenOptions::read()).await.unwrap();
+        assert!(file.read_bytes(CHUNK).await.is_err());
+    }
+}
@@ -9,11 +9,12 @@ pub(crate) type DavResult<T> = Result<T, DavError>;
 
 #[derive(Debug)]
 pub(crate) enum DavError {
-    XmlReadError,  // error reading/parsing xml
-    XmlParseError, // error interpreting xml
-    InvalidPath,   // error parsing path
-    IllegalPath,   // path not valid here
-    ForbiddenPath, // too many dotdots
+    XmlReadError,     // error reading/parsing xml
+    XmlParseError,    // error interpreting xml
+    XmlError(String), // unexpected xml, with an explanation
+    InvalidPath,      // error parsing path
+    IllegalPath,      // path not valid here
+    ForbiddenPath,    // too many dotdots
     UnknownDavMethod,
     ChanError,
     Utf8Error,
@@ -128,6 +129,7 @@ fn fserror_to_ioerror(e: FsError) -> io::Error {
         FsError::PathTooLong => io::Error::new(io::ErrorKind::Other, "PathTooLong"),
         FsError::TooLarge => io::Error::new(io::ErrorKind::Other, "TooLarge"),
         FsError::IsRemote => io::Error::new(io::ErrorKind::Other, "IsRemote"),
+        FsError::Unavailable(_) => io::Error::new(io::ErrorKind::Other, "Unavailable"),
     }
 }
 
@@ -153,6 +155,7 @@ fn fserror_to_status(e: &FsError) -> StatusCode {
         FsError::PathTooLong => StatusCode::URI_TOO_LONG,
         FsError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
         FsError::IsRemote => StatusCode::BAD_GATEWAY,
+        FsError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
     }
 }
 
@@ -161,6 +164,7 @@ impl DavError {
         match *self {
             DavError::XmlReadError => StatusCode::BAD_REQUEST,
             DavError::XmlParseError => StatusCode::BAD_REQUEST,
+            DavError::XmlError(_) => StatusCode::BAD_REQUEST,
             DavError::InvalidPath => StatusCode::BAD_REQUEST,
             DavError::IllegalPath => StatusCode::BAD_GATEWAY,
             DavError::ForbiddenPath => StatusCode::FORBIDDEN,
@@ -176,6 +180,27 @@ impl DavError {
         }
     }
 
+    // Text for the body of the error response, if any.
+    pub(crate) fn explanation(&self) -> Option<String> {
+        match *self {
+            DavError::XmlReadError => Some("malformed XML in request body".to_string()),
+            DavError::XmlParseError => Some("unexpected XML in request body".to_string()),
+            DavError::XmlError(ref msg) => Some(msg.clone()),
+            DavError::XmlReaderError(ref e) => {
+                Some(format!("malformed XML in request body: {}", e))
+            }
+            _ => None,
+        }
+    }
+
+    // Value for the Retry-After header, if any.
+    pub(crate) fn retry_after(&self) -> Option<u32> {
+        match *self {
+            DavError::FsError(FsError::Unavailable(secs)) => secs,
+            _ => None,
+        }
+    }
+
     pub(crate) fn must_close(&self) -> bool {
         !matches!(
             self,
@@ -11,7 +11,15 @@
 //! LOCK/UNLOCK always succeed, checking for locktokens in
 //! If: headers always succeeds, and nothing is every really locked.
 //!
-//! `FakeLs` implements such a fake locksystem.
+//! `FakeLs` implements such a fake locksystem:
+//!
+//! - LOCK returns a new token, with the lock in the `lockdiscovery`
+//!   of the response, and UNLOCK and refreshing a lock always succeed.
+//! - `check` always succeeds, so no request is ever blocked by a lock.
+//! - nothing is stored, so `lockdiscovery` in PROPFIND is always empty.
+//!
+//! Use it with [`DavConfig::locksystem`](crate::DavConfig::locksystem),
+//! or [`DavConfig::fake_locking`](crate::DavConfig::fake_locking).
 use std::time::{Duration, SystemTime};
 
 use uuid::Uuid;
@@ -125,3 +133,49 @@ impl DavLockSystem for FakeLs {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{put, req, run};
+    use crate::DavHandler;
+
+    const LOCKINFO: &str = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+
+    #[tokio::test]
+    async fn fake_locking() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .fake_locking()
+            .build_handler();
+        put(&dav, "/file.txt", "one").await;
+
+        let (resp, _) = run(&dav, req("OPTIONS", "/").body(Body::empty()).unwrap()).await;
+        assert!(resp.headers()["dav"].to_str().unwrap().contains('2'));
+        assert!(resp.headers()["allow"].to_str().unwrap().contains("LOCK"));
+
+        let lock = || req("LOCK", "/file.txt").body(Body::from(LOCKINFO)).unwrap();
+        let (resp, text) = run(&dav, lock()).await;
+        assert_eq!(resp.status(), 200);
+        let token = resp.headers()["lock-token"].to_str().unwrap().to_string();
+        let token = token.trim_matches(|c| c == '<' || c == '>');
+        assert!(text.contains("<D:lockdiscovery><D:activelock>"), "{}", text);
+        assert!(text.contains(token), "{}", text);
+
+        // another exclusive lock, and writes without a token, just work.
+        let (resp, text) = run(&dav, lock()).await;
+        assert_eq!(resp.status(), 200);
+        assert!(!text.contains(token), "{}", text);
+        let r = req("PUT", "/file.txt").body(Body::from("two")).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 204);
+        let r = req("DELETE", "/file.txt").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 204);
+
+        let r = req("UNLOCK", "/file.txt").header("Lock-Token", format!("<{}>", token));
+        assert_eq!(
+            run(&dav, r.body(Body::empty()).unwrap()).await.0.status(),
+            204
+        );
+    }
+}
@@ -3,9 +3,11 @@
 //! You only need this if you are going to implement your own
 //! filesystem backend. Otherwise, just use 'LocalFs' or 'MemFs'.
 //!
+use std::any::Any;
 use std::fmt::Debug;
 use std::io::SeekFrom;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use futures_util::{future, Future, FutureExt, Stream, TryFutureExt};
@@ -48,8 +50,14 @@ pub enum FsError {
     PathTooLong,
     /// The file being PUT is too large (413)
     TooLarge,
-    /// Trying to MOVE over a mount boundary (EXDEV) (502)
+    /// Trying to MOVE over a mount boundary (EXDEV) (502). Filesystems
+    /// that are made up of several backends return this from `rename`
+    /// and `copy` when the destination is on another backend.
     IsRemote,
+    /// The backend is temporarily unavailable (503). The client can try
+    /// again, after the number of seconds given here, if any. That is
+    /// sent in a `Retry-After` header.
+    Unavailable(Option<u32>),
 }
 /// The Result type.
 pub type FsResult<T> = std::result::Result<T, FsError>;
@@ -117,6 +125,9 @@ pub type FsFuture<'a, T> = Pin<Box<dyn Future<Output = FsResult<T>> + Send + 'a>
 /// Convenience alias for a boxed Stream.
 pub type FsStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
 
+/// Request-scoped context, see `DavConfig::context` and `DavFileSystem::with_context`.
+pub type DavContext = Arc<dyn Any + Send + Sync>;
+
 /// Used as argument to the read_dir() method.
 /// It is:
 ///
@@ -135,17 +146,51 @@ pub enum ReadDirMeta {
     None,
 }
 
+/// Returned by [`DavFileSystem::rename_ext`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameKind {
+    /// The filesystem moved the file or directory itself.
+    Native,
+    /// The filesystem cannot move between these two paths, for example
+    /// because they are on different mounts. Nothing was changed.
+    Unsupported,
+}
+
 /// The trait that defines a filesystem.
 pub trait DavFileSystem: Sync + Send + BoxCloneFs {
     /// Open a file.
     fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<Box<dyn DavFile>>;
 
+    /// Open a file, when the metadata of the file is already known.
+    ///
+    /// `meta` is only a hint, it was retrieved by `metadata()` just before
+    /// this call. Implementations can use it to skip a `stat`, for example
+    /// when `DavFile::metadata()` is called on the opened file.
+    ///
+    /// The default implementation ignores the hint and calls `open`.
+    fn open_with_meta<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+        meta: Box<dyn DavMetaData>,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        let _ = meta;
+        self.open(path, options)
+    }
+
     /// Perform read_dir.
+    ///
+    /// An entry whose name is known, but that cannot be read otherwise, can
+    /// be returned as an entry whose `metadata` fails: PROPFIND lists it
+    /// with the status of the error. When not even the name is known, the
+    /// stream can return an error, and go on with the other entries after
+    /// that. PROPFIND then lists the entries that could be read, and notes
+    /// in the `responsedescription` that the listing is incomplete.
     fn read_dir<'a>(
         &'a self,
         path: &'a DavPath,
         meta: ReadDirMeta,
-    ) -> FsFuture<FsStream<Box<dyn DavDirEntry>>>;
+    ) -> FsFuture<FsStream<FsResult<Box<dyn DavDirEntry>>>>;
 
     /// Return the metadata of a file or directory.
     fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<Box<dyn DavMetaData>>;
@@ -178,6 +223,22 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("remove_dir")
     }
 
+    /// Remove a directory and everything below it.
+    ///
+    /// Backends that can delete an entire subtree in one operation
+    /// can implement this, DELETE tries it first. If it returns
+    /// FsError::NotImplemented, DELETE walks the tree and removes
+    /// all entries one by one, and reports each entry that could not
+    /// be removed. Any other error is reported for the directory only,
+    /// so if the tree might not be removed completely, it is better to
+    /// return FsError::NotImplemented before removing anything.
+    ///
+    /// The default implementation returns FsError::NotImplemented.
+    #[allow(unused_variables)]
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        notimplemented_fut!("remove_dir_all")
+    }
+
     /// Remove a file.
     ///
     /// The default implementation returns FsError::NotImplemented.
@@ -199,12 +260,36 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("rename")
     }
 
+    /// Rename a file or directory, and report how it was done.
+    ///
+    /// Like `rename`, but a filesystem that cannot move between `from`
+    /// and `to` returns `Ok(RenameKind::Unsupported)` instead of an
+    /// error. MOVE can then fall back to copying and deleting, see
+    /// `DavConfig::cross_fs_fallback`.
+    ///
+    /// The default implementation calls `rename`, and maps
+    /// `FsError::IsRemote` to `RenameKind::Unsupported`.
+    fn rename_ext<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, RenameKind> {
+        Box::pin(async move {
+            match self.rename(from, to).await {
+                Ok(()) => Ok(RenameKind::Native),
+                Err(FsError::IsRemote) => Ok(RenameKind::Unsupported),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
     /// Copy a file
     ///
     /// Should also copy the DAV properties, if properties
     /// are implemented.
     ///
-    /// The default implementation returns FsError::NotImplemented.
+    /// An existing destination file is not removed before this is called.
+    /// So a backend that can tell that `to` already has the same content,
+    /// like a content-addressed store, can return without copying anything.
+    ///
+    /// The default implementation returns FsError::NotImplemented,
+    /// and COPY then reads the file and writes it to the destination.
     #[allow(unused_variables)]
     fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
         notimplemented_fut!("copy")
@@ -241,6 +326,13 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
 
     /// Patch the DAV properties of a node (add/remove props)
     ///
+    /// A PROPPATCH is atomic (RFC4918 9.2): if one of the changes fails,
+    /// none of them may be applied. An implementation that can, applies
+    /// all or nothing, and reports `424 Failed Dependency` for the changes
+    /// it did not make because another one failed. If some changes were
+    /// made anyway, the handler puts back the old values with a second
+    /// `patch_props` call.
+    ///
     /// The default implementation returns FsError::NotImplemented.
     #[allow(unused_variables)]
     fn patch_props<'a>(
@@ -267,6 +359,21 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
         notimplemented_fut!("get_prop`")
     }
 
+    /// Return a filesystem for a request with this context.
+    ///
+    /// If a context was set with `DavConfig::context` (usually per
+    /// request, via `DavHandler::handle_with`), this is called once at
+    /// the start of the request, and the returned filesystem is
+    /// used for the rest of it. That way a backend can, for example,
+    /// serve a different root directory for every user or tenant.
+    /// Use `context.downcast_ref()` to get at the actual context type.
+    ///
+    /// The default implementation ignores the context and returns a clone of `self`.
+    #[allow(unused_variables)]
+    fn with_context(&self, context: &DavContext) -> Box<dyn DavFileSystem> {
+        self.box_clone()
+    }
+
     /// Get quota of this filesystem (used/total space).
     ///
     /// The first value returned is the amount of space used,
@@ -278,6 +385,57 @@ pub trait DavFileSystem: Sync + Send + BoxCloneFs {
     fn get_quota(&self) -> FsFuture<(u64, Option<u64>)> {
         notimplemented_fut!("get_quota`")
     }
+
+    /// The number of bytes that GET asks for in one `DavFile::read_bytes` call.
+    ///
+    /// Backends with a high latency per request can ask for larger chunks.
+    /// `DavConfig::read_buf_size` overrides this.
+    ///
+    /// The default implementation returns 16 KiB.
+    fn preferred_read_chunk_size(&self) -> usize {
+        16384
+    }
+
+    /// Get a URL where the content of a file can be retrieved directly.
+    ///
+    /// For example a signed URL of an object store. If this returns
+    /// `Some(url)`, GET and HEAD send a `302 Found` redirect to that URL
+    /// instead of the content of the file. Requests with a `Range` header, and
+    /// files whose content is transformed, are still served directly.
+    ///
+    /// The default implementation returns `None`.
+    #[allow(unused_variables)]
+    fn get_redirect_url<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        Box::pin(future::ready(Ok(None)))
+    }
+
+    /// Return the ETag of a collection.
+    ///
+    /// A filesystem that can tell cheaply when anything below a
+    /// collection changed can return a tag that changes when it does.
+    /// PROPFIND uses it for the `DAV:getetag` property of the
+    /// collection, so that sync clients can skip unchanged subtrees.
+    ///
+    /// The default implementation returns `None`, and the ETag of the
+    /// collection is taken from its metadata, see `DavMetaData::etag`.
+    #[allow(unused_variables)]
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        Box::pin(future::ready(Ok(None)))
+    }
+
+    /// Is this path a special node, like a mount point?
+    ///
+    /// Special nodes cannot be deleted or moved away; DELETE and MOVE of
+    /// such a path, and overwriting it with COPY or MOVE, are refused with
+    /// `403 Forbidden`. Only the path in the request is checked, not the
+    /// members of a collection that is deleted recursively. If this
+    /// returns an error, the request fails with that error.
+    ///
+    /// The default implementation returns `false`.
+    #[allow(unused_variables)]
+    fn is_special<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, bool> {
+        Box::pin(future::ready(Ok(false)))
+    }
 }
 
 // BoxClone trait.
@@ -337,12 +495,21 @@ pub trait DavFile: Debug + Send + Sync {
     fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>>;
     fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<()>;
     fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<()>;
+    /// Read at most `count` bytes. Empty `Bytes` means end of file.
     fn read_bytes(&mut self, count: usize) -> FsFuture<bytes::Bytes>;
     fn seek(&mut self, pos: SeekFrom) -> FsFuture<u64>;
     fn flush(&mut self) -> FsFuture<()>;
     fn redirect_url(&mut self) -> FsFuture<Option<String>> {
         future::ready(Ok(None)).boxed()
     }
+
+    /// Can `seek` be used to serve byte ranges. If not, GET always
+    /// sends the entire file, and advertises `Accept-Ranges: none`.
+    ///
+    /// Default: `true`.
+    fn is_seekable(&self) -> bool {
+        true
+    }
 }
 
 /// File metadata. Basically type, length, and some timestamps.
@@ -407,6 +574,25 @@ pub trait DavMetaData: Debug + BoxCloneMd + Send + Sync {
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Extra properties of the file or directory, like the storage class
+    /// or user metadata of an object store. They are returned by PROPFIND
+    /// for `allprop`, `propname`, and when asked for by name. They cannot
+    /// be changed with PROPPATCH. `xml` is the entire property element,
+    /// like for [`DavFileSystem::get_props`]. Default: none.
+    fn extra_props(&self) -> Vec<DavProp> {
+        Vec::new()
+    }
+
+    /// If this is a redirect reference (RFC4437), the URL it points to.
+    ///
+    /// A redirect reference has the `DAV:redirectref` resourcetype and a
+    /// `DAV:reftarget` property. GET, HEAD, PROPFIND and PROPPATCH of it
+    /// are answered with `302 Found` to the target, unless the request has
+    /// an `Apply-To-Redirect-Ref: T` header. Default: `None`.
+    fn redirect_ref(&self) -> Option<String> {
+        None
+    }
 }
 
 // generic Clone, calls implementation-specific box_clone().
@@ -437,7 +623,10 @@ pub struct OpenOptions {
     pub read: bool,
     /// open for writing
     pub write: bool,
-    /// open in write-append mode
+    /// open in write-append mode. Implies `write`. The file is
+    /// positioned at its end, and every write goes to the end of
+    /// the file, even with other writers appending at the same time.
+    /// With `create`, a missing file is created.
     pub append: bool,
     /// truncate file first when writing
     pub truncate: bool,
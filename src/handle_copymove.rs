@@ -26,6 +26,44 @@ async fn add_status<'a>(
 }
 
 impl crate::DavInner {
+    // Copy a file by reading it and writing it out again, for filesystems
+    // that do not implement `copy`. The data goes through in chunks of
+    // `preferred_read_chunk_size`, so the file is never in memory as a whole.
+    async fn copy_file(&self, source: &DavPath, dest: &DavPath, len: u64) -> FsResult<()> {
+        let mut src = self.fs.open(source, OpenOptions::read()).await?;
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        oo.truncate = true;
+        oo.size = Some(len);
+        let mut dst = self.fs.open(dest, oo).await?;
+        let chunk_size = self.fs.preferred_read_chunk_size().max(1);
+        loop {
+            let buf = src.read_bytes(chunk_size).await?;
+            if buf.is_empty() {
+                break;
+            }
+            dst.write_bytes(buf).await?;
+        }
+        dst.flush().await?;
+
+        // like `DavFileSystem::copy`, copy the properties as well.
+        if self.fs.have_props(source).await {
+            let props = self.fs.get_props(source, true).await?;
+            if !props.is_empty() {
+                let patch = props.into_iter().map(|p| (true, p)).collect();
+                self.fs.patch_props(dest, patch).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Copy `source` to `dest`, recursively.
+    //
+    // Nothing here is spawned, the copy runs as part of the request's
+    // future (or of the response body stream, once the first error has
+    // been sent). So if the client goes away and the server drops the
+    // request, the copy stops at the next entry. What was copied until
+    // then stays in place, like for a copy that failed halfway.
     pub(crate) fn do_copy<'a>(
         &'a self,
         source: &'a DavPath,
@@ -49,8 +87,20 @@ impl crate::DavInner {
 
             // if it's a file we can overwrite it.
             if !meta.is_dir() {
-                return match self.fs.copy(source, dest).await {
-                    Ok(_) => Ok(()),
+                let fallback = self.cross_fs_fallback.unwrap_or(false);
+                let res = match self.fs.copy(source, dest).await {
+                    Err(FsError::NotImplemented) => self.copy_file(source, dest, meta.len()).await,
+                    Err(FsError::IsRemote) if fallback => {
+                        debug!("do_copy: {} -> {}: copying the data", source, dest);
+                        self.copy_file(source, dest, meta.len()).await
+                    }
+                    res => res,
+                };
+                return match res {
+                    Ok(_) => {
+                        self.copy_mtime(&*meta, dest).await;
+                        Ok(())
+                    }
                     Err(e) => {
                         debug!("do_copy: self.fs.copy error: {:?}", e);
                         add_status(multierror, source, e).await
@@ -70,6 +120,7 @@ impl crate::DavInner {
 
             // only recurse when Depth > 0.
             if depth == Depth::Zero {
+                self.copy_mtime(&*meta, dest).await;
                 return Ok(());
             }
 
@@ -85,6 +136,13 @@ impl crate::DavInner {
             // Last seen error is returned from function.
             let mut retval = Ok::<_, DavError>(());
             while let Some(dirent) = entries.next().await {
+                let dirent = match dirent {
+                    Ok(dirent) => dirent,
+                    Err(e) => {
+                        retval = add_status(multierror, source, e).await;
+                        continue;
+                    }
+                };
                 // NOTE: dirent.metadata() behaves like symlink_metadata()
                 let meta = match dirent.metadata().await {
                     Ok(meta) => meta,
@@ -109,11 +167,26 @@ impl crate::DavInner {
                 }
             }
 
+            // after the members, because adding those changed it.
+            self.copy_mtime(&*meta, dest).await;
             retval
         }
         .boxed()
     }
 
+    // Set the modification time of `dest` to that of the source, if
+    // `preserve_mtime_on_copy` is set. Failing to do so is not an error.
+    async fn copy_mtime(&self, meta: &dyn DavMetaData, dest: &DavPath) {
+        if !self.preserve_mtime_on_copy.unwrap_or(false) {
+            return;
+        }
+        if let Ok(tm) = meta.modified() {
+            if let Err(e) = self.fs.set_modified(dest, tm).await {
+                debug!("do_copy: set_modified({}) error: {:?}", dest, e);
+            }
+        }
+    }
+
     // Right now we handle MOVE with a simple RENAME. RFC4918 #9.9.2 talks
     // about "partially failed moves", which means that we might have to
     // try to move directories with increasing granularity to move as much
@@ -127,16 +200,37 @@ impl crate::DavInner {
     // .. so for perfect compliance we might have to process all resources
     // one-by-one anyway. But seriously, who cares.
     //
+    // Only when the filesystem cannot rename between the two paths at
+    // all (like across mounts) and `cross_fs_fallback` is set, the move
+    // is done as a copy followed by a delete of the source.
+    //
     pub(crate) async fn do_move<'a>(
         &'a self,
         source: &'a DavPath,
         dest: &'a DavPath,
         multierror: &'a mut MultiError,
     ) -> DavResult<()> {
-        if let Err(e) = self.fs.rename(source, dest).await {
-            add_status(multierror, source, e).await
-        } else {
-            Ok(())
+        match self.fs.rename_ext(source, dest).await {
+            Ok(RenameKind::Native) => {
+                debug!("do_move: {} -> {}: native rename", source, dest);
+                Ok(())
+            }
+            Ok(RenameKind::Unsupported) if self.cross_fs_fallback.unwrap_or(false) => {
+                debug!("do_move: {} -> {}: copy and delete", source, dest);
+                self.do_copy(source, dest, dest, Depth::Infinity, multierror)
+                    .await?;
+                let meta = match self.fs.symlink_metadata(source).await {
+                    Ok(meta) => meta,
+                    Err(e) => return add_status(multierror, source, e).await,
+                };
+                self.delete_items(multierror, Depth::Infinity, meta, source)
+                    .await
+            }
+            Ok(RenameKind::Unsupported) => {
+                debug!("do_move: {} -> {}: cannot rename", source, dest);
+                add_status(multierror, source, FsError::IsRemote).await
+            }
+            Err(e) => add_status(multierror, source, e).await,
         }
     }
 
@@ -150,7 +244,8 @@ impl crate::DavInner {
             .headers()
             .typed_get::<davheaders::Overwrite>()
             .map_or(true, |o| o.0);
-        let depth = match req.headers().typed_get::<Depth>() {
+        let depth = req.headers().typed_try_get::<Depth>();
+        let depth = match depth.map_err(|_| DavError::Status(StatusCode::BAD_REQUEST))? {
             Some(Depth::Infinity) | None => Depth::Infinity,
             Some(Depth::Zero) if method == DavMethod::Copy => Depth::Zero,
             _ => return Err(StatusCode::BAD_REQUEST.into()),
@@ -161,10 +256,22 @@ impl crate::DavInner {
             Some(dest) => DavPath::from_str_and_prefix(&dest.0, &self.prefix)?,
             None => return Err(StatusCode::BAD_REQUEST.into()),
         };
+        if self.is_hidden(&dest) || self.is_nonportable(&dest) {
+            return Err(StatusCode::FORBIDDEN.into());
+        }
 
         // for MOVE, tread with care- if the path ends in "/" but it actually
         // is a symlink, we want to move the symlink, not what it points to.
         let mut path = self.path(req);
+        if method == DavMethod::Move {
+            if let Some(res) = self.protect_root(&path) {
+                return Ok(res);
+            }
+        }
+        // overwriting the root would delete it first.
+        if let Some(res) = self.protect_root(&dest) {
+            return Ok(res);
+        }
         let meta = if method == DavMethod::Move {
             let meta = self.fs.symlink_metadata(&path).await?;
             if meta.is_symlink() {
@@ -206,12 +313,28 @@ impl crate::DavInner {
             return Err(StatusCode::PRECONDITION_FAILED.into());
         }
 
+        // file onto a collection, or collection onto a file? Then the
+        // destination must be deleted first (RFC4918 9.8.4, 9.9.3),
+        // regardless of Depth.
+        let type_mismatch = exists && meta.is_dir() == dest_is_file;
+
         // check if source == dest
         if path == dest {
             return Err(StatusCode::FORBIDDEN.into());
         }
 
-        // check If and If-* headers for source URL
+        // special nodes, like mount points, cannot be moved or overwritten.
+        if method == DavMethod::Move && self.fs.is_special(&path).await? {
+            return Err(StatusCode::FORBIDDEN.into());
+        }
+        if exists && self.fs.is_special(&dest).await? {
+            return Err(StatusCode::FORBIDDEN.into());
+        }
+
+        // check If and If-* headers for source URL. the If-* headers are
+        // about the request-URI (RFC7232), a client that wants to make
+        // sure the destination did not change uses a tagged list in the
+        // If header: `If: <dest> (["etag"])`.
         let tokens = match if_match_get_tokens(req, Some(&meta), &self.fs, &self.ls, &path).await {
             Ok(t) => t,
             Err(s) => return Err(s.into()),
@@ -225,13 +348,13 @@ impl crate::DavInner {
             let principal = self.principal.as_deref();
             if method == DavMethod::Move {
                 // for MOVE check if source path is locked
-                if let Err(_l) = locksystem.check(&path, principal, false, true, t.clone()) {
-                    return Err(StatusCode::LOCKED.into());
+                if let Err(l) = locksystem.check(&path, principal, false, true, t.clone()) {
+                    return Ok(self.locked(&l));
                 }
             }
             // for MOVE and COPY check if destination is locked
-            if let Err(_l) = locksystem.check(&dest, principal, false, true, t) {
-                return Err(StatusCode::LOCKED.into());
+            if let Err(l) = locksystem.check(&dest, principal, false, true, t) {
+                return Ok(self.locked(&l));
             }
         }
 
@@ -242,7 +365,8 @@ impl crate::DavInner {
                 let mut multierror = MultiError::new(tx);
 
                 // see if we need to delete the destination first.
-                if overwrite && exists && depth != Depth::Zero && !dest_is_file {
+                if overwrite && exists && (type_mismatch || (depth != Depth::Zero && !dest_is_file))
+                {
                     trace!("handle_copymove: deleting destination {}", dest);
                     if self
                         .delete_items(&mut multierror, Depth::Infinity, dmeta.unwrap(), &dest)
@@ -251,10 +375,6 @@ impl crate::DavInner {
                     {
                         return Ok(());
                     }
-                    // should really do this per item, in case the delete partially fails. See TODO.md
-                    if let Some(ref locksystem) = self.ls {
-                        let _ = locksystem.delete(&dest);
-                    }
                 }
 
                 // COPY or MOVE.
@@ -292,3 +412,560 @@ impl crate::DavInner {
         multi_error(req_path, items).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{mkcol, put, req, run};
+    use crate::DavHandler;
+
+    async fn setup() -> DavHandler {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+        mkcol(&dav, "/dir/").await;
+        put(&dav, "/dir/inner.txt", "inner").await;
+        mkcol(&dav, "/other/").await;
+        put(&dav, "/other/sub.txt", "sub").await;
+        dav
+    }
+
+    async fn copy(dav: &DavHandler, from: &str, to: &str, overwrite: &str) -> u16 {
+        let r = req("COPY", from)
+            .header("Destination", to)
+            .header("Overwrite", overwrite)
+            .body(Body::empty())
+            .unwrap();
+        run(dav, r).await.0.status().as_u16()
+    }
+
+    async fn get(dav: &DavHandler, path: &str) -> (u16, String) {
+        let (resp, body) = run(dav, req("GET", path).body(Body::empty()).unwrap()).await;
+        (resp.status().as_u16(), body)
+    }
+
+    #[tokio::test]
+    async fn copy_file_onto_collection() {
+        let dav = setup().await;
+        assert_eq!(copy(&dav, "/file.txt", "/dir/", "F").await, 412);
+        assert_eq!(get(&dav, "/dir/inner.txt").await.0, 200);
+
+        assert_eq!(copy(&dav, "/file.txt", "/dir", "T").await, 204);
+        assert_eq!(get(&dav, "/dir").await, (200, "hello".to_string()));
+    }
+
+    #[tokio::test]
+    async fn copy_collection_onto_file() {
+        let dav = setup().await;
+        assert_eq!(copy(&dav, "/other/", "/file.txt", "F").await, 412);
+        assert_eq!(get(&dav, "/file.txt").await, (200, "hello".to_string()));
+
+        assert_eq!(copy(&dav, "/other/", "/file.txt", "T").await, 204);
+        assert_eq!(
+            get(&dav, "/file.txt/sub.txt").await,
+            (200, "sub".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn move_root() {
+        let dav = setup().await;
+        let r = req("MOVE", "/")
+            .header("Destination", "/moved/")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(&dav, r).await;
+        assert_eq!(resp.status(), 403);
+        assert!(body.contains("cannot-modify-protected-property"));
+        assert_eq!(get(&dav, "/file.txt").await, (200, "hello".to_string()));
+        assert_eq!(get(&dav, "/moved/file.txt").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn copy_onto_root() {
+        let dav = setup().await;
+        for method in ["COPY", "MOVE"] {
+            let r = req(method, "/other/")
+                .header("Destination", "/")
+                .header("Overwrite", "T")
+                .body(Body::empty())
+                .unwrap();
+            let (resp, body) = run(&dav, r).await;
+            assert_eq!(resp.status(), 403);
+            assert!(body.contains("cannot-modify-protected-property"));
+        }
+        assert_eq!(get(&dav, "/file.txt").await, (200, "hello".to_string()));
+        assert_eq!(get(&dav, "/dir/inner.txt").await.0, 200);
+        assert_eq!(get(&dav, "/sub.txt").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn copy_cancelled() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        // Copying a file takes a while.
+        #[derive(Clone)]
+        struct SlowFs(Box<MemFs>, Arc<AtomicUsize>);
+
+        #[async_trait]
+        impl ForwardFs for SlowFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                self.1.fetch_add(1, Ordering::SeqCst);
+                self.0.copy(from, to).await
+            }
+        }
+
+        let copies = Arc::new(AtomicUsize::new(0));
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(SlowFs(MemFs::new(), copies.clone())))
+            .build_handler();
+        mkcol(&dav, "/dir/").await;
+        for i in 0..100 {
+            put(&dav, &format!("/dir/{}.txt", i), "x").await;
+        }
+
+        // the client gives up, and the request is dropped.
+        let r = req("COPY", "/dir/")
+            .header("Destination", "/copy/")
+            .body(Body::empty())
+            .unwrap();
+        let res = tokio::time::timeout(Duration::from_millis(100), dav.handle(r)).await;
+        assert!(res.is_err());
+
+        let n = copies.load(Ordering::SeqCst);
+        assert!(n > 0 && n < 100, "{}", n);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(copies.load(Ordering::SeqCst), n);
+    }
+
+    #[tokio::test]
+    async fn copy_fallback() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use std::sync::{Arc, Mutex};
+
+        // Has no `copy`, and records the largest read and write.
+        #[derive(Clone)]
+        struct CountFs(Box<MemFs>, Arc<Mutex<(usize, usize)>>);
+
+        #[derive(Debug)]
+        struct CountFile(Box<dyn DavFile>, Arc<Mutex<(usize, usize)>>);
+
+        impl DavFile for CountFile {
+            fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+                self.0.metadata()
+            }
+            fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'_, ()> {
+                let mut max = self.1.lock().unwrap();
+                max.1 = max.1.max(buf.remaining());
+                self.0.write_buf(buf)
+            }
+            fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<'_, ()> {
+                let mut max = self.1.lock().unwrap();
+                max.1 = max.1.max(buf.len());
+                self.0.write_bytes(buf)
+            }
+            fn read_bytes(&mut self, count: usize) -> FsFuture<'_, bytes::Bytes> {
+                let mut max = self.1.lock().unwrap();
+                max.0 = max.0.max(count);
+                self.0.read_bytes(count)
+            }
+            fn seek(&mut self, pos: std::io::SeekFrom) -> FsFuture<'_, u64> {
+                self.0.seek(pos)
+            }
+            fn flush(&mut self) -> FsFuture<'_, ()> {
+                self.0.flush()
+            }
+        }
+
+        #[async_trait]
+        impl ForwardFs for CountFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn open(
+                &self,
+                path: &DavPath,
+                options: OpenOptions,
+            ) -> FsResult<Box<dyn DavFile>> {
+                let file = self.0.open(path, options).await?;
+                Ok(Box::new(CountFile(file, self.1.clone())))
+            }
+
+            // No native copy, so COPY goes through the files.
+            async fn copy(&self, _from: &DavPath, _to: &DavPath) -> FsResult<()> {
+                Err(FsError::NotImplemented)
+            }
+
+            fn preferred_read_chunk_size(&self) -> usize {
+                1000
+            }
+        }
+
+        let max = Arc::new(Mutex::new((0, 0)));
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(CountFs(MemFs::new(), max.clone())))
+            .build_handler();
+        let data = "0123456789".repeat(100_000);
+        put(&dav, "/big.txt", &data).await;
+        let r = req("PROPPATCH", "/big.txt")
+            .body(Body::from(
+                r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x"><D:set><D:prop><X:color>red</X:color></D:prop></D:set></D:propertyupdate>"#,
+            ))
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 207);
+
+        *max.lock().unwrap() = (0, 0);
+        assert_eq!(copy(&dav, "/big.txt", "/copy.txt", "F").await, 201);
+        assert_eq!(*max.lock().unwrap(), (1000, 1000));
+        assert_eq!(get(&dav, "/copy.txt").await, (200, data));
+
+        let r = req("PROPFIND", "/copy.txt")
+            .header("Depth", "0")
+            .body(Body::from(
+                r#"<D:propfind xmlns:D="DAV:"><D:prop><X:color xmlns:X="urn:x"/></D:prop></D:propfind>"#,
+            ))
+            .unwrap();
+        let (_, body) = run(&dav, r).await;
+        assert!(body.contains(">red<"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn move_across_mounts() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+
+        // Every top-level collection is a different mount.
+        #[derive(Clone)]
+        struct MountFs(Box<MemFs>);
+
+        fn mount(path: &DavPath) -> Vec<u8> {
+            path.as_bytes()
+                .split(|&c| c == b'/')
+                .nth(1)
+                .unwrap()
+                .to_vec()
+        }
+
+        #[async_trait]
+        impl ForwardFs for MountFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+                if mount(from) != mount(to) {
+                    return Err(FsError::IsRemote);
+                }
+                self.0.rename(from, to).await
+            }
+
+            async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+                if mount(from) != mount(to) {
+                    return Err(FsError::IsRemote);
+                }
+                self.0.copy(from, to).await
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(MountFs(MemFs::new())))
+            .build_handler();
+        mkcol(&dav, "/one/").await;
+        mkcol(&dav, "/two/").await;
+        put(&dav, "/one/file.txt", "hello").await;
+
+        for method in ["MOVE", "COPY"] {
+            let r = req(method, "/one/file.txt")
+                .header("Destination", "/two/file.txt")
+                .body(Body::empty())
+                .unwrap();
+            assert_eq!(run(&dav, r).await.0.status(), 502, "{}", method);
+        }
+        assert_eq!(get(&dav, "/one/file.txt").await.0, 200);
+        assert_eq!(get(&dav, "/two/file.txt").await.0, 404);
+
+        // within a mount, it works.
+        let r = req("MOVE", "/one/file.txt")
+            .header("Destination", "/one/moved.txt")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn copy_same_content() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // Does not copy files that already have the same content.
+        #[derive(Clone)]
+        struct DedupFs(Box<MemFs>, Arc<AtomicUsize>);
+
+        impl DedupFs {
+            async fn content(&self, path: &DavPath) -> Option<bytes::Bytes> {
+                let mut file = self.0.open(path, OpenOptions::read()).await.ok()?;
+                file.read_bytes(1000).await.ok()
+            }
+        }
+
+        #[async_trait]
+        impl ForwardFs for DedupFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+                if self.content(to).await.is_some()
+                    && self.content(from).await == self.content(to).await
+                {
+                    return Ok(());
+                }
+                self.1.fetch_add(1, Ordering::SeqCst);
+                self.0.copy(from, to).await
+            }
+        }
+
+        let copied = Arc::new(AtomicUsize::new(0));
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(DedupFs(MemFs::new(), copied.clone())))
+            .build_handler();
+        put(&dav, "/a.txt", "same").await;
+        put(&dav, "/b.txt", "same").await;
+        put(&dav, "/c.txt", "other").await;
+
+        assert_eq!(copy(&dav, "/a.txt", "/b.txt", "T").await, 204);
+        assert_eq!(copied.load(Ordering::SeqCst), 0);
+        assert_eq!(copy(&dav, "/a.txt", "/c.txt", "T").await, 204);
+        assert_eq!(copied.load(Ordering::SeqCst), 1);
+        assert_eq!(get(&dav, "/c.txt").await, (200, "same".to_string()));
+    }
+
+    #[tokio::test]
+    async fn move_locked() {
+        const LOCKINFO: &str = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(crate::memls::MemLs::new())
+            .build_handler();
+        put(&dav, "/src.txt", "source").await;
+        put(&dav, "/other.txt", "other").await;
+        put(&dav, "/dest.txt", "dest").await;
+        let lock = |path: &'static str| {
+            let r = req("LOCK", path).body(Body::from(LOCKINFO)).unwrap();
+            run(&dav, r)
+        };
+        let mv = |from: &'static str, to: &'static str, tokens: Option<String>| {
+            let mut r = req("MOVE", from).header("Destination", to);
+            if let Some(t) = tokens {
+                r = r.header("If", t);
+            }
+            run(&dav, r.body(Body::empty()).unwrap())
+        };
+        let token =
+            |resp: http::Response<()>| resp.headers()["Lock-Token"].to_str().unwrap().to_string();
+        let src_token = token(lock("/src.txt").await.0);
+        let dest_token = token(lock("/dest.txt").await.0);
+
+        // a locked source.
+        let (resp, body) = mv("/src.txt", "/new.txt", None).await;
+        assert_eq!(resp.status(), 423);
+        assert!(
+            body.contains("<D:lock-token-submitted><D:href>/src.txt</D:href>"),
+            "{}",
+            body
+        );
+        // the token of another lock does not match the source at all.
+        let (resp, _) = mv("/src.txt", "/new.txt", Some(format!("({})", dest_token))).await;
+        assert_eq!(resp.status(), 412);
+
+        // a locked destination.
+        let (resp, body) = mv("/other.txt", "/dest.txt", None).await;
+        assert_eq!(resp.status(), 423);
+        assert!(
+            body.contains("<D:lock-token-submitted><D:href>/dest.txt</D:href>"),
+            "{}",
+            body
+        );
+
+        // both locked, both tokens needed.
+        let (resp, body) = mv("/src.txt", "/dest.txt", Some(format!("({})", src_token))).await;
+        assert_eq!(resp.status(), 423);
+        assert!(body.contains("<D:href>/dest.txt</D:href>"), "{}", body);
+        let both = format!("</src.txt> ({}) </dest.txt> ({})", src_token, dest_token);
+        let (resp, _) = mv("/src.txt", "/dest.txt", Some(both)).await;
+        assert_eq!(resp.status(), 204);
+        let (_, body) = run(&dav, req("GET", "/dest.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "source");
+    }
+
+    #[tokio::test]
+    async fn move_fallback() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+
+        // "/ro/" refuses renames, other top-level collections are mounts.
+        #[derive(Clone)]
+        struct SplitFs(Box<MemFs>);
+
+        fn mount(path: &DavPath) -> Vec<u8> {
+            path.as_bytes()
+                .split(|&c| c == b'/')
+                .nth(1)
+                .unwrap()
+                .to_vec()
+        }
+
+        #[async_trait]
+        impl ForwardFs for SplitFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+                if mount(from) == b"ro" {
+                    return Err(FsError::Forbidden);
+                }
+                if mount(from) != mount(to) {
+                    return Err(FsError::IsRemote);
+                }
+                self.0.rename(from, to).await
+            }
+
+            async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+                if mount(from) != mount(to) {
+                    return Err(FsError::IsRemote);
+                }
+                self.0.copy(from, to).await
+            }
+        }
+
+        let fs = SplitFs(MemFs::new());
+        let p = DavPath::new("/one/x").unwrap();
+        let q = DavPath::new("/two/x").unwrap();
+        assert_eq!(
+            DavFileSystem::rename_ext(&fs, &p, &q).await,
+            Ok(RenameKind::Unsupported)
+        );
+        let q = DavPath::new("/one/y").unwrap();
+        assert_eq!(
+            DavFileSystem::rename_ext(&fs, &p, &q).await,
+            Err(FsError::NotFound)
+        );
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(fs))
+            .cross_fs_fallback(true)
+            .build_handler();
+        mkcol(&dav, "/one/").await;
+        mkcol(&dav, "/one/dir/").await;
+        mkcol(&dav, "/two/").await;
+        mkcol(&dav, "/ro/").await;
+        put(&dav, "/one/file.txt", "hello").await;
+        put(&dav, "/one/dir/inner.txt", "inner").await;
+        put(&dav, "/ro/file.txt", "stays").await;
+
+        let mv = |from: &str, to: &str| {
+            req("MOVE", from)
+                .header("Destination", to)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // IsRemote: copied and deleted.
+        let r = mv("/one/file.txt", "/two/file.txt");
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+        assert_eq!(get(&dav, "/one/file.txt").await.0, 404);
+        assert_eq!(get(&dav, "/two/file.txt").await, (200, "hello".to_string()));
+
+        let r = mv("/one/dir/", "/two/dir/");
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+        assert_eq!(get(&dav, "/one/dir/inner.txt").await.0, 404);
+        assert_eq!(get(&dav, "/two/dir/inner.txt").await.1, "inner");
+
+        // any other error: no fallback.
+        let r = mv("/ro/file.txt", "/two/other.txt");
+        assert_eq!(run(&dav, r).await.0.status(), 403);
+        assert_eq!(get(&dav, "/ro/file.txt").await.1, "stays");
+        assert_eq!(get(&dav, "/two/other.txt").await.0, 404);
+
+        // COPY across mounts streams the data.
+        let r = req("COPY", "/two/file.txt")
+            .header("Destination", "/one/copy.txt")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+        assert_eq!(get(&dav, "/one/copy.txt").await.1, "hello");
+    }
+
+    #[tokio::test]
+    async fn preserve_mtime_on_copy() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use std::time::{Duration, SystemTime};
+
+        // Has no `copy`, so a copied file gets a new modification time.
+        #[derive(Clone)]
+        struct NoCopyFs(Box<MemFs>);
+
+        #[async_trait]
+        impl ForwardFs for NoCopyFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn copy(&self, _from: &DavPath, _to: &DavPath) -> FsResult<()> {
+                Err(FsError::NotImplemented)
+            }
+        }
+
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        for preserve in [false, true] {
+            let memfs = MemFs::new();
+            let dav = DavHandler::builder()
+                .filesystem(Box::new(NoCopyFs(memfs.clone())))
+                .preserve_mtime_on_copy(preserve)
+                .build_handler();
+            mkcol(&dav, "/dir/").await;
+            put(&dav, "/dir/file.txt", "data").await;
+            for path in ["/dir/file.txt", "/dir/"] {
+                let path = DavPath::new(path).unwrap();
+                memfs.set_modified(&path, old).await.unwrap();
+            }
+
+            assert_eq!(copy(&dav, "/dir/", "/copy/", "F").await, 201);
+            assert_eq!(copy(&dav, "/dir/file.txt", "/copy.txt", "F").await, 201);
+            for path in ["/copy/", "/copy/file.txt", "/copy.txt"] {
+                let meta = memfs.metadata(&DavPath::new(path).unwrap()).await;
+                let mtime = meta.unwrap().modified().unwrap();
+                assert_eq!(mtime == old, preserve, "{}", path);
+            }
+        }
+    }
+}
@@ -4,7 +4,7 @@ use http::{Request, Response, StatusCode};
 
 use crate::async_stream::AsyncStream;
 use crate::body::Body;
-use crate::conditional::if_match_get_tokens;
+use crate::conditional::{if_match_get_tokens, if_match_missing};
 use crate::davheaders::Depth;
 use crate::davpath::DavPath;
 use crate::errors::*;
@@ -35,6 +35,17 @@ async fn dir_status<'a>(res: &'a mut MultiError, path: &'a DavPath, e: FsError)
 }
 
 impl crate::DavInner {
+    // A resource is gone, so are the locks on it and below it. This
+    // is done per resource, so that when deleting a collection fails
+    // halfway, the locks on what is left stay in place.
+    fn forget_locks(&self, path: &DavPath) {
+        if let Some(ref locksystem) = self.ls {
+            let _ = locksystem.delete(path);
+        }
+    }
+
+    // Delete `path`, and with Depth infinity everything below it. The
+    // locks on the deleted resources are removed as well.
     pub(crate) fn delete_items<'a>(
         &'a self,
         res: &'a mut MultiError,
@@ -46,18 +57,35 @@ impl crate::DavInner {
             if !meta.is_dir() {
                 trace!("delete_items (file) {} {:?}", path, depth);
                 return match self.fs.remove_file(path).await {
-                    Ok(x) => Ok(x),
+                    Ok(()) => {
+                        self.forget_locks(path);
+                        Ok(())
+                    }
                     Err(e) => Err(add_status(res, path, e).await),
                 };
             }
             if depth == Depth::Zero {
                 trace!("delete_items (dir) {} {:?}", path, depth);
                 return match self.fs.remove_dir(path).await {
-                    Ok(x) => Ok(x),
+                    Ok(()) => {
+                        self.forget_locks(path);
+                        Ok(())
+                    }
                     Err(e) => Err(add_status(res, path, e).await),
                 };
             }
 
+            // see if the filesystem can remove the whole tree at once.
+            trace!("delete_items (dir) {} {:?}", path, depth);
+            match self.fs.remove_dir_all(path).await {
+                Ok(()) => {
+                    self.forget_locks(path);
+                    return Ok(());
+                }
+                Err(FsError::NotImplemented) => {}
+                Err(e) => return Err(dir_status(res, path, e).await),
+            }
+
             // walk over all entries.
             let mut entries = match self.fs.read_dir(path, ReadDirMeta::DataSymlink).await {
                 Ok(x) => Ok(x),
@@ -66,6 +94,15 @@ impl crate::DavInner {
 
             let mut result = Ok(());
             while let Some(dirent) = entries.next().await {
+                // an entry that cannot be read cannot be deleted either,
+                // which means the directory cannot be removed.
+                let dirent = match dirent {
+                    Ok(dirent) => dirent,
+                    Err(e) => {
+                        result = Err(add_status(res, path, e).await);
+                        continue;
+                    }
+                };
                 // if metadata() fails, skip to next entry.
                 // NOTE: dirent.metadata == symlink_metadata (!)
                 let meta = match dirent.metadata().await {
@@ -98,7 +135,10 @@ impl crate::DavInner {
             result?;
 
             match self.fs.remove_dir(path).await {
-                Ok(x) => Ok(x),
+                Ok(()) => {
+                    self.forget_locks(path);
+                    Ok(())
+                }
                 Err(e) => Err(dir_status(res, path, e).await),
             }
         }
@@ -108,14 +148,23 @@ impl crate::DavInner {
     pub(crate) async fn handle_delete(self, req: &Request<()>) -> DavResult<Response<Body>> {
         // RFC4918 9.6.1 DELETE for Collections.
         // Note that allowing Depth: 0 is NOT RFC compliant.
-        let depth = match req.headers().typed_get::<Depth>() {
+        let depth = req.headers().typed_try_get::<Depth>();
+        let depth = match depth.map_err(|_| DavError::Status(StatusCode::BAD_REQUEST))? {
             Some(Depth::Infinity) | None => Depth::Infinity,
             Some(Depth::Zero) => Depth::Zero,
             _ => return Err(DavError::Status(StatusCode::BAD_REQUEST)),
         };
 
         let mut path = self.path(req);
-        let meta = self.fs.symlink_metadata(&path).await?;
+        if let Some(res) = self.protect_root(&path) {
+            return Ok(res);
+        }
+        let meta = match self.fs.symlink_metadata(&path).await {
+            Err(FsError::NotFound) if if_match_missing(req) => {
+                return Err(DavError::Status(StatusCode::PRECONDITION_FAILED));
+            }
+            res => res?,
+        };
         if meta.is_symlink() {
             if let Ok(m2) = self.fs.metadata(&path).await {
                 path.add_slash_if(m2.is_dir());
@@ -123,6 +172,11 @@ impl crate::DavInner {
         }
         path.add_slash_if(meta.is_dir());
 
+        // special nodes, like mount points, cannot be deleted.
+        if self.fs.is_special(&path).await? {
+            return Err(DavError::Status(StatusCode::FORBIDDEN));
+        }
+
         // check the If and If-* headers.
         let tokens_res = if_match_get_tokens(req, Some(&meta), &self.fs, &self.ls, &path).await;
         let tokens = match tokens_res {
@@ -151,11 +205,6 @@ impl crate::DavInner {
                 // now delete the path recursively.
                 let fut = self.delete_items(&mut multierror, depth, meta, &path);
                 if let Ok(()) = fut.await {
-                    // Done. Now delete the path in the locksystem as well.
-                    // Should really do this per resource, in case the delete partially fails. See TODO.pm
-                    if let Some(ref locksystem) = self.ls {
-                        locksystem.delete(&path).ok();
-                    }
                     let _ = multierror.add_status(&path, StatusCode::NO_CONTENT).await;
                 }
                 Ok(())
@@ -165,3 +214,320 @@ impl crate::DavInner {
         multi_error(req_path, items).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{put, req, run};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn delete_root() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        let (resp, body) = run(&dav, req("DELETE", "/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 403);
+        assert!(body.contains("cannot-modify-protected-property"));
+        let (resp, _) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn delete_dir_all() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use std::sync::{Arc, Mutex};
+
+        // Records the remove_* calls, and only implements remove_dir_all.
+        #[derive(Clone)]
+        struct BulkFs(Box<MemFs>, Arc<Mutex<Vec<String>>>);
+
+        #[async_trait]
+        impl ForwardFs for BulkFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+                self.1.lock().unwrap().push(format!("remove_dir {}", path));
+                Err(FsError::Forbidden)
+            }
+
+            async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+                self.1.lock().unwrap().push(format!("remove_file {}", path));
+                Err(FsError::Forbidden)
+            }
+
+            async fn remove_dir_all(&self, path: &DavPath) -> FsResult<()> {
+                self.1
+                    .lock()
+                    .unwrap()
+                    .push(format!("remove_dir_all {}", path));
+                Ok(())
+            }
+        }
+
+        let memfs = MemFs::new();
+        let rw = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        crate::tests::mkcol(&rw, "/dir/").await;
+        crate::tests::mkcol(&rw, "/dir/sub/").await;
+        put(&rw, "/dir/sub/file.txt", "hello").await;
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(BulkFs(memfs, calls.clone())))
+            .build_handler();
+        let (resp, _) = run(&dav, req("DELETE", "/dir/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 204);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["remove_dir_all /dir".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_dir_walk() {
+        use crate::fs::FsError;
+        use crate::testutil::{FaultFs, FsOp};
+
+        let setup = || async {
+            let fs = FaultFs::new(MemFs::new());
+            let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+            crate::tests::mkcol(&dav, "/dir/").await;
+            crate::tests::mkcol(&dav, "/dir/sub/").await;
+            put(&dav, "/dir/a.txt", "a").await;
+            put(&dav, "/dir/sub/b.txt", "b").await;
+            (fs, dav)
+        };
+        let status = |dav: DavHandler, path: &'static str| async move {
+            let (resp, _) = run(&dav, req("GET", path).body(Body::empty()).unwrap()).await;
+            resp.status()
+        };
+
+        // remove_dir_all fails with a real error: no walk, nothing deleted.
+        let (fs, dav) = setup().await;
+        fs.fail_path(FsOp::RemoveDirAll, "/dir/", FsError::Forbidden);
+        let (resp, _) = run(&dav, req("DELETE", "/dir/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 403);
+        assert_eq!(status(dav.clone(), "/dir/a.txt").await, 200);
+
+        // not implemented: walk the tree. The collection itself cannot
+        // be removed, the members are gone.
+        let (fs, dav) = setup().await;
+        fs.fail(FsOp::RemoveDirAll, FsError::NotImplemented);
+        fs.fail_path(FsOp::RemoveDir, "/dir/", FsError::Exists);
+        let (resp, _) = run(&dav, req("DELETE", "/dir/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 409);
+        assert_eq!(status(dav.clone(), "/dir/a.txt").await, 404);
+        assert_eq!(status(dav.clone(), "/dir/sub/").await, 404);
+
+        // a member that cannot be removed is reported in a multistatus,
+        // and the collections above it stay.
+        let (fs, dav) = setup().await;
+        fs.fail(FsOp::RemoveDirAll, FsError::NotImplemented);
+        fs.fail_path(FsOp::RemoveFile, "/dir/sub/b.txt", FsError::Forbidden);
+        let (resp, body) = run(&dav, req("DELETE", "/dir/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 207);
+        assert!(body.contains("/dir/sub/b.txt"), "{}", body);
+        assert!(body.contains("403 Forbidden"), "{}", body);
+        assert_eq!(status(dav.clone(), "/dir/a.txt").await, 404);
+        assert_eq!(status(dav.clone(), "/dir/sub/b.txt").await, 200);
+    }
+
+    #[tokio::test]
+    async fn delete_with_body() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        let r = req("DELETE", "/file.txt")
+            .body(Body::from("ignored"))
+            .unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert_eq!(resp.status(), 204);
+        assert!(resp.headers().get("connection").is_none());
+        let (resp, _) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 404);
+
+        // a body is never buffered, one larger than max_drain_size
+        // only closes the connection.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .max_request_xml_size(100)
+            .max_drain_size(1000)
+            .build_handler();
+        for (size, close) in [(500, false), (2000, true)] {
+            put(&dav, "/file.txt", "hello").await;
+            let r = req("COPY", "/file.txt")
+                .header("Destination", "/copy.txt")
+                .body(Body::from("x".repeat(size)))
+                .unwrap();
+            let (resp, _) = run(&dav, r).await;
+            assert!(resp.status().is_success());
+            assert_eq!(resp.headers().contains_key("connection"), close);
+            for path in ["/file.txt", "/copy.txt"] {
+                let r = req("DELETE", path).body(Body::from("x".repeat(size)));
+                let (resp, _) = run(&dav, r.unwrap()).await;
+                assert_eq!(resp.status(), 204);
+                assert_eq!(resp.headers().contains_key("connection"), close);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_special() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+
+        // "/mnt/" is a mount point.
+        #[derive(Clone)]
+        struct MountFs(Box<MemFs>);
+
+        #[async_trait]
+        impl ForwardFs for MountFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn is_special(&self, path: &DavPath) -> FsResult<bool> {
+                match path.as_url_string().as_str() {
+                    "/mnt/" => Ok(true),
+                    "/broken/" => Err(FsError::GeneralFailure),
+                    _ => Ok(false),
+                }
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(MountFs(MemFs::new())))
+            .build_handler();
+        crate::tests::mkcol(&dav, "/mnt/").await;
+        crate::tests::mkcol(&dav, "/dir/").await;
+        crate::tests::mkcol(&dav, "/broken/").await;
+
+        let (resp, _) = run(&dav, req("DELETE", "/mnt/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 403);
+        let (resp, _) = run(&dav, req("DELETE", "/mnt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 403);
+        let r = req("MOVE", "/mnt/")
+            .header("Destination", "/other/")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 403);
+        let r = req("MOVE", "/dir/")
+            .header("Destination", "/mnt/")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 403);
+        let r = req("PROPFIND", "/mnt/")
+            .header("Depth", "0")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 207);
+
+        // if the filesystem cannot tell, the path is not touched.
+        let (resp, _) = run(&dav, req("DELETE", "/broken/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 500);
+        let r = req("COPY", "/dir/")
+            .header("Destination", "/broken/")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 500);
+        let r = req("PROPFIND", "/broken/")
+            .header("Depth", "0")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 207);
+
+        // other collections can still be deleted.
+        let (resp, _) = run(&dav, req("DELETE", "/dir/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 204);
+    }
+
+    #[tokio::test]
+    async fn delete_releases_locks() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::mkcol;
+        use crate::tests::ForwardFs;
+
+        // Refuses to remove "keep.txt".
+        #[derive(Clone)]
+        struct KeepFs(Box<MemFs>);
+
+        #[async_trait]
+        impl ForwardFs for KeepFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+                if path.file_name() == Some("keep.txt") {
+                    return Err(FsError::Forbidden);
+                }
+                self.0.remove_file(path).await
+            }
+        }
+
+        const LOCKINFO: &str = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(KeepFs(MemFs::new())))
+            .locksystem(crate::memls::MemLs::new())
+            .build_handler();
+        async fn lock(dav: &DavHandler, path: &str) -> String {
+            let r = req("LOCK", path).body(Body::from(LOCKINFO)).unwrap();
+            let (resp, _) = run(dav, r).await;
+            resp.headers()["Lock-Token"].to_str().unwrap().to_string()
+        }
+        async fn put_status(dav: &DavHandler, path: &str) -> http::StatusCode {
+            let r = req("PUT", path).body(Body::from("again")).unwrap();
+            run(dav, r).await.0.status()
+        }
+
+        let (resp, _) = run(
+            &dav,
+            req("DELETE", "/nope.txt").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(resp.status(), 404);
+
+        // a locked file can be created again after it was deleted.
+        put(&dav, "/file.txt", "hello").await;
+        let token = lock(&dav, "/file.txt").await;
+        let r = req("DELETE", "/file.txt")
+            .header("If", format!("({})", token))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 204);
+        assert_eq!(put_status(&dav, "/file.txt").await, 201);
+
+        // locks are removed only for what was actually deleted.
+        mkcol(&dav, "/dir/").await;
+        put(&dav, "/dir/a.txt", "a").await;
+        put(&dav, "/dir/keep.txt", "keep").await;
+        let a = lock(&dav, "/dir/a.txt").await;
+        let keep = lock(&dav, "/dir/keep.txt").await;
+        let r = req("DELETE", "/dir/")
+            .header(
+                "If",
+                format!("</dir/a.txt> ({}) </dir/keep.txt> ({})", a, keep),
+            )
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 207);
+        assert_eq!(put_status(&dav, "/dir/a.txt").await, 201);
+        assert_eq!(put_status(&dav, "/dir/keep.txt").await, 423);
+    }
+}
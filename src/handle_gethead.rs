@@ -1,5 +1,7 @@
 use std::cmp;
 use std::io::Write;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 use async_stream::try_stream;
 use futures_util::Stream;
@@ -15,8 +17,12 @@ use crate::davheaders;
 use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::*;
-use crate::util::systemtime_to_offsetdatetime;
-use crate::DavMethod;
+use crate::handle_props::stored_content_type;
+use crate::sniff;
+use crate::tar;
+use crate::transform::ByteStream;
+use crate::util::{glob_match_any, systemtime_to_offsetdatetime};
+use crate::{DavMethod, SortBy};
 
 struct Range {
     start: u64,
@@ -27,15 +33,52 @@ const BOUNDARY: &str = "BOUNDARY";
 const BOUNDARY_START: &str = "\n--BOUNDARY\n";
 const BOUNDARY_END: &str = "\n--BOUNDARY--\n";
 
-const READ_BUF_SIZE: usize = 16384;
+// Runs a stream in a separate task, that stays up to `buffers` items
+// ahead of the consumer. The task is aborted when this is dropped.
+struct ReadAhead {
+    rx: tokio::sync::mpsc::Receiver<std::io::Result<Bytes>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ReadAhead {
+    fn new(mut stream: ByteStream, buffers: usize) -> ReadAhead {
+        let (tx, rx) = tokio::sync::mpsc::channel(buffers);
+        let task = tokio::spawn(async move {
+            while let Some(item) = stream.next().await {
+                if tx.send(item).await.is_err() {
+                    break;
+                }
+            }
+        });
+        ReadAhead { rx, task }
+    }
+}
+
+impl Stream for ReadAhead {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
 
 impl crate::DavInner {
     pub(crate) async fn handle_get(&self, req: &Request<()>) -> DavResult<Response<Body>> {
         let head = req.method() == http::Method::HEAD;
         let mut path = self.path(req);
 
+        let mut meta = self.fs.metadata(&path).await?;
+        if let Some(res) = self.follow_redirect_ref(req, &*meta)? {
+            return Ok(res);
+        }
+
         // check if it's a directory.
-        let meta = self.fs.metadata(&path).await?;
         if meta.is_dir() {
             //
             // This is a directory. If the path doesn't end in "/", send a redir.
@@ -45,36 +88,126 @@ impl crate::DavInner {
             if !path.is_collection() {
                 let mut res = Response::new(Body::empty());
                 path.add_slash();
-                res.headers_mut().insert(
-                    "Location",
-                    path.with_prefix().as_url_string().parse().unwrap(),
-                );
+                res.headers_mut()
+                    .insert("Location", self.self_url(&path).parse().unwrap());
                 res.headers_mut().typed_insert(headers::ContentLength(0));
                 *res.status_mut() = StatusCode::FOUND;
                 return Ok(res);
             }
 
-            // If indexfile was set, use it.
-            if let Some(indexfile) = self.indexfile.as_ref() {
+            if self.allow_archive_download.unwrap_or(false) && wants_archive(req.uri().query()) {
+                return self.handle_archive(&path, head).await;
+            }
+
+            // serve the first of the index files that exists.
+            let index_files = self.index_files.as_deref().unwrap_or(&[]);
+            let mut index = None;
+            for name in index_files {
+                let mut p = path.clone();
+                p.push_segment(name.as_bytes());
+                match self.fs.metadata(&p).await {
+                    Ok(m) if m.is_file() => {
+                        index = Some((p, m));
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some((p, m)) = index {
+                path = p;
+                meta = m;
+            } else if let Some(indexfile) = self.indexfile.as_ref() {
+                // If indexfile was set, use it.
                 path.push_segment(indexfile.as_bytes());
+                meta = self.fs.metadata(&path).await?;
+            } else if !index_files.is_empty() && !self.autoindex_enabled() {
+                return Err(DavError::Status(StatusCode::NOT_FOUND));
             } else {
                 // Otherwise see if we need to generate a directory index.
                 return self.handle_autoindex(req, head).await;
             }
         }
 
+        // see if the content is going to be transformed, if so we
+        // cannot send a length, an etag, or ranges.
+        let transform = self.get_transform.as_ref().and_then(|t| t(&path, &*meta));
+
+        // the size of the file might not be what we can read.
+        let until_eof = self.read_until_eof.unwrap_or(false);
+
+        // can the client get the file from the backend directly? Ranges
+        // might not be supported there, so those are served by us.
+        if meta.is_file() && transform.is_none() && !req.headers().contains_key(http::header::RANGE)
+        {
+            if let Some(url) = self.fs.get_redirect_url(&path).await? {
+                let mut res = Response::new(Body::empty());
+                let location = url
+                    .parse()
+                    .map_err(|_| DavError::Status(StatusCode::INTERNAL_SERVER_ERROR))?;
+                res.headers_mut().insert("Location", location);
+                res.headers_mut().typed_insert(headers::ContentLength(0));
+                *res.status_mut() = StatusCode::FOUND;
+                return Ok(res);
+            }
+        }
+
+        // handle the if-headers before opening the file. If the result
+        // is 304 Not Modified or 412 Precondition Failed, we do not need
+        // the file at all. Not when redirecting, that comes first, the
+        // result is then used after it.
+        let cond = match meta.is_file() {
+            true => conditional::if_match(req, Some(&meta), &self.fs, &self.ls, &path).await,
+            false => None,
+        };
+        if self.redirect != Some(true) {
+            if let Some(s) = cond {
+                let mut res = Response::new(Body::empty());
+                if let Ok(modified) = meta.modified() {
+                    res.headers_mut()
+                        .typed_insert(headers::LastModified::from(modified));
+                }
+                if transform.is_none() {
+                    if let Some(etag) = davheaders::ETag::from_meta(&meta) {
+                        res.headers_mut().typed_insert(etag);
+                    }
+                }
+                // the file is not open yet so we cannot ask it if it is
+                // seekable, assume it is.
+                res.headers_mut()
+                    .typed_insert(match transform.is_some() || until_eof {
+                        true => headers::AcceptRanges::none(),
+                        false => headers::AcceptRanges::bytes(),
+                    });
+                if s != StatusCode::NOT_MODIFIED {
+                    res.headers_mut().typed_insert(headers::ContentLength(0));
+                } else if transform.is_none() && !until_eof {
+                    res.headers_mut()
+                        .typed_insert(headers::ContentLength(meta.len()));
+                }
+                self.add_immutable(&mut res, &path);
+                *res.status_mut() = s;
+                return Ok(res);
+            }
+        }
+
         // double check, is it a regular file.
-        let mut file = self.fs.open(&path, OpenOptions::read()).await?;
-        #[allow(unused_mut)]
-        let mut meta = file.metadata().await?;
         if !meta.is_file() {
             return Err(DavError::Status(StatusCode::METHOD_NOT_ALLOWED));
         }
+        let mut file = self
+            .fs
+            .open_with_meta(&path, OpenOptions::read(), meta.clone())
+            .await?;
 
         let len = meta.len();
         let mut curpos = 0u64;
-        let file_etag = davheaders::ETag::from_meta(&meta);
+        let file_etag = match transform {
+            Some(_) => None,
+            None => davheaders::ETag::from_meta(&meta),
+        };
 
+        let seekable = file.is_seekable() && transform.is_none() && !until_eof;
         let mut ranges = Vec::new();
         let mut do_range = match req.headers().typed_try_get::<davheaders::IfRange>() {
             Ok(Some(r)) => conditional::ifrange_match(&r, file_etag.as_ref(), meta.modified().ok()),
@@ -93,6 +226,7 @@ impl crate::DavInner {
         if let Some(etag) = file_etag {
             res.headers_mut().typed_insert(etag);
         }
+        self.add_immutable(&mut res, &path);
 
         match self.redirect {
             Some(redirect) => {
@@ -113,18 +247,22 @@ impl crate::DavInner {
         // Apache always adds an Accept-Ranges header, even with partial
         // responses where it should be pretty obvious. So something somewhere
         // probably depends on that.
-        res.headers_mut()
-            .typed_insert(headers::AcceptRanges::bytes());
+        res.headers_mut().typed_insert(if seekable {
+            headers::AcceptRanges::bytes()
+        } else {
+            headers::AcceptRanges::none()
+        });
 
-        // handle the if-headers.
-        if let Some(s) = conditional::if_match(req, Some(&meta), &self.fs, &self.ls, &path).await {
+        // the if-headers, evaluated above. they come before Range, so a
+        // 304 or 412 wins over a 206 (RFC9110 13.2.2).
+        if let Some(s) = cond {
             *res.status_mut() = s;
             no_body = true;
             do_range = false;
         }
 
         // see if we want to get one or more ranges.
-        if do_range {
+        if do_range && seekable {
             if let Some(r) = req.headers().typed_get::<headers::Range>() {
                 trace!("handle_gethead: range header {:?}", r);
                 use std::ops::Bound::*;
@@ -152,6 +290,26 @@ impl crate::DavInner {
             }
         }
 
+        let mut content_type = path.get_mime_type_str().to_string();
+        let mut stored = None;
+        if self.preserve_content_type.unwrap_or(false) {
+            stored = stored_content_type(self.fs.as_ref(), &path).await;
+        }
+        if let Some(ct) = stored {
+            content_type = ct;
+        } else if self.sniff_content_type.unwrap_or(false)
+            && seekable
+            && content_type == "application/octet-stream"
+        {
+            // look at the first bytes, then go back to the start.
+            let size = self.sniff_size.unwrap_or(64);
+            let data = file.read_bytes(size).await?;
+            file.seek(std::io::SeekFrom::Start(0)).await?;
+            if let Some(ct) = sniff::sniff(&data) {
+                content_type = ct.to_string();
+            }
+        }
+
         if !ranges.is_empty() {
             // seek to beginning of the first range.
             if file
@@ -196,17 +354,21 @@ impl crate::DavInner {
         }
 
         // set content-length and start if we're not doing multipart.
-        let content_type = path.get_mime_type_str();
-        if ranges.len() <= 1 {
+        if let Some(ref t) = transform {
+            res.headers_mut()
+                .typed_insert(davheaders::ContentType(t.content_type.clone()));
+        } else if ranges.len() <= 1 {
             res.headers_mut()
-                .typed_insert(davheaders::ContentType(content_type.to_owned()));
+                .typed_insert(davheaders::ContentType(content_type.clone()));
             let notmod = res.status() == StatusCode::NOT_MODIFIED;
             let len = if head || !no_body || notmod {
                 ranges[0].count
             } else {
                 0
             };
-            res.headers_mut().typed_insert(headers::ContentLength(len));
+            if !until_eof || (no_body && !head && !notmod) {
+                res.headers_mut().typed_insert(headers::ContentLength(len));
+            }
         }
 
         if head || no_body {
@@ -214,7 +376,10 @@ impl crate::DavInner {
         }
 
         // now just loop and send data.
-        let read_buf_size = self.read_buf_size.unwrap_or(READ_BUF_SIZE);
+        let read_buf_size = self
+            .read_buf_size
+            .unwrap_or_else(|| self.fs.preferred_read_chunk_size())
+            .max(1);
         let body_stream = try_stream! {
             let zero = [0; 4096];
 
@@ -251,6 +416,19 @@ impl crate::DavInner {
                     yield Bytes::from(hdrs);
                 }
 
+                if until_eof {
+                    // an empty read is the end of the file.
+                    loop {
+                        let buf = file.read_bytes(read_buf_size).await?;
+                        if buf.is_empty() {
+                            break;
+                        }
+                        trace!("sending {} bytes", buf.len());
+                        yield buf;
+                    }
+                    continue;
+                }
+
                 let mut remaining = range.count;
                 while remaining != 0 {
                     let blen = cmp::min(remaining, read_buf_size as u64) as usize;
@@ -273,12 +451,185 @@ impl crate::DavInner {
             }
         };
         let _: &dyn Stream<Item = Result<Bytes, std::io::Error>> = &body_stream;
+        let body_stream: ByteStream = match self.readahead.unwrap_or(0) {
+            0 => Box::pin(body_stream),
+            n => Box::pin(ReadAhead::new(Box::pin(body_stream), n)),
+        };
 
-        *res.body_mut() = Body::from_stream(body_stream);
+        let body_stream = match transform {
+            Some(t) => (t.wrap)(body_stream),
+            None => body_stream,
+        };
+        *res.body_mut() = Body::from_stream(self.metered(&path, body_stream));
+
+        Ok(res)
+    }
+
+    // Count the bytes of a response body for `on_transfer`.
+    fn metered(&self, path: &DavPath, body: ByteStream) -> ByteStream {
+        let mut meter = match self.transfer_meter(DavMethod::Get, path) {
+            Some(meter) => meter,
+            None => return body,
+        };
+        Box::pin(body.inspect(move |item| {
+            if let Ok(bytes) = item {
+                meter.sent(bytes.len() as u64);
+            }
+        }))
+    }
+
+    // Send a collection as a tar archive. The archive is generated while
+    // it is sent, so there is no Content-Length.
+    async fn handle_archive(&self, path: &DavPath, head: bool) -> DavResult<Response<Body>> {
+        // read the top directory here, so that errors get a status.
+        let top_entries = self.fs.read_dir(path, ReadDirMeta::DataSymlink).await?;
+
+        // the archive is named after the collection.
+        let dirname = path
+            .as_bytes()
+            .split(|&c| c == b'/')
+            .rfind(|s| !s.is_empty())
+            .map(|s| String::from_utf8_lossy(s).to_string());
+        let filename: String = dirname
+            .as_deref()
+            .unwrap_or("archive")
+            .chars()
+            .map(|c| match c {
+                c if c.is_ascii_alphanumeric() || "-_.+ ".contains(c) => c,
+                _ => '_',
+            })
+            .collect();
+
+        let mut res = Response::new(Body::empty());
+        res.headers_mut()
+            .insert("Content-Type", "application/x-tar".parse().unwrap());
+        let disposition = format!("attachment; filename=\"{}.tar\"", filename);
+        res.headers_mut()
+            .insert("Content-Disposition", disposition.parse().unwrap());
+        res.headers_mut()
+            .typed_insert(headers::AcceptRanges::none());
+        if head {
+            return Ok(res);
+        }
+
+        let fs = self.fs.clone();
+        let hide_patterns = self.hide_patterns.clone().unwrap_or_default();
+        let top = path.clone();
+        let read_buf_size = self
+            .read_buf_size
+            .unwrap_or_else(|| self.fs.preferred_read_chunk_size())
+            .max(1);
+        let body_stream = try_stream! {
+            // names in the archive start with the name of the collection.
+            let base = match dirname {
+                Some(ref d) => format!("{}/", d).into_bytes(),
+                None => Vec::new(),
+            };
+            if !base.is_empty() {
+                let mtime = fs.metadata(&top).await.and_then(|m| m.modified()).ok();
+                yield Bytes::from(tar::header(&base, 0, unix_time(mtime), true));
+            }
+
+            let mut top_entries = Some(top_entries);
+            let mut dirs = vec![top.clone()];
+            while let Some(dir) = dirs.pop() {
+                let mut entries = match top_entries.take() {
+                    Some(e) => e,
+                    None => match fs.read_dir(&dir, ReadDirMeta::DataSymlink).await {
+                        Ok(e) => e,
+                        Err(_) => continue,
+                    },
+                };
+                let mut list = Vec::new();
+                while let Some(dirent) = entries.next().await {
+                    // the archive has what could be read.
+                    let dirent = match dirent {
+                        Ok(dirent) => dirent,
+                        Err(e) => {
+                            debug!("archive: read_dir {}: {:?}", dir, e);
+                            continue;
+                        }
+                    };
+                    let name = dirent.name();
+                    if glob_match_any(&hide_patterns, &name) {
+                        continue;
+                    }
+                    match dirent.metadata().await {
+                        // no links, FIFOs or devices.
+                        Ok(meta) if !meta.is_symlink() && (meta.is_dir() || meta.is_file()) => {
+                            list.push((name, meta))
+                        }
+                        _ => {}
+                    }
+                }
+                list.sort_by(|a, b| a.0.cmp(&b.0));
+
+                let mut subdirs = Vec::new();
+                for (name, meta) in list {
+                    let mut npath = dir.clone();
+                    npath.push_segment(&name);
+                    let mut tname = base.clone();
+                    tname.extend_from_slice(&npath.as_bytes()[top.as_bytes().len()..]);
+                    let mtime = unix_time(meta.modified().ok());
+                    if meta.is_dir() {
+                        npath.add_slash();
+                        tname.push(b'/');
+                        yield Bytes::from(tar::header(&tname, 0, mtime, true));
+                        subdirs.push(npath);
+                        continue;
+                    }
 
+                    // a file that cannot be opened is left out.
+                    let mut file = match fs.open(&npath, OpenOptions::read()).await {
+                        Ok(f) => f,
+                        Err(_) => continue,
+                    };
+                    let size = meta.len();
+                    yield Bytes::from(tar::header(&tname, size, mtime, false));
+                    let mut remaining = size;
+                    while remaining > 0 {
+                        let n = cmp::min(remaining, read_buf_size as u64) as usize;
+                        let mut buf = file.read_bytes(n).await?;
+                        if buf.is_empty() {
+                            // the file got shorter, the size in the header
+                            // has been sent already. fill up with zeroes.
+                            buf = Bytes::from(vec![0; n]);
+                        }
+                        let buf = buf.slice(..cmp::min(buf.len() as u64, remaining) as usize);
+                        remaining -= buf.len() as u64;
+                        yield buf;
+                    }
+                    yield Bytes::from(vec![0; tar::padding(size)]);
+                }
+                dirs.extend(subdirs.into_iter().rev());
+            }
+            yield Bytes::from(tar::end().to_vec());
+        };
+        let _: &dyn Stream<Item = Result<Bytes, std::io::Error>> = &body_stream;
+        *res.body_mut() = Body::from_stream(self.metered(path, Box::pin(body_stream)));
         Ok(res)
     }
 
+    // add Cache-Control for immutable files.
+    fn add_immutable(&self, res: &mut Response<Body>, path: &DavPath) {
+        if matches!(self.immutable_if, Some(ref f) if f(path)) {
+            res.headers_mut().insert(
+                "Cache-Control",
+                "public, max-age=31536000, immutable".parse().unwrap(),
+            );
+        }
+    }
+
+    // Only allow index generation if explicitly set to true, _or_ if it was
+    // unset, and PROPFIND is explicitly allowed.
+    pub(crate) fn autoindex_enabled(&self) -> bool {
+        let allow_propfind = self
+            .allow
+            .map(|x| x.contains(DavMethod::PropFind))
+            .unwrap_or(false);
+        self.autoindex.unwrap_or(allow_propfind)
+    }
+
     pub(crate) async fn handle_autoindex(
         &self,
         req: &Request<()>,
@@ -287,15 +638,7 @@ impl crate::DavInner {
         let mut res = Response::new(Body::empty());
         let path = self.path(req);
 
-        // Is PROPFIND explicitly allowed?
-        let allow_propfind = self
-            .allow
-            .map(|x| x.contains(DavMethod::PropFind))
-            .unwrap_or(false);
-
-        // Only allow index generation if explicitly set to true, _or_ if it was
-        // unset, and PROPFIND is explicitly allowed.
-        if !self.autoindex.unwrap_or(allow_propfind) {
+        if !self.autoindex_enabled() {
             debug!(
                 "method {} not allowed on request {}",
                 req.method(),
@@ -306,10 +649,15 @@ impl crate::DavInner {
 
         // read directory or bail.
         let mut entries = self.fs.read_dir(&path, ReadDirMeta::Data).await?;
+        let hide_patterns = self.hide_patterns.clone().unwrap_or_default();
+        let default_sort = self.autoindex_sort.unwrap_or((SortBy::Name, false));
+        let (sort_by, descending) = sort_order(req.uri().query(), default_sort);
 
         // start output
         res.headers_mut()
             .insert("Content-Type", "text/html; charset=utf-8".parse().unwrap());
+        res.headers_mut()
+            .typed_insert(headers::AcceptRanges::none());
         *res.status_mut() = StatusCode::OK;
         if head {
             return Ok(res);
@@ -326,8 +674,15 @@ impl crate::DavInner {
 
             let mut dirents: Vec<Dirent> = Vec::new();
             while let Some(dirent) = entries.next().await {
+                let dirent = match dirent {
+                    Ok(dirent) => dirent,
+                    Err(e) => {
+                        debug!("index: read_dir {}: {:?}", path, e);
+                        continue;
+                    }
+                };
                 let mut name = dirent.name();
-                if name.starts_with(b".") {
+                if name.starts_with(b".") || glob_match_any(&hide_patterns, &name) {
                     continue;
                 }
                 let mut npath = path.clone();
@@ -345,17 +700,16 @@ impl crate::DavInner {
                 }
             }
 
-            // now we can sort the dirent struct.
+            // now we can sort the dirent struct. directories first.
             dirents.sort_by(|a, b| {
-                let adir = a.meta.is_dir();
-                let bdir = b.meta.is_dir();
-                if adir && !bdir {
-                    std::cmp::Ordering::Less
-                } else if bdir && !adir {
-                    std::cmp::Ordering::Greater
-                } else {
-                    (a.name).cmp(&b.name)
-                }
+                let order = match sort_by {
+                    SortBy::Name => cmp::Ordering::Equal,
+                    SortBy::Size => a.meta.len().cmp(&b.meta.len()),
+                    SortBy::Modified => a.meta.modified().ok().cmp(&b.meta.modified().ok()),
+                };
+                let order = order.then_with(|| a.name.cmp(&b.name));
+                let order = if descending { order.reverse() } else { order };
+                b.meta.is_dir().cmp(&a.meta.is_dir()).then(order)
             });
 
             // and output html
@@ -397,14 +751,26 @@ impl crate::DavInner {
                 <body>\n",
             );
             w.push_str(&format!("<h1>Index of {}</h1>", display_path(&path)));
-            w.push_str(
+            // clicking a column header sorts by that column, or
+            // reverses the order if it is already sorted by it.
+            let header = |by: SortBy, c: &str, title: &str| {
+                let o = if by == sort_by && !descending { "D" } else { "A" };
+                format!("<a href=\"?C={}&amp;O={}\">{}</a>", c, o, title)
+            };
+            w.push_str(&format!(
                 "\
                 <table>\n\
                 <tr>\n\
-                    <th class=\"left mw20\">Name</th>\n\
-                    <th class=\"left\">Last modified</th>\n\
-                    <th>Size</th>\n\
-                </tr>\n\
+                    <th class=\"left mw20\">{}</th>\n\
+                    <th class=\"left\">{}</th>\n\
+                    <th>{}</th>\n\
+                </tr>\n",
+                header(SortBy::Name, "N", "Name"),
+                header(SortBy::Modified, "M", "Last modified"),
+                header(SortBy::Size, "S", "Size"),
+            ));
+            w.push_str(
+                "\
                 <tr><th colspan=\"3\"><hr></th></tr>\n\
                 <tr>\n\
                     <td><a href=\"..\">Parent Directory</a></td>\n\
@@ -453,6 +819,46 @@ impl crate::DavInner {
     }
 }
 
+// Is this a request for a tar archive: ?archive=tar.
+fn wants_archive(query: Option<&str>) -> bool {
+    query
+        .unwrap_or("")
+        .split(['&', ';'])
+        .any(|param| param == "archive=tar")
+}
+
+// Seconds since the epoch, for a tar header.
+fn unix_time(tm: Option<std::time::SystemTime>) -> u64 {
+    tm.and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Apache-style sort order in the query string of an autoindex page:
+// C=N|S|M for the column, and O=A|D for ascending or descending.
+fn sort_order(query: Option<&str>, default: (SortBy, bool)) -> (SortBy, bool) {
+    let (mut sort_by, mut descending) = default;
+    let mut order = None;
+    for param in query.unwrap_or("").split(['&', ';']) {
+        match param {
+            "C=N" => sort_by = SortBy::Name,
+            "C=S" => sort_by = SortBy::Size,
+            "C=M" => sort_by = SortBy::Modified,
+            "O=A" => order = Some(false),
+            "O=D" => order = Some(true),
+            _ => continue,
+        }
+        // a column without an order is ascending.
+        if param.starts_with('C') {
+            descending = false;
+        }
+    }
+    if let Some(order) = order {
+        descending = order;
+    }
+    (sort_by, descending)
+}
+
 fn display_size(size: u64) -> String {
     let (formatted, unit) = ["KiB", "MiB", "GiB", "TiB", "PiB"]
         .iter()
@@ -496,3 +902,975 @@ fn display_path(path: &DavPath) -> String {
 
     dpath
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{mkcol, put, req, run};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn get_transform() {
+        use crate::transform::{ByteStream, Transform};
+        use bytes::Bytes;
+        use futures_util::{stream, StreamExt, TryStreamExt};
+        use std::sync::Arc;
+
+        // a very small markdown renderer: only knows about headings.
+        fn render(input: ByteStream) -> ByteStream {
+            let html = async move {
+                let md: Vec<u8> = input.map_ok(|b| b.to_vec()).try_concat().await?;
+                let mut html = String::new();
+                for line in String::from_utf8_lossy(&md).lines() {
+                    match line.strip_prefix("# ") {
+                        Some(h) => html.push_str(&format!("<h1>{}</h1>\n", h)),
+                        None => html.push_str(&format!("<p>{}</p>\n", line)),
+                    }
+                }
+                Ok(Bytes::from(html))
+            };
+            stream::once(html).boxed()
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .get_transform(Arc::new(|path, _meta| {
+                if !path.as_url_string().ends_with(".md") {
+                    return None;
+                }
+                Some(Transform::new("text/html; charset=utf-8", render))
+            }))
+            .build_handler();
+        put(&dav, "/readme.md", "# Hello\nworld").await;
+        put(&dav, "/file.txt", "# Hello").await;
+
+        let r = req("GET", "/readme.md")
+            .header("Range", "bytes=0-3")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(&dav, r).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "text/html; charset=utf-8");
+        assert!(resp.headers().get("content-length").is_none());
+        assert_eq!(body, "<h1>Hello</h1>\n<p>world</p>\n");
+
+        let (_, body) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "# Hello");
+    }
+
+    #[tokio::test]
+    async fn not_modified_without_open() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // Counts the calls to open().
+        #[derive(Clone)]
+        struct CountingFs(Box<MemFs>, Arc<AtomicUsize>);
+
+        #[async_trait]
+        impl ForwardFs for CountingFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn open(
+                &self,
+                path: &DavPath,
+                options: OpenOptions,
+            ) -> FsResult<Box<dyn DavFile>> {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                self.0.open(path, options).await
+            }
+        }
+
+        let memfs = MemFs::new();
+        let opens = Arc::new(AtomicUsize::new(0));
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(CountingFs(memfs.clone(), opens.clone())))
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        let (resp, _) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        let etag = resp.headers()["etag"].clone();
+
+        opens.store(0, Ordering::SeqCst);
+        let r = req("GET", "/file.txt")
+            .header("If-None-Match", etag.clone())
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(&dav, r).await;
+        assert_eq!(resp.status(), 304);
+        assert_eq!(resp.headers()["etag"], etag);
+        assert_eq!(body, "");
+        assert_eq!(opens.load(Ordering::SeqCst), 0);
+    }
+
+    async fn get_range(dav: &DavHandler, if_range: &str) -> (u16, String) {
+        let r = req("GET", "/file.txt")
+            .header("Range", "bytes=0-3")
+            .header("If-Range", if_range)
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(dav, r).await;
+        (resp.status().as_u16(), body)
+    }
+
+    #[tokio::test]
+    async fn if_range_date() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "0123456789").await;
+
+        let (resp, _) = run(&dav, req("HEAD", "/file.txt").body(Body::empty()).unwrap()).await;
+        let lastmod = resp.headers()["last-modified"]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        assert_eq!(get_range(&dav, &lastmod).await, (206, "0123".to_string()));
+        let old = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(get_range(&dav, old).await, (200, "0123456789".to_string()));
+    }
+
+    #[tokio::test]
+    async fn if_range_etag() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "0123456789").await;
+
+        let (resp, _) = run(&dav, req("HEAD", "/file.txt").body(Body::empty()).unwrap()).await;
+        let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+
+        assert_eq!(get_range(&dav, &etag).await, (206, "0123".to_string()));
+        let weak = format!("W/{}", etag);
+        assert_eq!(
+            get_range(&dav, &weak).await,
+            (200, "0123456789".to_string())
+        );
+        assert_eq!(get_range(&dav, "\"nope\"").await.0, 200);
+    }
+
+    #[tokio::test]
+    async fn accept_ranges() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+        put(&dav, "/file.txt", "0123456789").await;
+
+        let (resp, _) = run(&dav, req("HEAD", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.headers()["accept-ranges"], "bytes");
+
+        let r = req("HEAD", "/file.txt")
+            .header("Range", "bytes=2-5")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(&dav, r).await;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.headers()["content-range"], "bytes 2-5/10");
+        assert_eq!(resp.headers()["content-length"], "4");
+        assert_eq!(body, "");
+
+        let (resp, _) = run(&dav, req("HEAD", "/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["accept-ranges"], "none");
+    }
+
+    #[tokio::test]
+    async fn accept_ranges_not_seekable() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+
+        // Files that cannot seek.
+        #[derive(Clone)]
+        struct PipeFs(Box<MemFs>);
+
+        #[derive(Debug)]
+        struct PipeFile(Box<dyn DavFile>);
+
+        impl DavFile for PipeFile {
+            fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
+                self.0.metadata()
+            }
+            fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<()> {
+                self.0.write_buf(buf)
+            }
+            fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<()> {
+                self.0.write_bytes(buf)
+            }
+            fn read_bytes(&mut self, count: usize) -> FsFuture<bytes::Bytes> {
+                self.0.read_bytes(count)
+            }
+            fn seek(&mut self, _pos: std::io::SeekFrom) -> FsFuture<u64> {
+                Box::pin(futures_util::future::err(FsError::NotImplemented))
+            }
+            fn flush(&mut self) -> FsFuture<()> {
+                self.0.flush()
+            }
+            fn is_seekable(&self) -> bool {
+                false
+            }
+        }
+
+        #[async_trait]
+        impl ForwardFs for PipeFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn open(
+                &self,
+                path: &DavPath,
+                options: OpenOptions,
+            ) -> FsResult<Box<dyn DavFile>> {
+                Ok(Box::new(PipeFile(self.0.open(path, options).await?)))
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(PipeFs(MemFs::new())))
+            .build_handler();
+        put(&dav, "/file.txt", "0123456789").await;
+
+        let r = req("GET", "/file.txt")
+            .header("Range", "bytes=2-5")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(&dav, r).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["accept-ranges"], "none");
+        assert_eq!(body, "0123456789");
+    }
+
+    #[tokio::test]
+    async fn open_with_meta() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // Counts metadata lookups, on the filesystem and on open files.
+        #[derive(Clone)]
+        struct StatFs(Box<MemFs>, Arc<AtomicUsize>);
+
+        #[derive(Debug)]
+        struct StatFile(Box<dyn DavFile>, Arc<AtomicUsize>);
+
+        impl DavFile for StatFile {
+            fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                self.0.metadata()
+            }
+            fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<()> {
+                self.0.write_buf(buf)
+            }
+            fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<()> {
+                self.0.write_bytes(buf)
+            }
+            fn read_bytes(&mut self, count: usize) -> FsFuture<bytes::Bytes> {
+                self.0.read_bytes(count)
+            }
+            fn seek(&mut self, pos: std::io::SeekFrom) -> FsFuture<u64> {
+                self.0.seek(pos)
+            }
+            fn flush(&mut self) -> FsFuture<()> {
+                self.0.flush()
+            }
+        }
+
+        #[async_trait]
+        impl ForwardFs for StatFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn open(
+                &self,
+                path: &DavPath,
+                options: OpenOptions,
+            ) -> FsResult<Box<dyn DavFile>> {
+                let file = self.0.open(path, options).await?;
+                Ok(Box::new(StatFile(file, self.1.clone())))
+            }
+
+            async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                self.0.metadata(path).await
+            }
+        }
+
+        let stats = Arc::new(AtomicUsize::new(0));
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(StatFs(MemFs::new(), stats.clone())))
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        stats.store(0, Ordering::SeqCst);
+        let (resp, body) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(body, "hello");
+        // the file is looked up once, not again after it was opened.
+        assert_eq!(stats.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn immutable_if() {
+        use std::sync::Arc;
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .immutable_if(Arc::new(|path| {
+                path.as_url_string().starts_with("/assets/")
+            }))
+            .build_handler();
+        mkcol(&dav, "/assets/").await;
+        put(&dav, "/assets/0a1b2c.js", "x").await;
+        put(&dav, "/index.html", "y").await;
+
+        let (resp, _) = run(
+            &dav,
+            req("GET", "/assets/0a1b2c.js").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(
+            resp.headers()["cache-control"],
+            "public, max-age=31536000, immutable"
+        );
+        let (resp, _) = run(&dav, req("GET", "/index.html").body(Body::empty()).unwrap()).await;
+        assert!(resp.headers().get("cache-control").is_none());
+    }
+
+    #[tokio::test]
+    async fn get_redirect_url() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+
+        // An object store that hands out signed URLs.
+        #[derive(Clone)]
+        struct SignedFs(Box<MemFs>);
+
+        #[async_trait]
+        impl ForwardFs for SignedFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn get_redirect_url(&self, path: &DavPath) -> FsResult<Option<String>> {
+                Ok(Some(format!(
+                    "https://store.example.com{}?sig=abc",
+                    path.as_url_string()
+                )))
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(SignedFs(MemFs::new())))
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        let (resp, body) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 302);
+        assert_eq!(
+            resp.headers()["location"],
+            "https://store.example.com/file.txt?sig=abc"
+        );
+        assert_eq!(body, "");
+
+        let (resp, body) = run(&dav, req("HEAD", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 302);
+        assert_eq!(
+            resp.headers()["location"],
+            "https://store.example.com/file.txt?sig=abc"
+        );
+        assert_eq!(body, "");
+
+        // ranges are served directly.
+        let r = req("GET", "/file.txt")
+            .header("Range", "bytes=1-2")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(&dav, r).await;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(body, "el");
+    }
+
+    #[tokio::test]
+    async fn preferred_read_chunk_size() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use std::sync::{Arc, Mutex};
+
+        // Records the size of every read.
+        #[derive(Clone)]
+        struct RecordFs(Box<MemFs>, Arc<Mutex<Vec<usize>>>);
+
+        #[derive(Debug)]
+        struct RecordFile(Box<dyn DavFile>, Arc<Mutex<Vec<usize>>>);
+
+        impl DavFile for RecordFile {
+            fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
+                self.0.metadata()
+            }
+            fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<()> {
+                self.0.write_buf(buf)
+            }
+            fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<()> {
+                self.0.write_bytes(buf)
+            }
+            fn read_bytes(&mut self, count: usize) -> FsFuture<bytes::Bytes> {
+                self.1.lock().unwrap().push(count);
+                self.0.read_bytes(count)
+            }
+            fn seek(&mut self, pos: std::io::SeekFrom) -> FsFuture<u64> {
+                self.0.seek(pos)
+            }
+            fn flush(&mut self) -> FsFuture<()> {
+                self.0.flush()
+            }
+        }
+
+        #[async_trait]
+        impl ForwardFs for RecordFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn open(
+                &self,
+                path: &DavPath,
+                options: OpenOptions,
+            ) -> FsResult<Box<dyn DavFile>> {
+                let file = self.0.open(path, options).await?;
+                Ok(Box::new(RecordFile(file, self.1.clone())))
+            }
+
+            fn preferred_read_chunk_size(&self) -> usize {
+                1000
+            }
+        }
+
+        let reads = Arc::new(Mutex::new(Vec::new()));
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(RecordFs(MemFs::new(), reads.clone())))
+            .build_handler();
+        put(&dav, "/file.txt", &"x".repeat(2500)).await;
+
+        reads.lock().unwrap().clear();
+        let (_, body) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body.len(), 2500);
+        assert_eq!(*reads.lock().unwrap(), vec![1000, 1000, 500]);
+
+        // the configured size wins.
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(RecordFs(MemFs::new(), reads.clone())))
+            .read_buf_size(2000)
+            .build_handler();
+        put(&dav, "/file.txt", &"x".repeat(2500)).await;
+        reads.lock().unwrap().clear();
+        run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(*reads.lock().unwrap(), vec![2000, 500]);
+    }
+
+    #[tokio::test]
+    async fn sniff_content_type() {
+        use http_body_util::BodyExt;
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .sniff_content_type(true)
+            .build_handler();
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&[0; 100]);
+        for path in ["/image", "/image.txt"] {
+            let r = req("PUT", path).body(Body::from(png.clone())).unwrap();
+            run(&dav, r).await;
+        }
+        put(&dav, "/other", "hello").await;
+
+        let resp = dav
+            .handle(req("GET", "/image").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(resp.headers()["content-type"], "image/png");
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, png);
+
+        let r = req("GET", "/image")
+            .header("Range", "bytes=1-3")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(&dav, r).await;
+        assert_eq!(resp.headers()["content-type"], "image/png");
+        assert_eq!(body, "PNG");
+
+        // a known extension is not overruled.
+        let (resp, _) = run(&dav, req("HEAD", "/image.txt").body(Body::empty()).unwrap()).await;
+        assert!(resp.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let (resp, body) = run(&dav, req("GET", "/other").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.headers()["content-type"], "application/octet-stream");
+        assert_eq!(body, "hello");
+    }
+
+    #[tokio::test]
+    async fn index_files() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .index_files(vec!["index.html".to_string(), "index.htm".to_string()])
+            .build_handler();
+        mkcol(&dav, "/site/").await;
+        mkcol(&dav, "/empty/").await;
+        put(&dav, "/site/index.htm", "old").await;
+        put(&dav, "/site/index.html", "<h1>hi</h1>").await;
+
+        let (resp, body) = run(&dav, req("GET", "/site/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "text/html");
+        assert_eq!(body, "<h1>hi</h1>");
+
+        // no index file, and no autoindex.
+        let (resp, _) = run(&dav, req("GET", "/empty/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 404);
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .index_files(vec!["index.html".to_string()])
+            .autoindex(true)
+            .build_handler();
+        mkcol(&dav, "/empty/").await;
+        let (resp, body) = run(&dav, req("GET", "/empty/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert!(body.contains("Index of"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn autoindex_sort() {
+        async fn listing(dav: &DavHandler, uri: &str) -> Vec<String> {
+            let (resp, body) = run(dav, req("GET", uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 200);
+            body.split("<td><a href=\"")
+                .skip(2)
+                .map(|s| s.split('"').next().unwrap().to_string())
+                .collect()
+        }
+
+        for sort in [None, Some((crate::SortBy::Size, true))] {
+            let mut config = DavHandler::builder()
+                .filesystem(MemFs::new())
+                .autoindex(true);
+            if let Some((sort_by, descending)) = sort {
+                config = config.autoindex_sort(sort_by, descending);
+            }
+            let dav = config.build_handler();
+            mkcol(&dav, "/dir/").await;
+            mkcol(&dav, "/dir/sub/").await;
+            put(&dav, "/dir/a.txt", "aa").await;
+            put(&dav, "/dir/b.txt", "bbbbbb").await;
+            put(&dav, "/dir/c.txt", "c").await;
+
+            let by_size = ["/dir/sub/", "/dir/b.txt", "/dir/a.txt", "/dir/c.txt"];
+            let by_name = ["/dir/sub/", "/dir/a.txt", "/dir/b.txt", "/dir/c.txt"];
+            if sort.is_some() {
+                assert_eq!(listing(&dav, "/dir/").await, by_size);
+            } else {
+                assert_eq!(listing(&dav, "/dir/").await, by_name);
+            }
+            assert_eq!(listing(&dav, "/dir/?C=S&O=D").await, by_size);
+            assert_eq!(listing(&dav, "/dir/?C=S;O=D").await, by_size);
+            assert_eq!(listing(&dav, "/dir/?C=N&O=A").await, by_name);
+            assert_eq!(
+                listing(&dav, "/dir/?C=N&O=D").await,
+                ["/dir/sub/", "/dir/c.txt", "/dir/b.txt", "/dir/a.txt"]
+            );
+        }
+
+        // the column headers link to the other orders.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .autoindex(true)
+            .build_handler();
+        let (_, body) = run(&dav, req("GET", "/?C=S&O=A").body(Body::empty()).unwrap()).await;
+        assert!(
+            body.contains(r#"<a href="?C=S&amp;O=D">Size</a>"#),
+            "{}",
+            body
+        );
+        assert!(
+            body.contains(r#"<a href="?C=N&amp;O=A">Name</a>"#),
+            "{}",
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn readahead() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use http_body_util::BodyExt;
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use tokio::sync::Notify;
+
+        // Counts the reads, and notes when the file is dropped.
+        #[derive(Debug, Default)]
+        struct Stats {
+            reads: AtomicUsize,
+            dropped: AtomicBool,
+            changed: Notify,
+        }
+
+        impl Stats {
+            async fn wait_for(&self, done: impl Fn(&Stats) -> bool) {
+                while !done(self) {
+                    self.changed.notified().await;
+                }
+            }
+        }
+
+        #[derive(Clone)]
+        struct CountFs(Box<MemFs>, Arc<Stats>);
+
+        #[derive(Debug)]
+        struct CountFile(Box<dyn DavFile>, Arc<Stats>);
+
+        impl Drop for CountFile {
+            fn drop(&mut self) {
+                self.1.dropped.store(true, Ordering::SeqCst);
+                self.1.changed.notify_one();
+            }
+        }
+
+        impl DavFile for CountFile {
+            fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
+                self.0.metadata()
+            }
+            fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<()> {
+                self.0.write_buf(buf)
+            }
+            fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<()> {
+                self.0.write_bytes(buf)
+            }
+            fn read_bytes(&mut self, count: usize) -> FsFuture<bytes::Bytes> {
+                self.1.reads.fetch_add(1, Ordering::SeqCst);
+                self.1.changed.notify_one();
+                self.0.read_bytes(count)
+            }
+            fn seek(&mut self, pos: std::io::SeekFrom) -> FsFuture<u64> {
+                self.0.seek(pos)
+            }
+            fn flush(&mut self) -> FsFuture<()> {
+                self.0.flush()
+            }
+        }
+
+        #[async_trait]
+        impl ForwardFs for CountFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn open(
+                &self,
+                path: &DavPath,
+                options: OpenOptions,
+            ) -> FsResult<Box<dyn DavFile>> {
+                let file = self.0.open(path, options).await?;
+                Ok(Box::new(CountFile(file, self.1.clone())))
+            }
+        }
+
+        let content: String = (0..10).map(|i| i.to_string().repeat(100)).collect();
+        let memfs = MemFs::new();
+        let rw = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        put(&rw, "/file", &content).await;
+
+        for buffers in [0, 2] {
+            let stats = Arc::new(Stats::default());
+            let dav = DavHandler::builder()
+                .filesystem(Box::new(CountFs(memfs.clone(), stats.clone())))
+                .read_buf_size(100)
+                .readahead(buffers)
+                .build_handler();
+
+            let (_, body) = run(&dav, req("GET", "/file").body(Body::empty()).unwrap()).await;
+            assert_eq!(body, content);
+
+            // while the client is busy with the first chunk, the next
+            // ones are read only if reading ahead. Without, the body
+            // is only read when it is polled.
+            stats.reads.store(0, Ordering::SeqCst);
+            let resp = dav
+                .handle(req("GET", "/file").body(Body::empty()).unwrap())
+                .await;
+            let mut body = resp.into_body();
+            let frame = body.frame().await.unwrap().unwrap();
+            assert_eq!(frame.into_data().unwrap().len(), 100);
+            match buffers {
+                0 => assert_eq!(stats.reads.load(Ordering::SeqCst), 1),
+                _ => stats.wait_for(|s| s.reads.load(Ordering::SeqCst) > 2).await,
+            }
+
+            // a client that goes away stops the reads.
+            drop(body);
+            stats.wait_for(|s| s.dropped.load(Ordering::SeqCst)).await;
+            assert!(stats.reads.load(Ordering::SeqCst) <= 1 + buffers + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn range_not_modified() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "hello world").await;
+        let (resp, _) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+        let modified = resp.headers()["last-modified"]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let get = |header: &'static str, value: String| {
+            let r = req("GET", "/file.txt")
+                .header("Range", "bytes=0-4")
+                .header(header, value);
+            run(&dav, r.body(Body::empty()).unwrap())
+        };
+
+        for (header, value) in [
+            ("If-None-Match", etag.clone()),
+            ("If-Modified-Since", modified),
+        ] {
+            let (resp, body) = get(header, value).await;
+            assert_eq!(resp.status(), 304, "{}", header);
+            assert!(resp.headers().get("content-range").is_none());
+            assert_eq!(body, "");
+        }
+
+        let (resp, body) = get("If-None-Match", "\"other\"".to_string()).await;
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.headers()["content-range"], "bytes 0-4/11");
+        assert_eq!(body, "hello");
+
+        // a failed If-Match wins over the Range, too.
+        let (resp, _) = get("If-Match", "\"other\"".to_string()).await;
+        assert_eq!(resp.status(), 412);
+    }
+
+    #[tokio::test]
+    async fn archive_download() {
+        let fs = MemFs::new();
+        let rw = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        mkcol(&rw, "/folder/").await;
+        mkcol(&rw, "/folder/sub/").await;
+        put(&rw, "/folder/a.txt", "hello").await;
+        put(&rw, "/folder/skip.tmp", "hidden").await;
+        let big = "0123456789".repeat(60);
+        put(&rw, "/folder/sub/b.txt", &big).await;
+        put(&rw, "/other.txt", "not included").await;
+
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .allow_archive_download(true)
+            .hide_patterns(vec!["*.tmp".to_string()])
+            .build_handler();
+
+        let r = req("GET", "/folder/?archive=tar")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(&dav, r).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "application/x-tar");
+        assert_eq!(
+            resp.headers()["content-disposition"],
+            "attachment; filename=\"folder.tar\""
+        );
+
+        // walk over the archive.
+        let data = body.as_bytes();
+        assert_eq!(data.len() % 512, 0);
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while data[pos..pos + 512].iter().any(|&c| c != 0) {
+            let hdr = &data[pos..pos + 512];
+            let name = String::from_utf8(hdr[..100].split(|&c| c == 0).next().unwrap().to_vec());
+            let size = std::str::from_utf8(&hdr[124..135]).unwrap();
+            let size = usize::from_str_radix(size, 8).unwrap();
+            let content = &data[pos + 512..pos + 512 + size];
+            entries.push((
+                name.unwrap(),
+                hdr[156],
+                String::from_utf8(content.to_vec()).unwrap(),
+            ));
+            pos += 512 + size.div_ceil(512) * 512;
+        }
+        assert!(data[pos..].iter().all(|&c| c == 0));
+        assert_eq!(data.len() - pos, 1024);
+
+        let names: Vec<_> = entries.iter().map(|e| (e.0.as_str(), e.1)).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("folder/", b'5'),
+                ("folder/a.txt", b'0'),
+                ("folder/sub/", b'5'),
+                ("folder/sub/b.txt", b'0'),
+            ]
+        );
+        assert_eq!(entries[1].2, "hello");
+        assert_eq!(entries[3].2, big);
+
+        // only when enabled, and only when asked for.
+        let r = req("GET", "/folder/").body(Body::empty()).unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert!(resp.headers().get("content-disposition").is_none());
+        let r = req("GET", "/folder/?archive=tar")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, _) = run(&rw, r).await;
+        assert!(resp.headers().get("content-disposition").is_none());
+    }
+
+    #[cfg(all(unix, feature = "localfs"))]
+    #[tokio::test]
+    async fn archive_skips_fifo() {
+        use crate::localfs::LocalFs;
+        use std::os::unix::ffi::OsStringExt;
+        use std::time::Duration;
+
+        let dir = std::env::temp_dir().join(format!("dav-server-fifo-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "hello").unwrap();
+        let fifo = std::ffi::CString::new(dir.join("fifo").into_os_string().into_vec()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        let dav = DavHandler::builder()
+            .filesystem(LocalFs::new(&dir, false, false, false))
+            .allow_archive_download(true)
+            .build_handler();
+        let r = req("GET", "/?archive=tar").body(Body::empty()).unwrap();
+        // opening the FIFO would block forever.
+        let (resp, body) = tokio::time::timeout(Duration::from_secs(10), run(&dav, r))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert!(body.contains("a.txt") && body.contains("hello"));
+        assert!(!body.contains("fifo"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_until_eof() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+
+        // Files that grow once they have been read up to their end.
+        #[derive(Clone)]
+        struct GrowFs(Box<MemFs>);
+
+        #[derive(Debug)]
+        struct GrowFile(Box<dyn DavFile>, Option<&'static str>);
+
+        impl DavFile for GrowFile {
+            fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
+                self.0.metadata()
+            }
+            fn write_buf(&mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<()> {
+                self.0.write_buf(buf)
+            }
+            fn write_bytes(&mut self, buf: bytes::Bytes) -> FsFuture<()> {
+                self.0.write_bytes(buf)
+            }
+            fn read_bytes(&mut self, count: usize) -> FsFuture<bytes::Bytes> {
+                Box::pin(async move {
+                    let buf = self.0.read_bytes(count).await?;
+                    match self.1.take() {
+                        Some(more) if buf.is_empty() => Ok(bytes::Bytes::from(more)),
+                        more => {
+                            self.1 = more;
+                            Ok(buf)
+                        }
+                    }
+                })
+            }
+            fn seek(&mut self, pos: std::io::SeekFrom) -> FsFuture<u64> {
+                self.0.seek(pos)
+            }
+            fn flush(&mut self) -> FsFuture<()> {
+                self.0.flush()
+            }
+        }
+
+        #[async_trait]
+        impl ForwardFs for GrowFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn open(
+                &self,
+                path: &DavPath,
+                options: OpenOptions,
+            ) -> FsResult<Box<dyn DavFile>> {
+                let file = self.0.open(path, options).await?;
+                Ok(Box::new(GrowFile(file, Some("line 2\n"))))
+            }
+        }
+
+        let fs = MemFs::new();
+        let plain = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        put(&plain, "/log.txt", "line 1\n").await;
+
+        // the size at open is all that is sent.
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(GrowFs(fs.clone())))
+            .read_buf_size(4)
+            .build_handler();
+        let (resp, body) = run(&dav, req("GET", "/log.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.headers()["content-length"], "7");
+        assert_eq!(body, "line 1\n");
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(GrowFs(fs)))
+            .read_buf_size(4)
+            .read_until_eof(true)
+            .build_handler();
+        let r = req("GET", "/log.txt")
+            .header("Range", "bytes=0-3")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, body) = run(&dav, r).await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get("content-length").is_none());
+        assert_eq!(resp.headers()["accept-ranges"], "none");
+        assert_eq!(body, "line 1\nline 2\n");
+
+        let (resp, body) = run(&dav, req("HEAD", "/log.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get("content-length").is_none());
+        assert_eq!(body, "");
+    }
+}
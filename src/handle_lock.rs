@@ -14,7 +14,8 @@ use crate::davpath::DavPath;
 use crate::errors::*;
 use crate::fs::{FsError, OpenOptions};
 use crate::ls::*;
-use crate::util::MemBuffer;
+use crate::multierror::multi_error;
+use crate::util::{dav_xml_error, MemBuffer};
 use crate::xmltree_ext::{self, ElementExt};
 use crate::DavResult;
 
@@ -68,12 +69,26 @@ impl crate::DavInner {
         }
 
         // handle Depth:
-        let deep = match req.headers().typed_get::<davheaders::Depth>() {
+        let depth = req.headers().typed_try_get::<davheaders::Depth>();
+        let mut deep = match depth.map_err(|_| DavError::Status(SC::BAD_REQUEST))? {
             Some(davheaders::Depth::Infinity) | None => true,
             Some(davheaders::Depth::Zero) => false,
             _ => return Err(SC::BAD_REQUEST.into()),
         };
 
+        // are infinite-depth locks allowed? on a non-collection
+        // "infinity" is the same as "0", so just drop it.
+        if deep && self.max_lock_depth == Some(LockDepth::Zero) {
+            if meta.as_ref().map(|m| m.is_dir()).unwrap_or(false) {
+                let ct = "application/xml; charset=utf-8".to_owned();
+                res.headers_mut().typed_insert(davheaders::ContentType(ct));
+                *res.status_mut() = SC::FORBIDDEN;
+                *res.body_mut() = dav_xml_error("<D:depth-lock-not-allowed/>");
+                return Ok(res);
+            }
+            deep = false;
+        }
+
         // handle the if-headers.
         if let Some(s) = if_match(req, meta.as_ref(), &self.fs, &self.ls, &path).await {
             return Err(s.into());
@@ -145,7 +160,18 @@ impl crate::DavInner {
         let principal = self.principal.as_deref();
         let lock = match locksystem.lock(&path, principal, owner.as_ref(), timeout, shared, deep) {
             Ok(lock) => lock,
-            Err(_) => return Err(SC::LOCKED.into()),
+            Err(conflict) => {
+                // a deep lock that conflicts with locks on members is
+                // answered with a multistatus that lists them (RFC4918 9.10.9).
+                let members = member_conflicts(&**locksystem, &path, &conflict, shared);
+                if !deep || members.is_empty() {
+                    return Err(SC::LOCKED.into());
+                }
+                let mut items: Vec<DavResult<(DavPath, SC)>> =
+                    members.into_iter().map(|p| Ok((p, SC::LOCKED))).collect();
+                items.push(Ok((path.clone(), SC::FAILED_DEPENDENCY)));
+                return multi_error(path, futures_util::stream::iter(items)).await;
+            }
         };
 
         // try to create file if it doesn't exist.
@@ -268,6 +294,38 @@ pub(crate) fn list_supportedlock(ls: Option<&Box<dyn DavLockSystem>>) -> Element
 }
 
 // process timeout header
+
+// The members of `path` that have a lock that conflicts with a new lock,
+// or an empty list if `conflict` is not on a member.
+fn member_conflicts(
+    ls: &dyn DavLockSystem,
+    path: &DavPath,
+    conflict: &DavLock,
+    shared: bool,
+) -> Vec<DavPath> {
+    let mut base = path.as_bytes().to_vec();
+    if !base.ends_with(b"/") {
+        base.push(b'/');
+    }
+    let is_member = |p: &DavPath| {
+        let p = p.as_bytes();
+        p.len() > base.len() && p.starts_with(&base)
+    };
+    if !is_member(&conflict.path) {
+        return Vec::new();
+    }
+    let mut paths = ls
+        .list()
+        .into_iter()
+        .filter(|l| is_member(&l.path) && (!shared || !l.shared))
+        .map(|l| l.path)
+        .collect::<Vec<_>>();
+    paths.push(conflict.path.clone());
+    paths.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+    paths.dedup_by(|a, b| a.as_bytes() == b.as_bytes());
+    paths
+}
+
 fn get_timeout(req: &Request<()>, refresh: bool, shared: bool) -> Option<Duration> {
     let max_timeout = if shared {
         Duration::new(86400, 0)
@@ -340,3 +398,112 @@ fn build_lock_prop(lock: &DavLock, full: bool) -> Element {
 
     prop
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::tests::{mkcol, req, run};
+    use crate::DavHandler;
+
+    const LOCKINFO: &str = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+
+    async fn lock(dav: &DavHandler, depth: &str) -> (u16, String) {
+        let r = req("LOCK", "/dir/")
+            .header("Depth", depth)
+            .body(Body::from(LOCKINFO))
+            .unwrap();
+        let (resp, body) = run(dav, r).await;
+        (resp.status().as_u16(), body)
+    }
+
+    #[tokio::test]
+    async fn lock_depth_infinity() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        mkcol(&dav, "/dir/").await;
+        let (status, body) = lock(&dav, "infinity").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("<D:depth>Infinity</D:depth>"));
+    }
+
+    #[tokio::test]
+    async fn lock_depth_zero_only() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .max_lock_depth(LockDepth::Zero)
+            .build_handler();
+        mkcol(&dav, "/dir/").await;
+        let (status, body) = lock(&dav, "infinity").await;
+        assert_eq!(status, 403);
+        assert!(body.contains("depth-lock-not-allowed"));
+        let (status, body) = lock(&dav, "0").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("<D:depth>0</D:depth>"));
+    }
+
+    #[tokio::test]
+    async fn lock_conflicting_member() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        mkcol(&dav, "/dir/").await;
+        mkcol(&dav, "/dir/sub/").await;
+        let r = req("LOCK", "/dir/sub/")
+            .header("Depth", "0")
+            .body(Body::from(LOCKINFO))
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 200);
+
+        let (status, body) = lock(&dav, "infinity").await;
+        assert_eq!(status, 207, "{}", body);
+        assert!(body.contains("<D:href>/dir/sub/</D:href>"), "{}", body);
+        assert!(body.contains("HTTP/1.1 423 Locked"), "{}", body);
+        assert!(body.contains("<D:href>/dir/</D:href>"), "{}", body);
+        assert!(body.contains("HTTP/1.1 424 Failed Dependency"), "{}", body);
+
+        // the lock was not granted.
+        assert_eq!(dav.list_locks().len(), 1);
+        let (status, _) = lock(&dav, "0").await;
+        assert_eq!(status, 200);
+    }
+
+    #[tokio::test]
+    async fn lock_unmapped_url() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let r = req("LOCK", "/new.txt").body(Body::from(LOCKINFO)).unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert_eq!(resp.status(), 201);
+        let token = resp.headers()["Lock-Token"].to_str().unwrap().to_string();
+
+        // without the token, the resource cannot be written.
+        let r = req("PUT", "/new.txt").body(Body::from("x")).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 423);
+        let r = req("PUT", "/new.txt")
+            .header("If", "(Not <DAV:no-lock>)")
+            .body(Body::from("x"))
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 423);
+
+        let r = req("PUT", "/new.txt")
+            .header("If", format!("({})", token))
+            .body(Body::from("hello"))
+            .unwrap();
+        assert!(run(&dav, r).await.0.status().is_success());
+        let r = req("PUT", "/new.txt")
+            .header("If", format!("</new.txt> ({})", token))
+            .body(Body::from("hello"))
+            .unwrap();
+        assert!(run(&dav, r).await.0.status().is_success());
+        let (_, body) = run(&dav, req("GET", "/new.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "hello");
+    }
+}
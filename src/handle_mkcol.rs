@@ -10,6 +10,9 @@ use crate::{DavError, DavResult};
 impl crate::DavInner {
     pub(crate) async fn handle_mkcol(&self, req: &Request<()>) -> DavResult<Response<Body>> {
         let mut path = self.path(req);
+        if self.is_nonportable(&path) {
+            return Err(DavError::Status(StatusCode::FORBIDDEN));
+        }
         let meta = self.fs.metadata(&path).await;
 
         // check the If and If-* headers.
@@ -38,9 +41,8 @@ impl crate::DavInner {
             Ok(()) => {
                 if path.is_collection() {
                     path.add_slash();
-                    res.headers_mut().typed_insert(davheaders::ContentLocation(
-                        path.with_prefix().as_url_string(),
-                    ));
+                    res.headers_mut()
+                        .typed_insert(davheaders::ContentLocation(self.self_url(&path)));
                 }
                 *res.status_mut() = StatusCode::CREATED;
             }
@@ -49,3 +51,23 @@ impl crate::DavInner {
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{req, run};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn mkcol_with_body() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+
+        let r = req("MKCOL", "/dir/").body(Body::from("<x/>")).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 415);
+        let r = req("MKCOL", "/dir/").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+    }
+}
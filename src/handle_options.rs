@@ -2,9 +2,28 @@ use headers::HeaderMapExt;
 use http::{Request, Response};
 
 use crate::body::Body;
+use crate::fs::FsError;
 use crate::util::{dav_method, DavMethod};
 use crate::DavResult;
 
+// All methods, in the order they are listed in the Public header.
+const PUBLIC: &[(&str, DavMethod)] = &[
+    ("OPTIONS", DavMethod::Options),
+    ("GET", DavMethod::Get),
+    ("HEAD", DavMethod::Head),
+    ("PUT", DavMethod::Put),
+    ("PATCH", DavMethod::Patch),
+    ("POST", DavMethod::Post),
+    ("DELETE", DavMethod::Delete),
+    ("MKCOL", DavMethod::MkCol),
+    ("PROPFIND", DavMethod::PropFind),
+    ("PROPPATCH", DavMethod::PropPatch),
+    ("COPY", DavMethod::Copy),
+    ("MOVE", DavMethod::Move),
+    ("LOCK", DavMethod::Lock),
+    ("UNLOCK", DavMethod::Unlock),
+];
+
 impl crate::DavInner {
     pub(crate) async fn handle_options(&self, req: &Request<()>) -> DavResult<Response<Body>> {
         let mut res = Response::new(Body::empty());
@@ -34,11 +53,22 @@ impl crate::DavInner {
             }
         };
 
+        // "OPTIONS *" is about the server, not about a resource, so
+        // the filesystem is not asked (RFC9110 9.3.7).
         let path = self.path(req);
-        let meta = self.fs.metadata(&path).await;
-        let is_unmapped = meta.is_err();
-        let is_file = meta.map(|m| m.is_file()).unwrap_or_default();
         let is_star = path.is_star() && method == DavMethod::Options;
+        let meta = if is_star {
+            Err(FsError::NotFound)
+        } else {
+            self.fs.metadata(&path).await
+        };
+        let is_unmapped = meta.is_err();
+        let is_file = meta.as_ref().map(|m| m.is_file()).unwrap_or_default();
+
+        // a collection can be read if it has an index.
+        let has_index = self.indexfile.is_some()
+            || matches!(self.index_files, Some(ref f) if !f.is_empty())
+            || self.autoindex_enabled();
 
         let mut v = Vec::new();
         if is_unmapped && !is_star {
@@ -47,26 +77,188 @@ impl crate::DavInner {
             mm(&mut v, "PUT", DavMethod::Put);
             mm(&mut v, "LOCK", DavMethod::Lock);
         } else {
-            if is_file || is_star {
+            if is_file || is_star || has_index {
                 mm(&mut v, "HEAD", DavMethod::Head);
                 mm(&mut v, "GET", DavMethod::Get);
+            }
+            if is_file || is_star {
                 mm(&mut v, "PATCH", DavMethod::Patch);
                 mm(&mut v, "PUT", DavMethod::Put);
             }
+            if !is_file && !is_star && self.allow_post_create.unwrap_or(false) {
+                mm(&mut v, "POST", DavMethod::Post);
+            }
             mm(&mut v, "OPTIONS", DavMethod::Options);
             mm(&mut v, "PROPFIND", DavMethod::PropFind);
+            mm(&mut v, "PROPPATCH", DavMethod::PropPatch);
             mm(&mut v, "COPY", DavMethod::Copy);
-            if path.as_url_string() != "/" {
+            if self.protect_root(&path).is_none() {
                 mm(&mut v, "MOVE", DavMethod::Move);
                 mm(&mut v, "DELETE", DavMethod::Delete);
             }
             mm(&mut v, "LOCK", DavMethod::Lock);
             mm(&mut v, "UNLOCK", DavMethod::Unlock);
+            if matches!(self.report_handlers, Some(ref r) if !r.is_empty()) {
+                mm(&mut v, "REPORT", DavMethod::Report);
+            }
         }
 
         let a = v.join(",").parse().unwrap();
         res.headers_mut().insert("allow", a);
 
+        // Office wants to see the methods of the server as a whole.
+        if self.microsoft_compat.unwrap_or(false) {
+            let mut v = Vec::new();
+            for (m, y) in PUBLIC {
+                if (!islock(*y) || self.ls.is_some())
+                    && (*y != DavMethod::Post || self.allow_post_create.unwrap_or(false))
+                    && self.allow.map(|x| x.contains(*y)).unwrap_or(true)
+                {
+                    v.push(*m);
+                }
+            }
+            res.headers_mut()
+                .insert("public", v.join(",").parse().unwrap());
+        }
+
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{mkcol, put, req, run};
+    use crate::{DavHandler, DavMethodSet};
+
+    async fn allow(dav: &DavHandler, path: &str) -> String {
+        let (resp, _) = run(dav, req("OPTIONS", path).body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        resp.headers()["allow"].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn options_allow() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+        mkcol(&dav, "/dir/").await;
+
+        assert_eq!(
+            allow(&dav, "/file.txt").await,
+            "HEAD,GET,PATCH,PUT,OPTIONS,PROPFIND,PROPPATCH,COPY,MOVE,DELETE"
+        );
+        assert_eq!(
+            allow(&dav, "/dir/").await,
+            "OPTIONS,PROPFIND,PROPPATCH,COPY,MOVE,DELETE"
+        );
+        assert_eq!(allow(&dav, "/").await, "OPTIONS,PROPFIND,PROPPATCH,COPY");
+        assert_eq!(allow(&dav, "/missing").await, "OPTIONS,MKCOL,PUT");
+
+        // read-only. PROPFIND is allowed, so there are directory indexes.
+        let fs = MemFs::new();
+        let rw = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        mkcol(&rw, "/dir/").await;
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .methods(DavMethodSet::WEBDAV_RO)
+            .build_handler();
+        assert_eq!(allow(&dav, "/dir/").await, "HEAD,GET,OPTIONS,PROPFIND");
+        assert_eq!(allow(&dav, "/missing").await, "OPTIONS");
+    }
+
+    #[tokio::test]
+    async fn options_star() {
+        use crate::testutil::{FaultFs, FsOp};
+
+        // the filesystem is not used at all.
+        let fs = FaultFs::new(MemFs::new());
+        fs.fail(FsOp::Metadata, crate::fs::FsError::GeneralFailure);
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .locksystem(crate::memls::MemLs::new())
+            .build_handler();
+        let (resp, body) = run(&dav, req("OPTIONS", "*").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(body, "");
+        assert_eq!(resp.headers()["dav"], "1,2,3,sabredav-partialupdate");
+        assert_eq!(
+            resp.headers()["allow"],
+            "HEAD,GET,PATCH,PUT,OPTIONS,PROPFIND,PROPPATCH,COPY,MOVE,DELETE,LOCK,UNLOCK"
+        );
+    }
+
+    #[tokio::test]
+    async fn method_not_allowed() {
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        let all = "HEAD,GET,PATCH,PUT,OPTIONS,PROPFIND,PROPPATCH,COPY,MOVE,DELETE";
+        for method in ["TRACE", "CONNECT", "FROBNICATE"] {
+            let (resp, body) =
+                run(&dav, req(method, "/file.txt").body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 405, "{}", method);
+            assert_eq!(resp.headers()["allow"], all, "{}", method);
+            assert_eq!(body, "");
+        }
+
+        // methods that are not enabled.
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .methods(DavMethodSet::WEBDAV_RO)
+            .build_handler();
+        let (resp, _) = run(
+            &dav,
+            req("DELETE", "/file.txt").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(resp.status(), 405);
+        assert_eq!(resp.headers()["allow"], "HEAD,GET,OPTIONS,PROPFIND");
+    }
+
+    #[tokio::test]
+    async fn microsoft_compat() {
+        let public = |dav: DavHandler| async move {
+            let (resp, _) = run(
+                &dav,
+                req("OPTIONS", "/file.txt").body(Body::empty()).unwrap(),
+            )
+            .await;
+            assert_eq!(resp.headers()["ms-author-via"], "DAV");
+            resp.headers()
+                .get("public")
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let builder = || DavHandler::builder().filesystem(MemFs::new());
+        assert_eq!(public(builder().build_handler()).await, None);
+        assert_eq!(
+            public(builder().microsoft_compat(true).build_handler())
+                .await
+                .as_deref(),
+            Some("OPTIONS,GET,HEAD,PUT,PATCH,DELETE,MKCOL,PROPFIND,PROPPATCH,COPY,MOVE")
+        );
+        let dav = builder()
+            .microsoft_compat(true)
+            .fake_locking()
+            .methods(DavMethodSet::WEBDAV_RW)
+            .build_handler();
+        assert_eq!(
+            public(dav).await.as_deref(),
+            Some(
+                "OPTIONS,GET,HEAD,PUT,PATCH,DELETE,MKCOL,PROPFIND,PROPPATCH,COPY,MOVE,LOCK,UNLOCK"
+            )
+        );
+        let dav = builder()
+            .microsoft_compat(true)
+            .methods(DavMethodSet::WEBDAV_RO)
+            .build_handler();
+        assert_eq!(
+            public(dav).await.as_deref(),
+            Some("OPTIONS,GET,HEAD,PROPFIND")
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::error::Error as StdError;
+
+use bytes::Buf;
+use headers::HeaderMapExt;
+use http::{Request, Response, StatusCode};
+use http_body::Body as HttpBody;
+use percent_encoding::percent_decode_str;
+
+use crate::body::Body;
+use crate::conditional::if_match_get_tokens;
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::{DavError, DavResult};
+
+// Longest name made from a Slug, in characters.
+const MAX_SLUG: usize = 100;
+// How many numbered names are tried before giving up.
+const MAX_TRIES: usize = 1000;
+
+// Turn a Slug header (RFC5023 9.7) into a safe file name.
+fn slug_to_name(slug: &str) -> String {
+    let slug = percent_decode_str(slug.trim()).decode_utf8_lossy();
+    let mut name = String::new();
+    for c in slug.chars() {
+        if c.is_alphanumeric() || c == '.' || c == '_' || c == '-' {
+            name.push(c);
+        } else if !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name: String = name
+        .trim_matches(['-', '.'])
+        .chars()
+        .take(MAX_SLUG)
+        .collect();
+    match name.trim_end_matches(['-', '.']) {
+        "" => "untitled".to_string(),
+        name => name.to_string(),
+    }
+}
+
+// "name.txt" -> "name-2.txt".
+fn numbered(name: &str, n: usize) -> String {
+    match name.rfind('.') {
+        Some(dot) => format!("{}-{}{}", &name[..dot], n, &name[dot..]),
+        None => format!("{}-{}", name, n),
+    }
+}
+
+impl crate::DavInner {
+    // POST to a collection creates a new member, named after the Slug
+    // header. The body is stored like with PUT.
+    pub(crate) async fn handle_post<ReqBody, ReqData, ReqError>(
+        self,
+        req: &Request<()>,
+        body: ReqBody,
+    ) -> DavResult<Response<Body>>
+    where
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+    {
+        let dir = self.path(req);
+        let meta = self.fs.metadata(&dir).await?;
+        if !meta.is_dir() {
+            return Err(DavError::StatusClose(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        let slug = req.headers().get("slug").and_then(|s| s.to_str().ok());
+        let name = slug_to_name(slug.unwrap_or(""));
+
+        // the If header is about the collection that was posted to.
+        let tokens = if_match_get_tokens(req, Some(&meta), &self.fs, &self.ls, &dir);
+        let tokens = tokens.await.map_err(DavError::StatusClose)?;
+        let tokens = tokens.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+
+        // find a free name, and claim it by creating an empty file. The
+        // name is checked first, like PUT would have done.
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        oo.create_new = true;
+        let mut path = None;
+        for n in 1..=MAX_TRIES {
+            let mut p = dir.clone();
+            p.add_slash();
+            match n {
+                1 => p.push_segment(name.as_bytes()),
+                n => p.push_segment(numbered(&name, n).as_bytes()),
+            }
+            if self.is_hidden(&p) || self.is_nonportable(&p) {
+                return Err(DavError::StatusClose(StatusCode::FORBIDDEN));
+            }
+            if let Some(ref locksystem) = self.ls {
+                let principal = self.principal.as_deref();
+                if locksystem
+                    .check(&p, principal, false, false, tokens.clone())
+                    .is_err()
+                {
+                    return Err(DavError::StatusClose(StatusCode::LOCKED));
+                }
+            }
+            match self.fs.open(&p, oo.clone()).await {
+                Ok(_) => {
+                    path = Some(p);
+                    break;
+                }
+                Err(FsError::Exists) => continue,
+                Err(e) => return Err(DavError::FsError(e)),
+            }
+        }
+        let path: DavPath = path.ok_or(DavError::StatusClose(StatusCode::CONFLICT))?;
+        debug!("POST: creating {}", path);
+
+        // now it is a PUT of the new file.
+        let mut put = Request::new(());
+        *put.method_mut() = req.method().clone();
+        *put.headers_mut() = req.headers().clone();
+        *put.uri_mut() = path
+            .with_prefix()
+            .as_url_string()
+            .parse()
+            .map_err(|_| DavError::StatusClose(StatusCode::BAD_REQUEST))?;
+        let mut res = match self.clone().handle_put(&put, body).await {
+            Ok(res) if res.status().is_success() => res,
+            other => {
+                let _ = self.fs.remove_file(&path).await;
+                return other;
+            }
+        };
+
+        *res.status_mut() = StatusCode::CREATED;
+        res.headers_mut()
+            .insert("Location", self.self_url(&path).parse().unwrap());
+        res.headers_mut().typed_insert(headers::ContentLength(0));
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+    use crate::tests::{mkcol, req, run};
+    use crate::DavHandler;
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slug_to_name("My Report.txt"), "My-Report.txt");
+        assert_eq!(slug_to_name("caf%C3%A9 %26 bar"), "café-bar");
+        assert_eq!(slug_to_name("../../etc/passwd"), "etc-passwd");
+        assert_eq!(slug_to_name(".hidden"), "hidden");
+        assert_eq!(slug_to_name("///"), "untitled");
+        assert_eq!(slug_to_name(""), "untitled");
+        assert_eq!(slug_to_name(&"x".repeat(300)).len(), MAX_SLUG);
+        assert_eq!(numbered("notes.txt", 2), "notes-2.txt");
+        assert_eq!(numbered("notes", 3), "notes-3");
+    }
+
+    #[tokio::test]
+    async fn post_create() {
+        let fs = MemFs::new();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .allow_post_create(true)
+            .build_handler();
+        mkcol(&dav, "/inbox/").await;
+
+        let post = |slug: &'static str, data: &'static str| {
+            let r = req("POST", "/inbox/").header("Slug", slug);
+            run(&dav, r.body(Body::from(data)).unwrap())
+        };
+        let (resp, _) = post("Meeting notes.txt", "first").await;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(resp.headers()["location"], "/inbox/Meeting-notes.txt");
+        let (resp, _) = post("Meeting notes.txt", "second").await;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(resp.headers()["location"], "/inbox/Meeting-notes-2.txt");
+        assert!(resp.headers().contains_key("etag"));
+
+        for (path, data) in [
+            ("/inbox/Meeting-notes.txt", "first"),
+            ("/inbox/Meeting-notes-2.txt", "second"),
+        ] {
+            let (resp, body) = run(&dav, req("GET", path).body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(body, data);
+        }
+
+        // only collections, and only when enabled.
+        let r = req("POST", "/inbox/Meeting-notes.txt").header("Slug", "x");
+        let (resp, _) = run(&dav, r.body(Body::from("x")).unwrap()).await;
+        assert_eq!(resp.status(), 405);
+        let plain = DavHandler::builder().filesystem(fs).build_handler();
+        let r = req("POST", "/inbox/").header("Slug", "x");
+        let (resp, _) = run(&plain, r.body(Body::from("x")).unwrap()).await;
+        assert_eq!(resp.status(), 405);
+        assert!(!resp.headers()["allow"].to_str().unwrap().contains("POST"));
+
+        let (resp, _) = run(&dav, req("OPTIONS", "/inbox/").body(Body::empty()).unwrap()).await;
+        assert!(resp.headers()["allow"].to_str().unwrap().contains("POST"));
+    }
+
+    #[tokio::test]
+    async fn post_checks_name() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(crate::memls::MemLs::new())
+            .allow_post_create(true)
+            .reject_nonportable_names(true)
+            .build_handler();
+        mkcol(&dav, "/inbox/").await;
+        mkcol(&dav, "/locked/").await;
+        let r = req("LOCK", "/locked/").body(Body::from(
+            r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#,
+        ));
+        let (resp, _) = run(&dav, r.unwrap()).await;
+        let token = resp.headers()["lock-token"].to_str().unwrap().to_string();
+
+        let post = |dir: &'static str, slug: &'static str| {
+            let r = req("POST", dir).header("Slug", slug);
+            run(&dav, r.body(Body::from("data")).unwrap())
+        };
+        assert_eq!(post("/inbox/", "CON").await.0.status(), 403);
+        assert_eq!(post("/locked/", "x.txt").await.0.status(), 423);
+
+        // nothing was created.
+        for path in ["/inbox/CON", "/locked/x.txt"] {
+            let (resp, _) = run(&dav, req("GET", path).body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 404, "{}", path);
+        }
+
+        // with the lock token it works.
+        let r = req("POST", "/locked/")
+            .header("Slug", "x.txt")
+            .header("If", format!("({})", token));
+        let (resp, _) = run(&dav, r.body(Body::from("data")).unwrap()).await;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(resp.headers()["location"], "/locked/x.txt");
+    }
+}
@@ -2,14 +2,16 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io::Cursor;
+use std::time::SystemTime;
 
 use async_stream::try_stream;
 use bytes::Bytes;
+use futures_util::future::{BoxFuture, FutureExt};
 use futures_util::stream::BoxStream;
 use futures_util::Stream;
 use futures_util::StreamExt;
 use headers::HeaderMapExt;
-use http::{Request, Response, StatusCode};
+use http::{HeaderValue, Request, Response, StatusCode};
 
 use crate::xmltree_ext::*;
 use xml::common::XmlVersion;
@@ -19,13 +21,14 @@ use xml::EmitterConfig;
 use xmltree::{Element, XMLNode};
 
 use crate::body::Body;
-use crate::conditional::if_match_get_tokens;
+use crate::conditional::{if_match_get_tokens, if_match_missing};
 use crate::davheaders;
 use crate::davpath::*;
 use crate::errors::*;
 use crate::fs::*;
 use crate::handle_lock::{list_lockdiscovery, list_supportedlock};
 use crate::ls::*;
+use crate::report::clark_name;
 use crate::util::MemBuffer;
 use crate::util::{dav_xml_error, systemtime_to_httpdate, systemtime_to_rfc3339};
 use crate::{DavInner, DavResult};
@@ -105,6 +108,11 @@ struct PropWriter {
     ls: Option<Box<dyn DavLockSystem>>,
     useragent: String,
     q_cache: QuotaCache,
+    minimal: bool,
+    // (namespace, prefix) to use instead of the usual ones.
+    prefixes: Vec<(String, String)>,
+    // collections that could not be listed completely.
+    incomplete: Vec<String>,
 }
 
 #[derive(Default, Clone, Copy)]
@@ -144,9 +152,12 @@ impl DavInner {
             .typed_insert(headers::CacheControl::new().with_no_cache());
         res.headers_mut().typed_insert(headers::Pragma::no_cache());
 
-        let depth = match req.headers().typed_get::<davheaders::Depth>() {
+        let depth = req.headers().typed_try_get::<davheaders::Depth>();
+        let depth = match depth.map_err(|_| DavError::Status(StatusCode::BAD_REQUEST))? {
             Some(davheaders::Depth::Infinity) | None => {
-                if req.headers().typed_get::<davheaders::XLitmus>().is_none() {
+                if !self.propfind_depth_infinity.unwrap_or(false)
+                    && req.headers().typed_get::<davheaders::XLitmus>().is_none()
+                {
                     let ct = "application/xml; charset=utf-8".to_owned();
                     res.headers_mut().typed_insert(davheaders::ContentType(ct));
                     *res.status_mut() = StatusCode::FORBIDDEN;
@@ -160,20 +171,30 @@ impl DavInner {
 
         // path and meta
         let mut path = self.path(req);
-        let meta = self.fs.metadata(&path).await?;
+        let meta = match self.fs.metadata(&path).await {
+            Ok(meta) => meta,
+            // a lock-null resource: it is locked, but does not exist.
+            Err(FsError::NotFound) if self.is_lock_null(&path) => Box::new(LockNullMeta),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(res) = self.follow_redirect_ref(req, &*meta)? {
+            return Ok(res);
+        }
         let meta = self.fixpath(&mut res, &mut path, meta);
 
+        // an empty body means allprop (RFC4918 9.1).
         let mut root = None;
-        if !xmldata.is_empty() {
+        if !xmldata.iter().all(|c| c.is_ascii_whitespace()) {
             root = match Element::parse(Cursor::new(xmldata)) {
                 Ok(t) => {
                     if t.name == "propfind" && t.namespace.as_deref() == Some("DAV:") {
                         Some(t)
                     } else {
-                        return Err(DavError::XmlParseError);
+                        let msg = format!("expected DAV:propfind, found {}", clark_name(&t));
+                        return Err(DavError::XmlError(msg));
                     }
                 }
-                Err(_) => return Err(DavError::XmlParseError),
+                Err(e) => return Err(DavError::XmlError(format!("propfind: {}", e))),
             };
         }
 
@@ -193,22 +214,66 @@ impl DavInner {
                         "allprop" => ("allprop", includes),
                         _ => return Err(DavError::XmlParseError),
                     },
-                    None => return Err(DavError::XmlParseError),
+                    None => {
+                        let msg = "DAV:propfind must contain DAV:prop, DAV:allprop or DAV:propname";
+                        return Err(DavError::XmlError(msg.to_string()));
+                    }
                 }
             }
         };
 
         trace!("propfind: type request: {}", name);
 
-        let mut pw = PropWriter::new(req, &mut res, name, props, &self.fs, self.ls.as_ref())?;
+        // read the directory before we start sending a 207 response,
+        // so that errors like a symlink loop get a proper status.
+        // An unreadable directory is still listed, without members.
+        let mut entries = None;
+        if meta.is_dir() && depth != davheaders::Depth::Zero {
+            match self.fs.read_dir(&path, self.readdir_meta()).await {
+                Ok(e) => entries = Some(e),
+                Err(e @ FsError::Forbidden) => error!("read_dir error {:?}", e),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let prefixes = self.xml_prefixes.clone().unwrap_or_default();
+        let mut pw = PropWriter::new(
+            req,
+            &mut res,
+            name,
+            props,
+            &self.fs,
+            self.ls.as_ref(),
+            prefixes,
+        )?;
+
+        // leave out the properties that were not found?
+        let mut applied = Vec::new();
+        if prefer(req, "return=minimal") {
+            applied.push("return=minimal");
+            pw.minimal = true;
+        } else if brief(req) {
+            pw.minimal = true;
+        }
+
+        // leave out the collection itself?
+        let noroot = entries.is_some() && prefer(req, "depth-noroot");
+        if noroot {
+            applied.push("depth-noroot");
+        }
+        if !applied.is_empty() {
+            let applied = HeaderValue::from_str(&applied.join(", ")).unwrap();
+            res.headers_mut().insert("Preference-Applied", applied);
+        }
 
         let body_stream = try_stream! {
-            let is_dir = meta.is_dir();
-            pw.write_props(&path, meta).await?;
-            yield pw.flush();
+            if !noroot {
+                pw.write_props(&path, meta).await?;
+                yield pw.flush();
+            }
 
-            if is_dir && depth != davheaders::Depth::Zero {
-                for await item in self.propfind_directory(&path, depth, &mut pw) {
+            if let Some(entries) = entries {
+                for await item in self.propfind_entries(&path, entries, depth, &mut pw) {
                     yield item?;
                 }
             }
@@ -227,11 +292,7 @@ impl DavInner {
         propwriter: &'a mut PropWriter,
     ) -> BoxStream<DavResult<Bytes>> {
         try_stream! {
-            let readdir_meta = match self.hide_symlinks {
-                Some(true) | None => ReadDirMeta::DataSymlink,
-                Some(false) => ReadDirMeta::Data,
-            };
-            let entries = match self.fs.read_dir(path, readdir_meta).await {
+            let entries = match self.fs.read_dir(path, self.readdir_meta()).await {
                 Ok(entries) => entries,
                 Err(e) => {
                     // if we cannot read_dir, just skip it.
@@ -239,14 +300,71 @@ impl DavInner {
                     return;
                 }
             };
+            for await item in self.propfind_entries(path, entries, depth, propwriter) {
+                yield item?;
+            }
+        }
+        .boxed()
+    }
+
+    // Is there a lock on this very path.
+    fn is_lock_null(&self, path: &DavPath) -> bool {
+        match self.ls {
+            Some(ref ls) => ls.discover(path).iter().any(|lock| lock.path == *path),
+            None => false,
+        }
+    }
+
+    fn readdir_meta(&self) -> ReadDirMeta {
+        match self.hide_symlinks {
+            Some(true) | None => ReadDirMeta::DataSymlink,
+            Some(false) => ReadDirMeta::Data,
+        }
+    }
 
+    // Render the entries of a directory. This consumes the stream one entry
+    // at a time, and yields the XML of every entry as soon as it is
+    // generated, so that huge directories are never held in memory.
+    //
+    // An entry whose metadata cannot be read gets a response with just
+    // the status of the error. Errors in the stream itself do not come
+    // with a name, there is no href to put them under. They are noted in
+    // the responsedescription of the multistatus, so the client can tell
+    // that the listing is not complete.
+    fn propfind_entries<'a>(
+        &'a self,
+        path: &'a DavPath,
+        entries: FsStream<FsResult<Box<dyn DavDirEntry>>>,
+        depth: davheaders::Depth,
+        propwriter: &'a mut PropWriter,
+    ) -> BoxStream<'a, DavResult<Bytes>> {
+        try_stream! {
+            let mut failed = None;
             for await dirent in entries {
+                let dirent = match dirent {
+                    Ok(dirent) => dirent,
+                    Err(e) => {
+                        debug!("read_dir error in {}: {:?}", path, e);
+                        failed = Some(DavError::from(e).statuscode());
+                        continue;
+                    }
+                };
+                let name = dirent.name();
+                if self.is_hidden_entry(&name) {
+                    continue;
+                }
                 let mut npath = path.clone();
-                npath.push_segment(&dirent.name());
+                npath.push_segment(&name);
                 let meta = match dirent.metadata().await {
                     Ok(meta) => meta,
+                    Err(FsError::NotFound) => {
+                        trace!("{} is gone. Skipping", npath);
+                        continue;
+                    }
                     Err(e) => {
-                        trace!("metadata error on {}. Skipping {:?}", npath, e);
+                        debug!("metadata error on {}: {:?}", npath, e);
+                        propwriter.write_status(&npath, DavError::from(e).statuscode())?;
+                        yield propwriter.flush();
                         continue;
                     }
                 };
@@ -266,6 +384,9 @@ impl DavInner {
                     }
                 }
             }
+            if let Some(status) = failed {
+                propwriter.incomplete(path, status);
+            }
         }
         .boxed()
     }
@@ -376,7 +497,15 @@ impl DavInner {
 
         // file must exist.
         let mut path = self.path(req);
-        let meta = self.fs.metadata(&path).await?;
+        let meta = match self.fs.metadata(&path).await {
+            Err(FsError::NotFound) if if_match_missing(req) => {
+                return Err(StatusCode::PRECONDITION_FAILED.into());
+            }
+            res => res?,
+        };
+        if let Some(res) = self.follow_redirect_ref(req, &*meta)? {
+            return Ok(res);
+        }
         let meta = self.fixpath(&mut res, &mut path, meta);
 
         // check the If and If-* headers.
@@ -398,14 +527,32 @@ impl DavInner {
                std::string::String::from_utf8_lossy(xmldata));
 
         // parse xml
-        let tree = Element::parse2(Cursor::new(xmldata))?;
-        if tree.name != "propertyupdate" {
-            return Err(DavError::XmlParseError);
+        let tree = match Element::parse(Cursor::new(xmldata)) {
+            Ok(tree) => tree,
+            Err(e) => return Err(DavError::XmlError(format!("proppatch: {}", e))),
+        };
+        if tree.name != "propertyupdate" || tree.namespace.as_deref() != Some("DAV:") {
+            let msg = format!("expected DAV:propertyupdate, found {}", clark_name(&tree));
+            return Err(DavError::XmlError(msg));
+        }
+
+        // refuse to process an unreasonable number of properties.
+        let max_props = self
+            .max_proppatch_props
+            .unwrap_or(crate::davhandler::MAX_PROPPATCH_PROPS);
+        let nprops: usize = tree
+            .child_elems_iter()
+            .flat_map(|e| e.child_elems_iter().filter(|e| e.name == "prop"))
+            .map(|e| e.child_elems_iter().count())
+            .sum();
+        if nprops > max_props {
+            return Err(DavError::Status(StatusCode::BAD_REQUEST));
         }
 
         let mut patch = Vec::new();
         let mut ret = Vec::new();
         let can_deadprop = self.fs.have_props(&path).await;
+        let extra_props = meta.extra_props();
 
         // walk over the element tree and feed "set" and "remove" items to
         // the liveprop_set/liveprop_remove functions. If skipped by those,
@@ -416,6 +563,14 @@ impl DavInner {
                 .filter(|e| e.name == "prop")
                 .flat_map(|e| e.child_elems_iter())
             {
+                // properties that come with the metadata are read-only.
+                if extra_props
+                    .iter()
+                    .any(|p| p.name == n.name && p.namespace == n.namespace)
+                {
+                    ret.push((StatusCode::FORBIDDEN, element_to_davprop(n)));
+                    continue;
+                }
                 match elem.name.as_str() {
                     "set" => match self.liveprop_set(n, can_deadprop) {
                         StatusCode::CONTINUE => patch.push((true, element_to_davprop_full(n))),
@@ -448,12 +603,37 @@ impl DavInner {
                     .map(|(_, p)| (StatusCode::FAILED_DEPENDENCY, p)),
             );
         } else if !patch.is_empty() {
-            // hmmm ... we assume nothing goes wrong here at the
-            // moment. if it does, we should roll back the earlier
-            // made changes to live props, but come on, we're not
-            // builing a transaction engine here.
+            // remember the old values, so that we can undo the changes
+            // if the filesystem applies a part of the patch.
+            let old = match patch.len() {
+                1 => Vec::new(),
+                _ => self.fs.get_props(&path, true).await.unwrap_or_default(),
+            };
             let deadret = self.fs.patch_props(&path, patch).await?;
-            ret.extend(deadret.into_iter());
+            if deadret.iter().all(|(s, _)| s.is_success()) {
+                ret.extend(deadret.into_iter());
+            } else {
+                let undo = deadret
+                    .iter()
+                    .filter(|(s, _)| s.is_success())
+                    .map(|(_, p)| {
+                        let prev = old
+                            .iter()
+                            .find(|o| o.name == p.name && o.namespace == p.namespace);
+                        match prev {
+                            Some(o) => (true, o.clone()),
+                            None => (false, p.clone()),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if !undo.is_empty() {
+                    self.fs.patch_props(&path, undo).await?;
+                }
+                ret.extend(deadret.into_iter().map(|(s, p)| match s.is_success() {
+                    true => (StatusCode::FAILED_DEPENDENCY, p),
+                    false => (s, p),
+                }));
+            }
         }
 
         // group by statuscode.
@@ -465,13 +645,88 @@ impl DavInner {
         }
 
         // And reply.
-        let mut pw = PropWriter::new(req, &mut res, "propertyupdate", Vec::new(), &self.fs, None)?;
+        let prefixes = self.xml_prefixes.clone().unwrap_or_default();
+        let mut pw = PropWriter::new(
+            req,
+            &mut res,
+            "propertyupdate",
+            Vec::new(),
+            &self.fs,
+            None,
+            prefixes,
+        )?;
         pw.write_propresponse(&path, hm)?;
         let body = pw.close();
         *res.body_mut() = Body::from(body);
 
         Ok(res)
     }
+
+    // The DAV:expand-property report (RFC3253 3.8).
+    pub(crate) async fn expand_property(
+        &self,
+        req: &Request<()>,
+        path: &DavPath,
+        body: &Element,
+    ) -> DavResult<Response<Body>> {
+        let specs = expand_specs(body)?;
+        let meta = self.fs.metadata(path).await?;
+
+        let mut props = Vec::new();
+        let mut todo: Vec<&ExpandSpec> = specs.iter().collect();
+        while let Some(spec) = todo.pop() {
+            props.push(spec.prop.clone());
+            todo.extend(spec.nested.iter());
+        }
+
+        let mut res = Response::new(Body::empty());
+        let prefixes = self.xml_prefixes.clone().unwrap_or_default();
+        let mut pw = PropWriter::new(
+            req,
+            &mut res,
+            "prop",
+            props,
+            &self.fs,
+            self.ls.as_ref(),
+            prefixes,
+        )?;
+        let response = pw.expand_response(self, path.clone(), meta, &specs).await?;
+        response.write_ev(&mut pw.emitter)?;
+        *res.body_mut() = Body::from(pw.close());
+        Ok(res)
+    }
+}
+
+// A DAV:property of an expand-property report, with the properties
+// to list of the resources that its value refers to.
+struct ExpandSpec {
+    prop: Element,
+    nested: Vec<ExpandSpec>,
+}
+
+fn expand_specs(elem: &Element) -> DavResult<Vec<ExpandSpec>> {
+    let mut specs = Vec::new();
+    for e in elem.child_elems_iter() {
+        if e.name != "property" || e.namespace.as_deref() != Some(NS_DAV_URI) {
+            continue;
+        }
+        let name = e.attributes.get("name");
+        let name = name.ok_or_else(|| DavError::XmlError("DAV:property without a name".into()))?;
+        let mut prop = Element::new(name);
+        let ns = e.attributes.get("namespace");
+        match ns.map_or(NS_DAV_URI, |ns| ns.as_str()) {
+            "" => {}
+            ns => {
+                prop.namespace = Some(ns.to_string());
+                prop = prop.ns("", ns);
+            }
+        }
+        specs.push(ExpandSpec {
+            prop,
+            nested: expand_specs(e)?,
+        });
+    }
+    Ok(specs)
 }
 
 impl PropWriter {
@@ -482,6 +737,7 @@ impl PropWriter {
         mut props: Vec<Element>,
         fs: &Box<dyn DavFileSystem>,
         ls: Option<&Box<dyn DavLockSystem>>,
+        prefixes: Vec<(String, String)>,
     ) -> DavResult<PropWriter> {
         let contenttype = "application/xml; charset=utf-8".parse().unwrap();
         res.headers_mut().insert("content-type", contenttype);
@@ -530,37 +786,104 @@ impl PropWriter {
             props.append(&mut v);
         }
 
+        let mut pw = PropWriter {
+            emitter,
+            name: name.to_string(),
+            props,
+            fs: fs.clone(),
+            ls: ls.cloned(),
+            useragent: ua.to_string(),
+            q_cache: Default::default(),
+            minimal: false,
+            prefixes,
+            incomplete: Vec::new(),
+        };
+
         // check the prop namespaces to see what namespaces
         // we need to put in the preamble.
-        let mut ev = XmlWEvent::start_element("D:multistatus").ns("D", NS_DAV_URI);
+        let mut decls = vec![(pw.prefix(NS_DAV_URI, "D"), NS_DAV_URI)];
         if name != "propertyupdate" {
-            let mut a = false;
-            let mut m = false;
-            for prop in &props {
-                match prop.namespace.as_deref() {
-                    Some(NS_APACHE_URI) => a = true,
-                    Some(NS_MS_URI) => m = true,
-                    _ => {}
+            let mut known = vec![(NS_APACHE_URI, "A"), (NS_MS_URI, "Z")];
+            for (ns, _) in &pw.prefixes {
+                if ns != NS_DAV_URI && ns != NS_APACHE_URI && ns != NS_MS_URI {
+                    known.push((ns, ""));
                 }
             }
-            if a {
-                ev = ev.ns("A", NS_APACHE_URI);
-            }
-            if m {
-                ev = ev.ns("Z", NS_MS_URI);
+            for (ns, pfx) in known {
+                let pfx = pw.prefix(ns, pfx);
+                let used = pw.props.iter().any(|p| p.namespace.as_deref() == Some(ns));
+                // there can only be one default namespace.
+                let clash = pfx.is_empty() && decls.iter().any(|(p, _)| p.is_empty());
+                if used && !clash {
+                    decls.push((pfx, ns));
+                }
             }
         }
-        emitter.write(ev)?;
+        let multistatus = pw.dav_name("multistatus");
+        let mut ev = XmlWEvent::start_element(multistatus.as_str());
+        for (pfx, ns) in &decls {
+            ev = match pfx.is_empty() {
+                true => ev.default_ns(*ns),
+                false => ev.ns(pfx.as_str(), *ns),
+            };
+        }
+        pw.emitter.write(ev)?;
 
-        Ok(PropWriter {
-            emitter,
-            name: name.to_string(),
-            props,
-            fs: fs.clone(),
-            ls: ls.cloned(),
-            useragent: ua.to_string(),
-            q_cache: Default::default(),
-        })
+        Ok(pw)
+    }
+
+    // The prefix to use for `namespace`: the configured one, or `default`.
+    fn prefix(&self, namespace: &str, default: &str) -> String {
+        self.prefixes
+            .iter()
+            .find(|(ns, _)| ns == namespace)
+            .map_or(default, |(_, pfx)| pfx.as_str())
+            .to_string()
+    }
+
+    // Name of an element in the DAV: namespace, with its prefix.
+    fn dav_name(&self, name: &str) -> String {
+        match self.prefix(NS_DAV_URI, "D").as_str() {
+            "" => name.to_string(),
+            pfx => format!("{}:{}", pfx, name),
+        }
+    }
+
+    // Give the elements in the namespaces that have a configured
+    // prefix that prefix. Elements that we build ourselves have a
+    // prefix but no namespace.
+    fn set_prefixes(&self, elem: &mut Element) {
+        let ns = match (elem.namespace.as_deref(), elem.prefix.as_deref()) {
+            (Some(ns), _) => Some(ns),
+            (None, Some("D")) => Some(NS_DAV_URI),
+            (None, Some("A")) => Some(NS_APACHE_URI),
+            (None, Some("Z")) => Some(NS_MS_URI),
+            _ => None,
+        };
+        let configured = ns.and_then(|ns| self.prefixes.iter().find(|(n, _)| n == ns));
+        if let Some((ns, pfx)) = configured {
+            elem.prefix = Some(pfx.clone()).filter(|p| !p.is_empty());
+            elem.namespace = Some(ns.clone());
+            // the emitter leaves this out if it is already in scope.
+            elem.namespaces
+                .get_or_insert_with(xmltree::Namespace::empty)
+                .force_put(pfx.clone(), ns.clone());
+        } else if ns.is_none() && elem.prefix.is_none() && self.prefix(NS_DAV_URI, "D").is_empty() {
+            // not in any namespace, so not in the default one either.
+            elem.attributes.insert("xmlns".to_string(), String::new());
+        }
+        // and drop the old prefixes for those namespaces.
+        if let Some(namespaces) = elem.namespaces.as_mut() {
+            let prefixes = &self.prefixes;
+            namespaces
+                .0
+                .retain(|p, ns| !prefixes.iter().any(|(n, c)| n == ns && c != p));
+        }
+        for child in elem.children.iter_mut() {
+            if let XMLNode::Element(e) = child {
+                self.set_prefixes(e);
+            }
+        }
     }
 
     fn build_elem<T>(
@@ -639,7 +962,7 @@ impl PropWriter {
         docontent: bool,
     ) -> DavResult<StatusElement> {
         // in some cases, a live property might be stored in the
-        // dead prop database, like DAV:displayname.
+        // dead prop database, like DAV:getcontentlanguage.
         let mut try_deadprop = false;
         let mut pfx = "";
 
@@ -664,20 +987,67 @@ impl PropWriter {
                             return self.build_elem(docontent, pfx, prop, tm);
                         }
                     }
-                    "displayname" | "getcontentlanguage" => {
+                    "displayname" => {
+                        // a stored displayname, or else the name of the resource.
+                        if self.fs.have_props(path).await {
+                            let dprop = element_to_davprop(prop);
+                            if let Ok(xml) = self.fs.get_prop(path, dprop).await {
+                                if let Ok(mut e) = Element::parse(Cursor::new(xml)) {
+                                    if !docontent {
+                                        e.children.clear();
+                                    }
+                                    return Ok(StatusElement {
+                                        status: StatusCode::OK,
+                                        element: e,
+                                    });
+                                }
+                            }
+                        }
+                        let name = path.file_name_bytes();
+                        if !name.is_empty() {
+                            let name = String::from_utf8_lossy(name).into_owned();
+                            return self.build_elem(docontent, pfx, prop, name);
+                        }
+                    }
+                    "getcontentlanguage" => {
                         try_deadprop = true;
                     }
+                    "reftarget" => {
+                        if let Some(target) = meta.redirect_ref() {
+                            let mut elem = prop.clone();
+                            if docontent {
+                                let href = Element::new2("D:href").text(target);
+                                elem.children.push(XMLNode::Element(href));
+                            }
+                            return Ok(StatusElement {
+                                status: StatusCode::OK,
+                                element: elem,
+                            });
+                        }
+                    }
                     "getetag" => {
+                        if meta.is_dir() {
+                            if let Ok(Some(etag)) = self.fs.collection_etag(path).await {
+                                return self.build_elem(docontent, pfx, prop, etag);
+                            }
+                        }
                         if let Some(etag) = meta.etag() {
                             return self.build_elem(docontent, pfx, prop, etag);
                         }
                     }
                     "getcontentlength" => {
+                        // collections do not have one (RFC4918 15.4), so it
+                        // is 404, and left out of allprop and propname.
                         if !meta.is_dir() {
                             return self.build_elem(docontent, pfx, prop, meta.len().to_string());
                         }
                     }
                     "getcontenttype" => {
+                        if !meta.is_dir() {
+                            if let Some(ct) = stored_content_type(self.fs.as_ref(), path).await {
+                                return self.build_elem(docontent, pfx, prop, ct);
+                            }
+                        }
                         return if meta.is_dir() {
                             self.build_elem(docontent, pfx, prop, "httpd/unix-directory")
                         } else {
@@ -696,18 +1066,28 @@ impl PropWriter {
                             let dir = Element::new2("D:collection");
                             elem.children.push(XMLNode::Element(dir));
                         }
+                        if meta.redirect_ref().is_some() && docontent {
+                            let rr = Element::new2("D:redirectref");
+                            elem.children.push(XMLNode::Element(rr));
+                        }
                         return Ok(StatusElement {
                             status: StatusCode::OK,
                             element: elem,
                         });
                     }
                     "supportedlock" => {
+                        if !docontent {
+                            return self.build_elem(false, pfx, prop, "");
+                        }
                         return Ok(StatusElement {
                             status: StatusCode::OK,
                             element: list_supportedlock(self.ls.as_ref()),
                         });
                     }
                     "lockdiscovery" => {
+                        if !docontent {
+                            return self.build_elem(false, pfx, prop, "");
+                        }
                         return Ok(StatusElement {
                             status: StatusCode::OK,
                             element: list_lockdiscovery(self.ls.as_ref(), path),
@@ -803,6 +1183,18 @@ impl PropWriter {
             }
         }
 
+        // properties that come with the metadata.
+        if let Some(p) = meta
+            .extra_props()
+            .into_iter()
+            .find(|p| p.name == prop.name && p.namespace == prop.namespace)
+        {
+            return Ok(StatusElement {
+                status: StatusCode::OK,
+                element: extra_prop_element(p, docontent),
+            });
+        }
+
         if try_deadprop && self.name == "prop" && self.fs.have_props(path).await {
             // asking for a specific property.
             let dprop = element_to_davprop(prop);
@@ -843,21 +1235,60 @@ impl PropWriter {
             let res = self
                 .build_prop(p, path, &*meta, &mut qc, do_content)
                 .await?;
-            if res.status == StatusCode::OK || (self.name != "propname" && self.name != "allprop") {
+            if res.status == StatusCode::OK
+                || (self.name != "propname" && self.name != "allprop" && !self.minimal)
+            {
                 add_sc_elem(&mut props, res.status, res.element);
             }
         }
         self.q_cache = qc;
 
+        // and list the properties that come with the metadata,
+        if self.name == "propname" || self.name == "allprop" {
+            let listed = self
+                .props
+                .iter()
+                .any(|e| e.name == "reftarget" && e.namespace.as_deref() == Some(NS_DAV_URI));
+            if meta.redirect_ref().is_some() && !listed {
+                let mut prop = Element::new2("D:reftarget");
+                prop.namespace = Some(NS_DAV_URI.to_string());
+                let res = self
+                    .build_prop(&prop, path, &*meta, &mut qc, do_content)
+                    .await?;
+                add_sc_elem(&mut props, res.status, res.element);
+            }
+            for p in meta.extra_props() {
+                let listed = self
+                    .props
+                    .iter()
+                    .any(|e| e.name == p.name && e.namespace == p.namespace);
+                if !listed {
+                    let elem = extra_prop_element(p, do_content);
+                    add_sc_elem(&mut props, StatusCode::OK, elem);
+                }
+            }
+        }
+
         // and list the dead properties as well.
         if (self.name == "propname" || self.name == "allprop") && self.fs.have_props(path).await {
             if let Ok(v) = self.fs.get_props(path, do_content).await {
+                // DAV:getcontenttype and DAV:displayname are listed
+                // as live properties already.
                 v.into_iter()
+                    .filter(|p| {
+                        !matches!(p.name.as_str(), "getcontenttype" | "displayname")
+                            || p.namespace.as_deref() != Some(NS_DAV_URI)
+                    })
                     .map(davprop_to_element)
                     .for_each(|e| add_sc_elem(&mut props, StatusCode::OK, e));
             }
         }
 
+        // a response without any propstat is not valid.
+        if self.minimal && props.is_empty() {
+            props.insert(StatusCode::OK, Vec::new());
+        }
+
         self.write_propresponse(path, props)
     }
 
@@ -866,9 +1297,11 @@ impl PropWriter {
         path: &DavPath,
         props: HashMap<StatusCode, Vec<Element>>,
     ) -> Result<(), DavError> {
-        self.emitter.write(XmlWEvent::start_element("D:response"))?;
+        let response = self.dav_name("response");
+        self.emitter
+            .write(XmlWEvent::start_element(response.as_str()))?;
         let p = path.with_prefix().as_url_string();
-        Element::new2("D:href")
+        Element::new2(self.dav_name("href").as_str())
             .text(p)
             .write_ev(&mut self.emitter)?;
 
@@ -876,13 +1309,22 @@ impl PropWriter {
         keys.sort();
         for status in keys {
             let v = props.get(status).unwrap();
-            self.emitter.write(XmlWEvent::start_element("D:propstat"))?;
-            self.emitter.write(XmlWEvent::start_element("D:prop"))?;
+            let (propstat, prop) = (self.dav_name("propstat"), self.dav_name("prop"));
+            self.emitter
+                .write(XmlWEvent::start_element(propstat.as_str()))?;
+            self.emitter
+                .write(XmlWEvent::start_element(prop.as_str()))?;
             for i in v.iter() {
-                i.write_ev(&mut self.emitter)?;
+                if self.prefixes.is_empty() {
+                    i.write_ev(&mut self.emitter)?;
+                } else {
+                    let mut i = i.clone();
+                    self.set_prefixes(&mut i);
+                    i.write_ev(&mut self.emitter)?;
+                }
             }
             self.emitter.write(XmlWEvent::end_element())?;
-            Element::new2("D:status")
+            Element::new2(self.dav_name("status").as_str())
                 .text("HTTP/1.1 ".to_string() + &status.to_string())
                 .write_ev(&mut self.emitter)?;
             self.emitter.write(XmlWEvent::end_element())?;
@@ -893,11 +1335,138 @@ impl PropWriter {
         Ok(())
     }
 
+    // The DAV:response of an expand-property report. A DAV:href in
+    // the value of a property with nested DAV:property elements is
+    // replaced by the response for the resource it refers to. Only
+    // hrefs that are an absolute path are expanded.
+    fn expand_response<'a>(
+        &'a mut self,
+        inner: &'a DavInner,
+        path: DavPath,
+        meta: Box<dyn DavMetaData>,
+        specs: &'a [ExpandSpec],
+    ) -> BoxFuture<'a, DavResult<Element>> {
+        async move {
+            let mut props = HashMap::new();
+            let mut qc = self.q_cache;
+            for spec in specs {
+                let mut res = self
+                    .build_prop(&spec.prop, &path, &*meta, &mut qc, true)
+                    .await?;
+                self.q_cache = qc;
+                if res.status == StatusCode::OK && !spec.nested.is_empty() {
+                    for node in res.element.children.iter_mut() {
+                        let href = match node {
+                            XMLNode::Element(e)
+                                if e.name == "href"
+                                    && e.namespace.as_deref().unwrap_or(NS_DAV_URI)
+                                        == NS_DAV_URI =>
+                            {
+                                e.get_text().unwrap_or_default().trim().to_string()
+                            }
+                            _ => continue,
+                        };
+                        if href.starts_with('/') {
+                            let elem = self.expand_href(inner, &path, &href, &spec.nested).await?;
+                            *node = XMLNode::Element(elem);
+                        }
+                    }
+                }
+                qc = self.q_cache;
+                add_sc_elem(&mut props, res.status, res.element);
+            }
+
+            let mut response = Element::new2(self.dav_name("response").as_str());
+            let p = path.with_prefix().as_url_string();
+            response.push_element(Element::new2(self.dav_name("href").as_str()).text(p));
+            let mut keys = props.keys().cloned().collect::<Vec<_>>();
+            keys.sort();
+            for status in keys {
+                let mut prop = Element::new2(self.dav_name("prop").as_str());
+                for mut e in props.remove(&status).unwrap() {
+                    if !self.prefixes.is_empty() {
+                        self.set_prefixes(&mut e);
+                    }
+                    prop.push_element(e);
+                }
+                let mut propstat = Element::new2(self.dav_name("propstat").as_str());
+                propstat.push_element(prop);
+                propstat.push_element(self.status_elem(status));
+                response.push_element(propstat);
+            }
+            Ok(response)
+        }
+        .boxed()
+    }
+
+    // The DAV:response for a DAV:href in an expanded property.
+    async fn expand_href(
+        &mut self,
+        inner: &DavInner,
+        base: &DavPath,
+        href: &str,
+        specs: &[ExpandSpec],
+    ) -> DavResult<Element> {
+        let path = match DavPath::from_str_and_prefix(href, base.prefix()) {
+            Ok(path) if !inner.is_hidden(&path) => path,
+            _ => return Ok(self.status_response(href, StatusCode::NOT_FOUND)),
+        };
+        match inner.fs.metadata(&path).await {
+            Ok(meta) => self.expand_response(inner, path, meta, specs).await,
+            Err(e) => {
+                let href = path.with_prefix().as_url_string();
+                Ok(self.status_response(&href, DavError::from(e).statuscode()))
+            }
+        }
+    }
+
+    fn status_response(&self, href: &str, status: StatusCode) -> Element {
+        let mut response = Element::new2(self.dav_name("response").as_str());
+        response.push_element(Element::new2(self.dav_name("href").as_str()).text(href));
+        response.push_element(self.status_elem(status));
+        response
+    }
+
+    fn status_elem(&self, status: StatusCode) -> Element {
+        Element::new2(self.dav_name("status").as_str())
+            .text("HTTP/1.1 ".to_string() + &status.to_string())
+    }
+
+    // A response with just a status, and no properties.
+    pub fn write_status(&mut self, path: &DavPath, status: StatusCode) -> Result<(), DavError> {
+        let response = self.dav_name("response");
+        self.emitter
+            .write(XmlWEvent::start_element(response.as_str()))?;
+        let p = path.with_prefix().as_url_string();
+        Element::new2(self.dav_name("href").as_str())
+            .text(p)
+            .write_ev(&mut self.emitter)?;
+        Element::new2(self.dav_name("status").as_str())
+            .text("HTTP/1.1 ".to_string() + &status.to_string())
+            .write_ev(&mut self.emitter)?;
+        self.emitter.write(XmlWEvent::end_element())?; // response
+        Ok(())
+    }
+
     pub fn flush(&mut self) -> Bytes {
         self.emitter.inner_mut().take()
     }
 
+    // Note that a collection could not be listed completely.
+    pub fn incomplete(&mut self, path: &DavPath, status: StatusCode) {
+        let p = path.with_prefix().as_url_string();
+        self.incomplete
+            .push(format!("listing of {} is incomplete: {}", p, status));
+    }
+
     pub fn close(&mut self) -> Bytes {
+        if !self.incomplete.is_empty() {
+            let text = self.incomplete.join("\n");
+            Element::new2(self.dav_name("responsedescription").as_str())
+                .text(text)
+                .write_ev(&mut self.emitter)
+                .expect("write responsedescription");
+        }
         self.emitter
             .write(XmlWEvent::end_element())
             .expect("write end_element()");
@@ -905,11 +1474,69 @@ impl PropWriter {
     }
 }
 
+// "Prefer: return=minimal" and "Prefer: depth-noroot" (RFC8144).
+// Metadata of a lock-null resource. It is not a collection, has no
+// content, and no modification time.
+#[derive(Debug, Clone)]
+struct LockNullMeta;
+
+impl DavMetaData for LockNullMeta {
+    fn len(&self) -> u64 {
+        0
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Err(FsError::NotFound)
+    }
+
+    fn is_dir(&self) -> bool {
+        false
+    }
+}
+
+fn prefer(req: &Request<()>, preference: &str) -> bool {
+    req.headers()
+        .get_all("prefer")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|p| p.split(';').next().unwrap_or("").trim() == preference)
+}
+
+// Microsoft's "Brief: t", which means the same thing.
+fn brief(req: &Request<()>) -> bool {
+    match req.headers().get("brief").and_then(|v| v.to_str().ok()) {
+        Some(v) => v.trim().eq_ignore_ascii_case("t"),
+        None => false,
+    }
+}
+
 fn add_sc_elem(hm: &mut HashMap<StatusCode, Vec<Element>>, sc: StatusCode, e: Element) {
     hm.entry(sc).or_default();
     hm.get_mut(&sc).unwrap().push(e)
 }
 
+// DAV:getcontenttype as a dead property, see `DavConfig::preserve_content_type`.
+// With `None` as content type, it can be used to remove the property.
+pub(crate) fn content_type_prop(content_type: Option<&str>) -> DavProp {
+    let mut elem = Element::new2("D:getcontenttype").ns("D", NS_DAV_URI);
+    elem.namespace = Some(NS_DAV_URI.to_string());
+    match content_type {
+        Some(ct) => element_to_davprop_full(&elem.text(ct.to_string())),
+        None => element_to_davprop(&elem),
+    }
+}
+
+// Get the content type that was stored as a dead property on PUT.
+pub(crate) async fn stored_content_type(fs: &dyn DavFileSystem, path: &DavPath) -> Option<String> {
+    if !fs.have_props(path).await {
+        return None;
+    }
+    let xml = fs.get_prop(path, content_type_prop(None)).await.ok()?;
+    let elem = Element::parse(Cursor::new(xml)).ok()?;
+    elem.get_text().map(|t| t.to_string())
+}
+
 fn element_to_davprop_full(elem: &Element) -> DavProp {
     let mut emitter = EventWriter::new(Cursor::new(Vec::new()));
     elem.write_ev(&mut emitter).ok();
@@ -944,3 +1571,1045 @@ fn davprop_to_element(prop: DavProp) -> Element {
     elem.namespace = prop.namespace.clone();
     elem
 }
+
+// A property from `DavMetaData::extra_props`, with or without its value.
+fn extra_prop_element(prop: DavProp, content: bool) -> Element {
+    if content {
+        davprop_to_element(prop)
+    } else {
+        davprop_to_element(DavProp { xml: None, ..prop })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::memls::MemLs;
+    use crate::tests::{mkcol, put, req, run};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn propfind_propname() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        let body = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x"><D:set><D:prop><X:color>blue</X:color></D:prop></D:set></D:propertyupdate>"#;
+        let r = req("PROPPATCH", "/file.txt")
+            .body(Body::from(body))
+            .unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert_eq!(resp.status(), 207);
+
+        let body = r#"<D:propfind xmlns:D="DAV:"><D:propname/></D:propfind>"#;
+        let r = req("PROPFIND", "/file.txt")
+            .header("Depth", "0")
+            .body(Body::from(body))
+            .unwrap();
+        let (resp, text) = run(&dav, r).await;
+        assert_eq!(resp.status(), 207);
+        assert!(
+            text.contains("<D:getcontentlength></D:getcontentlength>"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("<D:supportedlock></D:supportedlock>"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("<X:color xmlns:X=\"urn:x\"></X:color>"),
+            "{}",
+            text
+        );
+        assert!(!text.contains("blue"), "{}", text);
+        assert!(!text.contains(">5<"), "{}", text);
+        assert!(text.contains("HTTP/1.1 200 OK"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn request_limits() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .max_proppatch_props(2)
+            .max_request_xml_size(300)
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        let proppatch = |props: &str| {
+            let body = format!(
+                r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x"><D:set><D:prop>{}</D:prop></D:set></D:propertyupdate>"#,
+                props
+            );
+            req("PROPPATCH", "/file.txt")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let (resp, _) = run(&dav, proppatch("<X:a>1</X:a><X:b>2</X:b><X:c>3</X:c>")).await;
+        assert_eq!(resp.status(), 400);
+        let (resp, _) = run(&dav, proppatch("<X:a>1</X:a><X:b>2</X:b>")).await;
+        assert_eq!(resp.status(), 207);
+
+        let padding = " ".repeat(300);
+        let body = format!(
+            r#"<D:propfind xmlns:D="DAV:">{}<D:propname/></D:propfind>"#,
+            padding
+        );
+        let r = req("PROPFIND", "/file.txt")
+            .header("Depth", "0")
+            .body(Body::from(body))
+            .unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert_eq!(resp.status(), 413);
+    }
+
+    #[tokio::test]
+    async fn propfind_chunked() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .max_request_xml_size(8000)
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        // a body that arrives in small pieces, split in the middle of tags.
+        let chunked = |props: &str| {
+            let body = format!(
+                r#"<D:propfind xmlns:D="DAV:" xmlns:X="urn:x"><D:prop>{}<D:getcontentlength/></D:prop></D:propfind>"#,
+                props
+            );
+            let chunks = body
+                .into_bytes()
+                .chunks(7)
+                .map(|c| Ok::<_, std::io::Error>(bytes::Bytes::copy_from_slice(c)))
+                .collect::<Vec<_>>();
+            req("PROPFIND", "/file.txt")
+                .header("Depth", "0")
+                .body(Body::from_stream(futures_util::stream::iter(chunks)))
+                .unwrap()
+        };
+
+        let props: String = (0..200).map(|n| format!("<X:p{}/>", n)).collect();
+        let (resp, text) = run(&dav, chunked(&props)).await;
+        assert_eq!(resp.status(), 207);
+        assert!(text.contains(">5</D:getcontentlength>"), "{}", text);
+        assert!(text.contains("<X:p0"), "{}", text);
+        assert!(text.contains("<X:p199"), "{}", text);
+
+        // the limit is on the whole body, not on a chunk.
+        let props: String = (0..1000).map(|n| format!("<X:p{}/>", n)).collect();
+        let (resp, _) = run(&dav, chunked(&props)).await;
+        assert_eq!(resp.status(), 413);
+    }
+
+    #[tokio::test]
+    async fn propfind_streaming() {
+        use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use futures_util::{future, stream, FutureExt, StreamExt};
+        use http_body_util::BodyExt;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::SystemTime;
+
+        const ENTRIES: usize = 100_000;
+
+        #[derive(Debug, Clone)]
+        struct Meta(bool);
+
+        impl DavMetaData for Meta {
+            fn len(&self) -> u64 {
+                0
+            }
+            fn modified(&self) -> FsResult<SystemTime> {
+                Ok(SystemTime::UNIX_EPOCH)
+            }
+            fn is_dir(&self) -> bool {
+                self.0
+            }
+        }
+
+        struct Entry(usize);
+
+        impl DavDirEntry for Entry {
+            fn name(&self) -> Vec<u8> {
+                format!("f{}", self.0).into_bytes()
+            }
+            fn metadata(&self) -> FsFuture<Box<dyn DavMetaData>> {
+                future::ok(Box::new(Meta(false)) as Box<dyn DavMetaData>).boxed()
+            }
+        }
+
+        // A directory with many entries, generated lazily.
+        #[derive(Clone)]
+        struct BigDirFs(Arc<AtomicUsize>);
+
+        #[async_trait]
+        impl AsyncDavFileSystem for BigDirFs {
+            async fn open(&self, _: &DavPath, _: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+                Err(FsError::NotFound)
+            }
+
+            async fn read_dir(
+                &self,
+                _path: &DavPath,
+                _meta: ReadDirMeta,
+            ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+                let generated = self.0.clone();
+                let strm = stream::iter(0..ENTRIES).map(move |n| {
+                    generated.fetch_add(1, Ordering::SeqCst);
+                    Ok(Box::new(Entry(n)) as Box<dyn DavDirEntry>)
+                });
+                Ok(Box::pin(strm))
+            }
+
+            async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+                Ok(Box::new(Meta(path.as_url_string() == "/")))
+            }
+        }
+
+        let generated = Arc::new(AtomicUsize::new(0));
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(BigDirFs(generated.clone())))
+            .build_handler();
+        let r = req("PROPFIND", "/")
+            .header("Depth", "1")
+            .body(Body::empty())
+            .unwrap();
+        let resp = dav.handle(r).await;
+        assert_eq!(resp.status(), 207);
+
+        // the first entries are sent before the directory has been read.
+        let mut body = resp.into_body();
+        let mut responses = 0;
+        let mut frames = 0;
+        while let Some(frame) = body.frame().await {
+            if let Ok(data) = frame.unwrap().into_data() {
+                responses += String::from_utf8_lossy(&data)
+                    .matches("<D:response>")
+                    .count();
+            }
+            frames += 1;
+            if frames == 10 {
+                assert!(generated.load(Ordering::SeqCst) < 100);
+            }
+        }
+        assert_eq!(generated.load(Ordering::SeqCst), ENTRIES);
+        assert_eq!(responses, ENTRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn hide_patterns() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .hide_patterns(vec![".*".to_string(), "*.bak".to_string()])
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        // hidden files cannot be created, so put them in place without the filter.
+        let rw = DavHandler::builder()
+            .filesystem(dav.config.fs.clone().unwrap())
+            .build_handler();
+        put(&rw, "/.htpasswd", "secret").await;
+        put(&rw, "/file.bak", "old").await;
+        crate::tests::mkcol(&rw, "/.git/").await;
+        put(&rw, "/.git/config", "config").await;
+
+        let r = req("PROPFIND", "/")
+            .header("Depth", "1")
+            .body(Body::empty())
+            .unwrap();
+        let (resp, text) = run(&dav, r).await;
+        assert_eq!(resp.status(), 207);
+        assert!(text.contains("/file.txt"), "{}", text);
+        assert!(!text.contains("htpasswd"), "{}", text);
+        assert!(!text.contains(".git"), "{}", text);
+        assert!(!text.contains(".bak"), "{}", text);
+
+        for path in ["/.htpasswd", "/file.bak", "/.git/config", "/.git/"] {
+            let (resp, _) = run(&dav, req("GET", path).body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 404, "{}", path);
+        }
+        let r = req("PUT", "/.env").body(Body::from("x")).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 404);
+        let r = req("MOVE", "/file.txt")
+            .header("Destination", "/.env")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 403);
+        let (resp, _) = run(&rw, req("GET", "/.htpasswd").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn propfind_depth() {
+        use crate::tests::mkcol;
+
+        async fn hrefs(dav: &DavHandler, depth: Option<&str>) -> (u16, Vec<String>) {
+            let mut r = req("PROPFIND", "/a/");
+            if let Some(depth) = depth {
+                r = r.header("Depth", depth);
+            }
+            let (resp, text) = run(dav, r.body(Body::empty()).unwrap()).await;
+            let mut hrefs: Vec<String> = text
+                .split("<D:href>")
+                .skip(1)
+                .map(|s| s.split('<').next().unwrap().to_string())
+                .collect();
+            hrefs.sort();
+            (resp.status().as_u16(), hrefs)
+        }
+
+        let fs = MemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        mkcol(&dav, "/a/").await;
+        put(&dav, "/a/f1", "1").await;
+        mkcol(&dav, "/a/b/").await;
+        put(&dav, "/a/b/f2", "2").await;
+        mkcol(&dav, "/a/b/c/").await;
+
+        assert_eq!(hrefs(&dav, Some("0")).await, (207, vec!["/a/".to_string()]));
+        let one = ["/a/", "/a/b/", "/a/f1"];
+        assert_eq!(
+            hrefs(&dav, Some("1")).await,
+            (207, one.iter().map(|s| s.to_string()).collect())
+        );
+        assert_eq!(hrefs(&dav, Some("infinity")).await.0, 403);
+        assert_eq!(hrefs(&dav, None).await.0, 403);
+
+        let dav = DavHandler::builder()
+            .filesystem(fs)
+            .propfind_depth_infinity(true)
+            .build_handler();
+        let all: Vec<String> = ["/a/", "/a/b/", "/a/b/c/", "/a/b/f2", "/a/f1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(hrefs(&dav, Some("infinity")).await, (207, all.clone()));
+        assert_eq!(hrefs(&dav, None).await, (207, all));
+    }
+
+    #[tokio::test]
+    async fn malformed_xml() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        let cases = [
+            (
+                "PROPFIND",
+                r#"<D:propfind xmlns:D="DAV:"><D:prop>"#,
+                "propfind: ",
+            ),
+            (
+                "PROPFIND",
+                r#"<D:lockinfo xmlns:D="DAV:"/>"#,
+                "expected DAV:propfind",
+            ),
+            (
+                "PROPFIND",
+                r#"<D:propfind xmlns:D="DAV:"/>"#,
+                "must contain",
+            ),
+            ("PROPFIND", "not xml at all", "propfind: "),
+            (
+                "PROPPATCH",
+                r#"<D:propertyupdate xmlns:D="DAV:"><D:set>"#,
+                "proppatch: ",
+            ),
+            (
+                "PROPPATCH",
+                r#"<D:propfind xmlns:D="DAV:"/>"#,
+                "expected DAV:propertyupdate",
+            ),
+            ("PROPPATCH", "", "proppatch: "),
+        ];
+        for (method, body, expect) in cases {
+            let r = req(method, "/file.txt")
+                .header("Depth", "0")
+                .body(Body::from(body))
+                .unwrap();
+            let (resp, text) = run(&dav, r).await;
+            assert_eq!(resp.status(), 400, "{} {}", method, body);
+            assert_eq!(resp.headers()["content-type"], "text/plain; charset=utf-8");
+            assert!(text.contains(expect), "{} {}: {}", method, body, text);
+        }
+
+        // an empty PROPFIND is an allprop.
+        for body in ["", "\r\n"] {
+            let r = req("PROPFIND", "/file.txt")
+                .header("Depth", "0")
+                .body(Body::from(body))
+                .unwrap();
+            let (resp, text) = run(&dav, r).await;
+            assert_eq!(resp.status(), 207);
+            assert!(text.contains("<D:getcontentlength>5<"), "{}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn propfind_brief() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        async fn propfind(dav: &DavHandler, header: Option<(&str, &str)>, props: &str) -> String {
+            let body = format!(
+                r#"<D:propfind xmlns:D="DAV:"><D:prop>{}</D:prop></D:propfind>"#,
+                props
+            );
+            let mut r = req("PROPFIND", "/file.txt").header("Depth", "0");
+            if let Some((name, value)) = header {
+                r = r.header(name, value);
+            }
+            let (resp, text) = run(dav, r.body(Body::from(body)).unwrap()).await;
+            assert_eq!(resp.status(), 207);
+            text
+        }
+
+        let props = "<D:getcontentlength/><D:nosuchprop/>";
+        let text = propfind(&dav, None, props).await;
+        assert!(text.contains("404 Not Found"), "{}", text);
+
+        for header in [("Brief", "t"), ("Prefer", "return=minimal")] {
+            let text = propfind(&dav, Some(header), props).await;
+            assert!(!text.contains("404"), "{}", text);
+            assert!(!text.contains("nosuchprop"), "{}", text);
+            assert!(text.contains("<D:getcontentlength>5<"), "{}", text);
+
+            // nothing found: an empty propstat.
+            let text = propfind(&dav, Some(header), "<D:nosuchprop/>").await;
+            assert!(
+                text.contains("<D:propstat><D:prop></D:prop><D:status>HTTP/1.1 200 OK"),
+                "{}",
+                text
+            );
+        }
+
+        let text = propfind(&dav, Some(("Brief", "f")), props).await;
+        assert!(text.contains("404 Not Found"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn prefer_depth_noroot() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        mkcol(&dav, "/dir/").await;
+        put(&dav, "/dir/a.txt", "a").await;
+        put(&dav, "/dir/b.txt", "b").await;
+
+        let propfind = |prefer: Option<&str>| {
+            let mut r = req("PROPFIND", "/dir/").header("Depth", "1");
+            if let Some(prefer) = prefer {
+                r = r.header("Prefer", prefer);
+            }
+            run(&dav, r.body(Body::empty()).unwrap())
+        };
+
+        let (resp, text) = propfind(None).await;
+        assert!(resp.headers().get("Preference-Applied").is_none());
+        assert!(text.contains("<D:href>/dir/</D:href>"), "{}", text);
+
+        let (resp, text) = propfind(Some("depth-noroot")).await;
+        assert_eq!(resp.headers()["Preference-Applied"], "depth-noroot");
+        assert!(!text.contains("<D:href>/dir/</D:href>"), "{}", text);
+        assert!(text.contains("<D:href>/dir/a.txt</D:href>"), "{}", text);
+        assert!(text.contains("<D:href>/dir/b.txt</D:href>"), "{}", text);
+
+        let (resp, _) = propfind(Some("return=minimal, depth-noroot")).await;
+        assert_eq!(
+            resp.headers()["Preference-Applied"],
+            "return=minimal, depth-noroot"
+        );
+    }
+
+    #[tokio::test]
+    async fn extra_props() {
+        use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use futures_util::stream;
+        use std::time::SystemTime;
+
+        // An object with a storage class.
+        #[derive(Debug, Clone)]
+        struct Meta;
+
+        impl DavMetaData for Meta {
+            fn len(&self) -> u64 {
+                5
+            }
+            fn modified(&self) -> FsResult<SystemTime> {
+                Ok(SystemTime::UNIX_EPOCH)
+            }
+            fn is_dir(&self) -> bool {
+                false
+            }
+            fn extra_props(&self) -> Vec<DavProp> {
+                let xml = r#"<S:storage-class xmlns:S="urn:s3">GLACIER</S:storage-class>"#;
+                vec![DavProp {
+                    name: "storage-class".to_string(),
+                    prefix: Some("S".to_string()),
+                    namespace: Some("urn:s3".to_string()),
+                    xml: Some(xml.as_bytes().to_vec()),
+                }]
+            }
+        }
+
+        #[derive(Clone)]
+        struct ObjectFs;
+
+        #[async_trait]
+        impl AsyncDavFileSystem for ObjectFs {
+            async fn open(&self, _: &DavPath, _: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+                Err(FsError::NotFound)
+            }
+
+            async fn read_dir(
+                &self,
+                _path: &DavPath,
+                _meta: ReadDirMeta,
+            ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+                Ok(Box::pin(stream::empty()))
+            }
+
+            async fn metadata(&self, _path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+                Ok(Box::new(Meta))
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(ObjectFs))
+            .build_handler();
+        let propfind = |body: &'static str| {
+            let r = req("PROPFIND", "/object").header("Depth", "0");
+            run(&dav, r.body(Body::from(body)).unwrap())
+        };
+
+        let (_, text) = propfind(
+            r#"<D:propfind xmlns:D="DAV:"><D:prop><S:storage-class xmlns:S="urn:s3"/></D:prop></D:propfind>"#,
+        )
+        .await;
+        assert!(text.contains(">GLACIER</S:storage-class>"), "{}", text);
+        assert!(!text.contains("404"), "{}", text);
+
+        let (_, text) = propfind("").await;
+        assert!(text.contains(">GLACIER</S:storage-class>"), "{}", text);
+
+        let (_, text) = propfind(r#"<D:propfind xmlns:D="DAV:"><D:propname/></D:propfind>"#).await;
+        assert!(text.contains("storage-class"), "{}", text);
+        assert!(!text.contains("GLACIER"), "{}", text);
+
+        let r = req("PROPPATCH", "/object")
+            .body(Body::from(
+                r#"<D:propertyupdate xmlns:D="DAV:" xmlns:S="urn:s3"><D:set><D:prop><S:storage-class>STANDARD</S:storage-class></D:prop></D:set></D:propertyupdate>"#,
+            ))
+            .unwrap();
+        let (_, text) = run(&dav, r).await;
+        assert!(text.contains("403 Forbidden"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn collection_hrefs() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        mkcol(&dav, "/dir/").await;
+        mkcol(&dav, "/dir/sub/").await;
+        put(&dav, "/dir/file.txt", "hello").await;
+
+        let body = r#"<D:propfind xmlns:D="DAV:"><D:prop><D:resourcetype/></D:prop></D:propfind>"#;
+        let propfind = |path: &'static str| {
+            let r = req("PROPFIND", path).header("Depth", "1");
+            run(&dav, r.body(Body::from(body)).unwrap())
+        };
+        let collection = "<D:resourcetype><D:collection></D:collection></D:resourcetype>";
+        let file = "<D:resourcetype></D:resourcetype>";
+
+        for path in ["/dir", "/dir/"] {
+            let (resp, text) = propfind(path).await;
+            assert_eq!(resp.status(), 207);
+            let responses: Vec<&str> = text.split("<D:response>").skip(1).collect();
+            assert_eq!(responses.len(), 3, "{}", text);
+            for r in responses {
+                if r.contains("<D:href>/dir/</D:href>") || r.contains("<D:href>/dir/sub/</D:href>")
+                {
+                    assert!(r.contains(collection), "{}", r);
+                } else {
+                    assert!(r.contains("<D:href>/dir/file.txt</D:href>"), "{}", r);
+                    assert!(r.contains(file), "{}", r);
+                }
+            }
+        }
+
+        // a file never gets a slash.
+        let (_, text) = propfind("/dir/file.txt/").await;
+        assert!(text.contains("<D:href>/dir/file.txt</D:href>"), "{}", text);
+        assert!(text.contains(file), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn plus_in_name() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/a+b.txt", "plus").await;
+
+        let r = req("PROPFIND", "/").header("Depth", "1");
+        let (_, text) = run(&dav, r.body(Body::empty()).unwrap()).await;
+        assert!(text.contains("<D:href>/a%2Bb.txt</D:href>"), "{}", text);
+        assert!(!text.contains("a%20b"), "{}", text);
+
+        for path in ["/a+b.txt", "/a%2Bb.txt", "/a%2bb.txt"] {
+            let (resp, body) = run(&dav, req("GET", path).body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 200, "{}", path);
+            assert_eq!(body, "plus");
+        }
+        let (resp, _) = run(&dav, req("GET", "/a%20b.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn proppatch_atomic() {
+        use crate::asyncfs::async_trait;
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use crate::tests::ForwardFs;
+        use http::StatusCode;
+
+        // Applies what it can, but refuses to set "locked".
+        #[derive(Clone)]
+        struct PartialFs(Box<MemFs>);
+
+        #[async_trait]
+        impl ForwardFs for PartialFs {
+            fn inner(&self) -> &dyn DavFileSystem {
+                &*self.0
+            }
+
+            async fn patch_props(
+                &self,
+                path: &DavPath,
+                patch: Vec<(bool, DavProp)>,
+            ) -> FsResult<Vec<(StatusCode, DavProp)>> {
+                let mut ret = Vec::new();
+                for (set, prop) in patch {
+                    if prop.name == "locked" {
+                        ret.push((StatusCode::FORBIDDEN, prop));
+                    } else {
+                        ret.extend(self.0.patch_props(path, vec![(set, prop)]).await?);
+                    }
+                }
+                Ok(ret)
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(PartialFs(MemFs::new())))
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+        let proppatch = |body: String| {
+            let r = req("PROPPATCH", "/file.txt").body(Body::from(body));
+            run(&dav, r.unwrap())
+        };
+        let update = |props: &str| {
+            format!(
+                r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:z">{}</D:propertyupdate>"#,
+                props
+            )
+        };
+
+        let (_, text) = proppatch(update(
+            "<D:set><D:prop><Z:color>red</Z:color></D:prop></D:set>",
+        ))
+        .await;
+        assert!(text.contains("200 OK"), "{}", text);
+
+        let (resp, text) = proppatch(update(
+            "<D:set><D:prop><Z:color>blue</Z:color><Z:locked>yes</Z:locked>\
+             <Z:size>big</Z:size></D:prop></D:set>",
+        ))
+        .await;
+        assert_eq!(resp.status(), 207);
+        assert!(text.contains("403 Forbidden"), "{}", text);
+        assert!(text.contains("424 Failed Dependency"), "{}", text);
+        assert!(!text.contains("200 OK"), "{}", text);
+        let failed = text
+            .split("<D:propstat>")
+            .find(|p| p.contains("424"))
+            .unwrap();
+        assert!(
+            failed.contains("color") && failed.contains("size"),
+            "{}",
+            text
+        );
+        assert!(!failed.contains("locked"), "{}", text);
+
+        // nothing was changed.
+        let r = req("PROPFIND", "/file.txt").header("Depth", "0");
+        let (_, text) = run(&dav, r.body(Body::empty()).unwrap()).await;
+        assert!(text.contains(">red</Z:color>"), "{}", text);
+        assert!(!text.contains("size"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn redirect_ref() {
+        use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+        use crate::davpath::DavPath;
+        use crate::fs::*;
+        use futures_util::stream;
+        use std::time::SystemTime;
+
+        // Every path is a redirect reference.
+        #[derive(Debug, Clone)]
+        struct Meta;
+
+        impl DavMetaData for Meta {
+            fn len(&self) -> u64 {
+                0
+            }
+            fn modified(&self) -> FsResult<SystemTime> {
+                Ok(SystemTime::UNIX_EPOCH)
+            }
+            fn is_dir(&self) -> bool {
+                false
+            }
+            fn redirect_ref(&self) -> Option<String> {
+                Some("https://example.com/target".to_string())
+            }
+        }
+
+        #[derive(Clone)]
+        struct RefFs;
+
+        #[async_trait]
+        impl AsyncDavFileSystem for RefFs {
+            async fn open(&self, _: &DavPath, _: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+                Err(FsError::NotFound)
+            }
+
+            async fn read_dir(
+                &self,
+                _path: &DavPath,
+                _meta: ReadDirMeta,
+            ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+                Ok(Box::pin(stream::empty()))
+            }
+
+            async fn metadata(&self, _path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+                Ok(Box::new(Meta))
+            }
+        }
+
+        let dav = DavHandler::builder()
+            .filesystem(Box::new(RefFs))
+            .build_handler();
+
+        for method in ["GET", "HEAD", "PROPFIND"] {
+            let r = req(method, "/link").header("Depth", "0");
+            let (resp, _) = run(&dav, r.body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 302, "{}", method);
+            assert_eq!(resp.headers()["location"], "https://example.com/target");
+        }
+
+        let r = req("PROPFIND", "/link")
+            .header("Depth", "0")
+            .header("Apply-To-Redirect-Ref", "T");
+        let (resp, text) = run(&dav, r.body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 207);
+        assert!(
+            text.contains("<D:resourcetype><D:redirectref></D:redirectref></D:resourcetype>"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("<D:reftarget><D:href>https://example.com/target</D:href></D:reftarget>"),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn propfind_lock_null() {
+        const LOCKINFO: &str = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockinfo>"#;
+        let fs = MemFs::new();
+        let dav = DavHandler::builder()
+            .filesystem(fs.clone())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let r = req("LOCK", "/new.txt").body(Body::from(LOCKINFO)).unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert_eq!(resp.status(), 201);
+        let token = resp.headers()["Lock-Token"].to_str().unwrap().to_string();
+
+        // the file goes away behind the server's back, the lock stays.
+        let other = DavHandler::builder().filesystem(fs).build_handler();
+        let r = req("DELETE", "/new.txt").body(Body::empty()).unwrap();
+        assert_eq!(run(&other, r).await.0.status(), 204);
+
+        let propfind = |path: &str| {
+            let r = req("PROPFIND", path).header("Depth", "0");
+            run(&dav, r.body(Body::empty()).unwrap())
+        };
+        let (resp, text) = propfind("/new.txt").await;
+        assert_eq!(resp.status(), 207);
+        assert!(
+            text.contains("<D:resourcetype></D:resourcetype>"),
+            "{}",
+            text
+        );
+        assert!(text.contains("<D:lockdiscovery><D:activelock>"), "{}", text);
+        assert!(
+            text.contains(token.trim_matches(|c| c == '<' || c == '>')),
+            "{}",
+            text
+        );
+        assert!(text.contains("<D:supportedlock><D:lockentry>"), "{}", text);
+
+        assert_eq!(propfind("/other.txt").await.0.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn displayname() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/my%20file.txt", "data").await;
+
+        let propfind = |body: &'static str| {
+            let r = req("PROPFIND", "/my%20file.txt").header("Depth", "0");
+            run(&dav, r.body(Body::from(body)).unwrap())
+        };
+        let prop = r#"<D:propfind xmlns:D="DAV:"><D:prop><D:displayname/></D:prop></D:propfind>"#;
+        let allprop = r#"<D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#;
+
+        let (resp, text) = propfind(prop).await;
+        assert_eq!(resp.status(), 207);
+        assert!(
+            text.contains("<D:displayname>my file.txt</D:displayname>"),
+            "{}",
+            text
+        );
+
+        let patch = r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop>
+            <D:displayname>Quarterly report</D:displayname>
+            </D:prop></D:set></D:propertyupdate>"#;
+        let r = req("PROPPATCH", "/my%20file.txt").body(Body::from(patch));
+        assert_eq!(run(&dav, r.unwrap()).await.0.status(), 207);
+
+        let (_, text) = propfind(prop).await;
+        assert!(text.contains(">Quarterly report</"), "{}", text);
+        assert!(!text.contains("my file.txt"), "{}", text);
+        let (_, text) = propfind(allprop).await;
+        assert_eq!(text.matches("displayname>").count(), 2, "{}", text);
+        assert!(text.contains(">Quarterly report</"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn collection_contentlength() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        mkcol(&dav, "/dir/").await;
+        put(&dav, "/dir/file.txt", "hello").await;
+
+        let propfind = |body: &'static str| {
+            let r = req("PROPFIND", "/dir/").header("Depth", "1");
+            run(&dav, r.body(Body::from(body)).unwrap())
+        };
+        // one <D:response> per resource, the collection comes first.
+        let responses = |text: &str| -> Vec<String> {
+            text.split("<D:response>")
+                .skip(1)
+                .map(String::from)
+                .collect()
+        };
+
+        let prop =
+            r#"<D:propfind xmlns:D="DAV:"><D:prop><D:getcontentlength/></D:prop></D:propfind>"#;
+        let (resp, text) = propfind(prop).await;
+        assert_eq!(resp.status(), 207);
+        let r = responses(&text);
+        assert_eq!(r.len(), 2, "{}", text);
+        assert!(r[0].contains("<D:href>/dir/</D:href>"), "{}", text);
+        assert!(
+            r[0].contains("<D:getcontentlength></D:getcontentlength></D:prop><D:status>HTTP/1.1 404 Not Found"),
+            "{}",
+            text
+        );
+        assert!(
+            r[1].contains(
+                "<D:getcontentlength>5</D:getcontentlength></D:prop><D:status>HTTP/1.1 200 OK"
+            ),
+            "{}",
+            text
+        );
+
+        // allprop and propname leave it out for the collection.
+        for body in [
+            r#"<D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#,
+            r#"<D:propfind xmlns:D="DAV:"><D:propname/></D:propfind>"#,
+        ] {
+            let (_, text) = propfind(body).await;
+            let r = responses(&text);
+            assert!(!r[0].contains("getcontentlength"), "{}", text);
+            assert!(r[1].contains("<D:getcontentlength>"), "{}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn xml_prefixes() {
+        let fs = MemFs::new();
+        let prefixes = |p: &[(&str, &str)]| {
+            let p = p.iter().map(|(ns, pfx)| (ns.to_string(), pfx.to_string()));
+            DavHandler::builder()
+                .filesystem(fs.clone())
+                .xml_prefixes(p.collect())
+                .build_handler()
+        };
+        let dav = prefixes(&[("DAV:", ""), ("urn:x", "x")]);
+        put(&dav, "/file.txt", "hello").await;
+        let patch = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x"><D:set><D:prop>
+            <X:color>blue</X:color><plain xmlns="">p</plain>
+            </D:prop></D:set></D:propertyupdate>"#;
+        let r = req("PROPPATCH", "/file.txt").body(Body::from(patch));
+        let (resp, text) = run(&dav, r.unwrap()).await;
+        assert_eq!(resp.status(), 207);
+        assert!(text.contains("<multistatus xmlns=\"DAV:\">"), "{}", text);
+
+        let body = r#"<D:propfind xmlns:D="DAV:" xmlns:X="urn:x"><D:prop>
+            <D:getcontentlength/><D:resourcetype/><X:color/><plain xmlns=""/>
+            </D:prop></D:propfind>"#;
+        let propfind = |dav: DavHandler| async move {
+            let r = req("PROPFIND", "/").header("Depth", "1");
+            run(&dav, r.body(Body::from(body)).unwrap()).await
+        };
+        let (resp, text) = propfind(dav).await;
+        assert_eq!(resp.status(), 207);
+        assert!(!text.contains("D:"), "{}", text);
+        assert!(
+            text.contains("<multistatus xmlns=\"DAV:\" xmlns:x=\"urn:x\">"),
+            "{}",
+            text
+        );
+        assert!(text.contains("<href>/file.txt</href>"), "{}", text);
+        assert!(
+            text.contains("<resourcetype><collection></collection></resourcetype>"),
+            "{}",
+            text
+        );
+        assert!(text.contains("<x:color>blue</x:color>"), "{}", text);
+        assert!(text.contains("<plain xmlns=\"\">p</plain>"), "{}", text);
+
+        // the namespaces are still right.
+        let root = xmltree::Element::parse(text.as_bytes()).unwrap();
+        assert_eq!(root.namespace.as_deref(), Some("DAV:"));
+        let prop = root
+            .get_child("response")
+            .and_then(|r| r.get_child("propstat"))
+            .and_then(|p| p.get_child("prop"))
+            .unwrap();
+        assert_eq!(prop.namespace.as_deref(), Some("DAV:"));
+
+        let (_, text) = propfind(prefixes(&[("DAV:", "d")])).await;
+        assert!(
+            text.contains("<d:multistatus xmlns:d=\"DAV:\">"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("<d:getcontentlength>5</d:getcontentlength>"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("<X:color xmlns:X=\"urn:x\">blue</X:color>"),
+            "{}",
+            text
+        );
+        assert!(!text.contains("D:"), "{}", text);
+
+        // the default stays the same.
+        let (_, text) = propfind(prefixes(&[])).await;
+        assert!(
+            text.contains("<D:multistatus xmlns:D=\"DAV:\">"),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn read_dir_errors() {
+        use crate::fs::FsError;
+        use crate::testutil::{FaultFs, FsOp};
+
+        let memfs = MemFs::new();
+        let plain = DavHandler::builder()
+            .filesystem(memfs.clone())
+            .build_handler();
+        mkcol(&plain, "/dir/").await;
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            put(&plain, &format!("/dir/{}", name), "hello").await;
+        }
+        let fs = FaultFs::new(memfs);
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        let propfind = || {
+            let r = req("PROPFIND", "/dir/").header("Depth", "1");
+            run(&dav, r.body(Body::empty()).unwrap())
+        };
+
+        // an entry that cannot be read is listed with the error.
+        fs.fail_path(FsOp::Metadata, "/dir/b.txt", FsError::Forbidden);
+        let (resp, text) = propfind().await;
+        assert_eq!(resp.status(), 207);
+        assert_eq!(text.matches("<D:response>").count(), 4, "{}", text);
+        assert_eq!(
+            text.matches("<D:href>/dir/</D:href>").count(),
+            1,
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("<D:response><D:href>/dir/b.txt</D:href><D:status>HTTP/1.1 403 Forbidden</D:status></D:response>"),
+            "{}",
+            text
+        );
+        assert!(!text.contains("responsedescription"), "{}", text);
+
+        // an error without a name is noted for the whole listing.
+        fs.clear();
+        fs.fail_path(FsOp::ReadDirEntry, "/dir/b.txt", FsError::Forbidden);
+        let (resp, text) = propfind().await;
+        assert_eq!(resp.status(), 207);
+        assert_eq!(text.matches("<D:response>").count(), 3, "{}", text);
+        assert_eq!(
+            text.matches("<D:href>/dir/</D:href>").count(),
+            1,
+            "{}",
+            text
+        );
+        assert!(text.contains("<D:href>/dir/a.txt</D:href>"), "{}", text);
+        assert!(text.contains("<D:href>/dir/c.txt</D:href>"), "{}", text);
+        assert!(!text.contains("b.txt"), "{}", text);
+        assert!(
+            text.ends_with("</D:response><D:responsedescription>listing of /dir/ is incomplete: 403 Forbidden</D:responsedescription></D:multistatus>"),
+            "{}",
+            text
+        );
+
+        // a directory that cannot be read completely is not removed.
+        let (resp, _) = run(&dav, req("DELETE", "/dir/").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 403);
+        let r = req("PROPFIND", "/dir/").header("Depth", "1");
+        let (_, text) = run(&plain, r.body(Body::empty()).unwrap()).await;
+        assert!(text.contains("<D:href>/dir/b.txt</D:href>"), "{}", text);
+        assert!(!text.contains("a.txt"), "{}", text);
+    }
+}
@@ -2,21 +2,32 @@ use std::any::Any;
 use std::error::Error as StdError;
 use std::io;
 use std::panic;
+use std::time::{Duration, SystemTime};
 
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use bytes::{Buf, Bytes};
 use headers::HeaderMapExt;
+use http::HeaderValue;
 use http::StatusCode as SC;
 use http::{self, Request, Response};
 use http_body::Body as HttpBody;
 use http_body_util::BodyExt;
+use md5::{Digest, Md5};
 
 use crate::body::Body;
 use crate::conditional::if_match_get_tokens;
 use crate::davheaders;
+use crate::davpath::DavPath;
 use crate::fs::*;
+use crate::handle_props::content_type_prop;
+use crate::multipart::Multipart;
+use crate::util::DavMethod;
 use crate::{DavError, DavResult};
 
 const SABRE: &str = "application/x-sabredav-partialupdate";
+// 9999-12-31 23:59:59, the last second that fits in an HTTP date.
+const MAX_MTIME: u64 = 253_402_300_799;
 
 // This is a nice hack. If the type 'E' is actually an io::Error or a Box<io::Error>,
 // convert it back into a real io::Error. If it is a DavError or a Box<DavError>,
@@ -55,6 +66,44 @@ where
 }
 
 impl crate::DavInner {
+    // Create the collections above `path` that do not exist yet.
+    async fn create_parents(&self, path: &DavPath) -> DavResult<()> {
+        let mut missing = Vec::new();
+        let mut dir = path.parent();
+        while dir.as_bytes() != b"/" {
+            match self.fs.metadata(&dir).await {
+                Ok(meta) if meta.is_dir() => break,
+                Ok(_) => return Err(DavError::StatusClose(SC::CONFLICT)),
+                Err(FsError::NotFound) => {
+                    let parent = dir.parent();
+                    missing.push(dir);
+                    dir = parent;
+                }
+                Err(e) => return Err(DavError::FsError(e)),
+            }
+        }
+        for dir in missing.iter().rev() {
+            match self.fs.create_dir(dir).await {
+                Ok(()) | Err(FsError::Exists) => {}
+                Err(e) => return Err(DavError::FsError(e)),
+            }
+        }
+        Ok(())
+    }
+
+    // Move a staged upload over `path`. The dead properties of the file
+    // it replaces go along, like they would when writing to it directly.
+    async fn replace_file(&self, staging: &DavPath, path: &DavPath) -> FsResult<()> {
+        if self.fs.have_props(path).await {
+            let props = self.fs.get_props(path, true).await?;
+            if !props.is_empty() {
+                let patch = props.into_iter().map(|p| (true, p)).collect();
+                self.fs.patch_props(staging, patch).await?;
+            }
+        }
+        self.fs.rename(staging, path).await
+    }
+
     pub(crate) async fn handle_put<ReqBody, ReqData, ReqError>(
         self,
         req: &Request<()>,
@@ -98,6 +147,9 @@ impl crate::DavInner {
 
         let path = self.path(req);
         let meta = self.fs.metadata(&path).await;
+        if meta.is_err() && self.is_nonportable(&path) {
+            return Err(DavError::StatusClose(SC::FORBIDDEN));
+        }
 
         // close connection on error.
         let mut res = Response::new(Body::empty());
@@ -170,6 +222,33 @@ impl crate::DavInner {
             Err(_) => return Err(DavError::StatusClose(SC::BAD_REQUEST)),
         }
 
+        // an upload from a HTML form?
+        let mut multipart = None;
+        if self.accept_multipart.unwrap_or(false) && !do_range {
+            multipart = req
+                .headers()
+                .typed_get::<davheaders::ContentType>()
+                .and_then(|ct| Multipart::from_content_type(&ct.0));
+            if multipart.is_some() {
+                // the size of the body is not the size of the file.
+                oo.size = None;
+            }
+        }
+
+        // the digest of the body that the client expects.
+        let mut md5 = None;
+        if self.verify_content_md5.unwrap_or(false) {
+            if let Some(v) = req.headers().get("Content-MD5") {
+                let digest = v
+                    .to_str()
+                    .ok()
+                    .and_then(|v| STANDARD.decode(v.trim()).ok())
+                    .filter(|d| d.len() == 16)
+                    .ok_or(DavError::StatusClose(SC::BAD_REQUEST))?;
+                md5 = Some((Md5::new(), digest));
+            }
+        }
+
         // check the If and If-* headers.
         let tokens = if_match_get_tokens(req, meta.as_ref().ok(), &self.fs, &self.ls, &path);
         let tokens = match tokens.await {
@@ -202,26 +281,59 @@ impl crate::DavInner {
             oo.create_new = true;
         }
 
+        if self.auto_mkcol_on_put.unwrap_or(false) && oo.create && meta.is_err() {
+            self.create_parents(&path).await?;
+        }
+
+        // with Content-MD5, an existing file is only replaced once the
+        // body has been checked. until then, it is written next to it.
+        let staging = match md5 {
+            Some(_) if meta.is_ok() => {
+                if do_range {
+                    return Err(DavError::StatusClose(SC::NOT_IMPLEMENTED));
+                }
+                if oo.create_new {
+                    return Err(DavError::StatusClose(SC::PRECONDITION_FAILED));
+                }
+                let mut p = path.parent();
+                p.add_slash();
+                let name = String::from_utf8_lossy(path.file_name_bytes());
+                let id = uuid::Uuid::new_v4().simple();
+                p.push_segment(format!(".{}.upload-{}", name, id).as_bytes());
+                oo.create = true;
+                oo.create_new = true;
+                Some(p)
+            }
+            _ => None,
+        };
+
         let create = oo.create;
         let create_new = oo.create_new;
-        let mut file = match self.fs.open(&path, oo).await {
-            Ok(f) => f,
-            Err(FsError::NotFound) | Err(FsError::Exists) => {
+        let target = staging.as_ref().unwrap_or(&path);
+        let open_error = |e| match e {
+            FsError::NotFound | FsError::Exists => {
                 let s = if !create || create_new {
                     SC::PRECONDITION_FAILED
                 } else {
                     SC::CONFLICT
                 };
-                return Err(DavError::StatusClose(s));
+                DavError::StatusClose(s)
             }
-            Err(e) => return Err(DavError::FsError(e)),
+            e => DavError::FsError(e),
         };
 
-        if do_range {
-            // seek to beginning of requested data.
-            if file.seek(std::io::SeekFrom::Start(start)).await.is_err() {
-                return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
+        // a multipart body might not have a file part at all, so the
+        // file is only opened once that part starts.
+        let mut file = None;
+        if multipart.is_none() {
+            let mut f = self.fs.open(target, oo.clone()).await.map_err(open_error)?;
+            if do_range {
+                // seek to beginning of requested data.
+                if f.seek(std::io::SeekFrom::Start(start)).await.is_err() {
+                    return Err(DavError::StatusClose(SC::RANGE_NOT_SATISFIABLE));
+                }
             }
+            file = Some(f);
         }
 
         res.headers_mut()
@@ -231,40 +343,153 @@ impl crate::DavInner {
 
         // loop, read body, write to file.
         let mut total = 0u64;
+        let method = match *req.method() {
+            http::Method::PATCH => DavMethod::Patch,
+            http::Method::POST => DavMethod::Post,
+            _ => DavMethod::Put,
+        };
+        let mut meter = self.transfer_meter(method, &path);
+
+        let verify = md5.is_some();
+        let written = async {
+            let mut buf_bytes = None;
+            while let Some(data) = body.frame().await {
+                let frame = data.map_err(|e| to_ioerror(e))?;
+                let mut buf = frame
+                    .into_data()
+                    .unwrap_or_else(|_| panic!("Unexpected non-DATA frame"));
+                let buflen = buf.remaining();
+                total += buflen as u64;
+                if let Some(ref mut meter) = meter {
+                    meter.received(buflen as u64);
+                }
+                // consistency check.
+                if have_count && total > count {
+                    break;
+                }
+                if let Some((ref mut hasher, _)) = md5 {
+                    // hash the body as it was sent.
+                    let data = buf.copy_to_bytes(buflen);
+                    hasher.update(&data);
+                    buf_bytes = Some(data);
+                }
+                if let Some(ref mut mp) = multipart {
+                    let data = match buf_bytes.take() {
+                        Some(data) => data,
+                        None => buf.copy_to_bytes(buflen),
+                    };
+                    let parts = mp
+                        .feed(&data)
+                        .map_err(|_| DavError::StatusClose(SC::BAD_REQUEST))?;
+                    if file.is_none() && mp.in_file() {
+                        file = Some(self.fs.open(target, oo.clone()).await.map_err(open_error)?);
+                    }
+                    if let Some(ref mut file) = file {
+                        for bytes in parts {
+                            file.write_bytes(bytes).await?;
+                        }
+                    }
+                    continue;
+                }
+                // without multipart, the file was opened above.
+                let file = file.as_mut().unwrap();
+                if let Some(data) = buf_bytes.take() {
+                    file.write_bytes(data).await?;
+                    continue;
+                }
+                // The `Buf` might actually be a `Bytes`.
+                let b = {
+                    let b: &mut dyn std::any::Any = &mut buf;
+                    b.downcast_mut::<Bytes>()
+                };
+                if let Some(bytes) = b {
+                    let bytes = std::mem::replace(bytes, Bytes::new());
+                    file.write_bytes(bytes).await?;
+                } else {
+                    file.write_buf(Box::new(buf)).await?;
+                }
+            }
+            if let Some(ref mut file) = file {
+                file.flush().await?;
+            }
 
-        while let Some(data) = body.frame().await {
-            let frame = data.map_err(|e| to_ioerror(e))?;
-            let mut buf = frame
-                .into_data()
-                .unwrap_or_else(|_| panic!("Unexpected non-DATA frame"));
-            let buflen = buf.remaining();
-            total += buflen as u64;
-            // consistency check.
             if have_count && total > count {
-                break;
+                error!("PUT file: sender is sending more bytes than expected");
+                return Err(DavError::StatusClose(SC::BAD_REQUEST));
             }
-            // The `Buf` might actually be a `Bytes`.
-            let b = {
-                let b: &mut dyn std::any::Any = &mut buf;
-                b.downcast_mut::<Bytes>()
-            };
-            if let Some(bytes) = b {
-                let bytes = std::mem::replace(bytes, Bytes::new());
-                file.write_bytes(bytes).await?;
-            } else {
-                file.write_buf(Box::new(buf)).await?;
+
+            if have_count && total < count {
+                error!("PUT file: premature EOF on input");
+                return Err(DavError::StatusClose(SC::BAD_REQUEST));
+            }
+
+            if let Some(Err(_)) = multipart.as_ref().map(|mp| mp.finish()) {
+                error!("PUT file: no file found in multipart body");
+                return Err(DavError::StatusClose(SC::BAD_REQUEST));
+            }
+            if let Some((hasher, digest)) = md5.take() {
+                if hasher.finalize()[..] != digest[..] {
+                    error!("PUT file: Content-MD5 mismatch");
+                    return Err(DavError::StatusClose(SC::BAD_REQUEST));
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        // on failure, nothing of the upload is kept. a staged upload is
+        // closed first, an open file cannot be renamed or removed on
+        // every platform.
+        if staging.is_some() {
+            file = None;
+        }
+        let written = match (written, staging.as_ref()) {
+            (Ok(()), Some(staging)) => self
+                .replace_file(staging, &path)
+                .await
+                .map_err(|e| e.into()),
+            (written, _) => written,
+        };
+        if let Err(e) = written {
+            if let Some(ref staging) = staging {
+                let _ = self.fs.remove_file(staging).await;
+            } else if verify && meta.is_err() {
+                let _ = self.fs.remove_file(&path).await;
             }
+            return Err(e);
         }
-        file.flush().await?;
 
-        if have_count && total > count {
-            error!("PUT file: sender is sending more bytes than expected");
-            return Err(DavError::StatusClose(SC::BAD_REQUEST));
+        // store the content-type, or forget about a previous one.
+        if self.preserve_content_type.unwrap_or(false) && self.fs.have_props(&path).await {
+            let ct = match multipart {
+                Some(ref mp) => mp.content_type().map(|ct| ct.to_string()),
+                None => req
+                    .headers()
+                    .typed_get::<davheaders::ContentType>()
+                    .map(|ct| ct.0),
+            };
+            let patch = vec![(ct.is_some(), content_type_prop(ct.as_deref()))];
+            if let Err(e) = self.fs.patch_props(&path, patch).await {
+                debug!("PUT: failed to store content-type: {:?}", e);
+            }
         }
 
-        if have_count && total < count {
-            error!("PUT file: premature EOF on input");
-            return Err(DavError::StatusClose(SC::BAD_REQUEST));
+        // ownCloud / Nextcloud clients send the modification time of the file.
+        if let Some(mtime) = req
+            .headers()
+            .get("X-OC-MTime")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|&v| v <= MAX_MTIME)
+            .and_then(|v| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(v)))
+        {
+            match self.fs.set_modified(&path, mtime).await {
+                Ok(()) => {
+                    res.headers_mut()
+                        .insert("X-OC-MTime", HeaderValue::from_static("accepted"));
+                }
+                Err(e) => debug!("PUT: failed to set modification time: {:?}", e),
+            }
         }
 
         // Report whether we created or updated the file.
@@ -279,7 +504,19 @@ impl crate::DavInner {
         // no errors, connection may be kept open.
         res.headers_mut().remove(http::header::CONNECTION);
 
-        if let Ok(m) = file.metadata().await {
+        // send the new ETag and Last-Modified, so that the client does
+        // not have to ask for them. The metadata of the open file is
+        // usually cheap to get, if not, ask the filesystem. A staged
+        // file has been renamed, ask the filesystem about it, too.
+        let newmeta = match (staging, file) {
+            (None, Some(mut file)) => file.metadata().await,
+            _ => Err(FsError::NotImplemented),
+        };
+        let newmeta = match newmeta {
+            Ok(m) => Ok(m),
+            Err(_) => self.fs.metadata(&path).await,
+        };
+        if let Ok(m) = newmeta {
             if let Some(etag) = davheaders::ETag::from_meta(&m) {
                 res.headers_mut().typed_insert(etag);
             }
@@ -291,3 +528,247 @@ impl crate::DavInner {
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_MTIME;
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{req, run};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn put_etag() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+
+        for (content, status) in [("hello", 201), ("hello, world", 204)] {
+            let r = req("PUT", "/file.txt").body(Body::from(content)).unwrap();
+            let (resp, _) = run(&dav, r).await;
+            assert_eq!(resp.status(), status);
+            let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+            let modified = resp.headers()["last-modified"].clone();
+
+            let r = req("GET", "/file.txt").body(Body::empty()).unwrap();
+            let (resp, _) = run(&dav, r).await;
+            assert_eq!(resp.headers()["etag"], etag.as_str());
+            assert_eq!(resp.headers()["last-modified"], modified);
+
+            let r = req("PROPFIND", "/file.txt").header("Depth", "0");
+            let (_, text) = run(&dav, r.body(Body::empty()).unwrap()).await;
+            let getetag = format!("<D:getetag>{}</D:getetag>", etag.trim_matches('"'));
+            assert!(text.contains(&getetag), "{}", text);
+
+            let r = req("GET", "/file.txt").header("If-None-Match", etag.as_str());
+            assert_eq!(
+                run(&dav, r.body(Body::empty()).unwrap()).await.0.status(),
+                304
+            );
+        }
+    }
+
+    async fn put_get(dav: &DavHandler) -> String {
+        let r = req("PUT", "/file.txt")
+            .header("Content-Type", "text/plain; charset=iso-8859-1")
+            .body(Body::from("hello"))
+            .unwrap();
+        let (resp, _) = run(dav, r).await;
+        assert!(resp.status().is_success());
+        let (resp, _) = run(dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        resp.headers()["content-type"].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn preserve_content_type() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .preserve_content_type(true)
+            .build_handler();
+        assert_eq!(put_get(&dav).await, "text/plain; charset=iso-8859-1");
+
+        let r = req("PROPFIND", "/file.txt")
+            .header("Depth", "0")
+            .body(Body::empty())
+            .unwrap();
+        let (_, body) = run(&dav, r).await;
+        assert_eq!(
+            body.matches("getcontenttype>text/plain; charset=iso-8859-1<")
+                .count(),
+            1
+        );
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        assert_eq!(put_get(&dav).await, "text/plain");
+    }
+
+    #[tokio::test]
+    async fn accept_multipart() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .accept_multipart(true)
+            .build_handler();
+        let body = "--b0undary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"file.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            hello\r\n--b0undary--\r\n";
+        let r = req("PUT", "/file.txt")
+            .header("Content-Type", "multipart/form-data; boundary=b0undary")
+            .body(Body::from(body))
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+        let (_, content) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(content, "hello");
+
+        // no file part, the file is left alone and nothing is created.
+        for path in ["/file.txt", "/new.txt"] {
+            let r = req("PUT", path)
+                .header("Content-Type", "multipart/form-data; boundary=b0undary")
+                .body(Body::from("--b0undary--\r\n"))
+                .unwrap();
+            assert_eq!(run(&dav, r).await.0.status(), 400);
+        }
+        let (_, content) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(content, "hello");
+        let (resp, _) = run(&dav, req("GET", "/new.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn oc_mtime() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let r = req("PUT", "/file.txt")
+            .header("X-OC-MTime", "1500000000")
+            .body(Body::from("hello"))
+            .unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(resp.headers()["x-oc-mtime"], "accepted");
+        assert_eq!(
+            resp.headers()["last-modified"],
+            "Fri, 14 Jul 2017 02:40:00 GMT"
+        );
+
+        let (resp, _) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(
+            resp.headers()["last-modified"],
+            "Fri, 14 Jul 2017 02:40:00 GMT"
+        );
+
+        // times that cannot be represented are ignored.
+        for mtime in [u64::MAX, MAX_MTIME + 1] {
+            let r = req("PUT", "/file.txt")
+                .header("X-OC-MTime", mtime.to_string())
+                .body(Body::from("hello"))
+                .unwrap();
+            let (resp, _) = run(&dav, r).await;
+            assert_eq!(resp.status(), 204);
+            assert!(!resp.headers().contains_key("x-oc-mtime"));
+        }
+    }
+
+    #[tokio::test]
+    async fn content_md5() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .verify_content_md5(true)
+            .build_handler();
+        let put = |path: &str, md5: &str| {
+            req("PUT", path)
+                .header("Content-MD5", md5)
+                .body(Body::from("hello"))
+                .unwrap()
+        };
+
+        let (resp, _) = run(&dav, put("/good.txt", "XUFAKrxLKna5cZ2REBfFkg==")).await;
+        assert_eq!(resp.status(), 201);
+        let (_, body) = run(&dav, req("GET", "/good.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "hello");
+
+        // a mismatch leaves no file behind.
+        let (resp, _) = run(&dav, put("/bad.txt", "1B2M2Y8AsgTpgAmY7PhCfg==")).await;
+        assert_eq!(resp.status(), 400);
+        let (resp, _) = run(&dav, req("GET", "/bad.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 404);
+
+        let (resp, _) = run(&dav, put("/bad.txt", "not base64")).await;
+        assert_eq!(resp.status(), 400);
+
+        // an existing file is only replaced by a body that matches.
+        let r = req("PUT", "/good.txt")
+            .header("Content-MD5", "XUFAKrxLKna5cZ2REBfFkg==")
+            .body(Body::from("corrupted"))
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 400);
+        let (_, body) = run(&dav, req("GET", "/good.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "hello");
+        let r = req("PUT", "/good.txt")
+            .header("Content-MD5", "RJl/h7iR+JRyt/K75OAAww==")
+            .body(Body::from("hello again"))
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 204);
+        let (_, body) = run(&dav, req("GET", "/good.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "hello again");
+
+        // nothing is left of the uploads.
+        let r = req("PROPFIND", "/").header("Depth", "1");
+        let (_, body) = run(&dav, r.body(Body::empty()).unwrap()).await;
+        assert_eq!(body.matches("<D:href>").count(), 2, "{}", body);
+
+        // the properties of a replaced file are kept.
+        let body = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:z"><D:set><D:prop><Z:color>blue</Z:color></D:prop></D:set></D:propertyupdate>"#;
+        let r = req("PROPPATCH", "/good.txt").body(Body::from(body));
+        assert_eq!(run(&dav, r.unwrap()).await.0.status(), 207);
+        assert_eq!(
+            run(&dav, put("/good.txt", "XUFAKrxLKna5cZ2REBfFkg=="))
+                .await
+                .0
+                .status(),
+            204
+        );
+        let body = r#"<D:propfind xmlns:D="DAV:" xmlns:Z="urn:z"><D:prop><Z:color/></D:prop></D:propfind>"#;
+        let r = req("PROPFIND", "/good.txt").header("Depth", "0");
+        let (_, body) = run(&dav, r.body(Body::from(body)).unwrap()).await;
+        assert!(body.contains(">blue</Z:color>"), "{}", body);
+        assert!(body.contains("200 OK") && !body.contains("404"), "{}", body);
+
+        // a partial update cannot be checked before it is written.
+        let r = req("PUT", "/good.txt")
+            .header("Content-MD5", "XUFAKrxLKna5cZ2REBfFkg==")
+            .header("Content-Range", "bytes 0-4/*")
+            .body(Body::from("hello"))
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 501);
+    }
+
+    #[tokio::test]
+    async fn auto_mkcol_on_put() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        let r = req("PUT", "/a/b/c.txt").body(Body::from("c")).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 409);
+
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .auto_mkcol_on_put(true)
+            .build_handler();
+        let r = req("PUT", "/a/b/c.txt").body(Body::from("c")).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+        let (resp, _) = run(
+            &dav,
+            req("PROPFIND", "/a/b/")
+                .header("Depth", "0")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(resp.status(), 207);
+        let (_, body) = run(&dav, req("GET", "/a/b/c.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "c");
+    }
+}
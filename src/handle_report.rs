@@ -0,0 +1,158 @@
+use std::io::Cursor;
+
+use headers::HeaderMapExt;
+use http::{Request, Response, StatusCode};
+use xmltree::Element;
+
+use crate::body::Body;
+use crate::davheaders;
+use crate::errors::*;
+use crate::report::{clark_name, ReportRequest};
+use crate::util::dav_xml_error;
+use crate::DavResult;
+
+impl crate::DavInner {
+    pub(crate) async fn handle_report(
+        &self,
+        req: &Request<()>,
+        xmldata: &[u8],
+    ) -> DavResult<Response<Body>> {
+        let reports = match self.report_handlers {
+            Some(ref r) if !r.is_empty() => r,
+            _ => return Err(DavError::UnknownDavMethod),
+        };
+
+        let path = self.path(req);
+        self.fs.metadata(&path).await?;
+
+        let body = match Element::parse(Cursor::new(xmldata)) {
+            Ok(body) => body,
+            Err(_) => return Err(DavError::XmlParseError),
+        };
+        let name = clark_name(&body);
+        trace!("report: {}", name);
+
+        let handler = match reports.get(&name) {
+            Some(h) => h,
+            None => {
+                let mut res = Response::new(dav_xml_error("<D:supported-report/>"));
+                let ct = "application/xml; charset=utf-8".to_owned();
+                res.headers_mut().typed_insert(davheaders::ContentType(ct));
+                *res.status_mut() = StatusCode::FORBIDDEN;
+                return Ok(res);
+            }
+        };
+
+        let rreq = ReportRequest {
+            request: req,
+            path: &path,
+            body: &body,
+            fs: self.fs.as_ref(),
+            inner: self,
+        };
+        Ok(handler.report(rreq).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_util::future::{BoxFuture, FutureExt};
+    use http::Response;
+
+    use crate::body::Body;
+    use crate::fs::FsResult;
+    use crate::memfs::MemFs;
+    use crate::report::{ExpandProperty, ReportHandler, ReportRequest};
+    use crate::tests::{put, req, run};
+    use crate::DavHandler;
+
+    // Returns the size of the resource.
+    struct SizeReport;
+
+    impl ReportHandler for SizeReport {
+        fn report<'a>(&'a self, req: ReportRequest<'a>) -> BoxFuture<'a, FsResult<Response<Body>>> {
+            async move {
+                let meta = req.fs.metadata(req.path).await?;
+                Ok(Response::new(Body::from(format!("{}", meta.len()))))
+            }
+            .boxed()
+        }
+    }
+
+    async fn report(dav: &DavHandler, body: &str) -> (u16, String) {
+        let r = req("REPORT", "/file.txt")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (resp, body) = run(dav, r).await;
+        (resp.status().as_u16(), body)
+    }
+
+    #[tokio::test]
+    async fn custom_report() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .report_handler("{urn:x}size", Arc::new(SizeReport))
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+
+        assert_eq!(
+            report(&dav, r#"<X:size xmlns:X="urn:x"/>"#).await,
+            (200, "5".to_string())
+        );
+        let (status, body) = report(&dav, r#"<X:size xmlns:X="urn:other"/>"#).await;
+        assert_eq!(status, 403);
+        assert!(body.contains("<D:supported-report/>"), "{}", body);
+
+        let r = req("OPTIONS", "/file.txt").body(Body::empty()).unwrap();
+        let (resp, _) = run(&dav, r).await;
+        assert!(resp.headers()["allow"].to_str().unwrap().contains("REPORT"));
+
+        // without report handlers REPORT is not implemented.
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+        assert_eq!(report(&dav, r#"<X:size xmlns:X="urn:x"/>"#).await.0, 501);
+    }
+
+    #[tokio::test]
+    async fn expand_property() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .report_handler("{DAV:}expand-property", Arc::new(ExpandProperty))
+            .build_handler();
+        put(&dav, "/file.txt", "hello").await;
+        put(&dav, "/b.txt", "hello, world").await;
+        let r = req("PROPPATCH", "/file.txt")
+            .body(Body::from(
+                r#"<D:propertyupdate xmlns:D="DAV:" xmlns:X="urn:x"><D:set><D:prop><X:links><D:href>/b.txt</D:href><D:href>/gone.txt</D:href></X:links></D:prop></D:set></D:propertyupdate>"#,
+            ))
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 207);
+
+        let (status, body) = report(
+            &dav,
+            r#"<D:expand-property xmlns:D="DAV:">
+                <D:property name="getcontentlength"/>
+                <D:property name="links" namespace="urn:x">
+                    <D:property name="getcontentlength"/>
+                </D:property>
+            </D:expand-property>"#,
+        )
+        .await;
+        assert_eq!(status, 207);
+        assert!(body.contains("<D:href>/file.txt</D:href><D:propstat><D:prop><D:getcontentlength>5</D:getcontentlength>"), "{}", body);
+        assert!(body.contains("<D:response><D:href>/b.txt</D:href><D:propstat><D:prop><D:getcontentlength>12</D:getcontentlength>"), "{}", body);
+        assert!(body.contains("<D:response><D:href>/gone.txt</D:href><D:status>HTTP/1.1 404 Not Found</D:status></D:response>"), "{}", body);
+        assert_eq!(body.matches("<D:response>").count(), 3);
+
+        let (status, _) = report(
+            &dav,
+            r#"<D:expand-property xmlns:D="DAV:"><D:property/></D:expand-property>"#,
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+}
@@ -30,7 +30,8 @@
 //! from the `http` and `http_body` crates. That means that you can use it
 //! straight away with http libraries / frameworks that also work with
 //! those types, like hyper. Compatibility modules for [actix-web][actix-compat]
-//! and [warp][warp-compat] are also provided.
+//! and [warp][warp-compat] are also provided. With the `tower` feature,
+//! `DavHandler` implements `tower::Service`.
 //!
 //! ## Implemented standards.
 //!
@@ -62,6 +63,16 @@
 //! - [`MemLs`]: ephemeral in-memory locksystem.
 //! - [`FakeLs`]: fake locksystem. just enough LOCK/UNLOCK support for macOS/Windows.
 //!
+//! [`ChainedLs`] combines several locksystems into a primary with fallbacks,
+//! [`QuotaFs`] limits the total size of the files in a filesystem,
+//! [`EncryptedFs`] stores the contents of the files encrypted (with the
+//! `encryptedfs` feature),
+//! [`VirtualFiles`] adds generated files to a filesystem, and
+//! [`SingleFileFs`] shares just one file.
+//!
+//! Filesystems that do CPU heavy work on every read or write can use
+//! [`BlockingFile`] to run that work on the blocking thread pool.
+//!
 //! ## Example.
 //!
 //! Example server using [hyper] that serves the /tmp directory in r/w mode. You should be
@@ -117,6 +128,12 @@
 //! [`MemFs`]: memfs/index.html
 //! [`LocalFs`]: localfs/index.html
 //! [`FakeLs`]: fakels/index.html
+//! [`ChainedLs`]: chainedls/index.html
+//! [`QuotaFs`]: quotafs/index.html
+//! [`EncryptedFs`]: encryptedfs/index.html
+//! [`VirtualFiles`]: virtualfiles/index.html
+//! [`SingleFileFs`]: singlefilefs/index.html
+//! [`BlockingFile`]: blockingfile/index.html
 //! [actix-compat]: actix/index.html
 //! [warp-compat]: warp/index.html
 //! [README_litmus]: https://github.com/messense/dav-server-rs/blob/main/README.litmus-test.md
@@ -145,8 +162,13 @@ mod handle_gethead;
 mod handle_lock;
 mod handle_mkcol;
 mod handle_options;
+mod handle_post;
 mod handle_props;
 mod handle_put;
+mod handle_report;
+#[cfg(any(docsrs, feature = "localfs"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "localfs")))]
+mod localfs_handles;
 #[cfg(any(docsrs, feature = "localfs"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "localfs")))]
 mod localfs_macos;
@@ -154,13 +176,24 @@ mod localfs_macos;
 #[cfg_attr(docsrs, doc(cfg(feature = "localfs")))]
 mod localfs_windows;
 mod multierror;
+mod multipart;
+mod sniff;
+mod tar;
+#[cfg(test)]
+mod tests;
 mod tree;
 mod util;
 mod voidfs;
 mod xmltree_ext;
 
+pub mod asyncfs;
+pub mod blockingfile;
 pub mod body;
+pub mod chainedls;
 pub mod davpath;
+#[cfg(any(docsrs, feature = "encryptedfs"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "encryptedfs")))]
+pub mod encryptedfs;
 pub mod fakels;
 pub mod fs;
 #[cfg(any(docsrs, feature = "localfs"))]
@@ -171,6 +204,16 @@ pub mod ls;
 #[cfg_attr(docsrs, doc(cfg(feature = "memfs")))]
 pub mod memfs;
 pub mod memls;
+pub mod quotafs;
+pub mod report;
+#[cfg(any(docsrs, feature = "localfs"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "localfs")))]
+pub mod singlefilefs;
+#[cfg(any(test, docsrs, feature = "testutil"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "testutil")))]
+pub mod testutil;
+pub mod transform;
+pub mod virtualfiles;
 
 #[cfg(any(docsrs, feature = "actix-compat"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "actix-compat")))]
@@ -184,5 +227,5 @@ pub(crate) use crate::davhandler::DavInner;
 pub(crate) use crate::errors::{DavError, DavResult};
 pub(crate) use crate::fs::*;
 
-pub use crate::davhandler::{DavConfig, DavHandler};
+pub use crate::davhandler::{BuildError, DavConfig, DavHandler, SortBy, TransferFn, TransferStats};
 pub use crate::util::{DavMethod, DavMethodSet};
@@ -6,6 +6,7 @@
 
 use std::any::Any;
 use std::collections::VecDeque;
+use std::fmt::Debug;
 use std::future::Future;
 use std::io::{self, Read, Seek, SeekFrom, Write};
 #[cfg(unix)]
@@ -31,6 +32,7 @@ use libc;
 
 use crate::davpath::DavPath;
 use crate::fs::*;
+use crate::localfs_handles::{CachedHandle, HandleCache};
 use crate::localfs_macos::DUCacheBuilder;
 
 const RUNTIME_TYPE_BASIC: u32 = 1;
@@ -81,6 +83,40 @@ where
     }
 }
 
+// Like blocking(), but use the spawner if it was set.
+async fn blocking_with<F, R>(spawner: &Option<Arc<dyn SpawnBlocking>>, func: F) -> R
+where
+    F: FnOnce() -> R,
+    F: Send + 'static,
+    R: Send + 'static,
+{
+    let spawner = match spawner {
+        Some(spawner) => spawner,
+        None => return blocking(func).await,
+    };
+    let (tx, rx) = futures_channel::oneshot::channel();
+    spawner
+        .spawn_blocking(Box::new(move || {
+            let _ = tx.send(func());
+        }))
+        .await;
+    rx.await.expect("blocking task did not complete")
+}
+
+/// Run blocking code on an async runtime other than tokio.
+///
+/// By default `LocalFs` runs its blocking filesystem calls via tokio's
+/// `spawn_blocking()` or `block_in_place()`, so it needs to run inside
+/// a tokio runtime. If you use another runtime, pass an implementation
+/// of this trait to `LocalFs::new_with_spawner`.
+///
+/// See `examples/localfs-spawner.rs` for an example.
+pub trait SpawnBlocking: Debug + Send + Sync {
+    /// Run `func` on a thread where blocking is allowed. The
+    /// returned future completes when `func` has returned.
+    fn spawn_blocking(&self, func: Box<dyn FnOnce() + Send + 'static>) -> BoxFuture<'static, ()>;
+}
+
 #[derive(Debug, Clone)]
 struct LocalFsMetaData(std::fs::Metadata);
 
@@ -99,10 +135,28 @@ pub(crate) struct LocalFsInner {
     pub macos: bool,
     pub is_file: bool,
     pub fs_access_guard: Option<Box<dyn Fn() -> Box<dyn Any> + Send + Sync + 'static>>,
+    pub spawner: Option<Arc<dyn SpawnBlocking>>,
+    pub fsync_on_flush: bool,
+    pub handle_cache: Option<Arc<HandleCache>>,
 }
 
 #[derive(Debug)]
-struct LocalFsFile(Option<std::fs::File>);
+struct LocalFsFile(
+    Option<std::fs::File>,
+    Option<Arc<dyn SpawnBlocking>>,
+    bool,
+    Option<(Arc<HandleCache>, CachedHandle)>,
+    Option<Box<dyn DavMetaData>>,
+);
+
+// read-only handles go back into the cache.
+impl Drop for LocalFsFile {
+    fn drop(&mut self) {
+        if let (Some(file), Some((cache, handle))) = (self.0.take(), self.3.take()) {
+            cache.put(handle, file);
+        }
+    }
+}
 
 struct LocalFsReadDir {
     fs: LocalFs,
@@ -148,6 +202,9 @@ impl LocalFs {
             case_insensitive,
             is_file: false,
             fs_access_guard: None,
+            spawner: None,
+            fsync_on_flush: false,
+            handle_cache: None,
         };
         Box::new({
             LocalFs {
@@ -168,6 +225,9 @@ impl LocalFs {
             case_insensitive: false,
             is_file: true,
             fs_access_guard: None,
+            spawner: None,
+            fsync_on_flush: false,
+            handle_cache: None,
         };
         Box::new({
             LocalFs {
@@ -192,6 +252,9 @@ impl LocalFs {
             case_insensitive,
             is_file: false,
             fs_access_guard,
+            spawner: None,
+            fsync_on_flush: false,
+            handle_cache: None,
         };
         Box::new({
             LocalFs {
@@ -200,6 +263,66 @@ impl LocalFs {
         })
     }
 
+    /// Like `new()`, but run blocking filesystem calls via `spawner`
+    /// instead of via tokio.
+    pub fn new_with_spawner<P: AsRef<Path>>(
+        base: P,
+        public: bool,
+        case_insensitive: bool,
+        macos: bool,
+        spawner: Arc<dyn SpawnBlocking>,
+    ) -> Box<LocalFs> {
+        let inner = LocalFsInner {
+            basedir: base.as_ref().to_path_buf(),
+            public,
+            macos,
+            case_insensitive,
+            is_file: false,
+            fs_access_guard: None,
+            spawner: Some(spawner),
+            fsync_on_flush: false,
+            handle_cache: None,
+        };
+        Box::new({
+            LocalFs {
+                inner: Arc::new(inner),
+            }
+        })
+    }
+
+    /// Call `fsync` when a file that was written to is flushed, which is
+    /// at the end of a PUT. On unix, the directory is synced as well
+    /// after a MOVE. Without this, data that the client was told is
+    /// stored can be lost when the server crashes. Default: false.
+    ///
+    /// Call this right after creating the filesystem, before it is cloned.
+    pub fn fsync_on_flush(mut self: Box<Self>, fsync: bool) -> Box<LocalFs> {
+        Arc::get_mut(&mut self.inner)
+            .expect("LocalFs::fsync_on_flush: filesystem has been cloned")
+            .fsync_on_flush = fsync;
+        self
+    }
+
+    /// Keep up to `size` files that were opened for reading open for
+    /// at most `max_age`, so that they can be used again without opening
+    /// them again. Changes made through this filesystem are seen right
+    /// away, changes made by others within `max_age`. Default: off.
+    ///
+    /// Call this right after creating the filesystem, before it is cloned.
+    pub fn handle_cache(mut self: Box<Self>, size: usize, max_age: Duration) -> Box<LocalFs> {
+        Arc::get_mut(&mut self.inner)
+            .expect("LocalFs::handle_cache: filesystem has been cloned")
+            .handle_cache = Some(Arc::new(HandleCache::new(size, max_age)));
+        self
+    }
+
+    // Forget cached handles of `path` and everything below it.
+    fn invalidate(&self, path: &Path) {
+        if let Some(ref cache) = self.inner.handle_cache {
+            cache.invalidate(path);
+        }
+    }
+
     fn fspath_dbg(&self, path: &DavPath) -> PathBuf {
         let mut pathbuf = self.inner.basedir.clone();
         if !self.inner.is_file {
@@ -208,16 +331,102 @@ impl LocalFs {
         pathbuf
     }
 
-    fn fspath(&self, path: &DavPath) -> PathBuf {
+    // Map a path to the local filesystem. A path with a segment that
+    // is not safe as a file name on this platform is forbidden.
+    fn fspath(&self, path: &DavPath) -> FsResult<PathBuf> {
+        if self.inner.is_file {
+            return Ok(self.inner.basedir.clone());
+        }
+        let pathbuf = path
+            .to_native(&self.inner.basedir)
+            .ok_or(FsError::Forbidden)?;
         if self.inner.case_insensitive {
-            crate::localfs_windows::resolve(&self.inner.basedir, path)
-        } else {
-            let mut pathbuf = self.inner.basedir.clone();
-            if !self.inner.is_file {
-                pathbuf.push(path.as_rel_ospath());
+            return Ok(crate::localfs_windows::resolve(&self.inner.basedir, path));
+        }
+        Ok(pathbuf)
+    }
+
+    // Open a file. The metadata from open_with_meta is kept for a file
+    // that is only read, and returned by its first `metadata()` call.
+    fn open_file<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+        meta: Option<Box<dyn DavMetaData>>,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        async move {
+            trace!("FS: open {:?}", self.fspath_dbg(path));
+            if self.is_forbidden(path) {
+                return Err(FsError::Forbidden);
             }
-            pathbuf
+            #[cfg(unix)]
+            let mode = if self.inner.public { 0o644 } else { 0o600 };
+            let path = self.fspath(path)?;
+            let spawner = self.inner.spawner.clone();
+            let fsync = self.inner.fsync_on_flush && (options.write || options.append);
+
+            // see if there's a cached handle we can use.
+            let read_only = !(options.write
+                || options.append
+                || options.truncate
+                || options.create
+                || options.create_new);
+            let meta = meta.filter(|_| read_only);
+            let mut cached = None;
+            if let Some(ref cache) = self.inner.handle_cache {
+                if read_only {
+                    let (file, handle) = cache.take(&path);
+                    let slot = Some((cache.clone(), handle));
+                    if let Some(file) = file {
+                        return Ok(
+                            Box::new(LocalFsFile(Some(file), spawner, fsync, slot, meta))
+                                as Box<dyn DavFile>,
+                        );
+                    }
+                    cached = slot;
+                } else {
+                    cache.invalidate(&path);
+                }
+            }
+
+            self.blocking(move || {
+                #[cfg(unix)]
+                let res = std::fs::OpenOptions::new()
+                    .read(options.read)
+                    .write(options.write)
+                    .append(options.append)
+                    .truncate(options.truncate)
+                    .create(options.create)
+                    .create_new(options.create_new)
+                    .mode(mode)
+                    .open(path);
+                #[cfg(windows)]
+                let res = std::fs::OpenOptions::new()
+                    .read(options.read)
+                    .write(options.write)
+                    .append(options.append)
+                    .truncate(options.truncate)
+                    .create(options.create)
+                    .create_new(options.create_new)
+                    .open(path);
+                // writes always go to the end, reads start there too.
+                let res = res.and_then(|mut file| {
+                    if options.append {
+                        file.seek(SeekFrom::End(0))?;
+                    }
+                    Ok(file)
+                });
+                match res {
+                    Ok(file) => Ok(
+                        Box::new(LocalFsFile(Some(file), spawner, fsync, cached, meta))
+                            as Box<dyn DavFile>,
+                    ),
+                    Err(e) => Err(e.into()),
+                }
+            })
+            .await
         }
+        .boxed()
     }
 
     // threadpool::blocking() adapter, also runs the before/after hooks.
@@ -228,7 +437,7 @@ impl LocalFs {
         R: Send + 'static,
     {
         let this = self.clone();
-        blocking(move || {
+        blocking_with(&self.inner.spawner, move || {
             let _guard = this.inner.fs_access_guard.as_ref().map(|f| f());
             func()
         })
@@ -244,7 +453,7 @@ impl DavFileSystem for LocalFs {
             if let Some(meta) = self.is_virtual(davpath) {
                 return Ok(meta);
             }
-            let path = self.fspath(davpath);
+            let path = self.fspath(davpath)?;
             if self.is_notfound(&path) {
                 return Err(FsError::NotFound);
             }
@@ -262,7 +471,7 @@ impl DavFileSystem for LocalFs {
             if let Some(meta) = self.is_virtual(davpath) {
                 return Ok(meta);
             }
-            let path = self.fspath(davpath);
+            let path = self.fspath(davpath)?;
             if self.is_notfound(&path) {
                 return Err(FsError::NotFound);
             }
@@ -281,10 +490,10 @@ impl DavFileSystem for LocalFs {
         &'a self,
         davpath: &'a DavPath,
         meta: ReadDirMeta,
-    ) -> FsFuture<FsStream<Box<dyn DavDirEntry>>> {
+    ) -> FsFuture<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
         async move {
             trace!("FS: read_dir {:?}", self.fspath_dbg(davpath));
-            let path = self.fspath(davpath);
+            let path = self.fspath(davpath)?;
             let path2 = path.clone();
             let iter = self.blocking(move || std::fs::read_dir(path)).await;
             match iter {
@@ -297,7 +506,7 @@ impl DavFileSystem for LocalFs {
                         iterator: Some(iterator),
                         fut: None,
                     };
-                    Ok(Box::pin(strm) as FsStream<Box<dyn DavDirEntry>>)
+                    Ok(Box::pin(strm) as FsStream<FsResult<Box<dyn DavDirEntry>>>)
                 }
                 Err(e) => Err(e.into()),
             }
@@ -306,42 +515,16 @@ impl DavFileSystem for LocalFs {
     }
 
     fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<Box<dyn DavFile>> {
-        async move {
-            trace!("FS: open {:?}", self.fspath_dbg(path));
-            if self.is_forbidden(path) {
-                return Err(FsError::Forbidden);
-            }
-            #[cfg(unix)]
-            let mode = if self.inner.public { 0o644 } else { 0o600 };
-            let path = self.fspath(path);
-            self.blocking(move || {
-                #[cfg(unix)]
-                let res = std::fs::OpenOptions::new()
-                    .read(options.read)
-                    .write(options.write)
-                    .append(options.append)
-                    .truncate(options.truncate)
-                    .create(options.create)
-                    .create_new(options.create_new)
-                    .mode(mode)
-                    .open(path);
-                #[cfg(windows)]
-                let res = std::fs::OpenOptions::new()
-                    .read(options.read)
-                    .write(options.write)
-                    .append(options.append)
-                    .truncate(options.truncate)
-                    .create(options.create)
-                    .create_new(options.create_new)
-                    .open(path);
-                match res {
-                    Ok(file) => Ok(Box::new(LocalFsFile(Some(file))) as Box<dyn DavFile>),
-                    Err(e) => Err(e.into()),
-                }
-            })
-            .await
-        }
-        .boxed()
+        self.open_file(path, options, None)
+    }
+
+    fn open_with_meta<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+        meta: Box<dyn DavMetaData>,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
+        self.open_file(path, options, Some(meta))
     }
 
     fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
@@ -352,7 +535,7 @@ impl DavFileSystem for LocalFs {
             }
             #[cfg(unix)]
             let mode = if self.inner.public { 0o755 } else { 0o700 };
-            let path = self.fspath(path);
+            let path = self.fspath(path)?;
             self.blocking(move || {
                 #[cfg(unix)]
                 {
@@ -376,9 +559,40 @@ impl DavFileSystem for LocalFs {
     fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
         async move {
             trace!("FS: remove_dir {:?}", self.fspath_dbg(path));
-            let path = self.fspath(path);
-            self.blocking(move || std::fs::remove_dir(path).map_err(|e| e.into()))
-                .await
+            let path = self.fspath(path)?;
+            let p = path.clone();
+            let res = self
+                .blocking(move || std::fs::remove_dir(p).map_err(|e| e.into()))
+                .await;
+            self.invalidate(&path);
+            res
+        }
+        .boxed()
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
+        async move {
+            // in macos mode some files are special, so let
+            // the handler delete the entries one by one.
+            if self.inner.macos {
+                return Err(FsError::NotImplemented);
+            }
+            trace!("FS: remove_dir_all {:?}", self.fspath_dbg(path));
+            let path = self.fspath(path)?;
+            let p = path.clone();
+            let res = self
+                .blocking(move || {
+                    // std::fs::remove_dir_all stops at the first error, with
+                    // part of the tree gone. If that might happen, leave it
+                    // to the handler, which reports every failing member.
+                    if !removable_tree(&p, true) {
+                        return Err(FsError::NotImplemented);
+                    }
+                    std::fs::remove_dir_all(p).map_err(|e| e.into())
+                })
+                .await;
+            self.invalidate(&path);
+            res
         }
         .boxed()
     }
@@ -389,9 +603,13 @@ impl DavFileSystem for LocalFs {
             if self.is_forbidden(path) {
                 return Err(FsError::Forbidden);
             }
-            let path = self.fspath(path);
-            self.blocking(move || std::fs::remove_file(path).map_err(|e| e.into()))
-                .await
+            let path = self.fspath(path)?;
+            let p = path.clone();
+            let res = self
+                .blocking(move || std::fs::remove_file(p).map_err(|e| e.into()))
+                .await;
+            self.invalidate(&path);
+            res
         }
         .boxed()
     }
@@ -406,26 +624,42 @@ impl DavFileSystem for LocalFs {
             if self.is_forbidden(from) || self.is_forbidden(to) {
                 return Err(FsError::Forbidden);
             }
-            let frompath = self.fspath(from);
-            let topath = self.fspath(to);
-            self.blocking(move || {
-                match std::fs::rename(&frompath, &topath) {
-                    Ok(v) => Ok(v),
-                    Err(e) => {
-                        // webdav allows a rename from a directory to a file.
-                        // note that this check is racy, and I'm not quite sure what
-                        // we should do if the source is a symlink. anyway ...
-                        if e.raw_os_error() == Some(libc::ENOTDIR) && frompath.is_dir() {
-                            // remove and try again.
-                            let _ = std::fs::remove_file(&topath);
-                            std::fs::rename(frompath, topath).map_err(|e| e.into())
-                        } else {
-                            Err(e.into())
+            let frompath = self.fspath(from)?;
+            let topath = self.fspath(to)?;
+            let fsync = self.inner.fsync_on_flush;
+            let paths = (frompath.clone(), topath.clone());
+            let res = self
+                .blocking(move || {
+                    match std::fs::rename(&frompath, &topath) {
+                        Ok(v) => Ok(v),
+                        Err(e) => {
+                            // webdav allows a rename from a directory to a file.
+                            // note that this check is racy, and I'm not quite sure what
+                            // we should do if the source is a symlink. anyway ...
+                            if e.raw_os_error() == Some(libc::ENOTDIR) && frompath.is_dir() {
+                                // remove and try again.
+                                let _ = std::fs::remove_file(&topath);
+                                std::fs::rename(&frompath, &topath)
+                            } else {
+                                Err(e)
+                            }
+                        }
+                    }?;
+                    // the rename is only durable once the directory is on disk.
+                    #[cfg(unix)]
+                    if fsync {
+                        if let Some(dir) = topath.parent() {
+                            std::fs::File::open(dir)?.sync_all()?;
                         }
                     }
-                }
-            })
-            .await
+                    #[cfg(not(unix))]
+                    let _ = fsync;
+                    Ok(())
+                })
+                .await;
+            self.invalidate(&paths.0);
+            self.invalidate(&paths.1);
+            res
         }
         .boxed()
     }
@@ -440,13 +674,13 @@ impl DavFileSystem for LocalFs {
             if self.is_forbidden(from) || self.is_forbidden(to) {
                 return Err(FsError::Forbidden);
             }
-            let path_from = self.fspath(from);
-            let path_to = self.fspath(to);
+            let path_from = self.fspath(from)?;
+            let path_to = self.fspath(to)?;
 
-            match self
-                .blocking(move || std::fs::copy(path_from, path_to))
-                .await
-            {
+            let p = path_to.clone();
+            let res = self.blocking(move || std::fs::copy(path_from, p)).await;
+            self.invalidate(&path_to);
+            match res {
                 Ok(_) => Ok(()),
                 Err(e) => {
                     debug!(
@@ -463,6 +697,67 @@ impl DavFileSystem for LocalFs {
     }
 }
 
+// Does it look like `path` and everything below it can be removed.
+// This is sync code, must be run in `blocking()`.
+fn removable_tree(path: &Path, top: bool) -> bool {
+    // removing the top directory needs write access to its parent.
+    if top && !path.parent().map(dir_writable).unwrap_or(false) {
+        return false;
+    }
+    if !dir_writable(path) {
+        return false;
+    }
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(_) => return false,
+        };
+        // DirEntry::metadata does not follow symlinks.
+        let meta = match entry.metadata() {
+            Ok(m) => m,
+            Err(_) => return false,
+        };
+        if meta.is_dir() {
+            if !removable_tree(&entry.path(), false) {
+                return false;
+            }
+        } else if cfg!(not(unix)) && meta.permissions().readonly() {
+            return false;
+        }
+    }
+    true
+}
+
+// Can entries be removed from this directory. A directory that its
+// owner cannot write to counts as not writable, even for root.
+#[cfg(unix)]
+fn dir_writable(path: &Path) -> bool {
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+    if meta.mode() & 0o200 == 0 {
+        return false;
+    }
+    let cpath = match std::ffi::CString::new(path.as_os_str().as_bytes()) {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+    unsafe { libc::access(cpath.as_ptr(), libc::W_OK | libc::X_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn dir_writable(path: &Path) -> bool {
+    match std::fs::metadata(path) {
+        Ok(m) => !m.permissions().readonly(),
+        Err(_) => false,
+    }
+}
+
 // read_batch() result.
 struct ReadDirBatch {
     iterator: Option<std::fs::ReadDir>,
@@ -523,16 +818,18 @@ impl LocalFsReadDir {
         let iterator = self.iterator.take();
         let fs = self.fs.clone();
         let do_meta = self.do_meta;
+        let spawner = fs.inner.spawner.clone();
 
         let fut: BoxFuture<ReadDirBatch> =
-            blocking(move || read_batch(iterator, fs, do_meta)).boxed();
+            async move { blocking_with(&spawner, move || read_batch(iterator, fs, do_meta)).await }
+                .boxed();
         fut
     }
 }
 
 // The stream implementation tries to be smart and batch I/O operations
 impl<'a> Stream for LocalFsReadDir {
-    type Item = Box<dyn DavDirEntry>;
+    type Item = FsResult<Box<dyn DavDirEntry>>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = Pin::into_inner(self);
@@ -569,8 +866,15 @@ impl<'a> Stream for LocalFsReadDir {
 
         // we filled the buffer, now pop from the buffer.
         match this.buffer.pop_front() {
-            Some(Ok(item)) => Poll::Ready(Some(Box::new(item))),
-            Some(Err(_)) | None => {
+            Some(Ok(item)) => Poll::Ready(Some(Ok(Box::new(item)))),
+            Some(Err(e)) => {
+                // the rest of the directory cannot be read. the cache
+                // would be incomplete, so drop it.
+                this.iterator.take();
+                this.dir_cache.take();
+                Poll::Ready(Some(Err(e.into())))
+            }
+            None => {
                 // fuse the iterator.
                 this.iterator.take();
                 // finish the cache.
@@ -662,8 +966,11 @@ impl DavDirEntry for LocalFsDirEntry {
 impl DavFile for LocalFsFile {
     fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
         async move {
+            if let Some(meta) = self.4.take() {
+                return Ok(meta);
+            }
             let file = self.0.take().unwrap();
-            let (meta, file) = blocking(move || (file.metadata(), file)).await;
+            let (meta, file) = blocking_with(&self.1, move || (file.metadata(), file)).await;
             self.0 = Some(file);
             Ok(Box::new(LocalFsMetaData(meta?)) as Box<dyn DavMetaData>)
         }
@@ -673,7 +980,7 @@ impl DavFile for LocalFsFile {
     fn write_bytes(&mut self, buf: Bytes) -> FsFuture<()> {
         async move {
             let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || (file.write_all(&buf), file)).await;
+            let (res, file) = blocking_with(&self.1, move || (file.write_all(&buf), file)).await;
             self.0 = Some(file);
             res.map_err(|e| e.into())
         }
@@ -683,7 +990,7 @@ impl DavFile for LocalFsFile {
     fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<()> {
         async move {
             let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || {
+            let (res, file) = blocking_with(&self.1, move || {
                 while buf.remaining() > 0 {
                     let n = match file.write(buf.chunk()) {
                         Ok(n) => n,
@@ -703,7 +1010,7 @@ impl DavFile for LocalFsFile {
     fn read_bytes(&mut self, count: usize) -> FsFuture<Bytes> {
         async move {
             let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || {
+            let (res, file) = blocking_with(&self.1, move || {
                 let mut buf = BytesMut::with_capacity(count);
                 let res = unsafe {
                     buf.set_len(count);
@@ -724,7 +1031,7 @@ impl DavFile for LocalFsFile {
     fn seek(&mut self, pos: SeekFrom) -> FsFuture<u64> {
         async move {
             let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || (file.seek(pos), file)).await;
+            let (res, file) = blocking_with(&self.1, move || (file.seek(pos), file)).await;
             self.0 = Some(file);
             res.map_err(|e| e.into())
         }
@@ -734,7 +1041,10 @@ impl DavFile for LocalFsFile {
     fn flush(&mut self) -> FsFuture<()> {
         async move {
             let mut file = self.0.take().unwrap();
-            let (res, file) = blocking(move || (file.flush(), file)).await;
+            let (mut res, mut file) = blocking_with(&self.1, move || (file.flush(), file)).await;
+            if res.is_ok() && self.2 {
+                (res, file) = blocking_with(&self.1, move || (file.sync_all(), file)).await;
+            }
             self.0 = Some(file);
             res.map_err(|e| e.into())
         }
@@ -816,3 +1126,255 @@ impl DavMetaData for LocalFsMetaData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    // Runs the blocking calls right away, and counts them.
+    #[derive(Debug, Default)]
+    struct CountSpawner(AtomicUsize);
+
+    impl SpawnBlocking for CountSpawner {
+        fn spawn_blocking(
+            &self,
+            func: Box<dyn FnOnce() + Send + 'static>,
+        ) -> BoxFuture<'static, ()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            func();
+            future::ready(()).boxed()
+        }
+    }
+
+    // Number of blocking calls made by `flush`.
+    async fn flush_calls(fsync: bool) -> usize {
+        let dir =
+            std::env::temp_dir().join(format!("dav-server-fsync-{}-{}", std::process::id(), fsync));
+        std::fs::create_dir_all(&dir).unwrap();
+        let spawner = Arc::new(CountSpawner::default());
+        let fs = LocalFs::new_with_spawner(&dir, false, false, false, spawner.clone())
+            .fsync_on_flush(fsync);
+
+        let path = DavPath::new("/file.txt").unwrap();
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        let mut file = fs.open(&path, oo).await.unwrap();
+        file.write_bytes(Bytes::from("hello")).await.unwrap();
+        let before = spawner.0.load(Ordering::SeqCst);
+        file.flush().await.unwrap();
+        let calls = spawner.0.load(Ordering::SeqCst) - before;
+
+        // a MOVE syncs the directory, that must work as well.
+        let dest = DavPath::new("/moved.txt").unwrap();
+        fs.rename(&path, &dest).await.unwrap();
+        assert_eq!(std::fs::read(dir.join("moved.txt")).unwrap(), b"hello");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        calls
+    }
+
+    #[tokio::test]
+    async fn fsync_on_flush() {
+        assert_eq!(flush_calls(false).await, 1);
+        assert_eq!(flush_calls(true).await, 2);
+    }
+
+    #[tokio::test]
+    async fn open_with_meta() {
+        let dir = std::env::temp_dir().join(format!("dav-server-meta-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let spawner = Arc::new(CountSpawner::default());
+        let fs = LocalFs::new_with_spawner(&dir, false, false, false, spawner.clone());
+        let path = DavPath::new("/file.txt").unwrap();
+        std::fs::write(dir.join("file.txt"), "hello").unwrap();
+        let meta = fs.metadata(&path).await.unwrap();
+
+        // a file that is read uses the metadata it was opened with.
+        let mut file = fs
+            .open_with_meta(&path, OpenOptions::read(), meta.clone())
+            .await
+            .unwrap();
+        let before = spawner.0.load(Ordering::SeqCst);
+        assert_eq!(file.metadata().await.unwrap().len(), 5);
+        assert_eq!(spawner.0.load(Ordering::SeqCst), before);
+
+        // a file that is written does not.
+        let mut file = fs
+            .open_with_meta(&path, OpenOptions::write(), meta)
+            .await
+            .unwrap();
+        file.write_bytes(Bytes::from("hello, world")).await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().len(), 12);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unsafe_name() {
+        let dir = std::env::temp_dir().join(format!("dav-server-native-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let spawner = Arc::new(CountSpawner::default());
+        let fs = LocalFs::new_with_spawner(&dir, false, false, false, spawner.clone());
+
+        // refused before the filesystem is touched.
+        let mut path = DavPath::new("/").unwrap();
+        path.push_segment(b"a\0b");
+        assert!(matches!(fs.metadata(&path).await, Err(FsError::Forbidden)));
+        assert!(matches!(
+            fs.open(&path, OpenOptions::read()).await,
+            Err(FsError::Forbidden)
+        ));
+        assert_eq!(spawner.0.load(Ordering::SeqCst), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn remove_dir_all() {
+        use crate::body::Body;
+        use crate::tests::{req, run};
+
+        let dir = std::env::temp_dir().join(format!("dav-server-rmtree-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("dir/sub")).unwrap();
+        std::fs::write(dir.join("dir/a.txt"), "a").unwrap();
+        std::fs::write(dir.join("dir/sub/b.txt"), "b").unwrap();
+        let fs = LocalFs::new(&dir, false, false, false);
+        let path = DavPath::new("/dir/").unwrap();
+
+        // a directory that cannot be written to: nothing is removed,
+        // the handler has to walk the tree.
+        let mode = |m| std::fs::Permissions::from_mode(m);
+        std::fs::set_permissions(dir.join("dir/sub"), mode(0o555)).unwrap();
+        assert!(matches!(
+            fs.remove_dir_all(&path).await,
+            Err(FsError::NotImplemented)
+        ));
+        assert!(dir.join("dir/a.txt").exists());
+
+        // the walk reports the member that could not be removed. Unless
+        // running as root, that can remove it anyway.
+        let dav = crate::DavHandler::builder()
+            .filesystem(fs.clone())
+            .build_handler();
+        let (resp, body) = run(&dav, req("DELETE", "/dir/").body(Body::empty()).unwrap()).await;
+        if unsafe { libc::geteuid() } != 0 {
+            assert_eq!(resp.status(), 207);
+            assert!(body.contains("/dir/sub/b.txt"), "{}", body);
+            assert!(!dir.join("dir/a.txt").exists());
+            assert!(dir.join("dir/sub/b.txt").exists());
+        }
+
+        // otherwise it is removed at once.
+        std::fs::create_dir_all(dir.join("dir/sub")).unwrap();
+        std::fs::set_permissions(dir.join("dir/sub"), mode(0o755)).unwrap();
+        std::fs::write(dir.join("dir/a.txt"), "a").unwrap();
+        fs.remove_dir_all(&path).await.unwrap();
+        assert!(!dir.join("dir").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn append() {
+        let dir = std::env::temp_dir().join(format!("dav-server-append-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fs = LocalFs::new(&dir, false, false, false);
+        let path = DavPath::new("/log.txt").unwrap();
+        let mut oo = OpenOptions::new();
+        oo.append = true;
+        oo.create = true;
+
+        let mut a = fs.open(&path, oo.clone()).await.unwrap();
+        a.write_bytes(Bytes::from("one\n")).await.unwrap();
+        let mut b = fs.open(&path, oo).await.unwrap();
+        assert_eq!(b.seek(SeekFrom::Current(0)).await.unwrap(), 4);
+        b.write_bytes(Bytes::from("two\n")).await.unwrap();
+        a.write_bytes(Bytes::from("three\n")).await.unwrap();
+        a.flush().await.unwrap();
+        b.flush().await.unwrap();
+        assert_eq!(
+            std::fs::read(dir.join("log.txt")).unwrap(),
+            b"one\ntwo\nthree\n"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Number of blocking calls made by 5 GETs of the same file.
+    async fn get_calls(cache: bool) -> usize {
+        use crate::body::Body;
+        use crate::tests::{req, run};
+
+        let dir = std::env::temp_dir().join(format!(
+            "dav-server-handles-{}-{}",
+            std::process::id(),
+            cache
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file.txt"), "hello").unwrap();
+        let spawner = Arc::new(CountSpawner::default());
+        let mut fs = LocalFs::new_with_spawner(&dir, false, false, false, spawner.clone());
+        if cache {
+            fs = fs.handle_cache(16, Duration::from_secs(60));
+        }
+        let dav = crate::DavHandler::builder().filesystem(fs).build_handler();
+
+        for _ in 0..5 {
+            let (resp, body) =
+                run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(body, "hello");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        spawner.0.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn handle_cache() {
+        // only the first GET opens the file.
+        assert_eq!(get_calls(false).await - get_calls(true).await, 4);
+
+        let dir = std::env::temp_dir().join(format!("dav-server-handles-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file.txt"), "hello").unwrap();
+        let fs = LocalFs::new(&dir, false, false, false).handle_cache(16, Duration::from_secs(60));
+        let cache = fs.inner.handle_cache.clone().unwrap();
+        let path = DavPath::new("/file.txt").unwrap();
+        let read = || async {
+            let mut file = fs.open(&path, OpenOptions::read()).await?;
+            file.read_bytes(100).await
+        };
+
+        // a cached handle is used even if the file is gone.
+        assert_eq!(read().await.unwrap(), "hello");
+        assert_eq!(cache.len(), 1);
+        std::fs::remove_file(dir.join("file.txt")).unwrap();
+        assert_eq!(read().await.unwrap(), "hello");
+
+        // but not after a change through the filesystem.
+        let mut oo = OpenOptions::write();
+        oo.create = true;
+        oo.truncate = true;
+        let mut file = fs.open(&path, oo).await.unwrap();
+        assert_eq!(cache.len(), 0);
+        file.write_bytes(Bytes::from("world")).await.unwrap();
+        drop(file);
+        assert_eq!(read().await.unwrap(), "world");
+        assert_eq!(cache.len(), 1);
+        fs.remove_file(&path).await.unwrap();
+        assert_eq!(cache.len(), 0);
+        assert!(matches!(read().await, Err(FsError::NotFound)));
+
+        // a handle in use during a change is not put back.
+        std::fs::write(dir.join("file.txt"), "again").unwrap();
+        let file = fs.open(&path, OpenOptions::read()).await.unwrap();
+        let dest = DavPath::new("/moved.txt").unwrap();
+        fs.rename(&path, &dest).await.unwrap();
+        drop(file);
+        assert_eq!(cache.len(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
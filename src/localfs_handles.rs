@@ -0,0 +1,130 @@
+// Cache of open file handles, for LocalFs.
+//
+// A file that is opened for reading only is not closed when the
+// `DavFile` is dropped, it is put in this cache. The next read-only
+// open of the same path takes it out again, which saves an `open`
+// and a `close` for every GET of a file that is read a lot.
+//
+// - entries are only valid for a short time, so that changes made
+//   behind our back (a file replaced by a rename) are seen soon.
+// - every change that we make to the filesystem through LocalFs
+//   removes the entries for the path, or for everything below it.
+// - a handle that is in use is not in the cache. If something
+//   was invalidated while it was in use, it is closed when it is
+//   dropped, instead of being put back.
+//
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+
+// Handle cache entry.
+struct Entry {
+    file: File,
+    // Time the handle was opened.
+    opened: Instant,
+}
+
+struct State {
+    cache: LruCache<PathBuf, Entry>,
+    // Incremented on every invalidation.
+    generation: u64,
+}
+
+// Open file handle cache.
+pub(crate) struct HandleCache {
+    state: Mutex<State>,
+    max_age: Duration,
+}
+
+// A handle that was taken from the cache, or that can be put in it.
+#[derive(Debug)]
+pub(crate) struct CachedHandle {
+    path: PathBuf,
+    opened: Instant,
+    generation: u64,
+}
+
+impl std::fmt::Debug for HandleCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandleCache")
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+impl HandleCache {
+    // return a new instance.
+    pub(crate) fn new(size: usize, max_age: Duration) -> HandleCache {
+        let size = NonZeroUsize::new(size).unwrap_or(NonZeroUsize::MIN);
+        HandleCache {
+            state: Mutex::new(State {
+                cache: LruCache::new(size),
+                generation: 0,
+            }),
+            max_age,
+        }
+    }
+
+    // Take the handle for `path` out of the cache, positioned at the
+    // start of the file. If there is none, returns a `CachedHandle`
+    // to pass to `put` once the file is opened and used.
+    pub(crate) fn take(&self, path: &Path) -> (Option<File>, CachedHandle) {
+        let mut state = self.state.lock();
+        let mut handle = CachedHandle {
+            path: path.to_path_buf(),
+            opened: Instant::now(),
+            generation: state.generation,
+        };
+        let entry = match state.cache.pop(path) {
+            Some(entry) if entry.opened.elapsed() < self.max_age => entry,
+            _ => return (None, handle),
+        };
+        drop(state);
+        let mut file = entry.file;
+        if file.seek(SeekFrom::Start(0)).is_err() {
+            return (None, handle);
+        }
+        trace!(target: "webdav_cache", "HandleCache: reusing {:?}", path);
+        handle.opened = entry.opened;
+        (Some(file), handle)
+    }
+
+    // Put a handle back, unless it is stale.
+    pub(crate) fn put(&self, handle: CachedHandle, file: File) {
+        let mut state = self.state.lock();
+        if handle.generation != state.generation || handle.opened.elapsed() >= self.max_age {
+            return;
+        }
+        let entry = Entry {
+            file,
+            opened: handle.opened,
+        };
+        state.cache.put(handle.path, entry);
+    }
+
+    // Forget about `path` and everything below it.
+    pub(crate) fn invalidate(&self, path: &Path) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+        let stale: Vec<PathBuf> = state
+            .cache
+            .iter()
+            .filter(|(p, _)| p.starts_with(path))
+            .map(|(p, _)| p.clone())
+            .collect();
+        for p in stale {
+            trace!(target: "webdav_cache", "HandleCache: invalidating {:?}", p);
+            state.cache.pop(&p);
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.state.lock().cache.len()
+    }
+}
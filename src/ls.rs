@@ -33,6 +33,15 @@ pub struct DavLock {
     pub deep: bool,
 }
 
+/// Maximum depth of a lock, see `DavConfig::max_lock_depth`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockDepth {
+    /// Only `Depth: 0` locks are allowed on collections.
+    Zero,
+    /// `Depth: infinity` locks are allowed as well (the default).
+    Infinity,
+}
+
 /// The trait that defines a locksystem.
 pub trait DavLockSystem: Debug + Sync + Send + BoxCloneLs {
     /// Lock a node. Returns `Ok(new_lock)` if succeeded,
@@ -75,6 +84,13 @@ pub trait DavLockSystem: Debug + Sync + Send + BoxCloneLs {
 
     /// Delete all locks at this path and below (after MOVE or DELETE)
     fn delete(&self, path: &DavPath) -> Result<(), ()>;
+
+    /// List all active locks, for example to find stuck locks.
+    ///
+    /// The default implementation returns an empty list.
+    fn list(&self) -> Vec<DavLock> {
+        Vec::new()
+    }
 }
 
 #[doc(hidden)]
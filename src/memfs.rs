@@ -6,7 +6,9 @@
 //! This means you have to create the instance once, using `MemFs::new`, store
 //! it in your handler struct, and clone() it every time you pass
 //! it to the DavHandler. As a MemFs struct is just a handle, cloning is cheap.
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::io::{Error, ErrorKind, SeekFrom};
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
@@ -48,7 +50,7 @@ struct MemFsFileNode {
     props: HashMap<String, DavProp>,
     mtime: SystemTime,
     crtime: SystemTime,
-    data: Vec<u8>,
+    data: Bytes,
 }
 
 #[derive(Debug, Clone)]
@@ -60,12 +62,23 @@ struct MemFsDirEntry {
     size: u64,
 }
 
+// Readers get a snapshot of the data when the file is opened. Writers
+// work on a copy, that replaces the data in the tree when it is flushed.
+// So concurrent readers never see a partly written file, and of two
+// concurrent writers the last one to flush wins. Except when appending:
+// then only the appended bytes are added to the data in the tree, so
+// nothing written by another appender is lost.
 #[derive(Debug)]
 struct MemFsFile {
     tree: Arc<Mutex<Tree>>,
     node_id: u64,
     pos: usize,
     append: bool,
+    // when appending, where the appended bytes start in `staged`.
+    appended_from: Option<usize>,
+    data: Bytes,
+    staged: Option<Vec<u8>>,
+    dirty: bool,
 }
 
 impl MemFs {
@@ -99,19 +112,29 @@ impl MemFs {
             }
             Err(e) => return Err(e),
         };
-        let node = tree.get_node_mut(node_id).unwrap();
+        let node = tree.get_node(node_id).unwrap();
         if node.is_dir() {
             return Err(FsError::Forbidden);
         }
-        if options.truncate {
-            node.as_file_mut()?.data.truncate(0);
-            node.update_mtime(SystemTime::now());
-        }
+        let data = node.as_file()?.data.clone();
+        let staged = match (options.write || options.append, options.truncate) {
+            (true, true) => Some(Vec::new()),
+            (true, false) => Some(data.to_vec()),
+            (false, _) => None,
+        };
+        let appended_from = match options.append && !options.truncate {
+            true => Some(data.len()),
+            false => None,
+        };
         Ok(Box::new(MemFsFile {
             tree: self.tree.clone(),
             node_id,
-            pos: 0,
+            pos: if options.append { data.len() } else { 0 },
             append: options.append,
+            appended_from,
+            data,
+            staged,
+            dirty: options.truncate,
         }))
     }
 }
@@ -139,21 +162,21 @@ impl DavFileSystem for MemFs {
         &'a self,
         path: &'a DavPath,
         _meta: ReadDirMeta,
-    ) -> FsFuture<FsStream<Box<dyn DavDirEntry>>> {
+    ) -> FsFuture<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
         async move {
             let tree = &*self.tree.lock().unwrap();
             let node_id = tree.lookup(path.as_bytes())?;
             if !tree.get_node(node_id)?.is_dir() {
                 return Err(FsError::Forbidden);
             }
-            let mut v: Vec<Box<dyn DavDirEntry>> = Vec::new();
+            let mut v: Vec<FsResult<Box<dyn DavDirEntry>>> = Vec::new();
             for (name, dnode_id) in tree.get_children(node_id)? {
                 if let Ok(node) = tree.get_node(dnode_id) {
-                    v.push(Box::new(node.as_dirent(&name)));
+                    v.push(Ok(Box::new(node.as_dirent(&name))));
                 }
             }
             let strm = futures_util::stream::iter(v);
-            Ok(Box::pin(strm) as FsStream<Box<dyn DavDirEntry>>)
+            Ok(Box::pin(strm) as FsStream<FsResult<Box<dyn DavDirEntry>>>)
         }
         .boxed()
     }
@@ -249,6 +272,16 @@ impl DavFileSystem for MemFs {
         .boxed()
     }
 
+    fn set_modified<'a>(&'a self, path: &'a DavPath, tm: SystemTime) -> FsFuture<'a, ()> {
+        async move {
+            let tree = &mut *self.tree.lock().unwrap();
+            let node_id = tree.lookup(path.as_bytes())?;
+            tree.get_node_mut(node_id)?.update_mtime(tm);
+            Ok(())
+        }
+        .boxed()
+    }
+
     fn have_props<'a>(&'a self, _path: &'a DavPath) -> BoxFuture<'a, bool> {
         future::ready(true).boxed()
     }
@@ -313,6 +346,32 @@ impl DavFileSystem for MemFs {
         }
         .boxed()
     }
+
+    // a hash of the names and etags of everything below the collection.
+    fn collection_etag<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Option<String>> {
+        async move {
+            let tree = &*self.tree.lock().unwrap();
+            let node_id = tree.lookup(path.as_bytes())?;
+            let mut hasher = DefaultHasher::new();
+            hash_subtree(tree, node_id, &mut hasher)?;
+            Ok(Some(format!("{:016x}", hasher.finish())))
+        }
+        .boxed()
+    }
+}
+
+fn hash_subtree(tree: &Tree, node_id: u64, hasher: &mut DefaultHasher) -> FsResult<()> {
+    let mut children: Vec<_> = tree.get_children(node_id)?.collect();
+    children.sort();
+    for (name, child_id) in children {
+        let node = tree.get_node(child_id)?;
+        name.hash(hasher);
+        node.as_dirent(&name).etag().hash(hasher);
+        if node.is_dir() {
+            hash_subtree(tree, child_id, hasher)?;
+        }
+    }
+    Ok(())
 }
 
 // small helper.
@@ -341,12 +400,38 @@ impl DavDirEntry for MemFsDirEntry {
     }
 }
 
+impl MemFsFile {
+    fn len(&self) -> usize {
+        match self.staged {
+            Some(ref staged) => staged.len(),
+            None => self.data.len(),
+        }
+    }
+
+    // Make room for `len` bytes at the current position.
+    fn write_at(&mut self, len: usize) -> FsResult<&mut [u8]> {
+        let staged = self.staged.as_mut().ok_or(FsError::Forbidden)?;
+        if self.append {
+            self.pos = staged.len();
+        }
+        let start = self.pos;
+        let end = start + len;
+        if end > staged.len() {
+            staged.resize(end, 0);
+        }
+        self.pos = end;
+        self.dirty = true;
+        Ok(&mut staged[start..end])
+    }
+}
+
 impl DavFile for MemFsFile {
     fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
         async move {
             let tree = &*self.tree.lock().unwrap();
             let node = tree.get_node(self.node_id)?;
-            let meta = node.as_dirent(b"");
+            let mut meta = node.as_dirent(b"");
+            meta.size = self.len() as u64;
             Ok(Box::new(meta) as Box<dyn DavMetaData>)
         }
         .boxed()
@@ -354,39 +439,22 @@ impl DavFile for MemFsFile {
 
     fn read_bytes(&mut self, count: usize) -> FsFuture<Bytes> {
         async move {
-            let tree = &*self.tree.lock().unwrap();
-            let node = tree.get_node(self.node_id)?;
-            let file = node.as_file()?;
-            let curlen = file.data.len();
-            let mut start = self.pos;
-            let mut end = self.pos + count;
-            if start > curlen {
-                start = curlen
-            }
-            if end > curlen {
-                end = curlen
-            }
-            let cnt = end - start;
-            self.pos += cnt;
-            Ok(Bytes::copy_from_slice(&file.data[start..end]))
+            let curlen = self.len();
+            let start = std::cmp::min(self.pos, curlen);
+            let end = std::cmp::min(self.pos.saturating_add(count), curlen);
+            self.pos += end - start;
+            let bytes = match self.staged {
+                Some(ref staged) => Bytes::copy_from_slice(&staged[start..end]),
+                None => self.data.slice(start..end),
+            };
+            Ok(bytes)
         }
         .boxed()
     }
 
     fn write_bytes(&mut self, buf: Bytes) -> FsFuture<()> {
         async move {
-            let tree = &mut *self.tree.lock().unwrap();
-            let node = tree.get_node_mut(self.node_id)?;
-            let file = node.as_file_mut()?;
-            if self.append {
-                self.pos = file.data.len();
-            }
-            let end = self.pos + buf.len();
-            if end > file.data.len() {
-                file.data.resize(end, 0);
-            }
-            file.data[self.pos..end].copy_from_slice(&buf);
-            self.pos = end;
+            self.write_at(buf.len())?.copy_from_slice(&buf);
             Ok(())
         }
         .boxed()
@@ -394,22 +462,12 @@ impl DavFile for MemFsFile {
 
     fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<()> {
         async move {
-            let tree = &mut *self.tree.lock().unwrap();
-            let node = tree.get_node_mut(self.node_id)?;
-            let file = node.as_file_mut()?;
-            if self.append {
-                self.pos = file.data.len();
-            }
-            let end = self.pos + buf.remaining();
-            if end > file.data.len() {
-                file.data.resize(end, 0);
-            }
+            let mut dest = self.write_at(buf.remaining())?;
             while buf.has_remaining() {
-                let b = buf.chunk();
-                let len = b.len();
-                file.data[self.pos..self.pos + len].copy_from_slice(b);
+                let len = buf.chunk().len();
+                dest[..len].copy_from_slice(buf.chunk());
+                dest = &mut dest[len..];
                 buf.advance(len);
-                self.pos += len;
             }
             Ok(())
         }
@@ -417,7 +475,35 @@ impl DavFile for MemFsFile {
     }
 
     fn flush(&mut self) -> FsFuture<()> {
-        future::ok(()).boxed()
+        async move {
+            if !self.dirty {
+                return Ok(());
+            }
+            let staged = self.staged.as_deref().unwrap_or_default();
+            let tree = &mut *self.tree.lock().unwrap();
+            let node = tree.get_node_mut(self.node_id)?;
+            let file = node.as_file_mut()?;
+            let data = match self.appended_from {
+                Some(from) => {
+                    // add to what is there now, not to what was there at open.
+                    let mut data = file.data.to_vec();
+                    data.extend_from_slice(&staged[from..]);
+                    Bytes::from(data)
+                }
+                None => Bytes::copy_from_slice(staged),
+            };
+            file.data = data.clone();
+            node.update_mtime(SystemTime::now());
+            if self.appended_from.is_some() {
+                self.appended_from = Some(data.len());
+                self.staged = Some(data.to_vec());
+                self.pos = data.len();
+            }
+            self.data = data;
+            self.dirty = false;
+            Ok(())
+        }
+        .boxed()
     }
 
     fn seek(&mut self, pos: SeekFrom) -> FsFuture<u64> {
@@ -428,12 +514,7 @@ impl DavFile for MemFsFile {
                     return Ok(npos);
                 }
                 SeekFrom::Current(npos) => (self.pos as u64, npos),
-                SeekFrom::End(npos) => {
-                    let tree = &*self.tree.lock().unwrap();
-                    let node = tree.get_node(self.node_id)?;
-                    let curlen = node.as_file()?.data.len() as u64;
-                    (curlen, npos)
-                }
+                SeekFrom::End(npos) => (self.len() as u64, npos),
             };
             if offset < 0 {
                 if -offset as u64 > start {
@@ -481,7 +562,7 @@ impl MemFsNode {
             crtime: SystemTime::now(),
             mtime: SystemTime::now(),
             props: HashMap::new(),
-            data: Vec::new(),
+            data: Bytes::new(),
         })
     }
 
@@ -598,3 +679,112 @@ fn file_name(path: &[u8]) -> Vec<u8> {
         .unwrap_or(b"")
         .to_vec()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::tests::{mkcol, put, req, run};
+    use crate::DavHandler;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writes() {
+        const SIZE: usize = 100_000;
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .read_buf_size(1000)
+            .build_handler();
+        put(&dav, "/file", &"a".repeat(SIZE)).await;
+
+        let mut tasks = Vec::new();
+        for n in 0..8u8 {
+            let dav = dav.clone();
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..10 {
+                    if n % 2 == 0 {
+                        // write the file in many small pieces.
+                        let c = b'a' + n;
+                        let chunks = (0..SIZE / 1000)
+                            .map(move |_| Ok::<_, std::io::Error>(Bytes::from(vec![c; 1000])));
+                        let body = Body::from_stream(futures_util::stream::iter(chunks));
+                        let r = req("PUT", "/file").body(body).unwrap();
+                        assert_eq!(run(&dav, r).await.0.status(), 204);
+                    } else {
+                        let r = req("GET", "/file").body(Body::empty()).unwrap();
+                        let (resp, body) = run(&dav, r).await;
+                        assert_eq!(resp.status(), 200);
+                        assert_eq!(body.len(), SIZE);
+                        let c = body.as_bytes()[0];
+                        assert!(body.bytes().all(|b| b == c), "torn read");
+                    }
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn append() {
+        let fs = MemFs::new();
+        let path = DavPath::new("/log.txt").unwrap();
+        let mut oo = OpenOptions::new();
+        oo.append = true;
+        oo.create = true;
+
+        let mut file = fs.open(&path, oo.clone()).await.unwrap();
+        file.write_bytes(Bytes::from("one\n")).await.unwrap();
+        file.flush().await.unwrap();
+        let mut file = fs.open(&path, oo.clone()).await.unwrap();
+        assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), 4);
+        file.write_bytes(Bytes::from("two\n")).await.unwrap();
+        file.flush().await.unwrap();
+
+        // two appenders at the same time both get their data in.
+        let mut a = fs.open(&path, oo.clone()).await.unwrap();
+        let mut b = fs.open(&path, oo).await.unwrap();
+        a.write_bytes(Bytes::from("three\n")).await.unwrap();
+        b.write_bytes(Bytes::from("four\n")).await.unwrap();
+        a.flush().await.unwrap();
+        b.flush().await.unwrap();
+        a.write_bytes(Bytes::from("five\n")).await.unwrap();
+        a.flush().await.unwrap();
+
+        let mut file = fs.open(&path, OpenOptions::read()).await.unwrap();
+        let data = file.read_bytes(100).await.unwrap();
+        assert_eq!(data, "one\ntwo\nthree\nfour\nfive\n");
+    }
+
+    #[tokio::test]
+    async fn collection_etag() {
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .build_handler();
+        async fn getetag(dav: &DavHandler, path: &str) -> String {
+            let body = r#"<D:propfind xmlns:D="DAV:"><D:prop><D:getetag/></D:prop></D:propfind>"#;
+            let r = req("PROPFIND", path).header("Depth", "0");
+            let (resp, text) = run(dav, r.body(Body::from(body)).unwrap()).await;
+            assert_eq!(resp.status(), 207);
+            let start = text.find("<D:getetag>").expect(&text) + "<D:getetag>".len();
+            let end = text[start..].find('<').unwrap();
+            text[start..start + end].to_string()
+        }
+
+        mkcol(&dav, "/dir/").await;
+        mkcol(&dav, "/dir/sub/").await;
+        let empty = getetag(&dav, "/dir/").await;
+        assert_eq!(getetag(&dav, "/dir/").await, empty);
+
+        put(&dav, "/dir/a.txt", "a").await;
+        let one = getetag(&dav, "/dir/").await;
+        assert_ne!(one, empty);
+
+        // a change deeper down changes it too.
+        put(&dav, "/dir/sub/b.txt", "b").await;
+        let two = getetag(&dav, "/dir/").await;
+        assert_ne!(two, one);
+        put(&dav, "/dir/sub/b.txt", "bb").await;
+        assert_ne!(getetag(&dav, "/dir/").await, two);
+    }
+}
@@ -29,6 +29,21 @@ struct MemLsInner {
     tree: Tree,
     #[allow(dead_code)]
     locks: HashMap<Vec<u8>, u64>,
+    // added to the current time, so that tests can move the clock.
+    clock_offset: Duration,
+}
+
+impl MemLsInner {
+    fn now(&self) -> SystemTime {
+        SystemTime::now() + self.clock_offset
+    }
+
+    // Locks that have timed out are removed when they are looked at,
+    // so no background task is needed to clean them up.
+    fn expire(&mut self) {
+        let now = self.now();
+        expire_locks_from_node(&mut self.tree, tree::ROOT_ID, now);
+    }
 }
 
 impl MemLs {
@@ -37,9 +52,15 @@ impl MemLs {
         let inner = MemLsInner {
             tree: Tree::new(Vec::new()),
             locks: HashMap::new(),
+            clock_offset: Duration::new(0, 0),
         };
         Box::new(MemLs(Arc::new(Mutex::new(inner))))
     }
+
+    #[cfg(test)]
+    fn advance_clock(&self, d: Duration) {
+        self.0.lock().unwrap().clock_offset += d;
+    }
 }
 
 impl DavLockSystem for MemLs {
@@ -53,6 +74,7 @@ impl DavLockSystem for MemLs {
         deep: bool,
     ) -> Result<DavLock, DavLock> {
         let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
 
         // any locks in the path?
         let rc = check_locks_to_path(&inner.tree, path, None, true, &Vec::new(), shared);
@@ -67,8 +89,8 @@ impl DavLockSystem for MemLs {
         }
 
         // create lock.
+        let timeout_at = timeout.map(|d| inner.now() + d);
         let node = get_or_create_path_node(&mut inner.tree, path);
-        let timeout_at = timeout.map(|d| SystemTime::now() + d);
         let lock = DavLock {
             token: Uuid::new_v4().urn().to_string(),
             path: path.clone(),
@@ -87,6 +109,7 @@ impl DavLockSystem for MemLs {
 
     fn unlock(&self, path: &DavPath, token: &str) -> Result<(), ()> {
         let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
         let node_id = match lookup_lock(&inner.tree, path, token) {
             None => {
                 trace!("unlock: {} not found at {}", token, path);
@@ -114,6 +137,8 @@ impl DavLockSystem for MemLs {
     ) -> Result<DavLock, ()> {
         trace!("refresh lock {}", token);
         let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
+        let timeout_at = timeout.map(|d| inner.now() + d);
         let node_id = match lookup_lock(&inner.tree, path, token) {
             None => {
                 trace!("lock not found");
@@ -124,7 +149,6 @@ impl DavLockSystem for MemLs {
         let node = inner.tree.get_node_mut(node_id).unwrap();
         let idx = node.iter().position(|n| n.token.as_str() == token).unwrap();
         let lock = &mut node[idx];
-        let timeout_at = timeout.map(|d| SystemTime::now() + d);
         lock.timeout = timeout;
         lock.timeout_at = timeout_at;
         Ok(lock.clone())
@@ -138,7 +162,8 @@ impl DavLockSystem for MemLs {
         deep: bool,
         submitted_tokens: Vec<&str>,
     ) -> Result<(), DavLock> {
-        let inner = &*self.0.lock().unwrap();
+        let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
         let _st = submitted_tokens.clone();
         let rc = check_locks_to_path(
             &inner.tree,
@@ -168,7 +193,8 @@ impl DavLockSystem for MemLs {
     }
 
     fn discover(&self, path: &DavPath) -> Vec<DavLock> {
-        let inner = &*self.0.lock().unwrap();
+        let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
         list_locks(&inner.tree, path)
     }
 
@@ -179,6 +205,15 @@ impl DavLockSystem for MemLs {
         }
         Ok(())
     }
+
+    fn list(&self) -> Vec<DavLock> {
+        let inner = &mut *self.0.lock().unwrap();
+        inner.expire();
+        let mut locks = Vec::new();
+        list_locks_from_node(&inner.tree, tree::ROOT_ID, &mut locks);
+        locks.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        locks
+    }
 }
 
 // check if there are any locks along the path.
@@ -372,6 +407,44 @@ fn list_locks(tree: &Tree, path: &DavPath) -> Vec<DavLock> {
     locks
 }
 
+// Remove timed out locks in a subtree, and the nodes that are left empty.
+fn expire_locks_from_node(tree: &mut Tree, node_id: u64, now: SystemTime) {
+    if let Ok(children) = tree.get_children(node_id) {
+        for (_, child_id) in children {
+            expire_locks_from_node(tree, child_id, now);
+        }
+    }
+    let empty = match tree.get_node_mut(node_id) {
+        Ok(locks) => {
+            locks.retain(|l| {
+                let expired = matches!(l.timeout_at, Some(t) if t <= now);
+                if expired {
+                    trace!("lock {} expired", l.token);
+                }
+                !expired
+            });
+            locks.is_empty()
+        }
+        Err(_) => false,
+    };
+    if empty && node_id != tree::ROOT_ID {
+        // fails if the node still has children, that is fine.
+        tree.delete_node(node_id).ok();
+    }
+}
+
+// Find all locks in a subtree.
+fn list_locks_from_node(tree: &Tree, node_id: u64, locks: &mut Vec<DavLock>) {
+    if let Ok(node) = tree.get_node(node_id) {
+        locks.extend_from_slice(node);
+    }
+    if let Ok(children) = tree.get_children(node_id) {
+        for (_, node_id) in children {
+            list_locks_from_node(tree, node_id, locks);
+        }
+    }
+}
+
 fn path_to_segs(path: &DavPath, include_root: bool) -> Vec<&[u8]> {
     let path = path.as_bytes();
     let mut segs: Vec<&[u8]> = path
@@ -390,3 +463,46 @@ fn get_child(tree: &Tree, node_id: u64, seg: &[u8]) -> FsResult<u64> {
     }
     tree.get_child(node_id, seg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expire() {
+        let ls = MemLs::new();
+        let path = DavPath::new("/dir/file.txt").unwrap();
+        let timeout = Some(Duration::from_secs(60));
+        let lock = ls.lock(&path, None, None, timeout, false, false).unwrap();
+        assert!(ls.check(&path, None, false, false, Vec::new()).is_err());
+        assert!(ls.lock(&path, None, None, timeout, false, false).is_err());
+
+        ls.advance_clock(Duration::from_secs(30));
+        assert!(ls.refresh(&path, &lock.token, timeout).is_ok());
+        ls.advance_clock(Duration::from_secs(59));
+        assert!(ls.check(&path, None, false, false, Vec::new()).is_err());
+
+        // the lock timed out: it is gone, and the resource can be locked again.
+        ls.advance_clock(Duration::from_secs(2));
+        assert!(ls.check(&path, None, false, false, Vec::new()).is_ok());
+        assert!(ls.discover(&path).is_empty());
+        assert!(ls.unlock(&path, &lock.token).is_err());
+        assert!(ls
+            .0
+            .lock()
+            .unwrap()
+            .tree
+            .get_children(tree::ROOT_ID)
+            .unwrap()
+            .next()
+            .is_none());
+        let lock2 = ls.lock(&path, None, None, timeout, false, false).unwrap();
+        assert_ne!(lock.token, lock2.token);
+
+        // locks without a timeout do not expire.
+        let path = DavPath::new("/other").unwrap();
+        ls.lock(&path, None, None, None, false, false).unwrap();
+        ls.advance_clock(Duration::from_secs(86400 * 365));
+        assert_eq!(ls.list().len(), 1);
+    }
+}
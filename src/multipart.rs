@@ -0,0 +1,263 @@
+// Incremental multipart/form-data parser, used by PUT.
+//
+// Only the content of the first part that has a filename is extracted.
+// The body is fed to the parser in chunks as it comes in, and only the
+// bytes that might be the start of a boundary are held back.
+use bytes::{Bytes, BytesMut};
+
+// Part headers larger than this are refused.
+const MAX_HEADER_SIZE: usize = 16384;
+
+#[derive(Debug, PartialEq)]
+enum State {
+    // Before the first boundary.
+    Preamble,
+    // Right after a boundary, "--" or CRLF follows.
+    Boundary,
+    // Headers of a part.
+    Headers,
+    // Content of a part that we skip.
+    Skip,
+    // Content of the file part.
+    File,
+    // Done, ignore the rest.
+    Done,
+}
+
+#[derive(Debug)]
+pub(crate) struct Multipart {
+    // "\r\n--boundary"
+    delim: Vec<u8>,
+    buf: BytesMut,
+    state: State,
+    found: bool,
+    content_type: Option<String>,
+}
+
+impl Multipart {
+    // Returns None if the content-type is not multipart/form-data
+    // with a valid boundary.
+    pub(crate) fn from_content_type(ct: &str) -> Option<Multipart> {
+        let mut params = ct.split(';');
+        let mtype = params.next()?.trim();
+        if !mtype.eq_ignore_ascii_case("multipart/form-data") {
+            return None;
+        }
+        let boundary = params.find_map(|p| {
+            let (name, value) = p.split_once('=')?;
+            if !name.trim().eq_ignore_ascii_case("boundary") {
+                return None;
+            }
+            Some(value.trim().trim_matches('"').to_string())
+        })?;
+        if boundary.is_empty() || boundary.len() > 70 {
+            return None;
+        }
+        Some(Multipart {
+            delim: format!("\r\n--{}", boundary).into_bytes(),
+            buf: BytesMut::new(),
+            state: State::Preamble,
+            found: false,
+            content_type: None,
+        })
+    }
+
+    // Content-Type of the file part, if it had one.
+    pub(crate) fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    // Feed the next chunk of the body. Returns the data of the
+    // file part that is complete so far.
+    pub(crate) fn feed(&mut self, data: &[u8]) -> Result<Vec<Bytes>, ()> {
+        let mut out = Vec::new();
+        if self.state == State::Done {
+            return Ok(out);
+        }
+        self.buf.extend_from_slice(data);
+
+        loop {
+            match self.state {
+                State::Preamble => {
+                    // the first boundary does not need a preceding CRLF.
+                    let delim = &self.delim[2..];
+                    if self.buf.starts_with(delim) {
+                        let _ = self.buf.split_to(delim.len());
+                        self.state = State::Boundary;
+                        continue;
+                    }
+                    match find(&self.buf, &self.delim) {
+                        Some(pos) => {
+                            let _ = self.buf.split_to(pos + self.delim.len());
+                            self.state = State::Boundary;
+                        }
+                        None => {
+                            self.hold_back();
+                            break;
+                        }
+                    }
+                }
+                State::Boundary => {
+                    if self.buf.len() < 2 {
+                        break;
+                    }
+                    if self.buf.starts_with(b"--") {
+                        // closing boundary.
+                        self.state = State::Done;
+                        break;
+                    }
+                    // skip transport padding up to the CRLF.
+                    match find(&self.buf, b"\r\n") {
+                        Some(pos) => {
+                            let _ = self.buf.split_to(pos + 2);
+                            self.state = State::Headers;
+                        }
+                        None if self.buf.len() > MAX_HEADER_SIZE => return Err(()),
+                        None => break,
+                    }
+                }
+                State::Headers => {
+                    // a part without headers starts with an empty line.
+                    let end = match find(&self.buf, b"\r\n\r\n") {
+                        _ if self.buf.starts_with(b"\r\n") => 0,
+                        Some(pos) => pos,
+                        None if self.buf.len() > MAX_HEADER_SIZE => return Err(()),
+                        None => break,
+                    };
+                    let headers = self.buf.split_to(end);
+                    let _ = self.buf.split_to(if end == 0 { 2 } else { 4 });
+                    let headers = String::from_utf8_lossy(&headers);
+                    if is_file_part(&headers) {
+                        self.content_type = header_value(&headers, "content-type");
+                        self.state = State::File;
+                    } else {
+                        self.state = State::Skip;
+                    }
+                }
+                State::Skip | State::File => {
+                    let file = self.state == State::File;
+                    match find(&self.buf, &self.delim) {
+                        Some(pos) => {
+                            let data = self.buf.split_to(pos);
+                            let _ = self.buf.split_to(self.delim.len());
+                            if file {
+                                if !data.is_empty() {
+                                    out.push(data.freeze());
+                                }
+                                self.found = true;
+                                self.state = State::Done;
+                                break;
+                            }
+                            self.state = State::Boundary;
+                        }
+                        None => {
+                            // keep what might be the start of the delimiter.
+                            let keep = self.delim.len() - 1;
+                            if self.buf.len() > keep {
+                                let data = self.buf.split_to(self.buf.len() - keep);
+                                if file {
+                                    out.push(data.freeze());
+                                }
+                            }
+                            break;
+                        }
+                    }
+                }
+                State::Done => break,
+            }
+        }
+        if self.state == State::Done {
+            self.buf.clear();
+        }
+        Ok(out)
+    }
+
+    // True once the headers of the file part have been read.
+    pub(crate) fn in_file(&self) -> bool {
+        self.state == State::File || self.found
+    }
+
+    // Called at the end of the body. Fails if no complete file part was found.
+    pub(crate) fn finish(&self) -> Result<(), ()> {
+        if self.found {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    fn hold_back(&mut self) {
+        let keep = self.delim.len() - 1;
+        if self.buf.len() > keep {
+            let _ = self.buf.split_to(self.buf.len() - keep);
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn header_value(headers: &str, name: &str) -> Option<String> {
+    headers.split("\r\n").find_map(|line| {
+        let (n, v) = line.split_once(':')?;
+        if n.trim().eq_ignore_ascii_case(name) {
+            Some(v.trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+// A part is a file if its Content-Disposition has a filename parameter.
+fn is_file_part(headers: &str) -> bool {
+    match header_value(headers, "content-disposition") {
+        Some(cd) => cd.split(';').skip(1).any(|p| {
+            let name = p.split('=').next().unwrap_or("").trim();
+            name.eq_ignore_ascii_case("filename") || name.eq_ignore_ascii_case("filename*")
+        }),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"field\"\r\n\r\n\
+        value\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        hello\r\n--world\r\n--XyZ--\r\n";
+
+    #[test]
+    fn test_multipart() {
+        // feed the body in chunks of every size.
+        for size in 1..BODY.len() {
+            let mut mp =
+                Multipart::from_content_type("multipart/form-data; boundary=\"XyZ\"").unwrap();
+            let mut content = Vec::new();
+            for chunk in BODY.as_bytes().chunks(size) {
+                let parts = mp.feed(chunk).unwrap();
+                assert!(parts.is_empty() || mp.in_file());
+                for b in parts {
+                    content.extend_from_slice(&b);
+                }
+            }
+            assert!(mp.finish().is_ok());
+            assert_eq!(content, b"hello\r\n--world", "chunk size {}", size);
+            assert_eq!(mp.content_type(), Some("text/plain"));
+        }
+
+        let mut mp = Multipart::from_content_type("multipart/form-data; boundary=XyZ").unwrap();
+        mp.feed(&BODY.as_bytes()[..100]).unwrap();
+        assert!(!mp.in_file());
+        assert!(mp.finish().is_err());
+        mp.feed(&BODY.as_bytes()[100..172]).unwrap();
+        assert!(mp.in_file());
+        assert!(mp.finish().is_err());
+        assert!(Multipart::from_content_type("text/plain").is_none());
+        assert!(Multipart::from_content_type("multipart/form-data").is_none());
+    }
+}
@@ -0,0 +1,436 @@
+//! Filesystem wrapper that enforces a quota.
+//!
+//! `QuotaFs` counts the bytes that are stored through it, and refuses
+//! writes that would go over the limit with `FsError::InsufficientStorage`
+//! (`507 Insufficient Storage`). Space is given back when a file is
+//! truncated, overwritten or removed. `get_quota` reports the usage and
+//! the limit, so clients can show them.
+//!
+//! Usage is counted from zero when the `QuotaFs` is created. If the
+//! filesystem already contains files, pass their total size to
+//! [`QuotaFs::with_used`].
+//!
+//! ```
+//! use dav_server::{memfs::MemFs, quotafs::QuotaFs, DavHandler};
+//!
+//! let fs = QuotaFs::new(MemFs::new(), 100 * 1024 * 1024);
+//! let dav = DavHandler::builder().filesystem(fs).build_handler();
+//! ```
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use bytes::{Buf, Bytes};
+use futures_util::FutureExt;
+
+use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+#[derive(Debug)]
+struct Usage {
+    used: Mutex<u64>,
+    max: u64,
+}
+
+impl Usage {
+    // Claim `n` more bytes.
+    fn reserve(&self, n: u64) -> FsResult<()> {
+        let mut used = self.used.lock().unwrap();
+        if *used + n > self.max {
+            return Err(FsError::InsufficientStorage);
+        }
+        *used += n;
+        Ok(())
+    }
+
+    fn fits(&self, n: u64) -> bool {
+        *self.used.lock().unwrap() + n <= self.max
+    }
+
+    fn release(&self, n: u64) {
+        let mut used = self.used.lock().unwrap();
+        *used = used.saturating_sub(n);
+    }
+}
+
+/// Filesystem wrapper with a quota.
+///
+/// Clones share the same usage counter.
+#[derive(Clone)]
+pub struct QuotaFs {
+    inner: Box<dyn DavFileSystem>,
+    usage: Arc<Usage>,
+}
+
+impl QuotaFs {
+    /// Wrap a filesystem, and limit it to `max_bytes`.
+    pub fn new(inner: Box<dyn DavFileSystem>, max_bytes: u64) -> Box<QuotaFs> {
+        QuotaFs::with_used(inner, max_bytes, 0)
+    }
+
+    /// Like `new`, for a filesystem that already uses `used` bytes.
+    pub fn with_used(inner: Box<dyn DavFileSystem>, max_bytes: u64, used: u64) -> Box<QuotaFs> {
+        Box::new(QuotaFs {
+            inner,
+            usage: Arc::new(Usage {
+                used: Mutex::new(used),
+                max: max_bytes,
+            }),
+        })
+    }
+
+    /// The number of bytes in use.
+    pub fn used(&self) -> u64 {
+        *self.usage.used.lock().unwrap()
+    }
+
+    // size of a file, 0 if it is not a file or does not exist.
+    async fn file_len(&self, path: &DavPath) -> u64 {
+        match self.inner.metadata(path).await {
+            Ok(meta) if meta.is_file() => meta.len(),
+            _ => 0,
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncDavFileSystem for QuotaFs {
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        if !options.write {
+            return self.inner.open(path, options).await;
+        }
+        let meta = self.inner.metadata(path).await;
+        let mut size = match meta {
+            Ok(ref meta) if meta.is_file() => meta.len(),
+            _ => 0,
+        };
+
+        // refuse early if we know the upload is not going to fit.
+        if let Some(n) = options.size {
+            let freed = if options.truncate { size } else { 0 };
+            if !self.usage.fits(n.saturating_sub(freed)) {
+                return Err(FsError::InsufficientStorage);
+            }
+        }
+
+        let truncate = options.truncate;
+        let append = options.append;
+        let file = self.inner.open(path, options).await?;
+        if truncate {
+            self.usage.release(size);
+            size = 0;
+        }
+        Ok(Box::new(QuotaFile {
+            file,
+            pos: if append { size } else { 0 },
+            size,
+            append,
+            usage: self.usage.clone(),
+            created: meta.err().map(|_| (self.inner.clone(), path.clone())),
+        }))
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        self.inner.read_dir(path, meta).await
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        self.inner.metadata(path).await
+    }
+
+    async fn symlink_metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        self.inner.symlink_metadata(path).await
+    }
+
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.inner.create_dir(path).await
+    }
+
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.inner.remove_dir(path).await
+    }
+
+    // the files in the tree would not be accounted for. without this,
+    // DELETE removes them one by one through `remove_file`.
+    async fn remove_dir_all(&self, _path: &DavPath) -> FsResult<()> {
+        Err(FsError::NotImplemented)
+    }
+
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        let len = self.file_len(path).await;
+        self.inner.remove_file(path).await?;
+        self.usage.release(len);
+        Ok(())
+    }
+
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        // an existing destination is overwritten.
+        let len = self.file_len(to).await;
+        self.inner.rename(from, to).await?;
+        self.usage.release(len);
+        Ok(())
+    }
+
+    async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        let len = self.file_len(from).await;
+        let freed = self.file_len(to).await;
+        if len > freed {
+            self.usage.reserve(len - freed)?;
+        }
+        match self.inner.copy(from, to).await {
+            Ok(()) => {
+                if freed > len {
+                    self.usage.release(freed - len);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                if len > freed {
+                    self.usage.release(len - freed);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn set_accessed(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner.set_accessed(path, tm).await
+    }
+
+    async fn set_modified(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner.set_modified(path, tm).await
+    }
+
+    async fn have_props(&self, path: &DavPath) -> bool {
+        self.inner.have_props(path).await
+    }
+
+    async fn patch_props(
+        &self,
+        path: &DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(http::StatusCode, DavProp)>> {
+        self.inner.patch_props(path, patch).await
+    }
+
+    async fn get_props(&self, path: &DavPath, do_content: bool) -> FsResult<Vec<DavProp>> {
+        self.inner.get_props(path, do_content).await
+    }
+
+    async fn get_prop(&self, path: &DavPath, prop: DavProp) -> FsResult<Vec<u8>> {
+        self.inner.get_prop(path, prop).await
+    }
+
+    fn with_context(&self, context: &DavContext) -> Box<dyn DavFileSystem> {
+        Box::new(QuotaFs {
+            inner: self.inner.with_context(context),
+            usage: self.usage.clone(),
+        })
+    }
+
+    async fn get_quota(&self) -> FsResult<(u64, Option<u64>)> {
+        Ok((self.used(), Some(self.usage.max)))
+    }
+
+    fn preferred_read_chunk_size(&self) -> usize {
+        self.inner.preferred_read_chunk_size()
+    }
+
+    async fn get_redirect_url(&self, path: &DavPath) -> FsResult<Option<String>> {
+        self.inner.get_redirect_url(path).await
+    }
+
+    async fn collection_etag(&self, path: &DavPath) -> FsResult<Option<String>> {
+        self.inner.collection_etag(path).await
+    }
+
+    async fn is_special(&self, path: &DavPath) -> FsResult<bool> {
+        self.inner.is_special(path).await
+    }
+}
+
+struct QuotaFile {
+    file: Box<dyn DavFile>,
+    pos: u64,
+    size: u64,
+    append: bool,
+    usage: Arc<Usage>,
+    // set if the file did not exist before it was opened.
+    created: Option<(Box<dyn DavFileSystem>, DavPath)>,
+}
+
+impl std::fmt::Debug for QuotaFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuotaFile")
+            .field("file", &self.file)
+            .field("pos", &self.pos)
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl QuotaFile {
+    // Account for a write of `len` bytes at the current position. Returns
+    // the number of bytes the file grows.
+    fn grow(&mut self, len: u64) -> FsResult<u64> {
+        if self.append {
+            self.pos = self.size;
+        }
+        let end = self.pos + len;
+        let grow = end.saturating_sub(self.size);
+        self.usage.reserve(grow)?;
+        self.pos = end;
+        self.size += grow;
+        Ok(grow)
+    }
+
+    // A write did not fit. If the file was created for it, and nothing
+    // was written yet, remove it again: the upload did not happen.
+    async fn refused(&mut self, err: FsError) -> FsError {
+        if let Some((fs, path)) = self.created.take() {
+            if self.size == 0 {
+                let _ = fs.remove_file(&path).await;
+            }
+        }
+        err
+    }
+
+    fn write_done(&mut self, grow: u64, res: FsResult<()>) -> FsResult<()> {
+        if res.is_err() {
+            // we do not know how much was written. assume nothing.
+            self.usage.release(grow);
+            self.size -= grow;
+        }
+        res
+    }
+}
+
+impl DavFile for QuotaFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.file.metadata()
+    }
+
+    fn write_buf(&mut self, buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        async move {
+            let grow = match self.grow(buf.remaining() as u64) {
+                Ok(grow) => grow,
+                Err(e) => return Err(self.refused(e).await),
+            };
+            let res = self.file.write_buf(buf).await;
+            self.write_done(grow, res)
+        }
+        .boxed()
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        async move {
+            let grow = match self.grow(buf.len() as u64) {
+                Ok(grow) => grow,
+                Err(e) => return Err(self.refused(e).await),
+            };
+            let res = self.file.write_bytes(buf).await;
+            self.write_done(grow, res)
+        }
+        .boxed()
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        async move {
+            let bytes = self.file.read_bytes(count).await?;
+            self.pos += bytes.len() as u64;
+            Ok(bytes)
+        }
+        .boxed()
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        async move {
+            let pos = self.file.seek(pos).await?;
+            self.pos = pos;
+            Ok(pos)
+        }
+        .boxed()
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        self.file.flush()
+    }
+
+    fn redirect_url(&mut self) -> FsFuture<'_, Option<String>> {
+        self.file.redirect_url()
+    }
+
+    fn is_seekable(&self) -> bool {
+        self.file.is_seekable()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{req, run};
+    use crate::DavHandler;
+
+    async fn put(dav: &DavHandler, path: &str, len: usize) -> u16 {
+        let r = req("PUT", path).body(Body::from("x".repeat(len))).unwrap();
+        run(dav, r).await.0.status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn quota() {
+        let fs = QuotaFs::new(MemFs::new(), 1000);
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+
+        assert_eq!(put(&dav, "/a", 600).await, 201);
+        assert_eq!(put(&dav, "/b", 600).await, 507);
+        assert_eq!(fs.used(), 600);
+
+        // overwriting a file first frees its space.
+        assert_eq!(put(&dav, "/a", 900).await, 204);
+        assert_eq!(fs.used(), 900);
+
+        let r = req("DELETE", "/a").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 204);
+        assert_eq!(fs.used(), 0);
+        // the failed PUT did not create the file.
+        let r = req("GET", "/b").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 404);
+        assert_eq!(put(&dav, "/b", 600).await, 201);
+
+        // copies count, too.
+        let r = req("COPY", "/b")
+            .header("Destination", "/c")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 507);
+        assert_eq!(
+            DavFileSystem::get_quota(&*fs).await.unwrap(),
+            (600, Some(1000))
+        );
+
+        let r = req("PROPFIND", "/")
+            .header("Depth", "0")
+            .body(Body::from(
+                r#"<D:propfind xmlns:D="DAV:"><D:prop><D:quota-available-bytes/></D:prop></D:propfind>"#,
+            ))
+            .unwrap();
+        let (_, body) = run(&dav, r).await;
+        assert!(body.contains(">400<"), "{}", body);
+
+        // removing a collection frees the space of the files in it.
+        let r = req("MKCOL", "/dir/").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+        assert_eq!(put(&dav, "/dir/d", 300).await, 201);
+        assert_eq!(fs.used(), 900);
+        let r = req("DELETE", "/dir/").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 204);
+        assert_eq!(fs.used(), 600);
+    }
+}
@@ -0,0 +1,104 @@
+//! Handlers for the REPORT method (RFC3253).
+//!
+//! A REPORT request body has a single top-level element that names the
+//! report, for example `<D:sync-collection xmlns:D="DAV:">`. Handlers are
+//! registered per report name with [`DavConfig::report_handler`], and the
+//! REPORT method is dispatched to the handler that matches the name of the
+//! top-level element. A REPORT for a report that has no handler is refused
+//! with `403 Forbidden` and a `DAV:supported-report` precondition. If no
+//! handlers are registered at all, REPORT is not implemented (501).
+//!
+//! [`ExpandProperty`] implements the standard `{DAV:}expand-property`
+//! report. It is not registered by default, add it like any other report.
+//!
+//! Report names are written in "Clark notation": `{namespace}name`,
+//! for example `{DAV:}expand-property` or `{urn:example}stats`.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use dav_server::{body::Body, fs::FsResult, memfs::MemFs, DavHandler};
+//! use dav_server::report::{ExpandProperty, ReportHandler, ReportRequest};
+//! use futures_util::future::{BoxFuture, FutureExt};
+//!
+//! struct Ping;
+//!
+//! impl ReportHandler for Ping {
+//!     fn report<'a>(&'a self, _req: ReportRequest<'a>) -> BoxFuture<'a, FsResult<http::Response<Body>>> {
+//!         async move { Ok(http::Response::new(Body::from("pong"))) }.boxed()
+//!     }
+//! }
+//!
+//! let dav = DavHandler::builder()
+//!     .filesystem(MemFs::new())
+//!     .report_handler("{urn:example}ping", Arc::new(Ping))
+//!     .report_handler("{DAV:}expand-property", Arc::new(ExpandProperty))
+//!     .build_handler();
+//! ```
+//!
+//! [`DavConfig::report_handler`]: crate::DavConfig::report_handler
+use futures_util::future::{BoxFuture, FutureExt};
+use http::{Request, Response};
+use xmltree::Element;
+
+use crate::body::Body;
+use crate::davpath::DavPath;
+use crate::fs::{DavFileSystem, FsResult};
+use crate::{DavError, DavInner};
+
+/// A REPORT request, as passed to a [`ReportHandler`].
+pub struct ReportRequest<'a> {
+    /// The request, without the body.
+    pub request: &'a Request<()>,
+    /// The path of the resource that the report is about.
+    pub path: &'a DavPath,
+    /// The parsed request body. Its name is the name of the report.
+    pub body: &'a Element,
+    /// The filesystem.
+    pub fs: &'a dyn DavFileSystem,
+    // For the built-in reports.
+    pub(crate) inner: &'a DavInner,
+}
+
+/// Generates the response to a REPORT.
+pub trait ReportHandler: Send + Sync {
+    /// Run the report. Errors are mapped to an HTTP status
+    /// like errors from the filesystem are.
+    fn report<'a>(&'a self, req: ReportRequest<'a>) -> BoxFuture<'a, FsResult<Response<Body>>>;
+}
+
+/// The `{DAV:}expand-property` report (RFC3253 3.8).
+///
+/// Lists the properties named by the `DAV:property` elements of the
+/// request. The `DAV:href` values of a property that has nested
+/// `DAV:property` elements are replaced by a `DAV:response` with those
+/// properties of the resource that the href refers to.
+pub struct ExpandProperty;
+
+impl ReportHandler for ExpandProperty {
+    fn report<'a>(&'a self, req: ReportRequest<'a>) -> BoxFuture<'a, FsResult<Response<Body>>> {
+        async move {
+            match req
+                .inner
+                .expand_property(req.request, req.path, req.body)
+                .await
+            {
+                Ok(res) => Ok(res),
+                Err(DavError::FsError(e)) => Err(e),
+                Err(e) => {
+                    let mut res = Response::new(Body::empty());
+                    *res.status_mut() = e.statuscode();
+                    Ok(res)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+// Name of an element in Clark notation.
+pub(crate) fn clark_name(elem: &Element) -> String {
+    match elem.namespace {
+        Some(ref ns) => format!("{{{}}}{}", ns, elem.name),
+        None => elem.name.clone(),
+    }
+}
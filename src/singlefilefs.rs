@@ -0,0 +1,193 @@
+//! Filesystem that serves a single file.
+//!
+//! [`SingleFileFs`] shares one file from the local disk, without the
+//! directory it is in. The root collection contains just that file,
+//! under the name it is mounted as. The file can be read with GET, HEAD
+//! and PROPFIND; everything else, and every other path, is refused.
+//!
+//! ```no_run
+//! use dav_server::{singlefilefs::SingleFileFs, DavHandler};
+//!
+//! let fs = SingleFileFs::new("/srv/reports/2024-q1.pdf", "report.pdf");
+//! let dav = DavHandler::builder().filesystem(fs).build_handler();
+//! ```
+use std::path::Path;
+use std::time::SystemTime;
+
+use futures_util::future;
+
+use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::localfs::LocalFs;
+
+/// Filesystem that serves a single file.
+#[derive(Clone)]
+pub struct SingleFileFs {
+    file: Box<LocalFs>,
+    name: String,
+}
+
+impl SingleFileFs {
+    /// Serve the file at `file` as `/<mount_as>`.
+    pub fn new<P: AsRef<Path>>(file: P, mount_as: &str) -> Box<SingleFileFs> {
+        Box::new(SingleFileFs {
+            file: LocalFs::new_file(file, false),
+            name: mount_as.trim_matches('/').to_string(),
+        })
+    }
+
+    fn is_root(&self, path: &DavPath) -> bool {
+        path.as_bytes() == b"/"
+    }
+
+    fn is_file(&self, path: &DavPath) -> bool {
+        path.as_bytes().strip_prefix(b"/") == Some(self.name.as_bytes())
+    }
+}
+
+#[async_trait]
+impl AsyncDavFileSystem for SingleFileFs {
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        if options.write || options.append || options.truncate || options.create {
+            return Err(FsError::Forbidden);
+        }
+        if !self.is_file(path) {
+            return Err(FsError::NotFound);
+        }
+        self.file.open(path, options).await
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        _meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        if !self.is_root(path) {
+            return Err(FsError::NotFound);
+        }
+        let entry = SingleDirEntry {
+            name: self.name.as_bytes().to_vec(),
+            meta: self.file.metadata(path).await?,
+        };
+        let entries = vec![Ok(Box::new(entry) as Box<dyn DavDirEntry>)];
+        Ok(Box::pin(futures_util::stream::iter(entries)))
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        if self.is_root(path) {
+            let modified = self.file.metadata(path).await?.modified()?;
+            return Ok(Box::new(RootMeta { modified }));
+        }
+        if !self.is_file(path) {
+            return Err(FsError::NotFound);
+        }
+        self.file.metadata(path).await
+    }
+
+    async fn create_dir(&self, _path: &DavPath) -> FsResult<()> {
+        Err(FsError::Forbidden)
+    }
+
+    async fn remove_dir(&self, _path: &DavPath) -> FsResult<()> {
+        Err(FsError::Forbidden)
+    }
+
+    async fn remove_file(&self, _path: &DavPath) -> FsResult<()> {
+        Err(FsError::Forbidden)
+    }
+
+    async fn rename(&self, _from: &DavPath, _to: &DavPath) -> FsResult<()> {
+        Err(FsError::Forbidden)
+    }
+
+    async fn copy(&self, _from: &DavPath, _to: &DavPath) -> FsResult<()> {
+        Err(FsError::Forbidden)
+    }
+
+    async fn set_accessed(&self, _path: &DavPath, _tm: SystemTime) -> FsResult<()> {
+        Err(FsError::Forbidden)
+    }
+
+    async fn set_modified(&self, _path: &DavPath, _tm: SystemTime) -> FsResult<()> {
+        Err(FsError::Forbidden)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RootMeta {
+    modified: SystemTime,
+}
+
+impl DavMetaData for RootMeta {
+    fn len(&self) -> u64 {
+        0
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Ok(self.modified)
+    }
+
+    fn is_dir(&self) -> bool {
+        true
+    }
+}
+
+struct SingleDirEntry {
+    name: Vec<u8>,
+    meta: Box<dyn DavMetaData>,
+}
+
+impl DavDirEntry for SingleDirEntry {
+    fn name(&self) -> Vec<u8> {
+        self.name.clone()
+    }
+
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        Box::pin(future::ok(self.meta.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::tests::{req, run};
+    use crate::DavHandler;
+
+    #[tokio::test]
+    async fn single_file() {
+        let dir = std::env::temp_dir().join(format!("dav-single-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shared.txt"), "shared").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+
+        let fs = SingleFileFs::new(dir.join("shared.txt"), "file.txt");
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        let get = |path: &'static str| run(&dav, req("GET", path).body(Body::empty()).unwrap());
+
+        let (resp, body) = get("/file.txt").await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(body, "shared");
+        for path in ["/shared.txt", "/secret.txt", "/file.txt/secret.txt"] {
+            assert_eq!(get(path).await.0.status(), 404, "{}", path);
+        }
+
+        let r = req("PROPFIND", "/").header("Depth", "1");
+        let (resp, text) = run(&dav, r.body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 207);
+        assert_eq!(text.matches("<D:response>").count(), 2, "{}", text);
+        assert!(text.contains("<D:href>/file.txt</D:href>"), "{}", text);
+        assert!(!text.contains("secret"), "{}", text);
+
+        let r = req("PUT", "/file.txt").body(Body::from("changed")).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 403);
+        let r = req("DELETE", "/file.txt").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 403);
+        let r = req("MKCOL", "/dir/").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 403);
+        assert_eq!(get("/file.txt").await.1, "shared");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+// Detect the content type of a file from its first bytes.
+//
+// Only a few well-known signatures are checked, enough for the
+// common types of files that are stored without an extension.
+
+// (offset, signature, content type)
+const MAGIC: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"BZh", "application/x-bzip2"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"\x1aE\xdf\xa3", "video/webm"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"<?xml", "application/xml"),
+    (0, b"\x7fELF", "application/x-executable"),
+];
+
+// Returns the content type that matches the start of a file.
+pub(crate) fn sniff(data: &[u8]) -> Option<&'static str> {
+    // RIFF containers say what they are at offset 8.
+    if data.starts_with(b"RIFF") && data.len() >= 12 {
+        match &data[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            b"AVI " => return Some("video/x-msvideo"),
+            _ => {}
+        }
+    }
+    MAGIC.iter().find_map(
+        |&(offset, magic, ct)| match data.get(offset..offset + magic.len()) {
+            Some(d) if d == magic => Some(ct),
+            _ => None,
+        },
+    )
+}
@@ -0,0 +1,112 @@
+// Headers of a tar archive (POSIX ustar), for downloading a collection.
+//
+// Only what is needed to write an archive is here: directories and
+// regular files. Names longer than 100 bytes get a GNU long name entry
+// first, sizes that do not fit in 11 octal digits are stored in base-256.
+// Both are understood by all common tar implementations.
+
+pub(crate) const BLOCK: usize = 512;
+
+// Header block(s) for an entry. Directory names end in a '/'.
+pub(crate) fn header(name: &[u8], size: u64, mtime: u64, is_dir: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(BLOCK);
+    if name.len() > 100 {
+        let mut data = name.to_vec();
+        data.push(0);
+        out.extend_from_slice(&block(b"././@LongLink", data.len() as u64, 0, b'L'));
+        out.extend_from_slice(&data);
+        out.resize(out.len() + padding(data.len() as u64), 0);
+    }
+    let typeflag = if is_dir { b'5' } else { b'0' };
+    out.extend_from_slice(&block(&name[..name.len().min(100)], size, mtime, typeflag));
+    out
+}
+
+// Number of zero bytes after `size` bytes of file data.
+pub(crate) fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+// Two zero blocks mark the end of the archive.
+pub(crate) fn end() -> [u8; 2 * BLOCK] {
+    [0; 2 * BLOCK]
+}
+
+fn block(name: &[u8], size: u64, mtime: u64, typeflag: u8) -> [u8; BLOCK] {
+    let mut b = [0u8; BLOCK];
+    b[..name.len()].copy_from_slice(name);
+    let mode: &[u8] = if typeflag == b'5' {
+        b"0000755"
+    } else {
+        b"0000644"
+    };
+    b[100..107].copy_from_slice(mode);
+    b[108..115].copy_from_slice(b"0000000");
+    b[116..123].copy_from_slice(b"0000000");
+    number(&mut b[124..136], size);
+    number(&mut b[136..148], mtime);
+    b[156] = typeflag;
+    b[257..263].copy_from_slice(b"ustar\0");
+    b[263..265].copy_from_slice(b"00");
+
+    // the checksum is calculated with the checksum field set to spaces.
+    b[148..156].copy_from_slice(b"        ");
+    let sum: u32 = b.iter().map(|&c| c as u32).sum();
+    let sum = format!("{:06o}\0 ", sum);
+    b[148..156].copy_from_slice(sum.as_bytes());
+    b
+}
+
+// octal, NUL terminated. base-256 if it does not fit.
+fn number(field: &mut [u8], n: u64) {
+    let digits = field.len() - 1;
+    let s = format!("{:0width$o}", n, width = digits);
+    if s.len() == digits {
+        field[..digits].copy_from_slice(s.as_bytes());
+        field[digits] = 0;
+    } else {
+        field.iter_mut().for_each(|c| *c = 0);
+        let len = field.len();
+        field[len - 8..].copy_from_slice(&n.to_be_bytes());
+        field[0] = 0x80;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers() {
+        let h = header(b"dir/file.txt", 5, 0o1234, false);
+        assert_eq!(h.len(), BLOCK);
+        assert_eq!(&h[..12], b"dir/file.txt");
+        assert_eq!(&h[124..136], b"00000000005\0");
+        assert_eq!(&h[136..148], b"00000001234\0");
+        assert_eq!(h[156], b'0');
+        assert_eq!(&h[257..265], b"ustar\0\x30\x30");
+
+        let stored = std::str::from_utf8(&h[148..154]).unwrap();
+        let mut zeroed = h.clone();
+        zeroed[148..156].copy_from_slice(b"        ");
+        let sum: u32 = zeroed.iter().map(|&c| c as u32).sum();
+        assert_eq!(u32::from_str_radix(stored, 8).unwrap(), sum);
+
+        // long names get an extra entry.
+        let name = "x".repeat(150);
+        let h = header(name.as_bytes(), 0, 0, true);
+        assert_eq!(h.len(), 3 * BLOCK);
+        assert_eq!(h[156], b'L');
+        assert_eq!(&h[BLOCK..BLOCK + 150], name.as_bytes());
+        assert_eq!(h[2 * BLOCK + 156], b'5');
+
+        // 8 GiB does not fit in 11 octal digits.
+        let h = header(b"big", 1 << 33, 0, false);
+        assert_eq!(h[124], 0x80);
+        assert_eq!(&h[128..136], &(1u64 << 33).to_be_bytes());
+
+        assert_eq!(padding(0), 0);
+        assert_eq!(padding(5), 507);
+        assert_eq!(padding(512), 0);
+    }
+}
@@ -0,0 +1,275 @@
+// Helpers for the handler tests.
+//
+// The tests themselves live in the module they exercise, these
+// are just the bits to build a request, run it, and look at the result.
+use std::time::SystemTime;
+
+use http::{request, Request, Response, StatusCode};
+use http_body_util::BodyExt;
+
+use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+use crate::body::Body;
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::DavHandler;
+
+// Request builder for `method` on `path`.
+pub(crate) fn req(method: &str, path: &str) -> request::Builder {
+    Request::builder().method(method).uri(path)
+}
+
+// Run a request and collect the response body.
+pub(crate) async fn run(dav: &DavHandler, req: Request<Body>) -> (Response<()>, String) {
+    let (parts, body) = dav.handle(req).await.into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+    let body = String::from_utf8_lossy(&body).to_string();
+    (Response::from_parts(parts, ()), body)
+}
+
+// Create a file with some content.
+pub(crate) async fn put(dav: &DavHandler, path: &str, content: &str) {
+    let req = req("PUT", path)
+        .body(Body::from(content.to_string()))
+        .unwrap();
+    let (resp, _) = run(dav, req).await;
+    assert!(
+        resp.status().is_success(),
+        "PUT {}: {}",
+        path,
+        resp.status()
+    );
+}
+
+// Create a collection.
+pub(crate) async fn mkcol(dav: &DavHandler, path: &str) {
+    let req = req("MKCOL", path).body(Body::empty()).unwrap();
+    let (resp, _) = run(dav, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED, "MKCOL {}", path);
+}
+
+// A filesystem wrapper for tests. Everything is passed on to `inner()`,
+// a test only implements the methods it is about. Like the defaults of
+// `AsyncDavFileSystem`, open_with_meta, symlink_metadata and rename_ext
+// go through open, metadata and rename, so overriding those is enough.
+#[async_trait]
+pub(crate) trait ForwardFs: Clone + Send + Sync + 'static {
+    fn inner(&self) -> &dyn DavFileSystem;
+
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        self.inner().open(path, options).await
+    }
+
+    async fn open_with_meta(
+        &self,
+        path: &DavPath,
+        options: OpenOptions,
+        meta: Box<dyn DavMetaData>,
+    ) -> FsResult<Box<dyn DavFile>> {
+        let _ = meta;
+        ForwardFs::open(self, path, options).await
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        self.inner().read_dir(path, meta).await
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        self.inner().metadata(path).await
+    }
+
+    async fn symlink_metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        ForwardFs::metadata(self, path).await
+    }
+
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.inner().create_dir(path).await
+    }
+
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.inner().remove_dir(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &DavPath) -> FsResult<()> {
+        self.inner().remove_dir_all(path).await
+    }
+
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        self.inner().remove_file(path).await
+    }
+
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.inner().rename(from, to).await
+    }
+
+    async fn rename_ext(&self, from: &DavPath, to: &DavPath) -> FsResult<RenameKind> {
+        match ForwardFs::rename(self, from, to).await {
+            Ok(()) => Ok(RenameKind::Native),
+            Err(FsError::IsRemote) => Ok(RenameKind::Unsupported),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.inner().copy(from, to).await
+    }
+
+    async fn set_accessed(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner().set_accessed(path, tm).await
+    }
+
+    async fn set_modified(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner().set_modified(path, tm).await
+    }
+
+    async fn have_props(&self, path: &DavPath) -> bool {
+        self.inner().have_props(path).await
+    }
+
+    async fn patch_props(
+        &self,
+        path: &DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(StatusCode, DavProp)>> {
+        self.inner().patch_props(path, patch).await
+    }
+
+    async fn get_props(&self, path: &DavPath, do_content: bool) -> FsResult<Vec<DavProp>> {
+        self.inner().get_props(path, do_content).await
+    }
+
+    async fn get_prop(&self, path: &DavPath, prop: DavProp) -> FsResult<Vec<u8>> {
+        self.inner().get_prop(path, prop).await
+    }
+
+    async fn get_quota(&self) -> FsResult<(u64, Option<u64>)> {
+        self.inner().get_quota().await
+    }
+
+    fn preferred_read_chunk_size(&self) -> usize {
+        self.inner().preferred_read_chunk_size()
+    }
+
+    async fn get_redirect_url(&self, path: &DavPath) -> FsResult<Option<String>> {
+        self.inner().get_redirect_url(path).await
+    }
+
+    async fn collection_etag(&self, path: &DavPath) -> FsResult<Option<String>> {
+        self.inner().collection_etag(path).await
+    }
+
+    async fn is_special(&self, path: &DavPath) -> FsResult<bool> {
+        self.inner().is_special(path).await
+    }
+}
+
+#[async_trait]
+impl<T: ForwardFs> AsyncDavFileSystem for T {
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        ForwardFs::open(self, path, options).await
+    }
+
+    async fn open_with_meta(
+        &self,
+        path: &DavPath,
+        options: OpenOptions,
+        meta: Box<dyn DavMetaData>,
+    ) -> FsResult<Box<dyn DavFile>> {
+        ForwardFs::open_with_meta(self, path, options, meta).await
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        ForwardFs::read_dir(self, path, meta).await
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        ForwardFs::metadata(self, path).await
+    }
+
+    async fn symlink_metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        ForwardFs::symlink_metadata(self, path).await
+    }
+
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        ForwardFs::create_dir(self, path).await
+    }
+
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        ForwardFs::remove_dir(self, path).await
+    }
+
+    async fn remove_dir_all(&self, path: &DavPath) -> FsResult<()> {
+        ForwardFs::remove_dir_all(self, path).await
+    }
+
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        ForwardFs::remove_file(self, path).await
+    }
+
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        ForwardFs::rename(self, from, to).await
+    }
+
+    async fn rename_ext(&self, from: &DavPath, to: &DavPath) -> FsResult<RenameKind> {
+        ForwardFs::rename_ext(self, from, to).await
+    }
+
+    async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        ForwardFs::copy(self, from, to).await
+    }
+
+    async fn set_accessed(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        ForwardFs::set_accessed(self, path, tm).await
+    }
+
+    async fn set_modified(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        ForwardFs::set_modified(self, path, tm).await
+    }
+
+    async fn have_props(&self, path: &DavPath) -> bool {
+        ForwardFs::have_props(self, path).await
+    }
+
+    async fn patch_props(
+        &self,
+        path: &DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(StatusCode, DavProp)>> {
+        ForwardFs::patch_props(self, path, patch).await
+    }
+
+    async fn get_props(&self, path: &DavPath, do_content: bool) -> FsResult<Vec<DavProp>> {
+        ForwardFs::get_props(self, path, do_content).await
+    }
+
+    async fn get_prop(&self, path: &DavPath, prop: DavProp) -> FsResult<Vec<u8>> {
+        ForwardFs::get_prop(self, path, prop).await
+    }
+
+    async fn get_quota(&self) -> FsResult<(u64, Option<u64>)> {
+        ForwardFs::get_quota(self).await
+    }
+
+    fn preferred_read_chunk_size(&self) -> usize {
+        ForwardFs::preferred_read_chunk_size(self)
+    }
+
+    async fn get_redirect_url(&self, path: &DavPath) -> FsResult<Option<String>> {
+        ForwardFs::get_redirect_url(self, path).await
+    }
+
+    async fn collection_etag(&self, path: &DavPath) -> FsResult<Option<String>> {
+        ForwardFs::collection_etag(self, path).await
+    }
+
+    async fn is_special(&self, path: &DavPath) -> FsResult<bool> {
+        ForwardFs::is_special(self, path).await
+    }
+}
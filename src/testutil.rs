@@ -0,0 +1,1196 @@
+//! Filesystem wrappers for testing.
+//!
+//! [`FaultFs`] wraps another filesystem and makes selected operations
+//! fail with a specific [`FsError`], so that the way errors are turned
+//! into HTTP responses can be tested without a misbehaving backend.
+//!
+//! [`ChaosFs`] adds random latency and errors to the operations of
+//! another filesystem, from a seeded random number generator.
+//!
+//! [`VersionedMemFs`] is an in-memory filesystem with a version number
+//! per file, that is used as its etag.
+//!
+//! This module is available with the `testutil` feature.
+//!
+//! ```
+//! use dav_server::{fs::FsError, memfs::MemFs, testutil::{FaultFs, FsOp}, DavHandler};
+//!
+//! let fs = FaultFs::new(MemFs::new());
+//! fs.fail_path(FsOp::Open, "/full.txt", FsError::InsufficientStorage);
+//! let dav = DavHandler::builder().filesystem(fs).build_handler();
+//! ```
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, Bytes};
+use futures_util::StreamExt;
+
+use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+use crate::davpath::DavPath;
+use crate::fs::*;
+use crate::memfs::MemFs;
+
+/// Operations that [`FaultFs`] can make fail, and [`ChaosFs`] slow down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FsOp {
+    Open,
+    ReadDir,
+    /// An entry in the stream of `read_dir`, the path is the path of the
+    /// entry. The stream returns the error instead of the entry.
+    ReadDirEntry,
+    /// `metadata`, and `DavDirEntry::metadata` of an entry returned by
+    /// `read_dir`.
+    Metadata,
+    SymlinkMetadata,
+    CreateDir,
+    RemoveDir,
+    RemoveDirAll,
+    RemoveFile,
+    Rename,
+    Copy,
+    PatchProps,
+    GetProps,
+    GetProp,
+    /// `DavFile::read_bytes` on a file opened through `FaultFs`.
+    Read,
+    /// `DavFile::write_buf` and `DavFile::write_bytes`.
+    Write,
+    /// `DavFile::flush`.
+    Flush,
+}
+
+#[derive(Debug)]
+struct Fault {
+    op: FsOp,
+    path: Option<String>,
+    error: FsError,
+}
+
+#[derive(Debug, Default)]
+struct Faults(Mutex<Vec<Fault>>);
+
+impl Faults {
+    fn check(&self, op: FsOp, path: &DavPath) -> FsResult<()> {
+        let faults = self.0.lock().unwrap();
+        let url = path.as_url_string();
+        let url = trim_slash(&url);
+        for fault in faults.iter().filter(|f| f.op == op) {
+            match fault.path {
+                Some(ref p) if trim_slash(p) != url => {}
+                _ => return Err(fault.error),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn trim_slash(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        p => p,
+    }
+}
+
+/// Filesystem wrapper that injects errors.
+///
+/// Operations behave like the wrapped filesystem until a fault is
+/// registered for them with [`fail`](FaultFs::fail) or
+/// [`fail_path`](FaultFs::fail_path). Clones share the same set
+/// of faults, so they can be changed after the filesystem has been
+/// handed to a `DavHandler`.
+#[derive(Clone)]
+pub struct FaultFs {
+    inner: Box<dyn DavFileSystem>,
+    faults: Arc<Faults>,
+}
+
+impl FaultFs {
+    /// Wrap a filesystem.
+    pub fn new(inner: Box<dyn DavFileSystem>) -> Box<FaultFs> {
+        Box::new(FaultFs {
+            inner,
+            faults: Arc::new(Faults::default()),
+        })
+    }
+
+    /// Make `op` fail with `error` on every path.
+    pub fn fail(&self, op: FsOp, error: FsError) {
+        let fault = Fault {
+            op,
+            path: None,
+            error,
+        };
+        self.faults.0.lock().unwrap().push(fault);
+    }
+
+    /// Make `op` fail with `error` on `path` only.
+    ///
+    /// The path is the url-encoded path without prefix, a trailing
+    /// slash is ignored.
+    pub fn fail_path(&self, op: FsOp, path: &str, error: FsError) {
+        let fault = Fault {
+            op,
+            path: Some(path.to_string()),
+            error,
+        };
+        self.faults.0.lock().unwrap().push(fault);
+    }
+
+    /// Remove all faults.
+    pub fn clear(&self) {
+        self.faults.0.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl AsyncDavFileSystem for FaultFs {
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        self.faults.check(FsOp::Open, path)?;
+        let file = self.inner.open(path, options).await?;
+        Ok(Box::new(FaultFile {
+            file,
+            path: path.clone(),
+            faults: self.faults.clone(),
+        }))
+    }
+
+    async fn open_with_meta(
+        &self,
+        path: &DavPath,
+        options: OpenOptions,
+        meta: Box<dyn DavMetaData>,
+    ) -> FsResult<Box<dyn DavFile>> {
+        self.faults.check(FsOp::Open, path)?;
+        let file = self.inner.open_with_meta(path, options, meta).await?;
+        Ok(Box::new(FaultFile {
+            file,
+            path: path.clone(),
+            faults: self.faults.clone(),
+        }))
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        self.faults.check(FsOp::ReadDir, path)?;
+        let entries = self.inner.read_dir(path, meta).await?;
+        let mut dir = path.clone();
+        dir.add_slash();
+        let faults = self.faults.clone();
+        let entries = entries.map(move |entry| {
+            let entry = entry?;
+            let mut path = dir.clone();
+            path.push_segment(&entry.name());
+            faults.check(FsOp::ReadDirEntry, &path)?;
+            Ok(Box::new(FaultDirEntry {
+                entry,
+                path,
+                faults: faults.clone(),
+            }) as Box<dyn DavDirEntry>)
+        });
+        Ok(Box::pin(entries))
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        self.faults.check(FsOp::Metadata, path)?;
+        self.inner.metadata(path).await
+    }
+
+    async fn symlink_metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        self.faults.check(FsOp::SymlinkMetadata, path)?;
+        self.inner.symlink_metadata(path).await
+    }
+
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.faults.check(FsOp::CreateDir, path)?;
+        self.inner.create_dir(path).await
+    }
+
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.faults.check(FsOp::RemoveDir, path)?;
+        self.inner.remove_dir(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &DavPath) -> FsResult<()> {
+        self.faults.check(FsOp::RemoveDirAll, path)?;
+        self.inner.remove_dir_all(path).await
+    }
+
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        self.faults.check(FsOp::RemoveFile, path)?;
+        self.inner.remove_file(path).await
+    }
+
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.faults.check(FsOp::Rename, from)?;
+        self.inner.rename(from, to).await
+    }
+
+    async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.faults.check(FsOp::Copy, from)?;
+        self.inner.copy(from, to).await
+    }
+
+    async fn set_accessed(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner.set_accessed(path, tm).await
+    }
+
+    async fn set_modified(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner.set_modified(path, tm).await
+    }
+
+    async fn have_props(&self, path: &DavPath) -> bool {
+        self.inner.have_props(path).await
+    }
+
+    async fn patch_props(
+        &self,
+        path: &DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(http::StatusCode, DavProp)>> {
+        self.faults.check(FsOp::PatchProps, path)?;
+        self.inner.patch_props(path, patch).await
+    }
+
+    async fn get_props(&self, path: &DavPath, do_content: bool) -> FsResult<Vec<DavProp>> {
+        self.faults.check(FsOp::GetProps, path)?;
+        self.inner.get_props(path, do_content).await
+    }
+
+    async fn get_prop(&self, path: &DavPath, prop: DavProp) -> FsResult<Vec<u8>> {
+        self.faults.check(FsOp::GetProp, path)?;
+        self.inner.get_prop(path, prop).await
+    }
+
+    fn with_context(&self, context: &DavContext) -> Box<dyn DavFileSystem> {
+        Box::new(FaultFs {
+            inner: self.inner.with_context(context),
+            faults: self.faults.clone(),
+        })
+    }
+
+    async fn get_quota(&self) -> FsResult<(u64, Option<u64>)> {
+        self.inner.get_quota().await
+    }
+
+    fn preferred_read_chunk_size(&self) -> usize {
+        self.inner.preferred_read_chunk_size()
+    }
+
+    async fn get_redirect_url(&self, path: &DavPath) -> FsResult<Option<String>> {
+        self.inner.get_redirect_url(path).await
+    }
+
+    async fn collection_etag(&self, path: &DavPath) -> FsResult<Option<String>> {
+        self.inner.collection_etag(path).await
+    }
+
+    async fn is_special(&self, path: &DavPath) -> FsResult<bool> {
+        self.inner.is_special(path).await
+    }
+}
+
+struct FaultDirEntry {
+    entry: Box<dyn DavDirEntry>,
+    path: DavPath,
+    faults: Arc<Faults>,
+}
+
+impl DavDirEntry for FaultDirEntry {
+    fn name(&self) -> Vec<u8> {
+        self.entry.name()
+    }
+
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        if let Err(e) = self.faults.check(FsOp::Metadata, &self.path) {
+            return Box::pin(futures_util::future::err(e));
+        }
+        self.entry.metadata()
+    }
+
+    fn is_dir(&self) -> FsFuture<'_, bool> {
+        self.entry.is_dir()
+    }
+
+    fn is_file(&self) -> FsFuture<'_, bool> {
+        self.entry.is_file()
+    }
+
+    fn is_symlink(&self) -> FsFuture<'_, bool> {
+        self.entry.is_symlink()
+    }
+}
+
+#[derive(Debug)]
+struct FaultFile {
+    file: Box<dyn DavFile>,
+    path: DavPath,
+    faults: Arc<Faults>,
+}
+
+impl DavFile for FaultFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.file.metadata()
+    }
+
+    fn write_buf(&mut self, buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        if let Err(e) = self.faults.check(FsOp::Write, &self.path) {
+            return Box::pin(futures_util::future::err(e));
+        }
+        self.file.write_buf(buf)
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        if let Err(e) = self.faults.check(FsOp::Write, &self.path) {
+            return Box::pin(futures_util::future::err(e));
+        }
+        self.file.write_bytes(buf)
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        if let Err(e) = self.faults.check(FsOp::Read, &self.path) {
+            return Box::pin(futures_util::future::err(e));
+        }
+        self.file.read_bytes(count)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        self.file.seek(pos)
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        if let Err(e) = self.faults.check(FsOp::Flush, &self.path) {
+            return Box::pin(futures_util::future::err(e));
+        }
+        self.file.flush()
+    }
+
+    fn redirect_url(&mut self) -> FsFuture<'_, Option<String>> {
+        self.file.redirect_url()
+    }
+
+    fn is_seekable(&self) -> bool {
+        self.file.is_seekable()
+    }
+}
+
+/// What [`ChaosFs`] does to an operation.
+///
+/// Every call waits for a random time between `min_latency` and
+/// `max_latency`, and then fails with `error` with a probability of
+/// `error_rate` (0.0 is never, 1.0 is always).
+#[derive(Debug, Clone, Copy)]
+pub struct Chaos {
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    pub error_rate: f64,
+    pub error: FsError,
+}
+
+impl Default for Chaos {
+    fn default() -> Chaos {
+        Chaos {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+            error_rate: 0.0,
+            error: FsError::GeneralFailure,
+        }
+    }
+}
+
+/// Configuration of a [`ChaosFs`].
+///
+/// All operations use the same [`Chaos`], unless one is set for a
+/// specific operation with [`op`](ChaosConfig::op).
+#[derive(Debug, Clone)]
+pub struct ChaosConfig {
+    seed: u64,
+    default: Chaos,
+    ops: HashMap<FsOp, Chaos>,
+}
+
+impl ChaosConfig {
+    /// No latency and no errors. `seed` is the seed of the random
+    /// number generator.
+    pub fn new(seed: u64) -> ChaosConfig {
+        ChaosConfig {
+            seed,
+            default: Chaos::default(),
+            ops: HashMap::new(),
+        }
+    }
+
+    /// Latency between `min` and `max` for all operations.
+    pub fn latency(mut self, min: Duration, max: Duration) -> Self {
+        self.default.min_latency = min;
+        self.default.max_latency = max;
+        self
+    }
+
+    /// Make all operations fail with `error`, with a probability of `rate`.
+    pub fn errors(mut self, rate: f64, error: FsError) -> Self {
+        self.default.error_rate = rate;
+        self.default.error = error;
+        self
+    }
+
+    /// Use `chaos` for `op`, instead of the latency and errors of
+    /// the other operations.
+    pub fn op(mut self, op: FsOp, chaos: Chaos) -> Self {
+        self.ops.insert(op, chaos);
+        self
+    }
+}
+
+#[derive(Debug)]
+struct ChaosState {
+    config: ChaosConfig,
+    rng: Mutex<u64>,
+}
+
+impl ChaosState {
+    // xorshift64*, good enough to pick delays and errors.
+    fn random(rng: &mut u64) -> f64 {
+        *rng ^= *rng >> 12;
+        *rng ^= *rng << 25;
+        *rng ^= *rng >> 27;
+        let n = rng.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (n >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    async fn apply(&self, op: FsOp) -> FsResult<()> {
+        let chaos = self.config.ops.get(&op).unwrap_or(&self.config.default);
+        let (delay, fail) = {
+            let mut rng = self.rng.lock().unwrap();
+            let spread = chaos.max_latency.saturating_sub(chaos.min_latency);
+            let delay = chaos.min_latency + spread.mul_f64(Self::random(&mut rng));
+            (delay, Self::random(&mut rng) < chaos.error_rate)
+        };
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        match fail {
+            true => Err(chaos.error),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Filesystem wrapper that adds random latency and errors.
+///
+/// Unlike [`FaultFs`], which fails exactly the operations it is told to,
+/// this makes any operation slow or fail at random, to see how clients
+/// and timeouts cope with a flaky backend. The random numbers come from
+/// a generator with a fixed seed, so a sequence of operations gets the
+/// same delays and errors on every run. Clones share the generator.
+///
+/// ```
+/// use std::time::Duration;
+/// use dav_server::{fs::FsError, memfs::MemFs, testutil::{ChaosConfig, ChaosFs}, DavHandler};
+///
+/// let config = ChaosConfig::new(42)
+///     .latency(Duration::from_millis(5), Duration::from_millis(50))
+///     .errors(0.01, FsError::Unavailable(None));
+/// let fs = ChaosFs::new(MemFs::new(), config);
+/// let dav = DavHandler::builder().filesystem(fs).build_handler();
+/// ```
+#[derive(Clone)]
+pub struct ChaosFs {
+    inner: Box<dyn DavFileSystem>,
+    state: Arc<ChaosState>,
+}
+
+impl ChaosFs {
+    /// Wrap a filesystem.
+    pub fn new(inner: Box<dyn DavFileSystem>, config: ChaosConfig) -> Box<ChaosFs> {
+        // a zero state would only ever produce zeroes.
+        let seed = config.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        Box::new(ChaosFs {
+            inner,
+            state: Arc::new(ChaosState {
+                config,
+                rng: Mutex::new(seed),
+            }),
+        })
+    }
+}
+
+#[async_trait]
+impl AsyncDavFileSystem for ChaosFs {
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        self.state.apply(FsOp::Open).await?;
+        let file = self.inner.open(path, options).await?;
+        Ok(Box::new(ChaosFile {
+            file,
+            state: self.state.clone(),
+        }))
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        self.state.apply(FsOp::ReadDir).await?;
+        self.inner.read_dir(path, meta).await
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        self.state.apply(FsOp::Metadata).await?;
+        self.inner.metadata(path).await
+    }
+
+    async fn symlink_metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        self.state.apply(FsOp::SymlinkMetadata).await?;
+        self.inner.symlink_metadata(path).await
+    }
+
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.state.apply(FsOp::CreateDir).await?;
+        self.inner.create_dir(path).await
+    }
+
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.state.apply(FsOp::RemoveDir).await?;
+        self.inner.remove_dir(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &DavPath) -> FsResult<()> {
+        self.state.apply(FsOp::RemoveDirAll).await?;
+        self.inner.remove_dir_all(path).await
+    }
+
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        self.state.apply(FsOp::RemoveFile).await?;
+        self.inner.remove_file(path).await
+    }
+
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.state.apply(FsOp::Rename).await?;
+        self.inner.rename(from, to).await
+    }
+
+    async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.state.apply(FsOp::Copy).await?;
+        self.inner.copy(from, to).await
+    }
+
+    async fn set_accessed(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner.set_accessed(path, tm).await
+    }
+
+    async fn set_modified(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner.set_modified(path, tm).await
+    }
+
+    async fn have_props(&self, path: &DavPath) -> bool {
+        self.inner.have_props(path).await
+    }
+
+    async fn patch_props(
+        &self,
+        path: &DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(http::StatusCode, DavProp)>> {
+        self.state.apply(FsOp::PatchProps).await?;
+        self.inner.patch_props(path, patch).await
+    }
+
+    async fn get_props(&self, path: &DavPath, do_content: bool) -> FsResult<Vec<DavProp>> {
+        self.state.apply(FsOp::GetProps).await?;
+        self.inner.get_props(path, do_content).await
+    }
+
+    async fn get_prop(&self, path: &DavPath, prop: DavProp) -> FsResult<Vec<u8>> {
+        self.state.apply(FsOp::GetProp).await?;
+        self.inner.get_prop(path, prop).await
+    }
+
+    fn with_context(&self, context: &DavContext) -> Box<dyn DavFileSystem> {
+        Box::new(ChaosFs {
+            inner: self.inner.with_context(context),
+            state: self.state.clone(),
+        })
+    }
+
+    async fn get_quota(&self) -> FsResult<(u64, Option<u64>)> {
+        self.inner.get_quota().await
+    }
+
+    fn preferred_read_chunk_size(&self) -> usize {
+        self.inner.preferred_read_chunk_size()
+    }
+
+    async fn is_special(&self, path: &DavPath) -> FsResult<bool> {
+        self.inner.is_special(path).await
+    }
+}
+
+#[derive(Debug)]
+struct ChaosFile {
+    file: Box<dyn DavFile>,
+    state: Arc<ChaosState>,
+}
+
+impl DavFile for ChaosFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        self.file.metadata()
+    }
+
+    fn write_buf(&mut self, buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        Box::pin(async move {
+            self.state.apply(FsOp::Write).await?;
+            self.file.write_buf(buf).await
+        })
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        Box::pin(async move {
+            self.state.apply(FsOp::Write).await?;
+            self.file.write_bytes(buf).await
+        })
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        Box::pin(async move {
+            self.state.apply(FsOp::Read).await?;
+            self.file.read_bytes(count).await
+        })
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        self.file.seek(pos)
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        Box::pin(async move {
+            self.state.apply(FsOp::Flush).await?;
+            self.file.flush().await
+        })
+    }
+
+    fn is_seekable(&self) -> bool {
+        self.file.is_seekable()
+    }
+}
+
+/// Namespace of the `version` property of [`VersionedMemFs`].
+pub const VERSION_NS: &str = "urn:dav-server:testutil";
+
+type Versions = Arc<Mutex<HashMap<Vec<u8>, u64>>>;
+
+// key of a path in the version map.
+fn version_key(path: &[u8]) -> Vec<u8> {
+    match path.strip_suffix(b"/") {
+        Some(p) if !p.is_empty() => p.to_vec(),
+        _ => path.to_vec(),
+    }
+}
+
+/// In-memory filesystem that keeps a version number per file.
+///
+/// This is a [`MemFs`] that counts how often every file is written.
+/// The version starts at 1 when a file is created and goes up by one
+/// each time the file is opened for writing, copied to or moved to. It
+/// does not go back when a file is removed, so a file that is created
+/// again gets a new version.
+///
+/// The version is the strong etag of the file (`"v1"`, `"v2"`, ...),
+/// and the property `version` in the [`VERSION_NS`] namespace. That
+/// property is protected, PROPPATCH cannot change it. Other dead
+/// properties work like they do on `MemFs`.
+///
+/// Since the versions do not depend on the time, the etags are the
+/// same on every run, which makes it easy to test `If-Match` flows.
+///
+/// ```
+/// use dav_server::{testutil::VersionedMemFs, DavHandler};
+///
+/// let fs = VersionedMemFs::new();
+/// let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+/// assert_eq!(fs.version("/file.txt"), None);
+/// ```
+#[derive(Clone)]
+pub struct VersionedMemFs {
+    inner: Box<MemFs>,
+    versions: Versions,
+}
+
+impl VersionedMemFs {
+    /// Create a new, empty filesystem.
+    pub fn new() -> Box<VersionedMemFs> {
+        Box::new(VersionedMemFs {
+            inner: MemFs::new(),
+            versions: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// The current version of the file at `path` (url-encoded, without
+    /// prefix), if it was ever written.
+    pub fn version(&self, path: &str) -> Option<u64> {
+        let key = version_key(path.as_bytes());
+        self.versions.lock().unwrap().get(&key).copied()
+    }
+
+    fn get(&self, key: &[u8]) -> u64 {
+        self.versions.lock().unwrap().get(key).copied().unwrap_or(0)
+    }
+
+    fn bump(&self, key: Vec<u8>) {
+        *self.versions.lock().unwrap().entry(key).or_insert(0) += 1;
+    }
+
+    fn versioned(&self, key: &[u8], meta: Box<dyn DavMetaData>) -> Box<dyn DavMetaData> {
+        let version = self.get(key);
+        Box::new(VersionedMeta { meta, version })
+    }
+
+    fn version_prop(&self, key: &[u8], do_content: bool) -> DavProp {
+        let xml = format!(
+            "<V:version xmlns:V=\"{}\">{}</V:version>",
+            VERSION_NS,
+            self.get(key)
+        );
+        DavProp {
+            name: "version".to_string(),
+            prefix: Some("V".to_string()),
+            namespace: Some(VERSION_NS.to_string()),
+            xml: if do_content {
+                Some(xml.into_bytes())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+fn is_version_prop(prop: &DavProp) -> bool {
+    prop.name == "version" && prop.namespace.as_deref() == Some(VERSION_NS)
+}
+
+#[async_trait]
+impl AsyncDavFileSystem for VersionedMemFs {
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        let write = options.write;
+        let file = self.inner.open(path, options).await?;
+        if write {
+            self.bump(version_key(path.as_bytes()));
+        }
+        Ok(Box::new(VersionedFile {
+            file,
+            key: version_key(path.as_bytes()),
+            versions: self.versions.clone(),
+        }))
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        let stream = self.inner.read_dir(path, meta).await?;
+        let mut dir = version_key(path.as_bytes());
+        if !dir.ends_with(b"/") {
+            dir.push(b'/');
+        }
+        let versions = self.versions.clone();
+        let stream = stream.map(move |entry| {
+            let entry = entry?;
+            let mut key = dir.clone();
+            key.extend_from_slice(&entry.name());
+            Ok(Box::new(VersionedDirEntry {
+                entry,
+                key,
+                versions: versions.clone(),
+            }) as Box<dyn DavDirEntry>)
+        });
+        Ok(Box::pin(stream))
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        let meta = self.inner.metadata(path).await?;
+        Ok(self.versioned(&version_key(path.as_bytes()), meta))
+    }
+
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.inner.create_dir(path).await
+    }
+
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.inner.remove_dir(path).await
+    }
+
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        self.inner.remove_file(path).await
+    }
+
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.inner.rename(from, to).await?;
+        // everything that moved is a new version at its new path.
+        let from = version_key(from.as_bytes());
+        let to = version_key(to.as_bytes());
+        let moved = self
+            .versions
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|k| **k == from || (k.starts_with(&from) && k[from.len()] == b'/'))
+            .map(|k| [&to[..], &k[from.len()..]].concat())
+            .collect::<Vec<_>>();
+        for key in moved {
+            self.bump(key);
+        }
+        Ok(())
+    }
+
+    async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.inner.copy(from, to).await?;
+        self.bump(version_key(to.as_bytes()));
+        Ok(())
+    }
+
+    async fn set_modified(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.inner.set_modified(path, tm).await
+    }
+
+    async fn have_props(&self, _path: &DavPath) -> bool {
+        true
+    }
+
+    async fn patch_props(
+        &self,
+        path: &DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(http::StatusCode, DavProp)>> {
+        // the version is protected. if it is in the patch, nothing is changed.
+        if patch.iter().any(|(_, p)| is_version_prop(p)) {
+            let res = patch
+                .into_iter()
+                .map(|(_, p)| {
+                    let status = if is_version_prop(&p) {
+                        http::StatusCode::FORBIDDEN
+                    } else {
+                        http::StatusCode::FAILED_DEPENDENCY
+                    };
+                    (status, DavProp { xml: None, ..p })
+                })
+                .collect();
+            return Ok(res);
+        }
+        self.inner.patch_props(path, patch).await
+    }
+
+    async fn get_props(&self, path: &DavPath, do_content: bool) -> FsResult<Vec<DavProp>> {
+        let mut props = self.inner.get_props(path, do_content).await?;
+        let key = version_key(path.as_bytes());
+        if self.inner.metadata(path).await?.is_file() {
+            props.push(self.version_prop(&key, do_content));
+        }
+        Ok(props)
+    }
+
+    async fn get_prop(&self, path: &DavPath, prop: DavProp) -> FsResult<Vec<u8>> {
+        if is_version_prop(&prop) && self.inner.metadata(path).await?.is_file() {
+            let key = version_key(path.as_bytes());
+            return Ok(self.version_prop(&key, true).xml.unwrap());
+        }
+        self.inner.get_prop(path, prop).await
+    }
+
+    async fn get_quota(&self) -> FsResult<(u64, Option<u64>)> {
+        self.inner.get_quota().await
+    }
+}
+
+#[derive(Debug, Clone)]
+struct VersionedMeta {
+    meta: Box<dyn DavMetaData>,
+    version: u64,
+}
+
+impl DavMetaData for VersionedMeta {
+    fn len(&self) -> u64 {
+        self.meta.len()
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        self.meta.modified()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.meta.is_dir()
+    }
+
+    fn etag(&self) -> Option<String> {
+        if self.meta.is_dir() {
+            return self.meta.etag();
+        }
+        Some(format!("v{}", self.version))
+    }
+
+    fn is_file(&self) -> bool {
+        self.meta.is_file()
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.meta.is_symlink()
+    }
+
+    fn accessed(&self) -> FsResult<SystemTime> {
+        self.meta.accessed()
+    }
+
+    fn created(&self) -> FsResult<SystemTime> {
+        self.meta.created()
+    }
+
+    fn status_changed(&self) -> FsResult<SystemTime> {
+        self.meta.status_changed()
+    }
+
+    fn executable(&self) -> FsResult<bool> {
+        self.meta.executable()
+    }
+}
+
+fn lookup_version(versions: &Versions, key: &[u8]) -> u64 {
+    versions.lock().unwrap().get(key).copied().unwrap_or(0)
+}
+
+struct VersionedDirEntry {
+    entry: Box<dyn DavDirEntry>,
+    key: Vec<u8>,
+    versions: Versions,
+}
+
+impl DavDirEntry for VersionedDirEntry {
+    fn name(&self) -> Vec<u8> {
+        self.entry.name()
+    }
+
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        Box::pin(async move {
+            let meta = self.entry.metadata().await?;
+            let version = lookup_version(&self.versions, &self.key);
+            Ok(Box::new(VersionedMeta { meta, version }) as Box<dyn DavMetaData>)
+        })
+    }
+}
+
+#[derive(Debug)]
+struct VersionedFile {
+    file: Box<dyn DavFile>,
+    key: Vec<u8>,
+    versions: Versions,
+}
+
+impl DavFile for VersionedFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        Box::pin(async move {
+            let meta = self.file.metadata().await?;
+            let version = lookup_version(&self.versions, &self.key);
+            Ok(Box::new(VersionedMeta { meta, version }) as Box<dyn DavMetaData>)
+        })
+    }
+
+    fn write_buf(&mut self, buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        self.file.write_buf(buf)
+    }
+
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
+        self.file.write_bytes(buf)
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        self.file.read_bytes(count)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        self.file.seek(pos)
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        self.file.flush()
+    }
+
+    fn is_seekable(&self) -> bool {
+        self.file.is_seekable()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::tests::{mkcol, put, req, run};
+    use crate::DavHandler;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn error_status() {
+        let fs = FaultFs::new(MemFs::new());
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        put(&dav, "/file.txt", "hello").await;
+        mkcol(&dav, "/dir/").await;
+
+        let errors = [
+            (FsError::LoopDetected, 508),
+            (FsError::PathTooLong, 414),
+            (FsError::IsRemote, 502),
+            (FsError::InsufficientStorage, 507),
+            (FsError::Unavailable(None), 503),
+        ];
+        for (error, status) in errors {
+            let cases = [
+                (FsOp::Open, "GET", "/file.txt"),
+                (FsOp::Open, "PUT", "/file.txt"),
+                (FsOp::Write, "PUT", "/file.txt"),
+                (FsOp::Metadata, "PROPFIND", "/file.txt"),
+                (FsOp::ReadDir, "PROPFIND", "/dir/"),
+            ];
+            for (op, method, path) in cases {
+                fs.clear();
+                fs.fail_path(op, path, error);
+                let r = req(method, path)
+                    .header("Depth", "1")
+                    .body(match method {
+                        "PUT" => Body::from("data"),
+                        _ => Body::empty(),
+                    })
+                    .unwrap();
+                let (resp, _) = run(&dav, r).await;
+                assert_eq!(resp.status(), status, "{:?} {:?} {}", error, op, method);
+            }
+        }
+
+        // transient errors can say when to try again.
+        fs.clear();
+        fs.fail(FsOp::Metadata, FsError::Unavailable(Some(30)));
+        let (resp, _) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 503);
+        assert_eq!(resp.headers()["retry-after"], "30");
+        fs.clear();
+        fs.fail(FsOp::Metadata, FsError::Unavailable(None));
+        let (resp, _) = run(&dav, req("GET", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 503);
+        assert!(resp.headers().get("retry-after").is_none());
+
+        // a GET that fails halfway can only abort the body.
+        fs.clear();
+        put(&dav, "/file.txt", "hello").await;
+        fs.fail(FsOp::Read, FsError::LoopDetected);
+        let resp = dav
+            .handle(req("GET", "/file.txt").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.into_body().collect().await.is_err());
+
+        // an unreadable collection is listed without members.
+        fs.clear();
+        fs.fail(FsOp::ReadDir, FsError::Forbidden);
+        let r = req("PROPFIND", "/dir/")
+            .header("Depth", "1")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 207);
+    }
+
+    #[tokio::test]
+    async fn versions() {
+        let fs = VersionedMemFs::new();
+        let dav = DavHandler::builder().filesystem(fs.clone()).build_handler();
+        let put_if = |etag: &'static str| {
+            req("PUT", "/file.txt")
+                .header("If-Match", etag)
+                .body(Body::from("data"))
+                .unwrap()
+        };
+
+        put(&dav, "/file.txt", "hello").await;
+        assert_eq!(fs.version("/file.txt"), Some(1));
+        let (resp, _) = run(&dav, req("HEAD", "/file.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.headers()["etag"], "\"v1\"");
+
+        // optimistic concurrency: the second writer loses.
+        assert_eq!(run(&dav, put_if("\"v1\"")).await.0.status(), 204);
+        assert_eq!(run(&dav, put_if("\"v1\"")).await.0.status(), 412);
+        assert_eq!(fs.version("/file.txt"), Some(2));
+
+        let propfind = r#"<D:propfind xmlns:D="DAV:"><D:prop><V:version xmlns:V="urn:dav-server:testutil"/><D:getetag/></D:prop></D:propfind>"#;
+        let r = req("PROPFIND", "/file.txt")
+            .header("Depth", "0")
+            .body(Body::from(propfind))
+            .unwrap();
+        let (_, body) = run(&dav, r).await;
+        assert!(body.contains(">2</"), "{}", body);
+        assert!(body.contains("<D:getetag>v2</D:getetag>"), "{}", body);
+
+        // the version cannot be changed.
+        let proppatch = r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop><V:version xmlns:V="urn:dav-server:testutil">9</V:version></D:prop></D:set></D:propertyupdate>"#;
+        let r = req("PROPPATCH", "/file.txt")
+            .body(Body::from(proppatch))
+            .unwrap();
+        let (_, body) = run(&dav, r).await;
+        assert!(body.contains("403 Forbidden"), "{}", body);
+        assert_eq!(fs.version("/file.txt"), Some(2));
+
+        // a collection listing shows the same etags.
+        mkcol(&dav, "/dir/").await;
+        let r = req("COPY", "/file.txt")
+            .header("Destination", "/dir/copy.txt")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 201);
+        let r = req("PROPFIND", "/dir/")
+            .header("Depth", "1")
+            .body(Body::empty())
+            .unwrap();
+        let (_, body) = run(&dav, r).await;
+        assert!(body.contains("<D:getetag>v1</D:getetag>"), "{}", body);
+
+        // a file that is removed and created again gets a new version.
+        let r = req("DELETE", "/file.txt").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 204);
+        put(&dav, "/file.txt", "again").await;
+        assert_eq!(fs.version("/file.txt"), Some(3));
+    }
+
+    #[tokio::test]
+    async fn chaos_seeded() {
+        async fn outcome(seed: u64) -> Vec<bool> {
+            let config = ChaosConfig::new(seed).errors(0.5, FsError::Unavailable(None));
+            let fs: Box<dyn DavFileSystem> = ChaosFs::new(MemFs::new(), config);
+            let path = DavPath::new("/").unwrap();
+            let mut v = Vec::new();
+            for _ in 0..64 {
+                v.push(fs.metadata(&path).await.is_ok());
+            }
+            v
+        }
+        let first = outcome(7).await;
+        assert!(first.contains(&true) && first.contains(&false));
+        assert_eq!(outcome(7).await, first);
+        assert_ne!(outcome(8).await, first);
+
+        // per-operation settings, and latency.
+        let slow = Chaos {
+            min_latency: Duration::from_millis(20),
+            max_latency: Duration::from_millis(30),
+            ..Chaos::default()
+        };
+        let config = ChaosConfig::new(1)
+            .errors(1.0, FsError::GeneralFailure)
+            .op(FsOp::Metadata, slow);
+        let fs: Box<dyn DavFileSystem> = ChaosFs::new(MemFs::new(), config);
+        let path = DavPath::new("/").unwrap();
+        let start = std::time::Instant::now();
+        assert!(fs.metadata(&path).await.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let err = fs.read_dir(&path, ReadDirMeta::None).await.err();
+        assert_eq!(err, Some(FsError::GeneralFailure));
+    }
+}
@@ -0,0 +1,51 @@
+//! Transform the content of a file on GET.
+//!
+//! A transform function can be configured with [`DavConfig::get_transform`].
+//! It is called for every GET of a regular file, and if it returns
+//! a [`Transform`], the file content is passed through it before
+//! it is sent to the client. That can be used to, for example,
+//! render markdown files to HTML for a browser.
+//!
+//! Since the length of the transformed content is not known in
+//! advance, a transformed response has no `Content-Length` or `ETag`,
+//! and `Range` requests are ignored (a full 200 response is sent).
+//!
+//! [`DavConfig::get_transform`]: crate::DavConfig::get_transform
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures_util::Stream;
+
+use crate::davpath::DavPath;
+use crate::fs::DavMetaData;
+
+/// A stream of bytes, the (transformed) content of a file.
+pub type ByteStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// Function that decides if and how a file is transformed.
+pub type TransformFn = Arc<dyn Fn(&DavPath, &dyn DavMetaData) -> Option<Transform> + Send + Sync>;
+
+/// A content transform.
+pub struct Transform {
+    pub(crate) content_type: String,
+    pub(crate) wrap: Box<dyn FnOnce(ByteStream) -> ByteStream + Send>,
+}
+
+impl Transform {
+    /// Create a new transform.
+    ///
+    /// `content_type` is the `Content-Type` of the transformed content,
+    /// `wrap` gets the content of the file as a stream and returns
+    /// the stream that is sent to the client.
+    pub fn new<F>(content_type: impl Into<String>, wrap: F) -> Transform
+    where
+        F: FnOnce(ByteStream) -> ByteStream + Send + 'static,
+    {
+        Transform {
+            content_type: content_type.into(),
+            wrap: Box::new(wrap),
+        }
+    }
+}
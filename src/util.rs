@@ -28,6 +28,8 @@ pub enum DavMethod {
     Delete = 0x0400,
     Lock = 0x0800,
     Unlock = 0x1000,
+    Report = 0x2000,
+    Post = 0x4000,
 }
 
 // translate method into our own enum that has webdav methods as well.
@@ -39,6 +41,7 @@ pub(crate) fn dav_method(m: &http::Method) -> DavResult<DavMethod> {
         http::Method::PATCH => DavMethod::Patch,
         http::Method::DELETE => DavMethod::Delete,
         http::Method::OPTIONS => DavMethod::Options,
+        http::Method::POST => DavMethod::Post,
         _ => match m.as_str() {
             "PROPFIND" => DavMethod::PropFind,
             "PROPPATCH" => DavMethod::PropPatch,
@@ -47,6 +50,7 @@ pub(crate) fn dav_method(m: &http::Method) -> DavResult<DavMethod> {
             "MOVE" => DavMethod::Move,
             "LOCK" => DavMethod::Lock,
             "UNLOCK" => DavMethod::Unlock,
+            "REPORT" => DavMethod::Report,
             _ => {
                 return Err(DavError::UnknownDavMethod);
             }
@@ -77,7 +81,8 @@ impl DavMethodSet {
     pub const HTTP_RO: DavMethodSet =
         DavMethodSet(DavMethod::Get as u32 | DavMethod::Head as u32 | DavMethod::Options as u32);
     pub const HTTP_RW: DavMethodSet = DavMethodSet(Self::HTTP_RO.0 | DavMethod::Put as u32);
-    pub const WEBDAV_RO: DavMethodSet = DavMethodSet(Self::HTTP_RO.0 | DavMethod::PropFind as u32);
+    pub const WEBDAV_RO: DavMethodSet =
+        DavMethodSet(Self::HTTP_RO.0 | DavMethod::PropFind as u32 | DavMethod::Report as u32);
     pub const WEBDAV_RW: DavMethodSet = DavMethodSet(0xffffffff);
 
     /// New set, all methods allowed.
@@ -125,6 +130,8 @@ impl DavMethodSet {
                 "move" => DavMethod::Move as u32,
                 "lock" => DavMethod::Lock as u32,
                 "unlock" => DavMethod::Unlock as u32,
+                "report" => DavMethod::Report as u32,
+                "post" => DavMethod::Post as u32,
                 "http-ro" => Self::HTTP_RO.0,
                 "http-rw" => Self::HTTP_RW.0,
                 "webdav-ro" => Self::WEBDAV_RO.0,
@@ -199,6 +206,36 @@ impl Write for MemBuffer {
     }
 }
 
+// Match a name against a glob with '*' (any number of bytes)
+// and '?' (exactly one byte).
+pub(crate) fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // position of the last '*' and where we were in the name at that point.
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((sp, sn)) = star {
+            // let the last '*' match one more byte.
+            p = sp + 1;
+            n = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+// Does the name match any of the globs.
+pub(crate) fn glob_match_any(patterns: &[String], name: &[u8]) -> bool {
+    patterns.iter().any(|p| glob_match(p.as_bytes(), name))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -208,4 +245,17 @@ mod tests {
     fn test_rfc3339() {
         assert!(systemtime_to_rfc3339(UNIX_EPOCH) == "1970-01-01T00:00:00Z");
     }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b".*", b".git"));
+        assert!(glob_match(b".*", b"."));
+        assert!(!glob_match(b".*", b"file.txt"));
+        assert!(glob_match(b"*.bak", b"file.txt.bak"));
+        assert!(!glob_match(b"*.bak", b"file.bak.txt"));
+        assert!(glob_match(b"a?c*", b"abcdef"));
+        assert!(!glob_match(b"a?c", b"ac"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"*a*b", b"xxaxxb"));
+    }
 }
@@ -0,0 +1,495 @@
+//! Filesystem wrapper that adds virtual files.
+//!
+//! [`VirtualFiles`] wraps another filesystem and adds files that are not
+//! stored anywhere. Their content is generated when they are read, by a
+//! function that is registered for the path. That is useful for things
+//! like a generated manifest or a status page next to real files.
+//!
+//! Virtual files are listed in PROPFIND and can be read with GET and HEAD,
+//! but cannot be written, moved, removed or have their properties changed,
+//! those requests are refused with `403 Forbidden`. A virtual file hides a
+//! real file with the same name. The collection that it is in must exist
+//! in the wrapped filesystem.
+//!
+//! ```
+//! use std::collections::HashMap;
+//! use std::sync::Arc;
+//! use dav_server::{memfs::MemFs, DavHandler};
+//! use dav_server::virtualfiles::{VirtualFile, VirtualFileFn, VirtualFiles};
+//!
+//! let mut files: HashMap<String, VirtualFileFn> = HashMap::new();
+//! files.insert(
+//!     "/status.txt".to_string(),
+//!     Arc::new(|_path| VirtualFile::from_bytes("all systems go\n")),
+//! );
+//! let fs = VirtualFiles::new(MemFs::new(), files);
+//! let dav = DavHandler::builder().filesystem(fs).build_handler();
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use bytes::{Buf, Bytes};
+use futures_util::{future, Stream, StreamExt};
+
+use crate::asyncfs::{async_trait, AsyncDavFileSystem};
+use crate::davpath::DavPath;
+use crate::fs::*;
+
+/// The content of a virtual file, and its metadata.
+pub struct VirtualFile {
+    len: u64,
+    modified: SystemTime,
+    content: FsStream<FsResult<Bytes>>,
+}
+
+impl VirtualFile {
+    /// A file of `len` bytes, with the content coming from a stream.
+    ///
+    /// The stream must produce exactly `len` bytes. The modification
+    /// time is the current time.
+    pub fn new<S>(len: u64, content: S) -> VirtualFile
+    where
+        S: Stream<Item = FsResult<Bytes>> + Send + 'static,
+    {
+        VirtualFile {
+            len,
+            modified: SystemTime::now(),
+            content: Box::pin(content),
+        }
+    }
+
+    /// A file with content that is already known.
+    pub fn from_bytes(data: impl Into<Bytes>) -> VirtualFile {
+        let data = data.into();
+        VirtualFile::new(
+            data.len() as u64,
+            futures_util::stream::once(future::ok(data)),
+        )
+    }
+
+    /// Set the modification time.
+    pub fn modified(self, modified: SystemTime) -> VirtualFile {
+        VirtualFile { modified, ..self }
+    }
+
+    fn meta(&self) -> VirtualMeta {
+        VirtualMeta {
+            len: self.len,
+            modified: self.modified,
+        }
+    }
+}
+
+/// Function that generates a virtual file.
+///
+/// It is called with the path of the file every time the file is
+/// opened, or its metadata is needed. The content stream is only
+/// read when the file is read.
+pub type VirtualFileFn = Arc<dyn Fn(&DavPath) -> VirtualFile + Send + Sync>;
+
+struct Entry {
+    // url of the collection the file is in, without trailing slash.
+    parent: String,
+    name: Vec<u8>,
+    func: VirtualFileFn,
+}
+
+/// Filesystem wrapper that adds virtual files.
+#[derive(Clone)]
+pub struct VirtualFiles {
+    inner: Box<dyn DavFileSystem>,
+    files: Arc<HashMap<String, Entry>>,
+}
+
+fn trim_slash(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        p => p,
+    }
+}
+
+impl VirtualFiles {
+    /// Wrap a filesystem, and add the files in `files`.
+    ///
+    /// The keys are url-encoded paths without prefix, like `/status.txt`.
+    ///
+    /// Panics if a key is not a valid path.
+    pub fn new(
+        inner: Box<dyn DavFileSystem>,
+        files: HashMap<String, VirtualFileFn>,
+    ) -> Box<VirtualFiles> {
+        let files = files
+            .into_iter()
+            .map(|(key, func)| {
+                let path = DavPath::new(&key).expect("VirtualFiles: invalid path");
+                let entry = Entry {
+                    parent: trim_slash(&path.parent().as_url_string()).to_string(),
+                    name: path.file_name_bytes().to_vec(),
+                    func,
+                };
+                (trim_slash(&path.as_url_string()).to_string(), entry)
+            })
+            .collect();
+        Box::new(VirtualFiles {
+            inner,
+            files: Arc::new(files),
+        })
+    }
+
+    fn lookup(&self, path: &DavPath) -> Option<&Entry> {
+        self.files.get(trim_slash(&path.as_url_string()))
+    }
+
+    // writes to virtual files are refused.
+    fn check_writable(&self, path: &DavPath) -> FsResult<()> {
+        match self.lookup(path) {
+            Some(_) => Err(FsError::Forbidden),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncDavFileSystem for VirtualFiles {
+    async fn open(&self, path: &DavPath, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        let entry = match self.lookup(path) {
+            Some(entry) => entry,
+            None => return self.inner.open(path, options).await,
+        };
+        if options.write {
+            return Err(FsError::Forbidden);
+        }
+        let vf = (entry.func)(path);
+        Ok(Box::new(VirtualDavFile {
+            meta: vf.meta(),
+            content: Mutex::new(vf.content),
+            buf: Bytes::new(),
+            pos: 0,
+        }))
+    }
+
+    async fn read_dir(
+        &self,
+        path: &DavPath,
+        meta: ReadDirMeta,
+    ) -> FsResult<FsStream<FsResult<Box<dyn DavDirEntry>>>> {
+        let dir = trim_slash(&path.as_url_string()).to_string();
+        let mut entries = Vec::new();
+        for entry in self.files.values().filter(|e| e.parent == dir) {
+            let mut p = path.clone();
+            p.push_segment(&entry.name);
+            entries.push(Box::new(VirtualDirEntry {
+                name: entry.name.clone(),
+                meta: (entry.func)(&p).meta(),
+            }) as Box<dyn DavDirEntry>);
+        }
+
+        // real files with the same name are hidden.
+        let names = entries.iter().map(|e| e.name()).collect::<Vec<_>>();
+        let real = self.inner.read_dir(path, meta).await?.filter(move |e| {
+            let hidden = matches!(e, Ok(e) if names.contains(&e.name()));
+            future::ready(!hidden)
+        });
+        let entries = entries.into_iter().map(Ok);
+        Ok(Box::pin(futures_util::stream::iter(entries).chain(real)))
+    }
+
+    async fn metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        match self.lookup(path) {
+            Some(entry) => Ok(Box::new((entry.func)(path).meta())),
+            None => self.inner.metadata(path).await,
+        }
+    }
+
+    async fn symlink_metadata(&self, path: &DavPath) -> FsResult<Box<dyn DavMetaData>> {
+        match self.lookup(path) {
+            Some(_) => AsyncDavFileSystem::metadata(self, path).await,
+            None => self.inner.symlink_metadata(path).await,
+        }
+    }
+
+    async fn create_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.check_writable(path)?;
+        self.inner.create_dir(path).await
+    }
+
+    async fn remove_dir(&self, path: &DavPath) -> FsResult<()> {
+        self.check_writable(path)?;
+        self.inner.remove_dir(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &DavPath) -> FsResult<()> {
+        self.check_writable(path)?;
+        self.inner.remove_dir_all(path).await
+    }
+
+    async fn remove_file(&self, path: &DavPath) -> FsResult<()> {
+        self.check_writable(path)?;
+        self.inner.remove_file(path).await
+    }
+
+    async fn rename(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.check_writable(from)?;
+        self.check_writable(to)?;
+        self.inner.rename(from, to).await
+    }
+
+    async fn copy(&self, from: &DavPath, to: &DavPath) -> FsResult<()> {
+        self.check_writable(from)?;
+        self.check_writable(to)?;
+        self.inner.copy(from, to).await
+    }
+
+    async fn set_accessed(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.check_writable(path)?;
+        self.inner.set_accessed(path, tm).await
+    }
+
+    async fn set_modified(&self, path: &DavPath, tm: SystemTime) -> FsResult<()> {
+        self.check_writable(path)?;
+        self.inner.set_modified(path, tm).await
+    }
+
+    async fn have_props(&self, path: &DavPath) -> bool {
+        match self.lookup(path) {
+            Some(_) => false,
+            None => self.inner.have_props(path).await,
+        }
+    }
+
+    async fn patch_props(
+        &self,
+        path: &DavPath,
+        patch: Vec<(bool, DavProp)>,
+    ) -> FsResult<Vec<(http::StatusCode, DavProp)>> {
+        self.check_writable(path)?;
+        self.inner.patch_props(path, patch).await
+    }
+
+    async fn get_props(&self, path: &DavPath, do_content: bool) -> FsResult<Vec<DavProp>> {
+        match self.lookup(path) {
+            Some(_) => Ok(Vec::new()),
+            None => self.inner.get_props(path, do_content).await,
+        }
+    }
+
+    async fn get_prop(&self, path: &DavPath, prop: DavProp) -> FsResult<Vec<u8>> {
+        match self.lookup(path) {
+            Some(_) => Err(FsError::NotFound),
+            None => self.inner.get_prop(path, prop).await,
+        }
+    }
+
+    fn with_context(&self, context: &DavContext) -> Box<dyn DavFileSystem> {
+        Box::new(VirtualFiles {
+            inner: self.inner.with_context(context),
+            files: self.files.clone(),
+        })
+    }
+
+    async fn get_quota(&self) -> FsResult<(u64, Option<u64>)> {
+        self.inner.get_quota().await
+    }
+
+    fn preferred_read_chunk_size(&self) -> usize {
+        self.inner.preferred_read_chunk_size()
+    }
+
+    async fn get_redirect_url(&self, path: &DavPath) -> FsResult<Option<String>> {
+        match self.lookup(path) {
+            Some(_) => Ok(None),
+            None => self.inner.get_redirect_url(path).await,
+        }
+    }
+
+    async fn collection_etag(&self, path: &DavPath) -> FsResult<Option<String>> {
+        match self.lookup(path) {
+            Some(_) => Ok(None),
+            None => self.inner.collection_etag(path).await,
+        }
+    }
+
+    async fn is_special(&self, path: &DavPath) -> FsResult<bool> {
+        match self.lookup(path) {
+            Some(_) => Ok(false),
+            None => self.inner.is_special(path).await,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct VirtualMeta {
+    len: u64,
+    modified: SystemTime,
+}
+
+impl DavMetaData for VirtualMeta {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn modified(&self) -> FsResult<SystemTime> {
+        Ok(self.modified)
+    }
+
+    fn is_dir(&self) -> bool {
+        false
+    }
+}
+
+struct VirtualDirEntry {
+    name: Vec<u8>,
+    meta: VirtualMeta,
+}
+
+impl DavDirEntry for VirtualDirEntry {
+    fn name(&self) -> Vec<u8> {
+        self.name.clone()
+    }
+
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        Box::pin(future::ok(
+            Box::new(self.meta.clone()) as Box<dyn DavMetaData>
+        ))
+    }
+}
+
+struct VirtualDavFile {
+    meta: VirtualMeta,
+    content: Mutex<FsStream<FsResult<Bytes>>>,
+    // data from the stream that was not read yet.
+    buf: Bytes,
+    pos: u64,
+}
+
+impl fmt::Debug for VirtualDavFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VirtualDavFile")
+            .field("meta", &self.meta)
+            .field("pos", &self.pos)
+            .finish()
+    }
+}
+
+impl DavFile for VirtualDavFile {
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
+        Box::pin(future::ok(
+            Box::new(self.meta.clone()) as Box<dyn DavMetaData>
+        ))
+    }
+
+    fn write_buf(&mut self, _buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
+        Box::pin(future::err(FsError::Forbidden))
+    }
+
+    fn write_bytes(&mut self, _buf: Bytes) -> FsFuture<'_, ()> {
+        Box::pin(future::err(FsError::Forbidden))
+    }
+
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
+        Box::pin(async move {
+            while self.buf.is_empty() {
+                let content = self.content.get_mut().unwrap();
+                match content.next().await {
+                    Some(Ok(data)) => self.buf = data,
+                    Some(Err(e)) => return Err(e),
+                    None => return Ok(Bytes::new()),
+                }
+            }
+            let n = std::cmp::min(count, self.buf.len());
+            self.pos += n as u64;
+            Ok(self.buf.split_to(n))
+        })
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
+        // the content is a stream, we can only stay where we are.
+        let res = match pos {
+            SeekFrom::Start(n) if n == self.pos => Ok(n),
+            SeekFrom::Current(0) => Ok(self.pos),
+            _ => Err(FsError::NotImplemented),
+        };
+        Box::pin(future::ready(res))
+    }
+
+    fn flush(&mut self) -> FsFuture<'_, ()> {
+        Box::pin(future::ok(()))
+    }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::memfs::MemFs;
+    use crate::tests::{put, req, run};
+    use crate::DavHandler;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn virtual_files() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let h = hits.clone();
+        let mut files: HashMap<String, VirtualFileFn> = HashMap::new();
+        files.insert(
+            "/status.txt".to_string(),
+            Arc::new(move |_path| {
+                let n = h.fetch_add(1, Ordering::SeqCst);
+                // the content comes in more than one chunk.
+                let chunks = vec![
+                    Ok(Bytes::from("requests: ")),
+                    Ok(Bytes::from(n.to_string())),
+                ];
+                let len = 10 + n.to_string().len() as u64;
+                VirtualFile::new(len, futures_util::stream::iter(chunks))
+            }),
+        );
+        let fs = VirtualFiles::new(MemFs::new(), files);
+        let dav = DavHandler::builder().filesystem(fs).build_handler();
+        put(&dav, "/real.txt", "real").await;
+
+        let (resp, body) = run(&dav, req("GET", "/status.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "text/plain");
+        assert!(body.starts_with("requests: "), "{}", body);
+        assert_eq!(
+            resp.headers()["content-length"],
+            body.len().to_string().as_str()
+        );
+
+        let (resp, _) = run(
+            &dav,
+            req("HEAD", "/status.txt").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(resp.status(), 200);
+
+        let r = req("PROPFIND", "/")
+            .header("Depth", "1")
+            .body(Body::empty())
+            .unwrap();
+        let (_, body) = run(&dav, r).await;
+        assert!(body.contains("<D:href>/status.txt</D:href>"), "{}", body);
+        assert!(body.contains("<D:href>/real.txt</D:href>"), "{}", body);
+
+        // virtual files are read-only.
+        let r = req("PUT", "/status.txt").body(Body::from("x")).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 403);
+        let r = req("DELETE", "/status.txt").body(Body::empty()).unwrap();
+        assert_eq!(run(&dav, r).await.0.status(), 403);
+        let r = req("MOVE", "/real.txt")
+            .header("Destination", "/status.txt")
+            .body(Body::empty())
+            .unwrap();
+        assert_ne!(run(&dav, r).await.0.status().as_u16() / 100, 2);
+        let (_, body) = run(&dav, req("GET", "/real.txt").body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "real");
+    }
+}